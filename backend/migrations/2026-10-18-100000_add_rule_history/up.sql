@@ -0,0 +1,22 @@
+-- Audit trail of rule changes with before/after snapshots
+-- rule_id has no foreign key so the history outlives deleted rules
+CREATE TABLE rule_history (
+    id SERIAL PRIMARY KEY,
+    rule_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    -- Change type: "create", "update", "toggle", "delete", "rollback"
+    change_type TEXT NOT NULL,
+
+    -- Per-rule version number, incremented on every change
+    version INTEGER NOT NULL,
+
+    -- Full rule snapshots (NULL before a create / after a delete)
+    before_snapshot JSONB,
+    after_snapshot JSONB,
+
+    changed_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE(rule_id, version)
+);
+
+CREATE INDEX idx_rule_history_user ON rule_history(user_id);
@@ -0,0 +1,2 @@
+-- IANA timezone used to evaluate the user's time-based rules (e.g. cron expressions)
+ALTER TABLE users ADD COLUMN timezone TEXT NOT NULL DEFAULT 'Europe/Madrid';
@@ -0,0 +1,2 @@
+ALTER TABLE devices DROP COLUMN rated_power_w;
+ALTER TABLE users DROP COLUMN contracted_power_kw;
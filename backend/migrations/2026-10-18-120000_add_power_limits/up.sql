@@ -0,0 +1,4 @@
+-- Contracted power (ICP limit) of the household; NULL means no limit
+ALTER TABLE users ADD COLUMN contracted_power_kw DOUBLE PRECISION;
+-- Rated power drawn by a device when on; NULL means unknown (not counted)
+ALTER TABLE devices ADD COLUMN rated_power_w INTEGER;
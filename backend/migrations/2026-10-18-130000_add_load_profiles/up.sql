@@ -0,0 +1,2 @@
+-- Power drawn over an appliance cycle, in watts per 15 minute step; NULL means unknown
+ALTER TABLE devices ADD COLUMN load_profile JSONB;
@@ -0,0 +1,19 @@
+-- Home batteries used for price arbitrage
+CREATE TABLE batteries (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Device (e.g., inverter) that receives charge/discharge commands; NULL = plan only
+    device_id INTEGER REFERENCES devices(id) ON DELETE SET NULL,
+    name TEXT NOT NULL,
+    capacity_kwh DOUBLE PRECISION NOT NULL,
+    max_charge_kw DOUBLE PRECISION NOT NULL,
+    max_discharge_kw DOUBLE PRECISION NOT NULL,
+    -- Round-trip efficiency (0-1]
+    efficiency DOUBLE PRECISION NOT NULL DEFAULT 0.9,
+    min_soc_percent DOUBLE PRECISION NOT NULL DEFAULT 10,
+    -- Last known state of charge
+    current_soc_percent DOUBLE PRECISION NOT NULL DEFAULT 50,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_batteries_user ON batteries(user_id);
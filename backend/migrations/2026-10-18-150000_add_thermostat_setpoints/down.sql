@@ -0,0 +1 @@
+ALTER TABLE scheduled_executions DROP COLUMN setpoint_c;
@@ -0,0 +1,2 @@
+-- Target temperature of set_temperature executions
+ALTER TABLE scheduled_executions ADD COLUMN setpoint_c DOUBLE PRECISION;
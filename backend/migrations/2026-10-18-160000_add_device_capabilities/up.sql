@@ -0,0 +1,2 @@
+-- Capabilities reported by the provider on discovery; NULL uses the provider's defaults
+ALTER TABLE devices ADD COLUMN capabilities JSONB;
@@ -0,0 +1,10 @@
+-- Energy a metering device recorded per day, for cost accounting
+CREATE TABLE energy_readings (
+    id SERIAL PRIMARY KEY,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    date DATE NOT NULL,
+    energy_kwh DOUBLE PRECISION NOT NULL,
+    -- Last time the provider reported this day (today's total keeps growing)
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (device_id, date)
+);
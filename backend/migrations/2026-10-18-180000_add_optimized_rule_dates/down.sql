@@ -0,0 +1 @@
+DROP TABLE optimized_rule_dates;
@@ -0,0 +1,8 @@
+-- Days whose plan for a rule was set by the cost optimiser; the per-rule
+-- planner leaves them alone until the rule is replanned
+CREATE TABLE optimized_rule_dates (
+    rule_id INTEGER NOT NULL REFERENCES automation_rules(id) ON DELETE CASCADE,
+    date DATE NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (rule_id, date)
+);
@@ -0,0 +1 @@
+ALTER TABLE batteries DROP COLUMN soc_updated_at;
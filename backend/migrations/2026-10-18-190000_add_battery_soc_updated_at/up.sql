@@ -0,0 +1,3 @@
+-- When current_soc_percent was last read or set; commands aren't sent from
+-- a stale state of charge
+ALTER TABLE batteries ADD COLUMN soc_updated_at TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,343 @@
+use crate::{
+    api::deserialize_some,
+    db::DbPool,
+    models::{Battery, NewBattery},
+    schema::{batteries, devices, user_integrations},
+    services::{auth::Claims, battery_planner::BatteryPlannerService},
+};
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct CreateBatteryRequest {
+    pub name: String,
+    pub device_id: Option<i32>,
+    pub capacity_kwh: f64,
+    pub max_charge_kw: f64,
+    pub max_discharge_kw: f64,
+    #[serde(default = "default_efficiency")]
+    pub efficiency: f64,
+    #[serde(default = "default_min_soc_percent")]
+    pub min_soc_percent: f64,
+    #[serde(default = "default_current_soc_percent")]
+    pub current_soc_percent: f64,
+}
+
+fn default_efficiency() -> f64 {
+    0.9
+}
+
+fn default_min_soc_percent() -> f64 {
+    10.0
+}
+
+fn default_current_soc_percent() -> f64 {
+    50.0
+}
+
+#[derive(Deserialize)]
+pub struct UpdateBatteryRequest {
+    pub name: Option<String>,
+    /// Controlling device; null stops sending commands
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub device_id: Option<Option<i32>>,
+    pub capacity_kwh: Option<f64>,
+    pub max_charge_kw: Option<f64>,
+    pub max_discharge_kw: Option<f64>,
+    pub efficiency: Option<f64>,
+    pub min_soc_percent: Option<f64>,
+    /// Latest state of charge reading
+    pub current_soc_percent: Option<f64>,
+}
+
+impl UpdateBatteryRequest {
+    fn apply(&self, battery: &mut Battery) {
+        if let Some(ref name) = self.name {
+            battery.name = name.clone();
+        }
+        if let Some(device_id) = self.device_id {
+            battery.device_id = device_id;
+        }
+        if let Some(v) = self.capacity_kwh {
+            battery.capacity_kwh = v;
+        }
+        if let Some(v) = self.max_charge_kw {
+            battery.max_charge_kw = v;
+        }
+        if let Some(v) = self.max_discharge_kw {
+            battery.max_discharge_kw = v;
+        }
+        if let Some(v) = self.efficiency {
+            battery.efficiency = v;
+        }
+        if let Some(v) = self.min_soc_percent {
+            battery.min_soc_percent = v;
+        }
+        if let Some(v) = self.current_soc_percent {
+            battery.current_soc_percent = v;
+            battery.soc_updated_at = Utc::now().naive_utc();
+        }
+    }
+}
+
+/// Whether `device_id` belongs to the user
+fn owns_device(conn: &mut PgConnection, user_id: i32, device_id: i32) -> bool {
+    devices::table
+        .inner_join(user_integrations::table)
+        .filter(devices::id.eq(device_id))
+        .filter(user_integrations::user_id.eq(user_id))
+        .select(devices::id)
+        .first::<i32>(conn)
+        .is_ok()
+}
+
+fn find_battery(conn: &mut PgConnection, user_id: i32, battery_id: i32) -> Option<Battery> {
+    batteries::table
+        .filter(batteries::id.eq(battery_id))
+        .filter(batteries::user_id.eq(user_id))
+        .first::<Battery>(conn)
+        .ok()
+}
+
+/// List the user's batteries
+#[get("")]
+pub async fn list_batteries(pool: web::Data<DbPool>, claims: Claims) -> impl Responder {
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    match batteries::table
+        .filter(batteries::user_id.eq(user_id))
+        .order(batteries::id.asc())
+        .load::<Battery>(&mut conn)
+    {
+        Ok(list) => HttpResponse::Ok().json(list),
+        Err(_) => HttpResponse::InternalServerError().body("Error fetching batteries"),
+    }
+}
+
+/// Add a battery
+#[post("")]
+pub async fn create_battery(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    body: web::Json<CreateBatteryRequest>,
+) -> impl Responder {
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let body = body.into_inner();
+    let candidate = Battery {
+        id: 0,
+        user_id,
+        device_id: body.device_id,
+        name: body.name,
+        capacity_kwh: body.capacity_kwh,
+        max_charge_kw: body.max_charge_kw,
+        max_discharge_kw: body.max_discharge_kw,
+        efficiency: body.efficiency,
+        min_soc_percent: body.min_soc_percent,
+        current_soc_percent: body.current_soc_percent,
+        created_at: NaiveDateTime::default(),
+        soc_updated_at: NaiveDateTime::default(),
+    };
+    if let Err(e) = candidate.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if let Some(device_id) = candidate.device_id
+        && !owns_device(&mut conn, user_id, device_id)
+    {
+        return HttpResponse::NotFound().body("Device not found");
+    }
+
+    let new_battery = NewBattery {
+        user_id,
+        device_id: candidate.device_id,
+        name: candidate.name,
+        capacity_kwh: candidate.capacity_kwh,
+        max_charge_kw: candidate.max_charge_kw,
+        max_discharge_kw: candidate.max_discharge_kw,
+        efficiency: candidate.efficiency,
+        min_soc_percent: candidate.min_soc_percent,
+        current_soc_percent: candidate.current_soc_percent,
+    };
+    match diesel::insert_into(batteries::table)
+        .values(&new_battery)
+        .get_result::<Battery>(&mut conn)
+    {
+        Ok(battery) => HttpResponse::Created().json(battery),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// Update a battery's parameters or its latest state of charge
+#[put("/{battery_id}")]
+pub async fn update_battery(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<i32>,
+    body: web::Json<UpdateBatteryRequest>,
+) -> impl Responder {
+    let battery_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let mut battery = match find_battery(&mut conn, user_id, battery_id) {
+        Some(b) => b,
+        None => return HttpResponse::NotFound().body("Battery not found"),
+    };
+    body.apply(&mut battery);
+    if let Err(e) = battery.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if let Some(Some(device_id)) = body.device_id
+        && !owns_device(&mut conn, user_id, device_id)
+    {
+        return HttpResponse::NotFound().body("Device not found");
+    }
+
+    match diesel::update(batteries::table.find(battery_id))
+        .set((
+            batteries::name.eq(&battery.name),
+            batteries::device_id.eq(battery.device_id),
+            batteries::capacity_kwh.eq(battery.capacity_kwh),
+            batteries::max_charge_kw.eq(battery.max_charge_kw),
+            batteries::max_discharge_kw.eq(battery.max_discharge_kw),
+            batteries::efficiency.eq(battery.efficiency),
+            batteries::min_soc_percent.eq(battery.min_soc_percent),
+            batteries::current_soc_percent.eq(battery.current_soc_percent),
+            batteries::soc_updated_at.eq(battery.soc_updated_at),
+        ))
+        .get_result::<Battery>(&mut conn)
+    {
+        Ok(updated) => HttpResponse::Ok().json(updated),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// Delete a battery
+#[delete("/{battery_id}")]
+pub async fn delete_battery(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let battery_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    match diesel::delete(
+        batteries::table
+            .filter(batteries::id.eq(battery_id))
+            .filter(batteries::user_id.eq(user_id)),
+    )
+    .execute(&mut conn)
+    {
+        Ok(0) => HttpResponse::NotFound().body("Battery not found"),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({"deleted": true})),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to delete battery"),
+    }
+}
+
+/// Charge/idle/discharge plan from the current hour and its expected profit
+#[get("/{battery_id}/plan")]
+pub async fn get_battery_plan(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let battery_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let battery = match find_battery(&mut conn, user_id, battery_id) {
+        Some(b) => b,
+        None => return HttpResponse::NotFound().body("Battery not found"),
+    };
+
+    let planner = BatteryPlannerService::new(pool.get_ref().clone());
+    match planner.plan(&battery) {
+        Ok(plan) => HttpResponse::Ok().json(plan),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_battery_request_defaults() {
+        let json = r#"{"name": "Garage", "capacity_kwh": 10, "max_charge_kw": 5, "max_discharge_kw": 5}"#;
+        let request: CreateBatteryRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.efficiency, 0.9);
+        assert_eq!(request.min_soc_percent, 10.0);
+        assert_eq!(request.current_soc_percent, 50.0);
+        assert!(request.device_id.is_none());
+    }
+
+    #[test]
+    fn test_update_battery_request_clears_device() {
+        let mut battery = Battery {
+            id: 1,
+            user_id: 1,
+            device_id: Some(3),
+            name: "Garage".to_string(),
+            capacity_kwh: 10.0,
+            max_charge_kw: 5.0,
+            max_discharge_kw: 5.0,
+            efficiency: 0.9,
+            min_soc_percent: 10.0,
+            current_soc_percent: 50.0,
+            created_at: NaiveDateTime::default(),
+            soc_updated_at: NaiveDateTime::default(),
+        };
+
+        let request: UpdateBatteryRequest =
+            serde_json::from_str(r#"{"current_soc_percent": 80}"#).unwrap();
+        request.apply(&mut battery);
+        assert_eq!(battery.current_soc_percent, 80.0);
+        assert!(battery.soc_updated_at > NaiveDateTime::default());
+        assert_eq!(battery.device_id, Some(3));
+
+        let request: UpdateBatteryRequest = serde_json::from_str(r#"{"device_id": null}"#).unwrap();
+        request.apply(&mut battery);
+        assert_eq!(battery.device_id, None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{
+    db::DbPool,
+    services::{
+        auth::Claims,
+        config_transfer::{
+            parse_document, serialize_document, ConfigTransferService, ConflictPolicy,
+            DocumentFormat, ImportError,
+        },
+    },
+};
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+// ============================================================================
+// Request/Response DTOs
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: DocumentFormat,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub format: DocumentFormat,
+    /// Only report what would change
+    #[serde(default)]
+    pub dry_run: bool,
+    /// skip (default), overwrite or fail
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
+fn content_type(format: DocumentFormat) -> &'static str {
+    match format {
+        DocumentFormat::Json => "application/json",
+        DocumentFormat::Yaml => "application/yaml",
+    }
+}
+
+// ============================================================================
+// Endpoints
+// ============================================================================
+
+/// Export the user's devices and rules
+/// Query params:
+///   - format: json (default) or yaml
+#[get("/export")]
+pub async fn export_config(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    query: web::Query<ExportQuery>,
+) -> impl Responder {
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let service = ConfigTransferService::new(pool.get_ref().clone());
+    let doc = match service.export(user_id) {
+        Ok(doc) => doc,
+        Err(e) => return HttpResponse::InternalServerError().body(e),
+    };
+
+    match serialize_document(&doc, query.format) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(content_type(query.format))
+            .body(body),
+        Err(e) => HttpResponse::InternalServerError().body(e),
+    }
+}
+
+/// Import devices and rules from an export document
+/// Query params:
+///   - format: json (default) or yaml
+///   - dry_run: if true, only return the import report
+///   - on_conflict: skip (default), overwrite or fail
+#[post("/import")]
+pub async fn import_config(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    query: web::Query<ImportQuery>,
+    body: String,
+) -> impl Responder {
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let doc = match parse_document(&body, query.format) {
+        Ok(doc) => doc,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid document: {}", e)),
+    };
+
+    let service = ConfigTransferService::new(pool.get_ref().clone());
+    match service.import(user_id, &doc, query.on_conflict, query.dry_run) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(ImportError::Conflicts(report)) => HttpResponse::Conflict().json(report),
+        Err(ImportError::Invalid(e)) => HttpResponse::BadRequest().body(e),
+        Err(ImportError::Database(e)) => {
+            HttpResponse::InternalServerError().body(format!("Import failed: {}", e))
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use crate::{
+    api::deserialize_some,
     db::DbPool,
-    integrations::ProviderRegistry,
-    models::{Device, UserIntegration},
-    schema::{devices, user_integrations},
-    services::auth::Claims,
+    integrations::{DeviceCapabilities, ProviderRegistry},
+    models::{parse_load_profile, Device, UserIntegration},
+    schema::{automation_rules, devices, user_integrations},
+    services::{
+        auth::Claims, energy_readings::EnergyReadingService,
+        schedule_computation::ScheduleComputationService,
+        schedule_explainer::ScheduleExplainerService,
+    },
 };
 use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use chrono::{Duration, Local, NaiveDateTime};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -21,6 +27,11 @@ pub struct DeviceResponse {
     /// Device on/off state - defaults to false since we don't query real state on list
     /// The actual state is determined by controlling the device
     pub is_on: bool,
+    /// Power drawn when on, in watts
+    pub rated_power_w: Option<i32>,
+    /// Power over an appliance cycle, in watts per 15 minutes
+    pub load_profile: Option<serde_json::Value>,
+    pub capabilities: DeviceCapabilities,
 }
 
 #[derive(Deserialize)]
@@ -37,13 +48,41 @@ pub struct ListDevicesQuery {
 
 #[derive(Deserialize)]
 pub struct DeviceActionRequest {
-    pub action: String, // "turn_on" or "turn_off"
+    /// "turn_on", "turn_off", "set_brightness" or "set_temperature"
+    pub action: String,
+    /// Channel of a multi-channel device, defaults to 0
+    #[serde(default)]
+    pub channel: Option<u32>,
+    /// Percent, for "set_brightness"
+    #[serde(default)]
+    pub brightness: Option<u8>,
+    /// °C, for "set_temperature"
+    #[serde(default)]
+    pub temperature: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct ExplainQuery {
+    /// Local time to explain (YYYY-MM-DDTHH:MM[:SS]), defaults to now
+    pub at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EnergyQuery {
+    /// Days of history, including today (1-365, defaults to 30)
+    pub days: Option<i64>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdateDeviceRequest {
     pub is_managed: Option<bool>,
     pub name: Option<String>,
+    /// Rated power in watts; null clears it
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub rated_power_w: Option<Option<i32>>,
+    /// Watts per 15 minutes over an appliance cycle; null clears it
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub load_profile: Option<Option<Vec<f64>>>,
 }
 
 /// List all devices for the authenticated user
@@ -131,6 +170,10 @@ pub async fn list_devices(
                                 is_managed: device.is_managed,
                                 provider_name: provider_name.clone(),
                                 is_on: device.is_on,
+                                rated_power_w: device.rated_power_w,
+                                load_profile: device.load_profile.clone(),
+                                capabilities: registry
+                                    .device_capabilities(provider_name, device.capabilities.as_ref()),
                             });
                         }
                     }
@@ -171,6 +214,9 @@ pub async fn list_devices(
                     is_managed: device.is_managed,
                     provider_name: provider_name.clone(),
                     is_on,
+                    rated_power_w: device.rated_power_w,
+                    load_profile: device.load_profile.clone(),
+                    capabilities: registry.device_capabilities(provider_name, device.capabilities.as_ref()),
                 });
             }
         }
@@ -182,6 +228,7 @@ pub async fn list_devices(
     let response: Vec<DeviceResponse> = results
         .into_iter()
         .map(|(device, provider_name)| DeviceResponse {
+            capabilities: registry.device_capabilities(&provider_name, device.capabilities.as_ref()),
             id: device.id,
             integration_id: device.integration_id,
             external_id: device.external_id,
@@ -190,6 +237,8 @@ pub async fn list_devices(
             is_managed: device.is_managed,
             provider_name,
             is_on: device.is_on, // Use cached state from database
+            rated_power_w: device.rated_power_w,
+            load_profile: device.load_profile.clone(),
         })
         .collect();
 
@@ -280,6 +329,11 @@ pub async fn sync_devices(
     let mut new_count = 0;
 
     for device in discovered {
+        let capabilities = device
+            .capabilities
+            .as_ref()
+            .and_then(|caps| serde_json::to_value(caps).ok());
+
         // Check if device already exists
         let existing: Option<Device> = devices::table
             .filter(devices::integration_id.eq(integration.id))
@@ -288,14 +342,18 @@ pub async fn sync_devices(
             .optional()
             .unwrap_or(None);
 
-        if existing.is_some() {
-            // Update existing device name if changed
+        if let Some(existing) = existing {
+            // Keep the stored capabilities when discovery could not read them
+            let capabilities = synced_capabilities(existing.capabilities, capabilities);
             diesel::update(
                 devices::table
                     .filter(devices::integration_id.eq(integration.id))
                     .filter(devices::external_id.eq(&device.external_id)),
             )
-            .set(devices::name.eq(&device.name))
+            .set((
+                devices::name.eq(&device.name),
+                devices::capabilities.eq(&capabilities),
+            ))
             .execute(&mut conn)
             .ok();
             synced_count += 1;
@@ -308,6 +366,7 @@ pub async fn sync_devices(
                     devices::name.eq(&device.name),
                     devices::device_type.eq(&device.device_type),
                     devices::is_managed.eq(false), // Default to not managed
+                    devices::capabilities.eq(&capabilities),
                 ))
                 .execute(&mut conn)
                 .ok();
@@ -323,7 +382,7 @@ pub async fn sync_devices(
     }))
 }
 
-/// Control a device (turn on/off)
+/// Control a device (turn on/off, dim, set temperature)
 #[post("/{device_id}/control")]
 pub async fn control_device(
     pool: web::Data<DbPool>,
@@ -361,6 +420,13 @@ pub async fn control_device(
         None => return HttpResponse::InternalServerError().body("Provider not available"),
     };
 
+    // Reject actions the device doesn't support before contacting it
+    let channel = body.channel.unwrap_or(0);
+    let capabilities = registry.device_capabilities(&integration.provider_name, device.capabilities.as_ref());
+    if let Err(e) = capabilities.check_action(&body.action, channel) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
     // Parse credentials and login
     let credentials: serde_json::Value = match serde_json::from_str(&integration.credentials_json) {
         Ok(c) => c,
@@ -375,16 +441,31 @@ pub async fn control_device(
     };
 
     // Execute action
-    let result = match body.action.as_str() {
-        "turn_on" => provider.turn_on(&session, &device.external_id).await,
-        "turn_off" => provider.turn_off(&session, &device.external_id).await,
-        _ => return HttpResponse::BadRequest().body("Invalid action. Use 'turn_on' or 'turn_off'"),
+    let result = match (body.action.as_str(), body.channel) {
+        ("turn_on", Some(channel)) => provider.turn_on_channel(&session, &device.external_id, channel).await,
+        ("turn_on", None) => provider.turn_on(&session, &device.external_id).await,
+        ("turn_off", Some(channel)) => provider.turn_off_channel(&session, &device.external_id, channel).await,
+        ("turn_off", None) => provider.turn_off(&session, &device.external_id).await,
+        ("set_brightness", _) => match body.brightness {
+            Some(brightness) if brightness <= 100 => {
+                provider.set_brightness(&session, &device.external_id, brightness).await
+            }
+            _ => return HttpResponse::BadRequest().body("brightness must be between 0 and 100"),
+        },
+        ("set_temperature", _) => match body.temperature {
+            Some(celsius) if (5.0..=35.0).contains(&celsius) => {
+                provider.set_temperature(&session, &device.external_id, celsius).await
+            }
+            _ => return HttpResponse::BadRequest().body("temperature must be between 5 and 35"),
+        },
+        _ => return HttpResponse::BadRequest().body("Invalid action"),
     };
 
     match result {
         Ok(action_result) => {
-            // Update cached is_on state in database if action was successful
-            if action_result.success {
+            // Update cached is_on state in database if action was successful;
+            // the cached state follows the device's first channel
+            if action_result.success && channel == 0 {
                 if let Some(ref new_state) = action_result.new_state {
                     let _ = diesel::update(devices::table.filter(devices::id.eq(device_id)))
                         .set(devices::is_on.eq(new_state.is_on))
@@ -452,6 +533,207 @@ pub async fn get_device_state(
     }
 }
 
+/// Read the power a device is drawing and, if metered, its energy counter
+#[get("/{device_id}/power")]
+pub async fn get_device_power(
+    pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
+    claims: Claims,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let (device, integration): (Device, UserIntegration) = match devices::table
+        .inner_join(user_integrations::table)
+        .filter(devices::id.eq(device_id))
+        .filter(user_integrations::user_id.eq(user_id))
+        .select((Device::as_select(), UserIntegration::as_select()))
+        .first(&mut conn)
+    {
+        Ok(d) => d,
+        Err(_) => return HttpResponse::NotFound().body("Device not found"),
+    };
+
+    let capabilities = registry.device_capabilities(&integration.provider_name, device.capabilities.as_ref());
+    if let Err(e) = capabilities.check_action("read_power", 0) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let provider = match registry.get(&integration.provider_name) {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().body("Provider not available"),
+    };
+
+    let credentials: serde_json::Value = match serde_json::from_str(&integration.credentials_json) {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Invalid stored credentials"),
+    };
+
+    let session = match provider.login(&credentials).await {
+        Ok(s) => s,
+        Err(e) => {
+            return HttpResponse::BadRequest().body(format!("Authentication failed: {}", e))
+        }
+    };
+
+    match provider.read_power(&session, &device.external_id).await {
+        Ok(power_w) => {
+            // Not every metering device keeps an energy counter
+            let energy_kwh = provider.read_energy(&session, &device.external_id).await.ok();
+            HttpResponse::Ok().json(serde_json::json!({
+                "device_id": device_id,
+                "power_w": power_w,
+                "energy_kwh": energy_kwh,
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to read power: {}", e)),
+    }
+}
+
+/// Daily energy of a device with its estimated cost. Readings are refreshed
+/// from the provider when it keeps a history, then read from the database.
+/// Query params:
+///   - days: days of history including today (1-365, default 30)
+#[get("/{device_id}/energy")]
+pub async fn get_device_energy(
+    pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
+    claims: Claims,
+    path: web::Path<i32>,
+    query: web::Query<EnergyQuery>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let days = query.days.unwrap_or(30);
+    if !(1..=365).contains(&days) {
+        return HttpResponse::BadRequest().body("days must be between 1 and 365");
+    }
+
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let (device, integration): (Device, UserIntegration) = match devices::table
+        .inner_join(user_integrations::table)
+        .filter(devices::id.eq(device_id))
+        .filter(user_integrations::user_id.eq(user_id))
+        .select((Device::as_select(), UserIntegration::as_select()))
+        .first(&mut conn)
+    {
+        Ok(d) => d,
+        Err(_) => return HttpResponse::NotFound().body("Device not found"),
+    };
+
+    let capabilities = registry.device_capabilities(&integration.provider_name, device.capabilities.as_ref());
+    if let Err(e) = capabilities.check_action("read_energy", 0) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let service = EnergyReadingService::new(pool.get_ref().clone());
+
+    // Refreshing is best effort: stored readings are returned if it fails
+    if let Some(provider) = registry.get(&integration.provider_name)
+        && let Ok(credentials) = serde_json::from_str::<serde_json::Value>(&integration.credentials_json)
+        && let Ok(session) = provider.login(&credentials).await
+    {
+        match provider.read_daily_energy(&session, &device.external_id).await {
+            Ok(readings) => {
+                if let Err(e) = service.store(device.id, &readings) {
+                    log::warn!("Failed to store energy readings of device {}: {}", device.id, e);
+                }
+            }
+            Err(e) => log::debug!("No energy history from provider for device {}: {}", device.id, e),
+        }
+    }
+
+    let from = Local::now().date_naive() - Duration::days(days - 1);
+    match service.history(device.id, from) {
+        Ok(history) => {
+            let total_kwh: f64 = history.iter().map(|d| d.energy_kwh).sum();
+            let total_estimated_cost: f64 = history.iter().filter_map(|d| d.estimated_cost).sum();
+            HttpResponse::Ok().json(serde_json::json!({
+                "device_id": device_id,
+                "days": history,
+                "total_kwh": total_kwh,
+                "total_estimated_cost": total_estimated_cost,
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to load energy readings: {}", e)),
+    }
+}
+
+/// Explain why a device is on or off at a given hour
+/// Query params:
+///   - at: local time (YYYY-MM-DDTHH:MM[:SS]), defaults to now
+#[get("/{device_id}/explain")]
+pub async fn explain_device(
+    pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
+    claims: Claims,
+    path: web::Path<i32>,
+    query: web::Query<ExplainQuery>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let at = match &query.at {
+        Some(at) => match parse_explain_time(at) {
+            Some(at) => at,
+            None => {
+                return HttpResponse::BadRequest()
+                    .body("Invalid time format. Use YYYY-MM-DDTHH:MM[:SS]");
+            }
+        },
+        None => Local::now().naive_local(),
+    };
+
+    let service = ScheduleExplainerService::new(pool.get_ref().clone(), registry.into_inner());
+    let device = match service.find_user_device(device_id, user_id) {
+        Ok(Some(device)) => device,
+        Ok(None) => return HttpResponse::NotFound().body("Device not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(e),
+    };
+
+    match service.explain(&device, at) {
+        Ok(explanation) => HttpResponse::Ok().json(explanation),
+        Err(e) => {
+            HttpResponse::InternalServerError().body(format!("Failed to explain schedule: {}", e))
+        }
+    }
+}
+
+/// Capabilities to store for an existing device after a sync.
+fn synced_capabilities(
+    stored: Option<serde_json::Value>,
+    discovered: Option<serde_json::Value>,
+) -> Option<serde_json::Value> {
+    discovered.or(stored)
+}
+
+fn parse_explain_time(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+}
+
 /// Update device settings (e.g., is_managed flag)
 #[post("/{device_id}")]
 pub async fn update_device(
@@ -484,19 +766,58 @@ pub async fn update_device(
         return HttpResponse::NotFound().body("Device not found");
     }
 
-    // Update fields
-    if let Some(is_managed) = body.is_managed {
-        diesel::update(devices::table.filter(devices::id.eq(device_id)))
-            .set(devices::is_managed.eq(is_managed))
-            .execute(&mut conn)
-            .ok();
+    // Validate every field before writing any of them
+    let load_profile = body
+        .load_profile
+        .as_ref()
+        .map(|profile| profile.as_ref().map(|steps| serde_json::json!(steps)));
+    if let Some(Some(profile)) = &load_profile
+        && let Err(e) = parse_load_profile(profile)
+    {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if body.rated_power_w.is_some_and(|w| w.is_some_and(|w| w < 0)) {
+        return HttpResponse::BadRequest().body("rated_power_w must not be negative");
     }
 
-    if let Some(ref name) = body.name {
-        diesel::update(devices::table.filter(devices::id.eq(device_id)))
-            .set(devices::name.eq(name))
-            .execute(&mut conn)
-            .ok();
+    let updated = conn.transaction::<(), diesel::result::Error, _>(|conn| {
+        let device = devices::table.filter(devices::id.eq(device_id));
+        if let Some(is_managed) = body.is_managed {
+            diesel::update(device).set(devices::is_managed.eq(is_managed)).execute(conn)?;
+        }
+        if let Some(ref name) = body.name {
+            diesel::update(device).set(devices::name.eq(name)).execute(conn)?;
+        }
+        if let Some(ref load_profile) = load_profile {
+            diesel::update(device).set(devices::load_profile.eq(load_profile)).execute(conn)?;
+        }
+        if let Some(rated_power_w) = body.rated_power_w {
+            diesel::update(device).set(devices::rated_power_w.eq(rated_power_w)).execute(conn)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = updated {
+        log::error!("Error updating device {}: {}", device_id, e);
+        return HttpResponse::InternalServerError().body("Error updating device");
+    }
+
+    let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+    if body.rated_power_w.is_some() {
+        // The household power budget is shared by all of the user's rules;
+        // replanning the device's rules also plans its appliance cycles
+        let device_rules: Vec<(i32, i32)> = automation_rules::table
+            .filter(automation_rules::device_id.eq(device_id))
+            .select((automation_rules::id, automation_rules::device_id))
+            .load(&mut conn)
+            .unwrap_or_default();
+        if let Err(e) = schedule_service.recompute_after_rules_change(user_id, &device_rules) {
+            log::warn!("Failed to recompute schedules for user {}: {}", user_id, e);
+        }
+    } else if load_profile.is_some() {
+        // Appliance cycles of this device are planned from the profile
+        if let Err(e) = schedule_service.recompute_appliance_cycles_for_device(device_id) {
+            log::warn!("Failed to recompute schedules for device {}: {}", device_id, e);
+        }
     }
 
     // Return updated device
@@ -550,6 +871,30 @@ pub async fn delete_device(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_explain_time() {
+        let at = parse_explain_time("2026-01-18T14:30").unwrap();
+        assert_eq!(at.to_string(), "2026-01-18 14:30:00");
+        assert!(parse_explain_time("2026-01-18T14:30:15").is_some());
+        assert!(parse_explain_time("2026-01-18").is_none());
+    }
+
+    #[test]
+    fn test_synced_capabilities_keeps_stored_when_not_discovered() {
+        let stored = serde_json::json!({"channel_count": 4});
+        assert_eq!(
+            synced_capabilities(Some(stored.clone()), None),
+            Some(stored.clone())
+        );
+
+        let discovered = serde_json::json!({"channel_count": 2});
+        assert_eq!(
+            synced_capabilities(Some(stored), Some(discovered.clone())),
+            Some(discovered)
+        );
+        assert_eq!(synced_capabilities(None, None), None);
+    }
+
     #[test]
     fn test_device_action_request_deserialization() {
         let json = r#"{"action": "turn_on"}"#;
@@ -557,6 +902,19 @@ mod tests {
         assert_eq!(request.action, "turn_on");
     }
 
+    #[test]
+    fn test_device_action_request_with_parameters() {
+        let json = r#"{"action": "turn_off", "channel": 2}"#;
+        let request: DeviceActionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.channel, Some(2));
+        assert!(request.brightness.is_none());
+
+        let json = r#"{"action": "set_temperature", "temperature": 21.5}"#;
+        let request: DeviceActionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.temperature, Some(21.5));
+        assert!(request.channel.is_none());
+    }
+
     #[test]
     fn test_sync_devices_request_deserialization() {
         let json = r#"{"integration_id": 5}"#;
@@ -578,5 +936,16 @@ mod tests {
         let request: UpdateDeviceRequest = serde_json::from_str(json).unwrap();
         assert_eq!(request.is_managed, Some(false));
         assert!(request.name.is_none());
+        assert!(request.rated_power_w.is_none());
+    }
+
+    #[test]
+    fn test_update_device_request_rated_power() {
+        let request: UpdateDeviceRequest = serde_json::from_str(r#"{"rated_power_w": 2000}"#).unwrap();
+        assert_eq!(request.rated_power_w, Some(Some(2000)));
+
+        // null clears the rated power
+        let request: UpdateDeviceRequest = serde_json::from_str(r#"{"rated_power_w": null}"#).unwrap();
+        assert_eq!(request.rated_power_w, Some(None));
     }
 }
@@ -2,11 +2,15 @@ use actix_web::web;
 
 pub mod auth;
 pub mod automation;
+pub mod batteries;
+pub mod config_transfer;
 pub mod devices;
 pub mod integrations;
 pub mod prices;
 pub mod rules;
 pub mod schedules;
+pub mod templates;
+pub mod users;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     // Auth routes (public)
@@ -16,6 +20,13 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(auth::login),
     );
 
+    // User settings routes (protected)
+    cfg.service(
+        web::scope("/api/users")
+            .service(users::get_me)
+            .service(users::update_me),
+    );
+
     // Integration routes (protected)
     cfg.service(
         web::scope("/api/integrations")
@@ -31,6 +42,9 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(devices::sync_devices)
             .service(devices::control_device)
             .service(devices::get_device_state)
+            .service(devices::get_device_power)
+            .service(devices::get_device_energy)
+            .service(devices::explain_device)
             .service(devices::update_device)
             .service(devices::delete_device),
     );
@@ -42,9 +56,27 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(rules::get_rule)
             .service(rules::create_rule)
             .service(rules::update_rule)
+            .service(rules::update_energy_target)
             .service(rules::delete_rule)
             .service(rules::toggle_rule)
-            .service(rules::get_rule_executions),
+            .service(rules::get_rule_executions)
+            .service(rules::get_rule_history_entries)
+            .service(rules::rollback_rule_version),
+    );
+
+    // Rule template routes (protected)
+    cfg.service(
+        web::scope("/api/templates")
+            .service(templates::list_templates)
+            .service(templates::get_template)
+            .service(templates::instantiate_template),
+    );
+
+    // Import/export routes (protected)
+    cfg.service(
+        web::scope("/api/config")
+            .service(config_transfer::export_config)
+            .service(config_transfer::import_config),
     );
 
     // Automation engine routes (protected)
@@ -53,10 +85,21 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(automation::run_automation),
     );
 
+    // Battery routes (protected)
+    cfg.service(
+        web::scope("/api/batteries")
+            .service(batteries::list_batteries)
+            .service(batteries::create_battery)
+            .service(batteries::get_battery_plan)
+            .service(batteries::update_battery)
+            .service(batteries::delete_battery),
+    );
+
     // Schedule routes (protected)
     cfg.service(
         web::scope("/api/schedules")
-            .service(schedules::get_schedule),
+            .service(schedules::get_schedule)
+            .service(schedules::optimize_schedule),
     );
 
     // Price routes (public - no auth required for price info)
@@ -71,3 +114,13 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(prices::sync_prices_for_date),
     );
 }
+
+/// Deserialize a present field (including `null`) as `Some`, so that
+/// `Option<Option<T>>` can tell "not sent" from "cleared"
+pub(crate) fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
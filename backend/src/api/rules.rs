@@ -1,12 +1,21 @@
 use crate::{
     db::DbPool,
-    models::{AutomationRule, NewAutomationRule, RuleExecution},
+    integrations::ProviderRegistry,
+    models::{
+        parse_rule_profiles, validate_rule_config, AutomationRule, NewAutomationRule, RuleChangeType, RuleExecution,
+        RuleType,
+    },
     schema::{automation_rules, devices, rule_executions, user_integrations},
-    services::{auth::Claims, schedule_computation::ScheduleComputationService},
+    services::{
+        auth::Claims,
+        rule_history::{get_rule_history, record_rule_change, rollback_rule, RollbackError},
+        schedule_computation::{chained_successors, validate_rule_chain, ScheduleComputationService},
+    },
 };
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
-use chrono::{Local, Utc};
+use chrono::{Local, NaiveDateTime, Utc};
 use diesel::prelude::*;
+use diesel::PgConnection;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
@@ -37,6 +46,19 @@ pub struct UpdateRuleRequest {
     pub priority: Option<i32>,
 }
 
+/// New charging target for an EV charging rule; omitted fields are kept
+#[derive(Deserialize)]
+pub struct EnergyTargetRequest {
+    pub target_energy_kwh: Option<f64>,
+    pub current_soc_percent: Option<f64>,
+    pub departure_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RollbackRequest {
+    pub version: i32,
+}
+
 #[derive(Serialize)]
 pub struct RuleResponse {
     pub id: i32,
@@ -166,10 +188,16 @@ pub async fn get_rule(
     }
 }
 
+fn invalid_rule_type_message() -> String {
+    let valid_types: Vec<&str> = RuleType::ALL.iter().map(RuleType::as_str).collect();
+    format!("Invalid rule_type. Must be one of: {:?}", valid_types)
+}
+
 /// Create a new automation rule
 #[post("")]
 pub async fn create_rule(
     pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
     claims: Claims,
     body: web::Json<CreateRuleRequest>,
 ) -> impl Responder {
@@ -184,12 +212,8 @@ pub async fn create_rule(
     };
 
     // Validate rule_type
-    let valid_types = ["price_threshold", "cheapest_hours", "time_schedule", "manual"];
-    if !valid_types.contains(&body.rule_type.as_str()) {
-        return HttpResponse::BadRequest().body(format!(
-            "Invalid rule_type. Must be one of: {:?}",
-            valid_types
-        ));
+    if RuleType::from_str(&body.rule_type).is_none() {
+        return HttpResponse::BadRequest().body(invalid_rule_type_message());
     }
 
     // Validate action
@@ -201,19 +225,25 @@ pub async fn create_rule(
         ));
     }
 
-    // Verify device belongs to user
-    let device_belongs_to_user = devices::table
-        .inner_join(user_integrations::table)
-        .filter(devices::id.eq(body.device_id))
-        .filter(user_integrations::user_id.eq(user_id))
-        .select(devices::id)
-        .first::<i32>(&mut conn)
-        .is_ok();
+    // Validate seasonal/weekday profiles and type-specific config
+    if let Err(e) = validate_rule_config(&body.rule_type, &body.config) {
+        return HttpResponse::BadRequest().body(e);
+    }
 
-    if !device_belongs_to_user {
+    // Verify device belongs to user
+    if !device_belongs_to_user(&mut conn, body.device_id, user_id) {
         return HttpResponse::NotFound().body("Device not found");
     }
 
+    if let Err(e) = check_device_supports_rule(&mut conn, &registry, body.device_id, &body.rule_type) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    // Chained rules must follow another rule of the same user
+    if let Err(e) = validate_rule_chain(&mut conn, user_id, None, &body.rule_type, &body.config) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
     // Create the rule
     let new_rule = NewAutomationRule {
         user_id,
@@ -226,35 +256,79 @@ pub async fn create_rule(
         priority: body.priority.unwrap_or(100),
     };
 
-    match diesel::insert_into(automation_rules::table)
-        .values(&new_rule)
-        .get_result::<AutomationRule>(&mut conn)
-    {
-        Ok(rule) => {
-            // Compute schedules for this new rule (today and tomorrow)
-            let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
-            let today = Local::now().date_naive();
-            let tomorrow = today + chrono::Duration::days(1);
-
-            if let Err(e) = schedule_service.compute_schedule_for_rule(rule.id, today) {
-                log::warn!("Failed to compute today's schedule for new rule: {}", e);
-            }
-            if let Err(e) = schedule_service.compute_schedule_for_rule(rule.id, tomorrow) {
-                log::warn!("Failed to compute tomorrow's schedule for new rule: {}", e);
-            }
-
-            HttpResponse::Created().json(rule)
-        }
+    match insert_rule(pool.get_ref(), &mut conn, &new_rule) {
+        Ok(rule) => HttpResponse::Created().json(rule),
         Err(e) => {
             HttpResponse::InternalServerError().body(format!("Failed to create rule: {}", e))
         }
     }
 }
 
+/// Check that a device belongs to one of the user's integrations
+pub(crate) fn device_belongs_to_user(conn: &mut PgConnection, device_id: i32, user_id: i32) -> bool {
+    devices::table
+        .inner_join(user_integrations::table)
+        .filter(devices::id.eq(device_id))
+        .filter(user_integrations::user_id.eq(user_id))
+        .select(devices::id)
+        .first::<i32>(conn)
+        .is_ok()
+}
+
+/// Device action a rule type performs, as checked against the device's capabilities
+fn rule_device_action(rule_type: &str) -> &'static str {
+    if rule_type == RuleType::Thermostat.as_str() {
+        "set_temperature"
+    } else {
+        "turn_on"
+    }
+}
+
+/// Check that a device can do what rules of `rule_type` ask of it
+fn check_device_supports_rule(
+    conn: &mut PgConnection,
+    registry: &ProviderRegistry,
+    device_id: i32,
+    rule_type: &str,
+) -> Result<(), String> {
+    let (provider_name, capabilities): (String, Option<JsonValue>) = devices::table
+        .inner_join(user_integrations::table)
+        .filter(devices::id.eq(device_id))
+        .select((user_integrations::provider_name, devices::capabilities))
+        .first(conn)
+        .map_err(|_| "Device not found".to_string())?;
+    registry
+        .device_capabilities(&provider_name, capabilities.as_ref())
+        .check_action(rule_device_action(rule_type), 0)
+}
+
+/// Insert a rule and replan the user's rules around it
+pub(crate) fn insert_rule(
+    pool: &DbPool,
+    conn: &mut PgConnection,
+    new_rule: &NewAutomationRule,
+) -> QueryResult<AutomationRule> {
+    let rule = conn.transaction(|conn| {
+        let rule = diesel::insert_into(automation_rules::table)
+            .values(new_rule)
+            .get_result::<AutomationRule>(conn)?;
+        record_rule_change(conn, new_rule.user_id, RuleChangeType::Create, None, Some(&rule))?;
+        Ok::<_, diesel::result::Error>(rule)
+    })?;
+
+    let schedule_service = ScheduleComputationService::new(pool.clone());
+    if let Err(e) = schedule_service.recompute_after_rule_change(rule.id) {
+        log::warn!("Failed to compute schedules for new rule {}: {}", rule.id, e);
+    }
+
+    Ok(rule)
+}
+
 /// Update an existing rule
 #[put("/{rule_id}")]
 pub async fn update_rule(
     pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
     claims: Claims,
     path: web::Path<i32>,
     body: web::Json<UpdateRuleRequest>,
@@ -270,23 +344,23 @@ pub async fn update_rule(
         Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
     };
 
-    // Verify rule exists and belongs to user
-    let rule_exists = automation_rules::table
+    // Verify rule exists and belongs to user (kept as the history "before" snapshot)
+    let before: AutomationRule = match automation_rules::table
         .filter(automation_rules::id.eq(rule_id))
         .filter(automation_rules::user_id.eq(user_id))
-        .select(automation_rules::id)
-        .first::<i32>(&mut conn)
-        .is_ok();
-
-    if !rule_exists {
-        return HttpResponse::NotFound().body("Rule not found");
-    }
+        .first(&mut conn)
+    {
+        Ok(r) => r,
+        Err(_) => return HttpResponse::NotFound().body("Rule not found"),
+    };
 
     // Validate rule_type if provided
     if let Some(ref rule_type) = body.rule_type {
-        let valid_types = ["price_threshold", "cheapest_hours", "time_schedule", "manual"];
-        if !valid_types.contains(&rule_type.as_str()) {
-            return HttpResponse::BadRequest().body("Invalid rule_type");
+        if RuleType::from_str(rule_type).is_none() {
+            return HttpResponse::BadRequest().body(invalid_rule_type_message());
+        }
+        if let Err(e) = check_device_supports_rule(&mut conn, &registry, before.device_id, rule_type) {
+            return HttpResponse::BadRequest().body(e);
         }
     }
 
@@ -298,57 +372,53 @@ pub async fn update_rule(
         }
     }
 
-    // Build update query
-    let now = Utc::now().naive_utc();
-
-    // Update each field if provided
-    if let Some(ref name) = body.name {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::name.eq(name))
-            .execute(&mut conn)
-            .ok();
-    }
-
-    if let Some(ref rule_type) = body.rule_type {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::rule_type.eq(rule_type))
-            .execute(&mut conn)
-            .ok();
-    }
-
-    if let Some(ref action) = body.action {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::action.eq(action))
-            .execute(&mut conn)
-            .ok();
-    }
-
-    if let Some(ref config) = body.config {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::config.eq(config))
-            .execute(&mut conn)
-            .ok();
-    }
-
-    if let Some(is_enabled) = body.is_enabled {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::is_enabled.eq(is_enabled))
-            .execute(&mut conn)
-            .ok();
-    }
-
-    if let Some(priority) = body.priority {
-        diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-            .set(automation_rules::priority.eq(priority))
-            .execute(&mut conn)
-            .ok();
+    // Validate the resulting config against the resulting rule type
+    if body.config.is_some() || body.rule_type.is_some() {
+        let rule_type = body.rule_type.as_ref().unwrap_or(&before.rule_type);
+        let config = body.config.as_ref().unwrap_or(&before.config);
+        if let Err(e) = validate_rule_config(rule_type, config) {
+            return HttpResponse::BadRequest().body(e);
+        }
+        if let Err(e) = validate_rule_chain(&mut conn, user_id, Some(rule_id), rule_type, config) {
+            return HttpResponse::BadRequest().body(e);
+        }
     }
 
-    // Update the updated_at timestamp
-    diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-        .set(automation_rules::updated_at.eq(now))
-        .execute(&mut conn)
-        .ok();
+    // Apply the changes and record them as one version
+    let now = Utc::now().naive_utc();
+    let updated = conn.transaction::<AutomationRule, diesel::result::Error, _>(|conn| {
+        let target = automation_rules::table.filter(automation_rules::id.eq(rule_id));
+        if let Some(ref name) = body.name {
+            diesel::update(target).set(automation_rules::name.eq(name)).execute(conn)?;
+        }
+        if let Some(ref rule_type) = body.rule_type {
+            diesel::update(target).set(automation_rules::rule_type.eq(rule_type)).execute(conn)?;
+        }
+        if let Some(ref action) = body.action {
+            diesel::update(target).set(automation_rules::action.eq(action)).execute(conn)?;
+        }
+        if let Some(ref config) = body.config {
+            diesel::update(target).set(automation_rules::config.eq(config)).execute(conn)?;
+        }
+        if let Some(is_enabled) = body.is_enabled {
+            diesel::update(target).set(automation_rules::is_enabled.eq(is_enabled)).execute(conn)?;
+        }
+        if let Some(priority) = body.priority {
+            diesel::update(target).set(automation_rules::priority.eq(priority)).execute(conn)?;
+        }
+        let rule = diesel::update(target)
+            .set(automation_rules::updated_at.eq(now))
+            .get_result::<AutomationRule>(conn)?;
+        record_rule_change(conn, user_id, RuleChangeType::Update, Some(&before), Some(&rule))?;
+        Ok(rule)
+    });
+    let rule = match updated {
+        Ok(rule) => rule,
+        Err(e) => {
+            log::error!("Error updating rule {}: {}", rule_id, e);
+            return HttpResponse::InternalServerError().body("Error updating rule");
+        }
+    };
 
     // Recompute schedules if config, enabled status, or rule type changed
     let should_recompute = body.config.is_some()
@@ -358,28 +428,117 @@ pub async fn update_rule(
 
     if should_recompute {
         let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
-        let today = Local::now().date_naive();
-        let tomorrow = today + chrono::Duration::days(1);
+        if let Err(e) = schedule_service.recompute_after_rule_change(rule_id) {
+            log::warn!("Failed to recompute schedules for rule {}: {}", rule_id, e);
+        }
+    }
+
+    HttpResponse::Ok().json(rule)
+}
+
+/// Update the energy target of an EV charging rule and replan its charging
+#[put("/{rule_id}/energy-target")]
+pub async fn update_energy_target(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<i32>,
+    body: web::Json<EnergyTargetRequest>,
+) -> impl Responder {
+    let rule_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let before: AutomationRule = match automation_rules::table
+        .filter(automation_rules::id.eq(rule_id))
+        .filter(automation_rules::user_id.eq(user_id))
+        .first(&mut conn)
+    {
+        Ok(r) => r,
+        Err(_) => return HttpResponse::NotFound().body("Rule not found"),
+    };
 
-        // Delete old schedules and recompute
-        let _ = schedule_service.delete_schedule_for_rule(rule_id);
+    if before.rule_type != RuleType::EvCharging.as_str() {
+        return HttpResponse::BadRequest().body("Rule is not an EV charging rule");
+    }
+
+    let config = merge_energy_target(&before.config, &body, Local::now().naive_local());
+    if let Err(e) = validate_rule_config(&before.rule_type, &config) {
+        return HttpResponse::BadRequest().body(e);
+    }
 
-        if let Err(e) = schedule_service.compute_schedule_for_rule(rule_id, today) {
-            log::warn!("Failed to recompute today's schedule for rule {}: {}", rule_id, e);
+    let updated = conn.transaction::<AutomationRule, diesel::result::Error, _>(|conn| {
+        let rule = diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
+            .set((
+                automation_rules::config.eq(&config),
+                automation_rules::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result(conn)?;
+        record_rule_change(conn, user_id, RuleChangeType::Update, Some(&before), Some(&rule))?;
+        Ok(rule)
+    });
+    let rule = match updated {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Error updating energy target of rule {}: {}", rule_id, e);
+            return HttpResponse::InternalServerError().body("Error updating rule");
         }
-        if let Err(e) = schedule_service.compute_schedule_for_rule(rule_id, tomorrow) {
-            log::warn!("Failed to recompute tomorrow's schedule for rule {}: {}", rule_id, e);
+    };
+
+    if rule.is_enabled {
+        let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+        if let Err(e) = schedule_service.recompute_after_rule_change(rule_id) {
+            log::warn!("Failed to replan EV charging rule {}: {}", rule_id, e);
         }
     }
 
-    // Return updated rule
-    match automation_rules::table
-        .find(rule_id)
-        .first::<AutomationRule>(&mut conn)
-    {
-        Ok(rule) => HttpResponse::Ok().json(rule),
-        Err(_) => HttpResponse::InternalServerError().body("Error fetching updated rule"),
+    HttpResponse::Ok().json(rule)
+}
+
+/// Apply an energy target update to a rule config. A new state of charge is
+/// stamped with the time it was read. Keys overridden by the profile active
+/// today are written into that profile so the update takes effect.
+fn merge_energy_target(config: &JsonValue, update: &EnergyTargetRequest, now: NaiveDateTime) -> JsonValue {
+    let mut config = config.clone();
+    let active = parse_rule_profiles(&config)
+        .unwrap_or_default()
+        .iter()
+        .position(|p| p.matches(now.date()));
+
+    if let Some(target) = update.target_energy_kwh {
+        set_active_key(&mut config, active, "target_energy_kwh", serde_json::json!(target));
+    }
+    if let Some(soc) = update.current_soc_percent {
+        let target = set_active_key(&mut config, active, "current_soc_percent", serde_json::json!(soc));
+        target["soc_updated_at"] = serde_json::json!(now);
+    }
+    if let Some(ref departure) = update.departure_time {
+        set_active_key(&mut config, active, "departure_time", serde_json::json!(departure));
     }
+    config
+}
+
+/// Set `key` in the active profile if it overrides it, otherwise in the base
+/// config. Returns the object the key was written to.
+fn set_active_key<'a>(
+    config: &'a mut JsonValue,
+    active: Option<usize>,
+    key: &str,
+    value: JsonValue,
+) -> &'a mut JsonValue {
+    let overridden = active.is_some_and(|i| config["profiles"][i]["config"].get(key).is_some());
+    let target = match active {
+        Some(i) if overridden => &mut config["profiles"][i]["config"],
+        _ => config,
+    };
+    target[key] = value;
+    target
 }
 
 /// Delete a rule
@@ -401,24 +560,51 @@ pub async fn delete_rule(
     };
 
     // Verify rule exists and belongs to user
-    let rule_exists = automation_rules::table
+    let rule: AutomationRule = match automation_rules::table
         .filter(automation_rules::id.eq(rule_id))
         .filter(automation_rules::user_id.eq(user_id))
-        .select(automation_rules::id)
-        .first::<i32>(&mut conn)
-        .is_ok();
+        .first(&mut conn)
+    {
+        Ok(r) => r,
+        Err(_) => return HttpResponse::NotFound().body("Rule not found"),
+    };
 
-    if !rule_exists {
-        return HttpResponse::NotFound().body("Rule not found");
+    // Chained rules following this one would be left without a predecessor
+    let successors = match automation_rules::table
+        .filter(automation_rules::user_id.eq(user_id))
+        .filter(automation_rules::rule_type.eq(RuleType::Chained.as_str()))
+        .load::<AutomationRule>(&mut conn)
+    {
+        Ok(rules) => chained_successors(rules, rule_id),
+        Err(_) => return HttpResponse::InternalServerError().body("Error fetching rules"),
+    };
+    if !successors.is_empty() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Rule is the predecessor of chained rules",
+            "successors": successors
+                .iter()
+                .map(|r| serde_json::json!({"id": r.id, "name": r.name}))
+                .collect::<Vec<_>>(),
+        }));
     }
 
-    // Delete rule (executions will cascade)
-    match diesel::delete(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-        .execute(&mut conn)
-    {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({"deleted": true})),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to delete rule"),
+    // Delete rule (executions will cascade, history is kept)
+    let deleted = conn.transaction::<(), diesel::result::Error, _>(|conn| {
+        diesel::delete(automation_rules::table.filter(automation_rules::id.eq(rule_id))).execute(conn)?;
+        record_rule_change(conn, user_id, RuleChangeType::Delete, Some(&rule), None)?;
+        Ok(())
+    });
+    if deleted.is_err() {
+        return HttpResponse::InternalServerError().body("Failed to delete rule");
+    }
+
+    // The hours the rule held go back to the user's other rules
+    let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+    if let Err(e) = schedule_service.recompute_after_rules_change(user_id, &[(rule_id, rule.device_id)]) {
+        log::warn!("Failed to recompute schedules after deleting rule {}: {}", rule_id, e);
     }
+
+    HttpResponse::Ok().json(serde_json::json!({"deleted": true}))
 }
 
 /// Toggle a rule's enabled status
@@ -453,30 +639,25 @@ pub async fn toggle_rule(
     let new_status = !rule.is_enabled;
     let now = Utc::now().naive_utc();
 
-    diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
-        .set((
-            automation_rules::is_enabled.eq(new_status),
-            automation_rules::updated_at.eq(now),
-        ))
-        .execute(&mut conn)
-        .ok();
+    let toggled = conn.transaction::<(), diesel::result::Error, _>(|conn| {
+        let after = diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
+            .set((
+                automation_rules::is_enabled.eq(new_status),
+                automation_rules::updated_at.eq(now),
+            ))
+            .get_result::<AutomationRule>(conn)?;
+        record_rule_change(conn, user_id, RuleChangeType::Toggle, Some(&rule), Some(&after))?;
+        Ok(())
+    });
+    if let Err(e) = toggled {
+        log::error!("Error toggling rule {}: {}", rule_id, e);
+        return HttpResponse::InternalServerError().body("Error updating rule");
+    }
 
     // Recompute schedules based on new enabled status
     let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
-    let today = Local::now().date_naive();
-    let tomorrow = today + chrono::Duration::days(1);
-
-    // Delete old schedules and recompute
-    let _ = schedule_service.delete_schedule_for_rule(rule_id);
-
-    if new_status {
-        // Rule is now enabled, compute schedules
-        if let Err(e) = schedule_service.compute_schedule_for_rule(rule_id, today) {
-            log::warn!("Failed to compute today's schedule for toggled rule {}: {}", rule_id, e);
-        }
-        if let Err(e) = schedule_service.compute_schedule_for_rule(rule_id, tomorrow) {
-            log::warn!("Failed to compute tomorrow's schedule for toggled rule {}: {}", rule_id, e);
-        }
+    if let Err(e) = schedule_service.recompute_after_rule_change(rule_id) {
+        log::warn!("Failed to recompute schedules for toggled rule {}: {}", rule_id, e);
     }
 
     HttpResponse::Ok().json(serde_json::json!({
@@ -546,6 +727,81 @@ pub async fn get_rule_executions(
     HttpResponse::Ok().json(response)
 }
 
+/// Get the change history of a rule (also available after the rule is deleted)
+#[get("/{rule_id}/history")]
+pub async fn get_rule_history_entries(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let rule_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    match get_rule_history(&mut conn, rule_id, user_id) {
+        Ok(entries) if entries.is_empty() => HttpResponse::NotFound().body("Rule not found"),
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(_) => HttpResponse::InternalServerError().body("Error fetching rule history"),
+    }
+}
+
+/// Roll a rule back to the state recorded in a history version
+#[post("/{rule_id}/rollback")]
+pub async fn rollback_rule_version(
+    pool: web::Data<DbPool>,
+    registry: web::Data<ProviderRegistry>,
+    claims: Claims,
+    path: web::Path<i32>,
+    body: web::Json<RollbackRequest>,
+) -> impl Responder {
+    let rule_id = path.into_inner();
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let check_device = |conn: &mut PgConnection, device_id: i32, rule_type: &str| {
+        check_device_supports_rule(conn, &registry, device_id, rule_type)
+    };
+    let rule = match rollback_rule(&mut conn, rule_id, user_id, body.version, check_device) {
+        Ok(rule) => rule,
+        Err(RollbackError::RuleNotFound) => return HttpResponse::NotFound().body("Rule not found"),
+        Err(RollbackError::VersionNotFound) => {
+            return HttpResponse::NotFound().body("Version not found")
+        }
+        Err(RollbackError::DeviceNotFound) => {
+            return HttpResponse::NotFound().body("Device not found")
+        }
+        Err(RollbackError::UnsupportedDevice(e)) => return HttpResponse::BadRequest().body(e),
+        Err(RollbackError::InvalidSnapshot(e)) => {
+            return HttpResponse::UnprocessableEntity().body(format!("Invalid snapshot: {}", e))
+        }
+        Err(RollbackError::Database(e)) => {
+            return HttpResponse::InternalServerError().body(format!("Rollback failed: {}", e))
+        }
+    };
+
+    // Recompute schedules for the restored config
+    let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+    if let Err(e) = schedule_service.recompute_after_rule_change(rule_id) {
+        log::warn!("Failed to recompute schedule for rolled back rule {}: {}", rule_id, e);
+    }
+
+    HttpResponse::Ok().json(rule)
+}
+
 #[derive(Deserialize)]
 pub struct PaginationQuery {
     pub limit: Option<i32>,
@@ -583,6 +839,75 @@ mod tests {
         assert!(request.name.is_none());
     }
 
+    #[test]
+    fn test_rollback_request_deserialization() {
+        let request: RollbackRequest = serde_json::from_str(r#"{"version": 3}"#).unwrap();
+        assert_eq!(request.version, 3);
+    }
+
+    #[test]
+    fn test_merge_energy_target_stamps_soc() {
+        let config = serde_json::json!({
+            "target_energy_kwh": 20.0,
+            "charger_power_kw": 7.4,
+            "departure_time": "07:30",
+            "battery_capacity_kwh": 60.0
+        });
+        let update: EnergyTargetRequest =
+            serde_json::from_str(r#"{"target_energy_kwh": 45.0, "current_soc_percent": 35.0}"#).unwrap();
+        let now = chrono::NaiveDate::from_ymd_opt(2026, 3, 10)
+            .unwrap()
+            .and_hms_opt(22, 5, 0)
+            .unwrap();
+
+        let merged = merge_energy_target(&config, &update, now);
+        assert_eq!(merged["target_energy_kwh"], 45.0);
+        assert_eq!(merged["current_soc_percent"], 35.0);
+        assert_eq!(merged["soc_updated_at"], "2026-03-10T22:05:00");
+        assert_eq!(merged["departure_time"], "07:30");
+        assert!(validate_rule_config("ev_charging", &merged).is_ok());
+    }
+
+    #[test]
+    fn test_merge_energy_target_writes_into_active_profile() {
+        let config = serde_json::json!({
+            "target_energy_kwh": 20.0,
+            "charger_power_kw": 7.4,
+            "departure_time": "07:30",
+            "battery_capacity_kwh": 60.0,
+            "profiles": [
+                {"name": "Weekend", "days": ["sat", "sun"], "config": {"departure_time": "10:00"}},
+                {"name": "Weekdays", "days": ["mon", "tue", "wed", "thu", "fri"],
+                 "config": {"target_energy_kwh": 30.0, "current_soc_percent": 50.0}}
+            ]
+        });
+        let update: EnergyTargetRequest = serde_json::from_str(
+            r#"{"target_energy_kwh": 45.0, "current_soc_percent": 35.0, "departure_time": "06:45"}"#,
+        )
+        .unwrap();
+        // A Tuesday: the weekday profile applies
+        let now = chrono::NaiveDate::from_ymd_opt(2026, 3, 10)
+            .unwrap()
+            .and_hms_opt(22, 5, 0)
+            .unwrap();
+
+        let merged = merge_energy_target(&config, &update, now);
+        let weekdays = &merged["profiles"][1]["config"];
+        assert_eq!(weekdays["target_energy_kwh"], 45.0);
+        assert_eq!(weekdays["current_soc_percent"], 35.0);
+        assert_eq!(weekdays["soc_updated_at"], "2026-03-10T22:05:00");
+        assert_eq!(merged["target_energy_kwh"], 20.0);
+        // Not overridden today, so it goes into the base config
+        assert_eq!(merged["departure_time"], "06:45");
+        assert_eq!(merged["profiles"][0]["config"]["departure_time"], "10:00");
+
+        let resolved = crate::models::resolve_config_for_date(&merged, now.date());
+        assert_eq!(resolved["target_energy_kwh"], 45.0);
+        assert_eq!(resolved["current_soc_percent"], 35.0);
+        assert_eq!(resolved["departure_time"], "06:45");
+        assert!(validate_rule_config("ev_charging", &merged).is_ok());
+    }
+
     #[test]
     fn test_pagination_query_defaults() {
         let json = r#"{}"#;
@@ -590,4 +915,10 @@ mod tests {
         assert!(query.limit.is_none());
         assert!(query.offset.is_none());
     }
+
+    #[test]
+    fn test_rule_device_action() {
+        assert_eq!(rule_device_action("thermostat"), "set_temperature");
+        assert_eq!(rule_device_action("cheapest_hours"), "turn_on");
+    }
 }
@@ -2,9 +2,12 @@ use crate::{
     db::DbPool,
     models::ScheduledExecution,
     schema::{automation_rules, devices, scheduled_executions},
-    services::{auth::Claims, price_fetcher::PriceService, schedule_computation::ScheduleComputationService},
+    services::{
+        auth::Claims, cost_optimizer::CostOptimizerService, price_fetcher::PriceService,
+        schedule_computation::ScheduleComputationService,
+    },
 };
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, post, web, HttpResponse, Responder};
 use chrono::{Local, NaiveDate, Timelike};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -18,6 +21,13 @@ pub struct ScheduleQuery {
     pub date: Option<String>, // Format: YYYY-MM-DD
 }
 
+#[derive(Deserialize)]
+pub struct OptimizeQuery {
+    pub date: Option<String>, // Format: YYYY-MM-DD
+    #[serde(default)]
+    pub apply: bool,
+}
+
 #[derive(Serialize)]
 pub struct ScheduledHour {
     pub hour: u32,
@@ -128,6 +138,7 @@ pub async fn get_schedule(
                     }
                 }
                 "pending" => "pending",
+                "executing" => "executing",
                 "retrying" => "retrying",
                 "failed" => "failed",
                 "missed" => "missed",
@@ -157,3 +168,34 @@ pub async fn get_schedule(
         scheduled_hours: sorted_hours,
     })
 }
+
+/// Jointly plan the user's flexible loads at minimum cost within the contracted power.
+/// Returns the plan and only replaces the schedule when `apply=true`.
+#[post("/optimize")]
+pub async fn optimize_schedule(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    query: web::Query<OptimizeQuery>,
+) -> impl Responder {
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let date = match &query.date {
+        Some(d) => match NaiveDate::parse_from_str(d, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => return HttpResponse::BadRequest().body("Invalid date format. Use YYYY-MM-DD"),
+        },
+        None => Local::now().date_naive(),
+    };
+
+    let service = CostOptimizerService::new(pool.get_ref().clone());
+    match service.optimize(user_id, date, query.apply) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            log::error!("Error optimizing schedule for user {}: {}", user_id, e);
+            HttpResponse::InternalServerError().body("Error optimizing schedule")
+        }
+    }
+}
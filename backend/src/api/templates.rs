@@ -0,0 +1,124 @@
+use crate::{
+    api::rules::{device_belongs_to_user, insert_rule},
+    db::DbPool,
+    models::{validate_rule_config, NewAutomationRule},
+    services::{
+        auth::Claims,
+        rule_templates::{all_templates, find_template},
+    },
+};
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+// ============================================================================
+// Request/Response DTOs
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct InstantiateTemplateRequest {
+    pub device_id: i32,
+    /// Defaults to the template name
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Parameter values keyed by parameter key; missing ones use defaults
+    #[serde(default)]
+    pub params: JsonValue,
+    #[serde(default)]
+    pub is_enabled: Option<bool>,
+    #[serde(default)]
+    pub priority: Option<i32>,
+}
+
+// ============================================================================
+// Endpoints
+// ============================================================================
+
+/// List all built-in rule templates
+#[get("")]
+pub async fn list_templates(_claims: Claims) -> impl Responder {
+    HttpResponse::Ok().json(all_templates())
+}
+
+/// Get a single template with its parameters
+#[get("/{template_id}")]
+pub async fn get_template(_claims: Claims, path: web::Path<String>) -> impl Responder {
+    match find_template(&path.into_inner()) {
+        Some(template) => HttpResponse::Ok().json(template),
+        None => HttpResponse::NotFound().body("Template not found"),
+    }
+}
+
+/// Create an automation rule for a device from a template
+#[post("/{template_id}/instantiate")]
+pub async fn instantiate_template(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    path: web::Path<String>,
+    body: web::Json<InstantiateTemplateRequest>,
+) -> impl Responder {
+    let template = match find_template(&path.into_inner()) {
+        Some(t) => t,
+        None => return HttpResponse::NotFound().body("Template not found"),
+    };
+
+    let config = match template.build_config(&body.params) {
+        Ok(c) => c,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    // Parameter ranges are template specific; the rule type has its own checks
+    if let Err(e) = validate_rule_config(template.rule_type, &config) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    if !device_belongs_to_user(&mut conn, body.device_id, user_id) {
+        return HttpResponse::NotFound().body("Device not found");
+    }
+
+    let new_rule = NewAutomationRule {
+        user_id,
+        device_id: body.device_id,
+        name: body
+            .name
+            .clone()
+            .unwrap_or_else(|| template.name.to_string()),
+        rule_type: template.rule_type.to_string(),
+        action: template.action.to_string(),
+        config,
+        is_enabled: body.is_enabled.unwrap_or(true),
+        priority: body.priority.unwrap_or(template.priority),
+    };
+
+    match insert_rule(pool.get_ref(), &mut conn, &new_rule) {
+        Ok(rule) => HttpResponse::Created().json(rule),
+        Err(e) => {
+            HttpResponse::InternalServerError().body(format!("Failed to create rule: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instantiate_request_defaults() {
+        let req: InstantiateTemplateRequest =
+            serde_json::from_str(r#"{"device_id": 7}"#).unwrap();
+        assert_eq!(req.device_id, 7);
+        assert!(req.name.is_none());
+        assert!(req.params.is_null());
+        assert!(req.priority.is_none());
+    }
+}
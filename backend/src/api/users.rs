@@ -0,0 +1,157 @@
+use crate::{
+    api::deserialize_some,
+    db::DbPool,
+    models::{parse_timezone, User},
+    schema::users,
+    services::{auth::Claims, schedule_computation::ScheduleComputationService},
+};
+use actix_web::{get, put, web, HttpResponse, Responder};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// ============================================================================
+// Request/Response DTOs
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct UserSettingsResponse {
+    pub id: i32,
+    pub username: String,
+    pub timezone: String,
+    pub contracted_power_kw: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserSettingsRequest {
+    /// IANA timezone name (e.g., "Europe/Madrid")
+    pub timezone: Option<String>,
+    /// Contracted power in kW (e.g., 4.6); null removes the limit
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub contracted_power_kw: Option<Option<f64>>,
+}
+
+impl From<User> for UserSettingsResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            timezone: user.timezone,
+            contracted_power_kw: user.contracted_power_kw,
+        }
+    }
+}
+
+// ============================================================================
+// Endpoints
+// ============================================================================
+
+/// Get the authenticated user's settings
+#[get("/me")]
+pub async fn get_me(pool: web::Data<DbPool>, claims: Claims) -> impl Responder {
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    match users::table.find(user_id).first::<User>(&mut conn) {
+        Ok(user) => HttpResponse::Ok().json(UserSettingsResponse::from(user)),
+        Err(_) => HttpResponse::NotFound().body("User not found"),
+    }
+}
+
+/// Update the authenticated user's settings
+#[put("/me")]
+pub async fn update_me(
+    pool: web::Data<DbPool>,
+    claims: Claims,
+    body: web::Json<UpdateUserSettingsRequest>,
+) -> impl Responder {
+    let mut conn = match pool.get() {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Database connection error"),
+    };
+
+    let user_id: i32 = match claims.sub.parse() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    if let Some(ref timezone) = body.timezone {
+        if let Err(e) = parse_timezone(timezone) {
+            return HttpResponse::BadRequest().body(e);
+        }
+
+        if diesel::update(users::table.find(user_id))
+            .set(users::timezone.eq(timezone))
+            .execute(&mut conn)
+            .is_err()
+        {
+            return HttpResponse::InternalServerError().body("Failed to update timezone");
+        }
+
+        // Cron rules are evaluated in the user's timezone
+        let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+        if let Err(e) = schedule_service.recompute_cron_rules_for_user(user_id) {
+            log::warn!("Failed to recompute cron rules for user {}: {}", user_id, e);
+        }
+    }
+
+    if let Some(contracted_power_kw) = body.contracted_power_kw {
+        if contracted_power_kw.is_some_and(|kw| !kw.is_finite() || kw <= 0.0) {
+            return HttpResponse::BadRequest().body("contracted_power_kw must be positive");
+        }
+
+        if diesel::update(users::table.find(user_id))
+            .set(users::contracted_power_kw.eq(contracted_power_kw))
+            .execute(&mut conn)
+            .is_err()
+        {
+            return HttpResponse::InternalServerError().body("Failed to update contracted power");
+        }
+
+        // Replan every rule against the new power budget
+        let schedule_service = ScheduleComputationService::new(pool.get_ref().clone());
+        if let Err(e) = schedule_service.recompute_rules_for_user(user_id) {
+            log::warn!("Failed to recompute schedules for user {}: {}", user_id, e);
+        }
+    }
+
+    match users::table.find(user_id).first::<User>(&mut conn) {
+        Ok(user) => HttpResponse::Ok().json(UserSettingsResponse::from(user)),
+        Err(_) => HttpResponse::NotFound().body("User not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_user_settings_request_partial() {
+        let request: UpdateUserSettingsRequest = serde_json::from_str("{}").unwrap();
+        assert!(request.timezone.is_none());
+
+        let request: UpdateUserSettingsRequest =
+            serde_json::from_str(r#"{"timezone": "Atlantic/Canary"}"#).unwrap();
+        assert_eq!(request.timezone.as_deref(), Some("Atlantic/Canary"));
+    }
+
+    #[test]
+    fn test_update_user_settings_request_contracted_power() {
+        let request: UpdateUserSettingsRequest = serde_json::from_str("{}").unwrap();
+        assert!(request.contracted_power_kw.is_none());
+
+        let request: UpdateUserSettingsRequest =
+            serde_json::from_str(r#"{"contracted_power_kw": 4.6}"#).unwrap();
+        assert_eq!(request.contracted_power_kw, Some(Some(4.6)));
+
+        let request: UpdateUserSettingsRequest =
+            serde_json::from_str(r#"{"contracted_power_kw": null}"#).unwrap();
+        assert_eq!(request.contracted_power_kw, Some(None));
+    }
+}
@@ -3,6 +3,7 @@
 //! This binary runs as a daemon with proper cron scheduling:
 //! - sync-prices: Runs at startup and daily at 20:30 (when tomorrow's prices are published)
 //! - run-automation: Runs at the start of every hour (when prices change)
+//! - run-cron-rules: Runs every 10 seconds to execute due cron rule occurrences
 //!
 //! Environment variables:
 //!   DATABASE_URL - PostgreSQL connection string (required)
@@ -18,6 +19,7 @@ use tokio_cron_scheduler::{Job, JobScheduler};
 use backend::db::{self, DbPool};
 use backend::integrations::ProviderRegistry;
 use backend::services::automation_engine::AutomationEngine;
+use backend::services::energy_readings::EnergyReadingService;
 use backend::services::price_fetcher::PriceService;
 use backend::services::schedule_computation::ScheduleComputationService;
 
@@ -39,8 +41,12 @@ async fn main() {
 
     log::info!("Starting PVPC Cheap cron scheduler...");
 
+    // One registry for all jobs, so provider connections (e.g. Meross MQTT)
+    // stay open between runs
+    let registry = Arc::new(ProviderRegistry::new());
+
     // Run initial sync at startup
-    sync_prices_startup(pool.clone()).await;
+    sync_prices_startup(pool.clone(), registry.clone()).await;
 
     // Create scheduler
     let sched = JobScheduler::new().await.expect("Failed to create scheduler");
@@ -61,11 +67,13 @@ async fn main() {
     // Schedule run-automation at the start of every hour (Madrid timezone)
     // Cron: "0 0 * * * *" = second 0, minute 0, every hour
     let pool_auto = pool.clone();
+    let registry_auto = registry.clone();
     let automation_job = Job::new_async_tz("0 0 * * * *", Madrid, move |_uuid, _l| {
         let pool = pool_auto.clone();
+        let registry = registry_auto.clone();
         Box::pin(async move {
             log::info!("Scheduled run-automation triggered (hourly)");
-            run_scheduled_automation(pool).await;
+            run_scheduled_automation(pool, registry).await;
         })
     })
     .expect("Failed to create automation job");
@@ -77,10 +85,12 @@ async fn main() {
     // Schedule retry job every minute (Madrid timezone)
     // Cron: "0 * * * * *" = second 0, every minute
     let pool_retry = pool.clone();
+    let registry_retry = registry.clone();
     let retry_job = Job::new_async_tz("0 * * * * *", Madrid, move |_uuid, _l| {
         let pool = pool_retry.clone();
+        let registry = registry_retry.clone();
         Box::pin(async move {
-            retry_failed_executions(pool).await;
+            retry_failed_executions(pool, registry).await;
         })
     })
     .expect("Failed to create retry job");
@@ -89,6 +99,41 @@ async fn main() {
         .await
         .expect("Failed to add retry job");
 
+    // Execute due cron rule occurrences every 10 seconds
+    // Cron: "*/10 * * * * *" = every 10th second
+    let pool_cron = pool.clone();
+    let registry_cron = registry.clone();
+    let cron_rules_job = Job::new_async_tz("*/10 * * * * *", Madrid, move |_uuid, _l| {
+        let pool = pool_cron.clone();
+        let registry = registry_cron.clone();
+        Box::pin(async move {
+            run_due_cron_executions(pool, registry).await;
+        })
+    })
+    .expect("Failed to create cron rules job");
+    sched
+        .add(cron_rules_job)
+        .await
+        .expect("Failed to add cron rules job");
+
+    // Store the daily energy of metering devices at 23:55 (Madrid timezone)
+    // Cron: "0 55 23 * * *" = second 0, minute 55, hour 23, every day
+    let pool_energy = pool.clone();
+    let registry_energy = registry.clone();
+    let energy_job = Job::new_async_tz("0 55 23 * * *", Madrid, move |_uuid, _l| {
+        let pool = pool_energy.clone();
+        let registry = registry_energy.clone();
+        Box::pin(async move {
+            log::info!("Scheduled sync-energy triggered (23:55 Madrid)");
+            sync_energy_readings(pool, registry).await;
+        })
+    })
+    .expect("Failed to create sync-energy job");
+    sched
+        .add(energy_job)
+        .await
+        .expect("Failed to add sync-energy job");
+
     // Start the scheduler
     sched.start().await.expect("Failed to start scheduler");
 
@@ -96,6 +141,8 @@ async fn main() {
     log::info!("  - sync-prices: daily at 20:30");
     log::info!("  - run-automation: every hour at :00");
     log::info!("  - retry-failed: every minute");
+    log::info!("  - run-cron-rules: every 10 seconds");
+    log::info!("  - sync-energy: daily at 23:55");
 
     // Keep the process running
     loop {
@@ -104,7 +151,7 @@ async fn main() {
 }
 
 /// Sync prices at startup - loads today's prices and tomorrow's if past 20:30
-async fn sync_prices_startup(pool: Arc<DbPool>) {
+async fn sync_prices_startup(pool: Arc<DbPool>, registry: Arc<ProviderRegistry>) {
     let service = PriceService::new((*pool).clone());
     let schedule_service = ScheduleComputationService::new((*pool).clone());
     let now = Local::now();
@@ -187,7 +234,7 @@ async fn sync_prices_startup(pool: Arc<DbPool>) {
 
     // Run scheduled automation for current hour
     log::info!("Running initial automation check...");
-    run_scheduled_automation(pool).await;
+    run_scheduled_automation(pool, registry).await;
 }
 
 /// Daily sync at 20:30 - fetches tomorrow's prices and computes schedules
@@ -315,12 +362,16 @@ async fn run_automation(pool: Arc<DbPool>) {
 }
 
 /// Run scheduled automation for the current hour
-async fn run_scheduled_automation(pool: Arc<DbPool>) {
-    let registry = Arc::new(ProviderRegistry::new());
+async fn run_scheduled_automation(pool: Arc<DbPool>, registry: Arc<ProviderRegistry>) {
     let engine = AutomationEngine::new((*pool).clone(), registry);
 
     let results = engine.execute_current_hour().await;
 
+    let batteries = engine.execute_battery_plans().await;
+    if batteries > 0 {
+        log::info!("Battery plans: {} commands applied", batteries);
+    }
+
     let successful = results.iter().filter(|r| r.success).count();
     let failed = results.len() - successful;
 
@@ -343,9 +394,27 @@ async fn run_scheduled_automation(pool: Arc<DbPool>) {
     }
 }
 
+/// Store the daily energy readings of metering devices
+async fn sync_energy_readings(pool: Arc<DbPool>, registry: Arc<ProviderRegistry>) {
+    let service = EnergyReadingService::new((*pool).clone());
+    service.sync_all(&registry).await;
+}
+
+/// Execute cron rule occurrences that are due
+async fn run_due_cron_executions(pool: Arc<DbPool>, registry: Arc<ProviderRegistry>) {
+    let engine = AutomationEngine::new((*pool).clone(), registry);
+
+    let results = engine.execute_due_cron_executions().await;
+
+    for result in results.iter().filter(|r| !r.success) {
+        if let Some(ref error) = result.error_message {
+            log::error!("Cron rule {} failed: {}", result.rule_id, error);
+        }
+    }
+}
+
 /// Retry failed executions that are due for retry
-async fn retry_failed_executions(pool: Arc<DbPool>) {
-    let registry = Arc::new(ProviderRegistry::new());
+async fn retry_failed_executions(pool: Arc<DbPool>, registry: Arc<ProviderRegistry>) {
     let engine = AutomationEngine::new((*pool).clone(), registry);
     let schedule_service = ScheduleComputationService::new((*pool).clone());
 
@@ -0,0 +1,833 @@
+//! Generic MQTT provider for user-owned brokers
+//!
+//! The integration credentials hold the broker and the devices on it:
+//!
+//! ```json
+//! {
+//!   "broker_url": "mqtts://broker.example.com:8883",
+//!   "username": "pvpc", "password": "...",
+//!   "devices": [
+//!     {"id": "heater", "name": "Water heater", "preset": "tasmota", "topic": "tasmota_5A3F1C"},
+//!     {"id": "pump", "name": "Pool pump", "command_topic": "pool/pump/set",
+//!      "state_topic": "pool/pump", "command_template": "{\"relay\": \"{{state}}\"}",
+//!      "state_field": "relay"}
+//!   ]
+//! }
+//! ```
+//!
+//! A preset fills in the topics and templates of a firmware from its base
+//! topic; explicit fields override it. Device state is tracked from the
+//! messages received on the state topics.
+
+use super::mqtt::{MqttConfig, MqttConnection, MqttError};
+use super::{
+    DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
+    SmartHomeProvider,
+};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex};
+
+/// How long to wait for a device to report its state
+const STATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Close broker connections not used for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Firmware whose MQTT conventions are known
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttPreset {
+    /// `topic` is the device topic (`cmnd/<topic>/POWER`)
+    Tasmota,
+    /// `topic` is the friendly name (`zigbee2mqtt/<topic>`)
+    Zigbee2mqtt,
+    /// `topic` is the switch component (`<node>/switch/<name>`)
+    Esphome,
+}
+
+/// A device as configured by the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttDeviceConfig {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_device_type")]
+    pub device_type: String,
+    #[serde(default)]
+    pub preset: Option<MqttPreset>,
+    /// Base topic the preset builds on
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub command_topic: Option<String>,
+    /// Command payload, `{{state}}` is replaced by `payload_on`/`payload_off`
+    #[serde(default)]
+    pub command_template: Option<String>,
+    #[serde(default)]
+    pub payload_on: Option<String>,
+    #[serde(default)]
+    pub payload_off: Option<String>,
+    #[serde(default)]
+    pub state_topic: Option<String>,
+    /// Dotted path to the state in a JSON payload; the raw payload if unset
+    #[serde(default)]
+    pub state_field: Option<String>,
+    /// State value meaning "on" (case-insensitive), "ON" by default
+    #[serde(default)]
+    pub state_on: Option<String>,
+    #[serde(default)]
+    pub power_topic: Option<String>,
+    /// Dotted path to the power in watts; the raw payload if unset
+    #[serde(default)]
+    pub power_field: Option<String>,
+    /// Topic to publish to make the device report its state
+    #[serde(default)]
+    pub state_request_topic: Option<String>,
+    #[serde(default)]
+    pub state_request_payload: Option<String>,
+    /// Topic to publish to make the device report its power
+    #[serde(default)]
+    pub power_request_topic: Option<String>,
+    #[serde(default)]
+    pub power_request_payload: Option<String>,
+}
+
+fn default_device_type() -> String {
+    "switch".to_string()
+}
+
+/// Topics and payloads of a device after applying its preset
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceTopics {
+    pub command_topic: String,
+    pub command_template: String,
+    pub payload_on: String,
+    pub payload_off: String,
+    pub state_topic: String,
+    pub state_field: Option<String>,
+    pub state_on: String,
+    pub power_topic: Option<String>,
+    pub power_field: Option<String>,
+    pub state_request: Option<(String, String)>,
+    pub power_request: Option<(String, String)>,
+}
+
+impl MqttDeviceConfig {
+    /// Resolve the device's topics from its preset and overrides
+    pub fn topics(&self) -> Result<DeviceTopics, String> {
+        let base = match (&self.preset, &self.topic) {
+            (Some(_), None) => return Err(format!("Device '{}' needs a topic for its preset", self.id)),
+            (Some(preset), Some(topic)) => preset_topics(*preset, topic),
+            (None, _) => DeviceTopics {
+                command_topic: String::new(),
+                command_template: "{{state}}".to_string(),
+                payload_on: "ON".to_string(),
+                payload_off: "OFF".to_string(),
+                state_topic: String::new(),
+                state_field: None,
+                state_on: "ON".to_string(),
+                power_topic: None,
+                power_field: None,
+                state_request: None,
+                power_request: None,
+            },
+        };
+
+        let topics = DeviceTopics {
+            command_topic: self.command_topic.clone().unwrap_or(base.command_topic),
+            command_template: self.command_template.clone().unwrap_or(base.command_template),
+            payload_on: self.payload_on.clone().unwrap_or(base.payload_on),
+            payload_off: self.payload_off.clone().unwrap_or(base.payload_off),
+            state_topic: self.state_topic.clone().unwrap_or(base.state_topic),
+            state_field: self.state_field.clone().or(base.state_field),
+            state_on: self.state_on.clone().unwrap_or(base.state_on),
+            power_topic: self.power_topic.clone().or(base.power_topic),
+            power_field: self.power_field.clone().or(base.power_field),
+            state_request: match &self.state_request_topic {
+                Some(topic) => Some((topic.clone(), self.state_request_payload.clone().unwrap_or_default())),
+                None => base.state_request,
+            },
+            power_request: match &self.power_request_topic {
+                Some(topic) => Some((topic.clone(), self.power_request_payload.clone().unwrap_or_default())),
+                None => base.power_request,
+            },
+        };
+        if topics.command_topic.is_empty() || topics.state_topic.is_empty() {
+            return Err(format!(
+                "Device '{}' needs command_topic and state_topic or a preset",
+                self.id
+            ));
+        }
+        Ok(topics)
+    }
+}
+
+fn preset_topics(preset: MqttPreset, topic: &str) -> DeviceTopics {
+    match preset {
+        MqttPreset::Tasmota => DeviceTopics {
+            command_topic: format!("cmnd/{}/POWER", topic),
+            command_template: "{{state}}".to_string(),
+            payload_on: "ON".to_string(),
+            payload_off: "OFF".to_string(),
+            state_topic: format!("stat/{}/POWER", topic),
+            state_field: None,
+            state_on: "ON".to_string(),
+            // Telemetry (tele/<topic>/SENSOR) is periodic and not retained,
+            // so power is asked for with `Status 10`
+            power_topic: Some(format!("stat/{}/STATUS10", topic)),
+            power_field: Some("StatusSNS.ENERGY.Power".to_string()),
+            // An empty POWER command reports the current state
+            state_request: Some((format!("cmnd/{}/POWER", topic), String::new())),
+            power_request: Some((format!("cmnd/{}/Status", topic), "10".to_string())),
+        },
+        MqttPreset::Zigbee2mqtt => DeviceTopics {
+            command_topic: format!("zigbee2mqtt/{}/set", topic),
+            command_template: r#"{"state": "{{state}}"}"#.to_string(),
+            payload_on: "ON".to_string(),
+            payload_off: "OFF".to_string(),
+            state_topic: format!("zigbee2mqtt/{}", topic),
+            state_field: Some("state".to_string()),
+            state_on: "ON".to_string(),
+            power_topic: Some(format!("zigbee2mqtt/{}", topic)),
+            power_field: Some("power".to_string()),
+            state_request: Some((format!("zigbee2mqtt/{}/get", topic), r#"{"state": ""}"#.to_string())),
+            power_request: None,
+        },
+        // ESPHome retains its state messages, so there's nothing to request
+        MqttPreset::Esphome => DeviceTopics {
+            command_topic: format!("{}/command", topic),
+            command_template: "{{state}}".to_string(),
+            payload_on: "ON".to_string(),
+            payload_off: "OFF".to_string(),
+            state_topic: format!("{}/state", topic),
+            state_field: None,
+            state_on: "ON".to_string(),
+            power_topic: None,
+            power_field: None,
+            state_request: None,
+            power_request: None,
+        },
+    }
+}
+
+/// Value at a dotted path in a JSON payload, or the raw payload without a path
+fn extract(payload: &[u8], field: Option<&str>) -> Option<Value> {
+    match field {
+        None => Some(Value::String(String::from_utf8_lossy(payload).trim().to_string())),
+        Some(path) => {
+            let json: Value = serde_json::from_slice(payload).ok()?;
+            path.split('.').try_fold(json, |v, key| v.get(key).cloned())
+        }
+    }
+}
+
+fn parse_is_on(payload: &[u8], topics: &DeviceTopics) -> Option<bool> {
+    match extract(payload, topics.state_field.as_deref())? {
+        Value::Bool(b) => Some(b),
+        Value::String(s) => Some(s.eq_ignore_ascii_case(&topics.state_on)),
+        Value::Number(n) => Some(n.as_f64() != Some(0.0)),
+        _ => None,
+    }
+}
+
+fn parse_power(payload: &[u8], field: Option<&str>) -> Option<f64> {
+    match extract(payload, field)? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn map_mqtt_error(e: MqttError) -> ProviderError {
+    match e {
+        MqttError::Timeout => ProviderError::Timeout,
+        MqttError::ConnectionFailed(msg)
+            if msg.contains("BadUserNamePassword") || msg.contains("NotAuthorized") =>
+        {
+            ProviderError::AuthenticationFailed(msg)
+        }
+        MqttError::ConnectionFailed(msg) | MqttError::SubscribeFailed(msg) | MqttError::PublishFailed(msg) => {
+            ProviderError::ConnectionError(msg)
+        }
+        MqttError::Disconnected => ProviderError::ConnectionError("Broker disconnected".to_string()),
+        MqttError::InvalidResponse(msg) => ProviderError::Unknown(msg),
+    }
+}
+
+/// Broker settings and devices from the integration credentials
+#[derive(Debug, Clone, Deserialize)]
+struct BrokerCredentials {
+    broker_url: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    devices: Vec<MqttDeviceConfig>,
+}
+
+impl BrokerCredentials {
+    fn parse(credentials: &Value) -> Result<Self, ProviderError> {
+        serde_json::from_value(credentials.clone()).map_err(|_| ProviderError::InvalidCredentials)
+    }
+
+    /// Session key: a changed password must not reuse the old login, and
+    /// the password itself isn't kept in the key
+    fn key(&self) -> String {
+        let password = Sha256::digest(self.password.as_deref().unwrap_or("").as_bytes());
+        format!(
+            "{}|{}|{}",
+            self.broker_url,
+            self.username.as_deref().unwrap_or(""),
+            hex::encode(password)
+        )
+    }
+
+    fn device(&self, external_id: &str) -> Result<DeviceTopics, ProviderError> {
+        self.devices
+            .iter()
+            .find(|d| d.id == external_id)
+            .ok_or_else(|| ProviderError::DeviceNotFound(external_id.to_string()))?
+            .topics()
+            .map_err(ProviderError::Unknown)
+    }
+}
+
+/// A connection to one broker and the last message seen on each topic
+struct BrokerSession {
+    connection: MqttConnection,
+    last_messages: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>,
+    subscribed: Mutex<HashSet<String>>,
+    listener: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for BrokerSession {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+impl BrokerSession {
+    async fn connect(broker: &BrokerCredentials) -> Result<Self, ProviderError> {
+        let config = MqttConfig::from_url(&broker.broker_url, broker.username.clone(), broker.password.clone())
+            .map_err(|_| ProviderError::InvalidCredentials)?;
+        let connection = MqttConnection::connect(config).await.map_err(map_mqtt_error)?;
+
+        let last_messages: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>> = Default::default();
+        let mut messages = connection.messages();
+        let store = last_messages.clone();
+        let listener = tokio::spawn(async move {
+            loop {
+                match messages.recv().await {
+                    Ok(msg) => {
+                        store.lock().unwrap().insert(msg.topic, msg.payload);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => warn!("MQTT state tracking lagged {} messages", n),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            connection,
+            last_messages,
+            subscribed: Mutex::new(HashSet::new()),
+            listener,
+        })
+    }
+
+    /// Start tracking `topic` if not already tracked. A failed subscribe
+    /// is retried on the next call.
+    async fn track(&self, topic: &str) -> Result<(), ProviderError> {
+        let mut subscribed = self.subscribed.lock().await;
+        if !subscribed.contains(topic) {
+            self.connection.subscribe(topic).await.map_err(map_mqtt_error)?;
+            subscribed.insert(topic.to_string());
+        }
+        Ok(())
+    }
+
+    fn last_message(&self, topic: &str) -> Option<Vec<u8>> {
+        self.last_messages.lock().unwrap().get(topic).cloned()
+    }
+
+    /// Drop the last message on `topic`, so the next one is a fresh answer
+    fn forget(&self, topic: &str) {
+        self.last_messages.lock().unwrap().remove(topic);
+    }
+
+    /// Last message on `topic`, waiting for one to arrive if none has yet
+    async fn wait_for(&self, topic: &str) -> Option<Vec<u8>> {
+        let deadline = tokio::time::Instant::now() + STATE_TIMEOUT;
+        while tokio::time::Instant::now() < deadline {
+            if let Some(payload) = self.last_message(topic) {
+                return Some(payload);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        None
+    }
+}
+
+/// Session of one broker login. Connecting happens under the slot's lock,
+/// so concurrent operations on one broker connect only once while other
+/// brokers aren't held up.
+#[derive(Default)]
+struct Slot {
+    session: Option<Arc<BrokerSession>>,
+    last_used: Option<Instant>,
+}
+
+pub struct GenericMqttProvider {
+    sessions: Mutex<HashMap<String, Arc<Mutex<Slot>>>>,
+    idle_timeout: Duration,
+}
+
+impl Default for GenericMqttProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GenericMqttProvider {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            idle_timeout: IDLE_TIMEOUT,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_idle_timeout(idle_timeout: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            idle_timeout,
+        }
+    }
+
+    /// Connected session for the broker, reconnecting if it dropped
+    async fn session(&self, broker: &BrokerCredentials) -> Result<Arc<BrokerSession>, ProviderError> {
+        let slot = self.slot(&broker.key()).await;
+        let mut slot = slot.lock().await;
+
+        if let Some(session) = slot.session.clone()
+            && session.connection.is_connected().await
+        {
+            slot.last_used = Some(Instant::now());
+            return Ok(session);
+        }
+        slot.session = None;
+
+        info!("MQTT: connecting to {}", broker.broker_url);
+        let session = Arc::new(BrokerSession::connect(broker).await?);
+        slot.session = Some(session.clone());
+        slot.last_used = Some(Instant::now());
+        Ok(session)
+    }
+
+    /// Slot for `key`, closing idle sessions of other brokers
+    async fn slot(&self, key: &str) -> Arc<Mutex<Slot>> {
+        let mut sessions = self.sessions.lock().await;
+        let now = Instant::now();
+        let mut idle = Vec::new();
+        sessions.retain(|k, slot| {
+            if k == key {
+                return true;
+            }
+            // Slots in use are locked; leave them alone
+            let Ok(mut slot) = slot.try_lock() else { return true };
+            if slot.last_used.is_some_and(|used| now.duration_since(used) < self.idle_timeout) {
+                return true;
+            }
+            idle.extend(slot.session.take());
+            false
+        });
+        let slot = sessions.entry(key.to_string()).or_default().clone();
+        drop(sessions);
+
+        for session in idle {
+            info!("MQTT: closing idle broker connection");
+            let _ = session.connection.disconnect().await;
+        }
+        slot
+    }
+
+    async fn send(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        on: bool,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let broker = BrokerCredentials::parse(credentials)?;
+        let topics = broker.device(external_id)?;
+        let session = self.session(&broker).await?;
+        session.track(&topics.state_topic).await?;
+
+        let state = if on { &topics.payload_on } else { &topics.payload_off };
+        let payload = topics.command_template.replace("{{state}}", state);
+        debug!("MQTT: {} -> {}", topics.command_topic, payload);
+        session
+            .connection
+            .publish(&topics.command_topic, payload.as_bytes())
+            .await
+            .map_err(map_mqtt_error)?;
+
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Published {} to {}", payload, topics.command_topic)),
+            new_state: None,
+        })
+    }
+}
+
+#[async_trait]
+impl SmartHomeProvider for GenericMqttProvider {
+    fn provider_name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "MQTT (Tasmota, Zigbee2MQTT, ESPHome)"
+    }
+
+    /// Check the device definitions and that the broker accepts the login
+    async fn login(&self, credentials: &Value) -> Result<Value, ProviderError> {
+        let broker = BrokerCredentials::parse(credentials)?;
+        for device in &broker.devices {
+            device.topics().map_err(ProviderError::Unknown)?;
+        }
+        self.session(&broker).await?;
+        Ok(credentials.clone())
+    }
+
+    async fn list_devices(&self, credentials: &Value) -> Result<Vec<DiscoveredDevice>, ProviderError> {
+        let broker = BrokerCredentials::parse(credentials)?;
+        broker
+            .devices
+            .iter()
+            .map(|device| {
+                let topics = device.topics().map_err(ProviderError::Unknown)?;
+                Ok(DiscoveredDevice {
+                    external_id: device.id.clone(),
+                    name: device.name.clone(),
+                    device_type: device.device_type.clone(),
+                    capabilities: Some(DeviceCapabilities {
+                        can_read_consumption: topics.power_topic.is_some(),
+                        ..Default::default()
+                    }),
+                })
+            })
+            .collect()
+    }
+
+    async fn get_device_state(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceState, ProviderError> {
+        let broker = BrokerCredentials::parse(credentials)?;
+        let topics = broker.device(external_id)?;
+        let session = self.session(&broker).await?;
+        session.track(&topics.state_topic).await?;
+        if let Some(power_topic) = &topics.power_topic {
+            session.track(power_topic).await?;
+        }
+
+        if session.last_message(&topics.state_topic).is_none()
+            && let Some((topic, payload)) = &topics.state_request
+        {
+            session
+                .connection
+                .publish(topic, payload.as_bytes())
+                .await
+                .map_err(map_mqtt_error)?;
+        }
+        // Power is optional here: ask for it, but don't wait for it
+        if let Some(power_topic) = &topics.power_topic
+            && session.last_message(power_topic).is_none()
+            && let Some((topic, payload)) = &topics.power_request
+        {
+            session
+                .connection
+                .publish(topic, payload.as_bytes())
+                .await
+                .map_err(map_mqtt_error)?;
+        }
+
+        let payload = session.wait_for(&topics.state_topic).await.ok_or(ProviderError::Timeout)?;
+        let is_on = parse_is_on(&payload, &topics).ok_or_else(|| {
+            ProviderError::Unknown(format!(
+                "Unrecognised state on {}: {}",
+                topics.state_topic,
+                String::from_utf8_lossy(&payload)
+            ))
+        })?;
+        let power = topics
+            .power_topic
+            .as_ref()
+            .and_then(|t| session.last_message(t))
+            .and_then(|p| parse_power(&p, topics.power_field.as_deref()));
+
+        Ok(DeviceState {
+            is_on,
+            brightness: None,
+            temperature: None,
+            power_consumption_watts: power.map(|p| p as f32),
+        })
+    }
+
+    async fn turn_on(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.send(credentials, external_id, true).await
+    }
+
+    async fn turn_off(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.send(credentials, external_id, false).await
+    }
+
+    async fn read_power(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        let broker = BrokerCredentials::parse(credentials)?;
+        let topics = broker.device(external_id)?;
+        let Some(power_topic) = &topics.power_topic else {
+            return Err(ProviderError::Unsupported(format!("{} has no power topic", external_id)));
+        };
+        let session = self.session(&broker).await?;
+        session.track(power_topic).await?;
+        if let Some((topic, payload)) = &topics.power_request {
+            session.forget(power_topic);
+            session
+                .connection
+                .publish(topic, payload.as_bytes())
+                .await
+                .map_err(map_mqtt_error)?;
+        }
+        let payload = session.wait_for(power_topic).await.ok_or(ProviderError::Timeout)?;
+        parse_power(&payload, topics.power_field.as_deref())
+            .ok_or_else(|| ProviderError::Unknown(format!("No power reading on {}", power_topic)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::test_broker::TestBroker;
+    use serde_json::json;
+
+    fn device(value: Value) -> MqttDeviceConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_presets() {
+        let tasmota = device(json!({"id": "a", "name": "A", "preset": "tasmota", "topic": "plug"}))
+            .topics()
+            .unwrap();
+        assert_eq!(tasmota.command_topic, "cmnd/plug/POWER");
+        assert_eq!(tasmota.state_topic, "stat/plug/POWER");
+        assert_eq!(tasmota.power_topic.as_deref(), Some("stat/plug/STATUS10"));
+        assert_eq!(tasmota.power_request, Some(("cmnd/plug/Status".to_string(), "10".to_string())));
+
+        let z2m = device(json!({"id": "b", "name": "B", "preset": "zigbee2mqtt", "topic": "heater"}))
+            .topics()
+            .unwrap();
+        assert_eq!(z2m.command_topic, "zigbee2mqtt/heater/set");
+        assert_eq!(z2m.command_template.replace("{{state}}", &z2m.payload_on), r#"{"state": "ON"}"#);
+
+        let esphome = device(json!({"id": "c", "name": "C", "preset": "esphome", "topic": "node/switch/relay",
+                                    "payload_on": "on"}))
+            .topics()
+            .unwrap();
+        assert_eq!(esphome.command_topic, "node/switch/relay/command");
+        assert_eq!(esphome.payload_on, "on");
+
+        assert!(device(json!({"id": "d", "name": "D", "preset": "tasmota"})).topics().is_err());
+        assert!(device(json!({"id": "e", "name": "E", "command_topic": "x"})).topics().is_err());
+    }
+
+    #[test]
+    fn test_parse_state_and_power() {
+        let z2m = preset_topics(MqttPreset::Zigbee2mqtt, "x");
+        assert_eq!(parse_is_on(br#"{"state": "ON", "power": 12.5}"#, &z2m), Some(true));
+        assert_eq!(parse_is_on(br#"{"state": "OFF"}"#, &z2m), Some(false));
+        assert_eq!(parse_is_on(br#"{"linkquality": 80}"#, &z2m), None);
+        assert_eq!(parse_power(br#"{"state": "ON", "power": 12.5}"#, Some("power")), Some(12.5));
+
+        let tasmota = preset_topics(MqttPreset::Tasmota, "x");
+        assert_eq!(parse_is_on(b"ON", &tasmota), Some(true));
+        let status10 = br#"{"StatusSNS": {"Time": "2026-10-18T12:00:00", "ENERGY": {"Power": 1830}}}"#;
+        assert_eq!(parse_power(status10, tasmota.power_field.as_deref()), Some(1830.0));
+        assert_eq!(parse_power(b" 42.1 ", None), Some(42.1));
+    }
+
+    fn credentials(broker: &TestBroker) -> Value {
+        json!({
+            "broker_url": broker.url,
+            "username": "pvpc",
+            "password": "secret",
+            "devices": [
+                {"id": "heater", "name": "Water heater", "preset": "tasmota", "topic": "heater"},
+                {"id": "pump", "name": "Pool pump", "preset": "zigbee2mqtt", "topic": "pump"},
+                {"id": "fan", "name": "Fan", "preset": "esphome", "topic": "attic/switch/fan"}
+            ]
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_login_against_broker() {
+        let broker = TestBroker::start(Some(("pvpc", "secret"))).await;
+        let provider = GenericMqttProvider::new();
+
+        assert!(provider.login(&credentials(&broker)).await.is_ok());
+
+        // A wrong password doesn't get the session of the right one
+        let mut wrong = credentials(&broker);
+        wrong["password"] = json!("nope");
+        assert!(matches!(
+            provider.login(&wrong).await,
+            Err(ProviderError::AuthenticationFailed(_))
+        ));
+
+        let devices = provider.list_devices(&credentials(&broker)).await.unwrap();
+        assert_eq!(devices.len(), 3);
+        assert!(devices[0].capabilities.as_ref().unwrap().can_read_consumption);
+        assert!(!devices[2].capabilities.as_ref().unwrap().can_read_consumption);
+    }
+
+    #[actix_rt::test]
+    async fn test_commands_are_published() {
+        let broker = TestBroker::start(None).await;
+        let provider = GenericMqttProvider::new();
+        let creds = credentials(&broker);
+
+        provider.turn_on(&creds, "heater").await.unwrap();
+        provider.turn_off(&creds, "pump").await.unwrap();
+        assert!(matches!(
+            provider.turn_on(&creds, "missing").await,
+            Err(ProviderError::DeviceNotFound(_))
+        ));
+
+        let published = broker.wait_for_published(2).await;
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0], ("cmnd/heater/POWER".to_string(), "ON".to_string()));
+        assert_eq!(published[1], ("zigbee2mqtt/pump/set".to_string(), r#"{"state": "OFF"}"#.to_string()));
+        // Both commands went over one connection
+        assert_eq!(broker.client_count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_tasmota_power_is_requested() {
+        let broker = TestBroker::start(None).await;
+        let provider = GenericMqttProvider::new();
+        let creds = credentials(&broker);
+
+        // No telemetry has been published: the device answers Status 10
+        let responder = broker.clone();
+        tokio::spawn(async move {
+            loop {
+                if responder.published().iter().any(|(t, p)| t == "cmnd/heater/Status" && p == "10") {
+                    responder.publish(
+                        "stat/heater/STATUS10",
+                        r#"{"StatusSNS": {"ENERGY": {"Power": 1830, "Today": 2.4}}}"#,
+                        false,
+                    );
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        assert_eq!(provider.read_power(&creds, "heater").await.unwrap(), 1830.0);
+    }
+
+    #[actix_rt::test]
+    async fn test_idle_sessions_are_closed() {
+        let first = TestBroker::start(None).await;
+        let second = TestBroker::start(None).await;
+        let provider = GenericMqttProvider::with_idle_timeout(Duration::from_millis(0));
+
+        provider.turn_on(&credentials(&first), "heater").await.unwrap();
+        provider.turn_on(&credentials(&second), "heater").await.unwrap();
+
+        // The first broker's session was idle when the second was looked up
+        let sessions = provider.sessions.lock().await;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.contains_key(&BrokerCredentials::parse(&credentials(&second)).unwrap().key()));
+    }
+
+    #[test]
+    fn test_session_key_depends_on_password() {
+        let creds = json!({"broker_url": "mqtt://h:1883", "username": "u", "password": "secret"});
+        let key = BrokerCredentials::parse(&creds).unwrap().key();
+        assert!(!key.contains("secret"));
+
+        let mut other = creds.clone();
+        other["password"] = json!("other");
+        assert_ne!(BrokerCredentials::parse(&other).unwrap().key(), key);
+    }
+
+    #[actix_rt::test]
+    async fn test_state_is_tracked_from_state_topics() {
+        let broker = TestBroker::start(None).await;
+        let provider = GenericMqttProvider::new();
+        let creds = credentials(&broker);
+
+        broker.publish("attic/switch/fan/state", "ON", true);
+        assert!(provider.get_device_state(&creds, "fan").await.unwrap().is_on);
+
+        // Later updates replace the tracked state
+        broker.publish("attic/switch/fan/state", "OFF", false);
+        let mut is_on = true;
+        for _ in 0..40 {
+            is_on = provider.get_device_state(&creds, "fan").await.unwrap().is_on;
+            if !is_on {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!is_on);
+    }
+
+    #[actix_rt::test]
+    async fn test_state_request_and_power() {
+        let broker = TestBroker::start(None).await;
+        let provider = GenericMqttProvider::new();
+        let creds = credentials(&broker);
+        broker.publish("zigbee2mqtt/pump", r#"{"state": "ON", "power": 745.0}"#, true);
+
+        // The Tasmota device only answers when asked
+        let responder = broker.clone();
+        tokio::spawn(async move {
+            loop {
+                if responder.published().iter().any(|(t, _)| t == "cmnd/heater/POWER") {
+                    responder.publish("stat/heater/POWER", "OFF", false);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        let heater = provider.get_device_state(&creds, "heater").await.unwrap();
+        assert!(!heater.is_on);
+        assert_eq!(broker.published()[0], ("cmnd/heater/POWER".to_string(), String::new()));
+
+        let pump = provider.get_device_state(&creds, "pump").await.unwrap();
+        assert!(pump.is_on);
+        assert_eq!(pump.power_consumption_watts, Some(745.0));
+        assert_eq!(provider.read_power(&creds, "pump").await.unwrap(), 745.0);
+        assert!(matches!(
+            provider.read_power(&creds, "fan").await,
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,440 @@
+//! Home Assistant provider
+//!
+//! Talks to a Home Assistant instance through its REST API with a long-lived
+//! access token. Switch, light and climate entities are exposed as devices.
+
+use super::{
+    DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
+    SmartHomeProvider,
+};
+use crate::services::ha_client::{entity_domain, HaEntityState, HomeAssistantClient};
+use async_trait::async_trait;
+use log::info;
+use serde_json::{json, Value};
+
+/// Entity domains listed as devices
+const SUPPORTED_DOMAINS: [&str; 3] = ["switch", "light", "climate"];
+
+pub struct HomeAssistantProvider;
+
+impl Default for HomeAssistantProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HomeAssistantProvider {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Client for the instance in `credentials` ({"base_url", "token"})
+    fn client(credentials: &Value) -> Result<HomeAssistantClient, ProviderError> {
+        let base_url = credentials
+            .get("base_url")
+            .and_then(|v| v.as_str())
+            .ok_or(ProviderError::InvalidCredentials)?;
+        let token = credentials
+            .get("token")
+            .and_then(|v| v.as_str())
+            .ok_or(ProviderError::AuthenticationFailed(
+                "No token provided".to_string(),
+            ))?;
+        Ok(HomeAssistantClient::new(base_url.to_string(), token.to_string()))
+    }
+
+    /// Call a service and report the outcome as an action result
+    async fn call(
+        credentials: &Value,
+        domain: &str,
+        service: &str,
+        data: Value,
+        message: String,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::client(credentials)?
+            .call_service(domain, service, data)
+            .await
+            .map_err(map_error)?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(message),
+            new_state: None,
+        })
+    }
+}
+
+fn map_error(e: reqwest::Error) -> ProviderError {
+    match e.status().map(|s| s.as_u16()) {
+        Some(401) | Some(403) => ProviderError::AuthenticationFailed(e.to_string()),
+        Some(404) => ProviderError::DeviceNotFound(e.to_string()),
+        Some(429) => ProviderError::RateLimited,
+        _ if e.is_timeout() => ProviderError::Timeout,
+        _ if e.is_connect() => ProviderError::ConnectionError(e.to_string()),
+        _ => ProviderError::Unknown(e.to_string()),
+    }
+}
+
+/// Device type and capabilities of an entity, None for unsupported domains
+fn describe_entity(entity: &HaEntityState) -> Option<(&'static str, DeviceCapabilities)> {
+    let attributes = &entity.attributes;
+    match entity_domain(&entity.entity_id) {
+        "switch" => Some((
+            "switch",
+            DeviceCapabilities {
+                can_read_consumption: attributes.get("current_power_w").is_some(),
+                ..Default::default()
+            },
+        )),
+        "light" => {
+            let dimmable = attributes
+                .get("supported_color_modes")
+                .and_then(|m| m.as_array())
+                .is_some_and(|modes| modes.iter().any(|m| m != "onoff"));
+            Some((
+                "light",
+                DeviceCapabilities {
+                    can_dim: dimmable,
+                    ..Default::default()
+                },
+            ))
+        }
+        "climate" => Some((
+            "thermostat",
+            DeviceCapabilities {
+                can_set_temperature: true,
+                ..Default::default()
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// Device state from an entity state
+fn entity_to_state(entity: &HaEntityState) -> DeviceState {
+    let attributes = &entity.attributes;
+    let number = |key: &str| attributes.get(key).and_then(|v| v.as_f64());
+    DeviceState {
+        is_on: !matches!(entity.state.as_str(), "off" | "unavailable" | "unknown"),
+        // Home Assistant brightness is 0-255
+        brightness: number("brightness").map(|b| (b * 100.0 / 255.0).round() as u8),
+        temperature: number("current_temperature").map(|t| t as f32),
+        power_consumption_watts: number("current_power_w").map(|w| w as f32),
+    }
+}
+
+#[async_trait]
+impl SmartHomeProvider for HomeAssistantProvider {
+    fn provider_name(&self) -> &'static str {
+        "home_assistant"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Home Assistant"
+    }
+
+    /// Long-lived tokens don't expire, so the credentials are the session
+    async fn login(&self, credentials: &Value) -> Result<Value, ProviderError> {
+        Self::client(credentials)?.check_api().await.map_err(map_error)?;
+        Ok(credentials.clone())
+    }
+
+    async fn list_devices(&self, credentials: &Value) -> Result<Vec<DiscoveredDevice>, ProviderError> {
+        let states = Self::client(credentials)?.get_states().await.map_err(map_error)?;
+
+        let discovered: Vec<DiscoveredDevice> = states
+            .iter()
+            .filter(|e| SUPPORTED_DOMAINS.contains(&entity_domain(&e.entity_id)))
+            .filter_map(|entity| {
+                let (device_type, capabilities) = describe_entity(entity)?;
+                let name = entity
+                    .attributes
+                    .get("friendly_name")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or(&entity.entity_id);
+                Some(DiscoveredDevice {
+                    external_id: entity.entity_id.clone(),
+                    name: name.to_string(),
+                    device_type: device_type.to_string(),
+                    capabilities: Some(capabilities),
+                })
+            })
+            .collect();
+
+        info!("Home Assistant found {} devices", discovered.len());
+        Ok(discovered)
+    }
+
+    async fn get_device_state(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceState, ProviderError> {
+        let entity = Self::client(credentials)?
+            .get_state(external_id)
+            .await
+            .map_err(map_error)?;
+        Ok(entity_to_state(&entity))
+    }
+
+    async fn turn_on(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::client(credentials)?
+            .turn_on(external_id)
+            .await
+            .map_err(map_error)?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some("Device turned on".to_string()),
+            new_state: None,
+        })
+    }
+
+    async fn turn_off(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::client(credentials)?
+            .turn_off(external_id)
+            .await
+            .map_err(map_error)?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some("Device turned off".to_string()),
+            new_state: None,
+        })
+    }
+
+    async fn set_brightness(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        brightness: u8,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::call(
+            credentials,
+            "light",
+            "turn_on",
+            json!({ "entity_id": external_id, "brightness_pct": brightness }),
+            format!("Brightness set to {}%", brightness),
+        )
+        .await
+    }
+
+    async fn set_temperature(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        celsius: f64,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::call(
+            credentials,
+            "climate",
+            "set_temperature",
+            json!({ "entity_id": external_id, "temperature": celsius }),
+            format!("Setpoint set to {:.1} °C", celsius),
+        )
+        .await
+    }
+
+    async fn read_power(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        let state = self.get_device_state(credentials, external_id).await?;
+        state
+            .power_consumption_watts
+            .map(f64::from)
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} reports no power", external_id)))
+    }
+
+    /// Tokens are long-lived: check they still work instead of logging in again
+    async fn refresh_credentials(&self, credentials: &Value) -> Result<Value, ProviderError> {
+        self.login(credentials).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::sync::{Arc, Mutex};
+
+    const TOKEN: &str = "test-token";
+
+    type Calls = Arc<Mutex<Vec<(String, Value)>>>;
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            == Some(&format!("Bearer {}", TOKEN))
+    }
+
+    fn states() -> Value {
+        json!([
+            {"entity_id": "switch.water_heater", "state": "off",
+             "attributes": {"friendly_name": "Water heater", "current_power_w": 0.0}},
+            {"entity_id": "light.kitchen", "state": "on",
+             "attributes": {"friendly_name": "Kitchen", "brightness": 128, "supported_color_modes": ["brightness"]}},
+            {"entity_id": "climate.living_room", "state": "heat",
+             "attributes": {"current_temperature": 19.5, "temperature": 21}},
+            {"entity_id": "sensor.outdoor", "state": "12.3", "attributes": {}}
+        ])
+    }
+
+    #[get("/api/")]
+    async fn api_root(req: HttpRequest) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(json!({"message": "API running."}))
+    }
+
+    #[get("/api/states")]
+    async fn all_states(req: HttpRequest) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(states())
+    }
+
+    #[get("/api/states/{entity_id}")]
+    async fn one_state(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let entity_id = path.into_inner();
+        match states().as_array().unwrap().iter().find(|s| s["entity_id"] == entity_id) {
+            Some(state) => HttpResponse::Ok().json(state),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[post("/api/services/{domain}/{service}")]
+    async fn service(
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+        body: web::Json<Value>,
+        calls: web::Data<Calls>,
+    ) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let (domain, service) = path.into_inner();
+        calls
+            .lock()
+            .unwrap()
+            .push((format!("{}.{}", domain, service), body.into_inner()));
+        HttpResponse::Ok().json(json!([]))
+    }
+
+    /// Start a mock Home Assistant on a free local port
+    fn mock_server() -> (String, Calls) {
+        let calls: Calls = Arc::new(Mutex::new(Vec::new()));
+        let data = web::Data::new(calls.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(api_root)
+                .service(all_states)
+                .service(one_state)
+                .service(service)
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        (format!("http://{}", addr), calls)
+    }
+
+    fn credentials(base_url: &str, token: &str) -> Value {
+        json!({"base_url": base_url, "token": token})
+    }
+
+    #[actix_rt::test]
+    async fn test_login_checks_token() {
+        let (base_url, _) = mock_server();
+        let provider = HomeAssistantProvider::new();
+
+        assert!(provider.login(&credentials(&base_url, TOKEN)).await.is_ok());
+        let result = provider.login(&credentials(&base_url, "wrong")).await;
+        assert!(matches!(result, Err(ProviderError::AuthenticationFailed(_))));
+        assert!(matches!(
+            provider.login(&json!({"token": TOKEN})).await,
+            Err(ProviderError::InvalidCredentials)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_list_devices_maps_entities() {
+        let (base_url, _) = mock_server();
+        let provider = HomeAssistantProvider::new();
+
+        let devices = provider.list_devices(&credentials(&base_url, TOKEN)).await.unwrap();
+        let ids: Vec<&str> = devices.iter().map(|d| d.external_id.as_str()).collect();
+        assert_eq!(ids, vec!["switch.water_heater", "light.kitchen", "climate.living_room"]);
+
+        assert_eq!(devices[0].name, "Water heater");
+        assert!(devices[0].capabilities.as_ref().unwrap().can_read_consumption);
+        assert!(devices[1].capabilities.as_ref().unwrap().can_dim);
+        assert_eq!(devices[2].device_type, "thermostat");
+        assert_eq!(devices[2].name, "climate.living_room");
+        assert!(devices[2].capabilities.as_ref().unwrap().can_set_temperature);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_device_state() {
+        let (base_url, _) = mock_server();
+        let provider = HomeAssistantProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        let light = provider.get_device_state(&creds, "light.kitchen").await.unwrap();
+        assert!(light.is_on);
+        assert_eq!(light.brightness, Some(50));
+
+        let climate = provider.get_device_state(&creds, "climate.living_room").await.unwrap();
+        assert!(climate.is_on);
+        assert_eq!(climate.temperature, Some(19.5));
+
+        let missing = provider.get_device_state(&creds, "switch.missing").await;
+        assert!(matches!(missing, Err(ProviderError::DeviceNotFound(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_actions_call_services() {
+        let (base_url, calls) = mock_server();
+        let provider = HomeAssistantProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        assert!(provider.turn_on(&creds, "switch.water_heater").await.unwrap().success);
+        provider.turn_off(&creds, "light.kitchen").await.unwrap();
+        provider.set_brightness(&creds, "light.kitchen", 40).await.unwrap();
+        provider.set_temperature(&creds, "climate.living_room", 21.5).await.unwrap();
+
+        let calls = calls.lock().unwrap().clone();
+        let services: Vec<&str> = calls.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(
+            services,
+            vec!["switch.turn_on", "light.turn_off", "light.turn_on", "climate.set_temperature"]
+        );
+        assert_eq!(calls[0].1["entity_id"], "switch.water_heater");
+        assert_eq!(calls[2].1["brightness_pct"], 40);
+        assert_eq!(calls[3].1["temperature"], 21.5);
+    }
+
+    #[actix_rt::test]
+    async fn test_read_power() {
+        let (base_url, _) = mock_server();
+        let provider = HomeAssistantProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        assert_eq!(provider.read_power(&creds, "switch.water_heater").await.unwrap(), 0.0);
+        assert!(matches!(
+            provider.read_power(&creds, "light.kitchen").await,
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+}
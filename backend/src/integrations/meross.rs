@@ -1,5 +1,6 @@
+use super::meross_pool::MerossConnectionPool;
 use super::{
-    DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
+    DailyEnergy, DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
     SmartHomeProvider,
 };
 use async_trait::async_trait;
@@ -82,6 +83,8 @@ struct MerossChannel {
 
 pub struct MerossProvider {
     client: Client,
+    /// MQTT connections shared by all device operations
+    connections: MerossConnectionPool,
 }
 
 impl Default for MerossProvider {
@@ -94,6 +97,7 @@ impl MerossProvider {
     pub fn new() -> Self {
         Self {
             client: Client::new(),
+            connections: MerossConnectionPool::default(),
         }
     }
 
@@ -327,6 +331,124 @@ impl MerossProvider {
         error!("Meross login failed on all endpoints");
         Err(last_error)
     }
+
+    /// Fail early when there's no session token
+    fn require_token(credentials: &Value) -> Result<(), ProviderError> {
+        credentials
+            .get("token")
+            .and_then(|v| v.as_str())
+            .ok_or(ProviderError::AuthenticationFailed(
+                "No token provided".to_string(),
+            ))?;
+        Ok(())
+    }
+
+    /// Report a failed action as an unsuccessful result; credential errors
+    /// are still returned so the caller can refresh the session
+    fn action_result(
+        action: &str,
+        result: Result<DeviceActionResult, ProviderError>,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        match result {
+            Err(e @ ProviderError::AuthenticationFailed(_)) => Err(e),
+            Err(e) => {
+                error!("Meross {} failed: {}", action, e);
+                Ok(DeviceActionResult {
+                    success: false,
+                    message: Some(format!("MQTT request failed: {}", e)),
+                    new_state: None,
+                })
+            }
+            ok => ok,
+        }
+    }
+}
+
+/// Capabilities of a Meross device from its model (e.g. "mss310", "msl120")
+fn meross_capabilities(device_type: &str, channel_count: usize) -> DeviceCapabilities {
+    DeviceCapabilities {
+        can_toggle: true,
+        can_dim: device_type.starts_with("msl"),
+        can_set_temperature: device_type.starts_with("mts"),
+        // Only the mss3xx plugs meter electricity
+        can_read_consumption: device_type.starts_with("mss3"),
+        channel_count: channel_count as u32,
+    }
+}
+
+/// Separates the device uuid from the channel in channel external ids
+const CHANNEL_SEPARATOR: char = ':';
+
+/// Device uuid and channel of an external id ("uuid" or "uuid:channel")
+fn split_external_id(external_id: &str) -> (&str, Option<i32>) {
+    match external_id.rsplit_once(CHANNEL_SEPARATOR) {
+        Some((uuid, channel)) => match channel.parse() {
+            Ok(channel) => (uuid, Some(channel)),
+            Err(_) => (external_id, None),
+        },
+        None => (external_id, None),
+    }
+}
+
+/// Device uuid and the channel to address. A channel encoded in the
+/// external id wins over `channel`, which then must be 0.
+fn target_channel(external_id: &str, channel: u32) -> (&str, i32) {
+    match split_external_id(external_id) {
+        (uuid, Some(encoded)) => (uuid, encoded),
+        (uuid, None) => (uuid, channel as i32),
+    }
+}
+
+/// Devices for one Meross device. Single-outlet devices map to one device;
+/// strips (e.g. mss425) also get one device per outlet, with the channel in
+/// the external id. The strip itself stays as channel 0, which switches
+/// every outlet at once.
+fn discover_device(d: &MerossDeviceData) -> Vec<DiscoveredDevice> {
+    // Determine device type from Meross deviceType
+    let device_type = match d.device_type.as_str() {
+        t if t.starts_with("mss") => "switch", // Smart plugs
+        t if t.starts_with("msl") => "light",  // Smart lights
+        t if t.starts_with("msg") => "garage", // Garage door
+        t if t.starts_with("mts") => "thermostat",
+        _ => "unknown",
+    };
+
+    let channels = d.channels.as_deref().unwrap_or_default();
+    // Channel numbers can skip values (e.g. a USB outlet on channel 5)
+    let channel_number = |index: usize, channel: &MerossChannel| channel.channel.unwrap_or(index as i32);
+    let channel_count = channels
+        .iter()
+        .enumerate()
+        .map(|(index, channel)| channel_number(index, channel).max(0) as usize + 1)
+        .max()
+        .unwrap_or(1);
+    let mut discovered = vec![DiscoveredDevice {
+        capabilities: Some(meross_capabilities(&d.device_type, channel_count)),
+        external_id: d.uuid.clone(),
+        name: d.dev_name.clone(),
+        device_type: device_type.to_string(),
+    }];
+    if channels.len() <= 1 {
+        return discovered;
+    }
+
+    for (index, channel) in channels.iter().enumerate() {
+        let number = channel_number(index, channel);
+        if number == 0 {
+            continue;
+        }
+        let name = match channel.dev_name.as_deref() {
+            Some(name) if !name.is_empty() => format!("{} {}", d.dev_name, name),
+            _ => format!("{} {}", d.dev_name, number),
+        };
+        discovered.push(DiscoveredDevice {
+            capabilities: Some(meross_capabilities(&d.device_type, 1)),
+            external_id: format!("{}{}{}", d.uuid, CHANNEL_SEPARATOR, number),
+            name,
+            device_type: device_type.to_string(),
+        });
+    }
+    discovered
 }
 
 /// Simple base64 encoding without external dependency
@@ -405,25 +527,7 @@ impl SmartHomeProvider for MerossProvider {
 
         let devices = self.get_device_list(&api_url, token).await?;
 
-        let discovered: Vec<DiscoveredDevice> = devices
-            .into_iter()
-            .map(|d| {
-                // Determine device type from Meross deviceType
-                let device_type = match d.device_type.as_str() {
-                    t if t.starts_with("mss") => "switch", // Smart plugs
-                    t if t.starts_with("msl") => "light",  // Smart lights
-                    t if t.starts_with("msg") => "garage", // Garage door
-                    t if t.starts_with("mts") => "thermostat",
-                    _ => "unknown",
-                };
-
-                DiscoveredDevice {
-                    external_id: d.uuid,
-                    name: d.dev_name,
-                    device_type: device_type.to_string(),
-                }
-            })
-            .collect();
+        let discovered: Vec<DiscoveredDevice> = devices.iter().flat_map(discover_device).collect();
 
         info!("Meross found {} devices", discovered.len());
         Ok(discovered)
@@ -434,34 +538,25 @@ impl SmartHomeProvider for MerossProvider {
         credentials: &Value,
         external_id: &str,
     ) -> Result<DeviceState, ProviderError> {
-        // Validate we have required credentials
-        credentials
-            .get("token")
-            .and_then(|v| v.as_str())
-            .ok_or(ProviderError::AuthenticationFailed(
-                "No token provided".to_string(),
-            ))?;
-
-        // Create MQTT client and connect
-        let mut mqtt_client = super::meross_mqtt::MerossMqttClient::from_credentials(credentials)?;
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
 
-        match mqtt_client.connect().await {
-            Ok(()) => {
-                let result = mqtt_client.get_state(external_id).await;
-                let _ = mqtt_client.disconnect().await;
-                result
-            }
-            Err(e) => {
-                warn!("Meross MQTT connection failed for get_device_state: {}", e);
-                // Return default state on connection failure
-                Ok(DeviceState {
-                    is_on: false,
-                    brightness: None,
-                    temperature: None,
-                    power_consumption_watts: None,
-                })
+        if let Err(e) = self.connections.client(credentials).await {
+            if let ProviderError::AuthenticationFailed(_) = e {
+                return Err(e);
             }
+            warn!("Meross MQTT connection failed for get_device_state: {}", e);
+            // Return default state on connection failure
+            return Ok(DeviceState {
+                is_on: false,
+                brightness: None,
+                temperature: None,
+                power_consumption_watts: None,
+            });
         }
+        self.connections
+            .with_client(credentials, |mqtt| async move { mqtt.get_state(uuid, channel).await })
+            .await
     }
 
     async fn turn_on(
@@ -470,43 +565,14 @@ impl SmartHomeProvider for MerossProvider {
         external_id: &str,
     ) -> Result<DeviceActionResult, ProviderError> {
         info!("Meross turn_on called for device: {}", external_id);
-
-        // Validate we have required credentials
-        credentials
-            .get("token")
-            .and_then(|v| v.as_str())
-            .ok_or(ProviderError::AuthenticationFailed(
-                "No token provided".to_string(),
-            ))?;
-
-        // mqtt_domain from API is like "mqtt-eu-5.meross.com", port 2001 with TLS
-        let mqtt_domain = credentials
-            .get("mqtt_domain")
-            .and_then(|v| v.as_str())
-            .unwrap_or("mqtt-eu-5.meross.com");
-        info!("Meross MQTT domain: {} (port 2001 TLS)", mqtt_domain);
-
-        // Create MQTT client and connect
-        let mut mqtt_client = super::meross_mqtt::MerossMqttClient::from_credentials(credentials)?;
-        info!("Meross MQTT client created, attempting to connect...");
-
-        match mqtt_client.connect().await {
-            Ok(()) => {
-                info!("Meross MQTT connected successfully, sending turn_on command...");
-                let result = mqtt_client.turn_on(external_id, 0).await;
-                info!("Meross turn_on result: {:?}", result);
-                let _ = mqtt_client.disconnect().await;
-                result
-            }
-            Err(e) => {
-                error!("Meross MQTT connection failed for turn_on: {}", e);
-                Ok(DeviceActionResult {
-                    success: false,
-                    message: Some(format!("MQTT connection failed: {}", e)),
-                    new_state: None,
-                })
-            }
-        }
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+
+        let result = self
+            .connections
+            .with_client(credentials, |mqtt| async move { mqtt.turn_on(uuid, channel).await })
+            .await;
+        Self::action_result("turn_on", result)
     }
 
     async fn turn_off(
@@ -515,51 +581,114 @@ impl SmartHomeProvider for MerossProvider {
         external_id: &str,
     ) -> Result<DeviceActionResult, ProviderError> {
         info!("Meross turn_off called for device: {}", external_id);
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+
+        let result = self
+            .connections
+            .with_client(credentials, |mqtt| async move { mqtt.turn_off(uuid, channel).await })
+            .await;
+        Self::action_result("turn_off", result)
+    }
 
-        // Validate we have required credentials
-        credentials
-            .get("token")
-            .and_then(|v| v.as_str())
-            .ok_or(ProviderError::AuthenticationFailed(
-                "No token provided".to_string(),
-            ))?;
+    async fn turn_on_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, channel);
 
-        // mqtt_domain from API is like "mqtt-eu-5.meross.com", port 2001 with TLS
-        let mqtt_domain = credentials
-            .get("mqtt_domain")
-            .and_then(|v| v.as_str())
-            .unwrap_or("mqtt-eu-5.meross.com");
-        info!("Meross MQTT domain: {} (port 2001 TLS)", mqtt_domain);
-
-        // Create MQTT client and connect
-        let mut mqtt_client = super::meross_mqtt::MerossMqttClient::from_credentials(credentials)?;
-        info!("Meross MQTT client created, attempting to connect...");
-
-        match mqtt_client.connect().await {
-            Ok(()) => {
-                info!("Meross MQTT connected successfully, sending turn_off command...");
-                let result = mqtt_client.turn_off(external_id, 0).await;
-                info!("Meross turn_off result: {:?}", result);
-                let _ = mqtt_client.disconnect().await;
-                result
-            }
-            Err(e) => {
-                error!("Meross MQTT connection failed for turn_off: {}", e);
-                Ok(DeviceActionResult {
-                    success: false,
-                    message: Some(format!("MQTT connection failed: {}", e)),
-                    new_state: None,
-                })
-            }
-        }
+        let result = self
+            .connections
+            .with_client(credentials, |mqtt| async move {
+                mqtt.set_channel(uuid, channel, true).await
+            })
+            .await;
+        Self::action_result("turn_on_channel", result)
+    }
+
+    async fn turn_off_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, channel);
+
+        let result = self
+            .connections
+            .with_client(credentials, |mqtt| async move {
+                mqtt.set_channel(uuid, channel, false).await
+            })
+            .await;
+        Self::action_result("turn_off_channel", result)
+    }
+
+    async fn set_brightness(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        brightness: u8,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+        self.connections
+            .with_client(credentials, |mqtt| async move {
+                mqtt.set_brightness(uuid, channel, brightness).await
+            })
+            .await
+    }
+
+    async fn read_power(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+        self.connections
+            .with_client(credentials, |mqtt| async move { mqtt.read_power(uuid, channel).await })
+            .await
+    }
+
+    async fn read_daily_energy(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<Vec<DailyEnergy>, ProviderError> {
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+        self.connections
+            .with_client(credentials, |mqtt| async move { mqtt.read_daily_energy(uuid, channel).await })
+            .await
+    }
+
+    async fn set_temperature(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        celsius: f64,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        info!("Meross set_temperature called for device: {} ({:.1} °C)", external_id, celsius);
+        Self::require_token(credentials)?;
+        let (uuid, channel) = target_channel(external_id, 0);
+
+        let result = self
+            .connections
+            .with_client(credentials, |mqtt| async move {
+                mqtt.set_temperature(uuid, channel, celsius).await
+            })
+            .await;
+        Self::action_result("set_temperature", result)
     }
 
     fn get_capabilities(&self) -> DeviceCapabilities {
         DeviceCapabilities {
             can_toggle: true,
             can_dim: false,
+            // Only mts thermostats, known from their model at discovery
             can_set_temperature: false,
             can_read_consumption: true, // Meross plugs can read power
+            channel_count: 1,
         }
     }
 }
@@ -591,6 +720,20 @@ mod tests {
         assert!(!caps.can_set_temperature);
     }
 
+    #[test]
+    fn test_meross_capabilities_by_model() {
+        let plug = meross_capabilities("mss310", 1);
+        assert!(plug.can_read_consumption);
+        assert!(!plug.can_dim);
+
+        let strip = meross_capabilities("mss425f", 5);
+        assert!(!strip.can_read_consumption);
+        assert_eq!(strip.channel_count, 5);
+
+        assert!(meross_capabilities("msl120", 1).can_dim);
+        assert!(meross_capabilities("mts200", 1).can_set_temperature);
+    }
+
     #[test]
     fn test_base64_encode() {
         // Test base64 encoding
@@ -725,6 +868,17 @@ mod tests {
         assert!(matches!(result.unwrap_err(), ProviderError::AuthenticationFailed(_)));
     }
 
+    #[actix_rt::test]
+    async fn test_set_temperature_missing_token() {
+        let provider = MerossProvider::new();
+        let credentials = serde_json::json!({});
+
+        let result = provider.set_temperature(&credentials, "device_123", 21.0).await;
+
+        assert!(result.is_err());
+        assert!(matches!(result.unwrap_err(), ProviderError::AuthenticationFailed(_)));
+    }
+
     #[actix_rt::test]
     async fn test_get_device_state_missing_token() {
         let provider = MerossProvider::new();
@@ -785,6 +939,90 @@ mod tests {
         assert!(!state.is_on); // Default state
     }
 
+    #[actix_rt::test]
+    async fn test_channel_actions_report_connection_failure() {
+        let provider = MerossProvider::new();
+        let credentials = serde_json::json!({
+            "token": "some_token",
+            "user_id": "test_user",
+            "key": "test_key",
+            "mqtt_domain": "invalid.domain.test"
+        });
+
+        // Like turn_on/turn_off, a failed request is an unsuccessful action
+        let result = provider.turn_on_channel(&credentials, "strip1", 2).await.unwrap();
+        assert!(!result.success);
+        let result = provider.turn_off_channel(&credentials, "strip1", 2).await.unwrap();
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_split_external_id() {
+        assert_eq!(split_external_id("abc123"), ("abc123", None));
+        assert_eq!(split_external_id("abc123:2"), ("abc123", Some(2)));
+        assert_eq!(split_external_id("abc:def"), ("abc:def", None));
+        assert_eq!(target_channel("abc123", 3), ("abc123", 3));
+        assert_eq!(target_channel("abc123:2", 0), ("abc123", 2));
+    }
+
+    #[test]
+    fn test_discover_strip_channels() {
+        let strip: MerossDeviceData = serde_json::from_value(serde_json::json!({
+            "uuid": "strip1",
+            "devName": "Desk",
+            "deviceType": "mss425f",
+            "channels": [
+                {},
+                {"channel": 1, "devName": "Monitor", "type": "Switch"},
+                {"channel": 2, "devName": "", "type": "Switch"},
+                {"channel": 5, "devName": "USB", "type": "USB"}
+            ]
+        }))
+        .unwrap();
+
+        let devices = discover_device(&strip);
+        let ids: Vec<&str> = devices.iter().map(|d| d.external_id.as_str()).collect();
+        assert_eq!(ids, vec!["strip1", "strip1:1", "strip1:2", "strip1:5"]);
+        assert_eq!(devices[0].capabilities.as_ref().unwrap().channel_count, 6);
+        assert_eq!(devices[1].name, "Desk Monitor");
+        assert_eq!(devices[2].name, "Desk 2");
+        assert_eq!(devices[3].capabilities.as_ref().unwrap().channel_count, 1);
+        assert!(devices.iter().all(|d| d.device_type == "switch"));
+    }
+
+    #[test]
+    fn test_discover_strip_with_sparse_channels() {
+        let strip: MerossDeviceData = serde_json::from_value(serde_json::json!({
+            "uuid": "strip2",
+            "devName": "Kitchen",
+            "deviceType": "mss425f",
+            "channels": [{"channel": 0}, {"channel": 1}, {"channel": 2}, {"channel": 5}]
+        }))
+        .unwrap();
+
+        let devices = discover_device(&strip);
+        let caps = devices[0].capabilities.as_ref().unwrap();
+        assert_eq!(caps.channel_count, 6);
+        assert!(caps.check_action("turn_on", 5).is_ok());
+        assert!(caps.check_action("turn_on", 6).is_err());
+    }
+
+    #[test]
+    fn test_discover_single_channel_device() {
+        let plug: MerossDeviceData = serde_json::from_value(serde_json::json!({
+            "uuid": "plug1",
+            "devName": "Heater",
+            "deviceType": "mss310",
+            "channels": [{}]
+        }))
+        .unwrap();
+
+        let devices = discover_device(&plug);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].external_id, "plug1");
+        assert_eq!(devices[0].name, "Heater");
+    }
+
     #[test]
     fn test_default_trait() {
         let provider = MerossProvider::default();
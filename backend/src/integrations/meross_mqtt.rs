@@ -4,14 +4,14 @@
 //! This module implements the Meross-specific MQTT protocol.
 
 use super::mqtt::{MqttConfig, MqttConnection, MqttError};
-use super::{DeviceActionResult, DeviceState, ProviderError};
-use log::{debug, error, info, warn};
+use super::{DailyEnergy, DeviceActionResult, DeviceState, ProviderError};
+use chrono::NaiveDate;
+use log::{debug, info};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
 
 /// Meross message header
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,11 +58,51 @@ pub struct SystemAllResponse {
     pub all: Option<SystemAll>,
 }
 
+impl SystemAllResponse {
+    /// Device model, e.g. "mss310"
+    pub fn device_type(&self) -> Option<&str> {
+        self.all
+            .as_ref()?
+            .system
+            .as_ref()?
+            .hardware
+            .as_ref()?
+            .device_type
+            .as_deref()
+    }
+
+    /// Whether `channel` is on. Devices without per-channel state (`toggle`)
+    /// report a single state for every channel.
+    pub fn is_on(&self, channel: i32) -> bool {
+        let Some(digest) = self.all.as_ref().and_then(|a| a.digest.as_ref()) else {
+            return false;
+        };
+        if let Some(togglex) = &digest.togglex {
+            togglex.iter().any(|t| t.channel == channel && t.onoff == 1)
+        } else {
+            digest.toggle.as_ref().is_some_and(|t| t.onoff == 1)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct SystemAll {
+    pub system: Option<SystemInfo>,
     pub digest: Option<Digest>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct SystemInfo {
+    pub hardware: Option<Hardware>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hardware {
+    /// Model, e.g. "mss310"
+    #[serde(rename = "type")]
+    pub device_type: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Digest {
     pub togglex: Option<Vec<ToggleXState>>,
@@ -80,12 +120,15 @@ pub struct ToggleState {
     pub onoff: i32,
 }
 
-/// Meross MQTT client for controlling devices
+/// Meross MQTT client for controlling devices.
+/// Requests on one connection may run concurrently; responses are matched
+/// to requests by message id.
 pub struct MerossMqttClient {
-    connection: Option<Arc<Mutex<MqttConnection>>>,
+    connection: Option<Arc<MqttConnection>>,
     user_id: String,
     key: String,
     mqtt_domain: String,
+    use_tls: bool,
     /// App ID must be consistent between subscription topic and message headers
     app_id: String,
 }
@@ -98,6 +141,7 @@ impl MerossMqttClient {
             user_id,
             key,
             mqtt_domain,
+            use_tls: true,
             app_id: Self::generate_app_id(), // Generate once and reuse
         }
     }
@@ -125,11 +169,17 @@ impl MerossMqttClient {
             .and_then(|v| v.as_str())
             .unwrap_or("mqtt-eu-5.meross.com");
 
-        Ok(Self::new(
+        let mut client = Self::new(
             user_id.to_string(),
             key.to_string(),
             mqtt_domain.to_string(),
-        ))
+        );
+        // Only brokers other than the Meross cloud (e.g. a local one) go without TLS
+        client.use_tls = credentials
+            .get("use_tls")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        Ok(client)
     }
 
     /// Generate a unique message ID
@@ -206,7 +256,7 @@ impl MerossMqttClient {
         // Generate MQTT password: MD5(user_id + key)
         let mqtt_password = Self::generate_mqtt_password(&self.user_id, &self.key);
 
-        info!("Meross MQTT connecting to {}:{} (TLS TCP)", host, port);
+        info!("Meross MQTT connecting to {}:{} (TLS: {})", host, port, self.use_tls);
         debug!("Meross MQTT auth: user_id={}, password_hash={}", self.user_id, &mqtt_password[..8]);
 
         let config = MqttConfig {
@@ -215,7 +265,7 @@ impl MerossMqttClient {
             client_id,
             username: Some(self.user_id.clone()),
             password: Some(mqtt_password),
-            use_tls: true,
+            use_tls: self.use_tls,
             use_websocket: false, // Meross uses standard MQTT over TLS TCP, NOT WebSocket
             keep_alive_secs: 30,
         };
@@ -231,7 +281,7 @@ impl MerossMqttClient {
             .map_err(|e| ProviderError::ConnectionError(e.to_string()))?;
 
         info!("Meross MQTT connected and subscribed to {}", client_topic);
-        self.connection = Some(Arc::new(Mutex::new(connection)));
+        self.connection = Some(Arc::new(connection));
 
         Ok(())
     }
@@ -281,11 +331,13 @@ impl MerossMqttClient {
 
         debug!("Sending MQTT command to {}: {:?}", topic, message);
 
-        let conn = connection.lock().await;
-        let response = conn
+        let response = connection
             .request(&topic, &json, response_filter, 10)
             .await
-            .map_err(|e| ProviderError::ConnectionError(e.to_string()))?;
+            .map_err(|e| match e {
+                MqttError::Timeout => ProviderError::Timeout,
+                e => ProviderError::ConnectionError(e.to_string()),
+            })?;
 
         let response_msg: MerossMessage = response
             .parse_json()
@@ -303,29 +355,19 @@ impl MerossMqttClient {
             }
         });
 
-        match self
-            .send_command(device_uuid, "Appliance.Control.ToggleX", "SET", payload)
-            .await
-        {
-            Ok(_) => Ok(DeviceActionResult {
-                success: true,
-                message: Some("Device turned on".to_string()),
-                new_state: Some(DeviceState {
-                    is_on: true,
-                    brightness: None,
-                    temperature: None,
-                    power_consumption_watts: None,
-                }),
+        self.send_command(device_uuid, "Appliance.Control.ToggleX", "SET", payload)
+            .await?;
+
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some("Device turned on".to_string()),
+            new_state: Some(DeviceState {
+                is_on: true,
+                brightness: None,
+                temperature: None,
+                power_consumption_watts: None,
             }),
-            Err(e) => {
-                error!("Failed to turn on device {}: {}", device_uuid, e);
-                Ok(DeviceActionResult {
-                    success: false,
-                    message: Some(e.to_string()),
-                    new_state: None,
-                })
-            }
-        }
+        })
     }
 
     /// Turn device off
@@ -337,33 +379,114 @@ impl MerossMqttClient {
             }
         });
 
-        match self
-            .send_command(device_uuid, "Appliance.Control.ToggleX", "SET", payload)
-            .await
-        {
-            Ok(_) => Ok(DeviceActionResult {
-                success: true,
-                message: Some("Device turned off".to_string()),
-                new_state: Some(DeviceState {
-                    is_on: false,
-                    brightness: None,
-                    temperature: None,
-                    power_consumption_watts: None,
-                }),
+        self.send_command(device_uuid, "Appliance.Control.ToggleX", "SET", payload)
+            .await?;
+
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some("Device turned off".to_string()),
+            new_state: Some(DeviceState {
+                is_on: false,
+                brightness: None,
+                temperature: None,
+                power_consumption_watts: None,
             }),
-            Err(e) => {
-                error!("Failed to turn off device {}: {}", device_uuid, e);
-                Ok(DeviceActionResult {
-                    success: false,
-                    message: Some(e.to_string()),
-                    new_state: None,
-                })
-            }
+        })
+    }
+
+    /// Turn one channel of a device on or off
+    pub async fn set_channel(
+        &self,
+        device_uuid: &str,
+        channel: i32,
+        on: bool,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        if on {
+            self.turn_on(device_uuid, channel).await
+        } else {
+            self.turn_off(device_uuid, channel).await
         }
     }
 
-    /// Get device state
-    pub async fn get_state(&self, device_uuid: &str) -> Result<DeviceState, ProviderError> {
+    /// Set a light (msl) channel's brightness, in percent
+    pub async fn set_brightness(
+        &self,
+        device_uuid: &str,
+        channel: i32,
+        brightness: u8,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let payload = serde_json::json!({
+            "light": {
+                "channel": channel,
+                "luminance": brightness.min(100),
+                "capacity": LIGHT_CAPACITY_LUMINANCE
+            }
+        });
+
+        self.send_command(device_uuid, "Appliance.Control.Light", "SET", payload)
+            .await?;
+
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Brightness set to {}%", brightness)),
+            new_state: Some(DeviceState {
+                is_on: brightness > 0,
+                brightness: Some(brightness),
+                temperature: None,
+                power_consumption_watts: None,
+            }),
+        })
+    }
+
+    /// Read the power a metering plug (e.g. mss310) is drawing, in watts
+    pub async fn read_power(&self, device_uuid: &str, channel: i32) -> Result<f64, ProviderError> {
+        let payload = serde_json::json!({"electricity": {"channel": channel}});
+
+        let response = self
+            .send_command(device_uuid, "Appliance.Control.Electricity", "GET", payload)
+            .await?;
+
+        electricity_power_w(&response)
+    }
+
+    /// Energy a metering plug recorded per day (`Appliance.Control.ConsumptionX`),
+    /// usually the last 30 days
+    pub async fn read_daily_energy(&self, device_uuid: &str, channel: i32) -> Result<Vec<DailyEnergy>, ProviderError> {
+        let payload = serde_json::json!({"channel": channel});
+
+        let response = self
+            .send_command(device_uuid, "Appliance.Control.ConsumptionX", "GET", payload)
+            .await?;
+
+        consumption_daily_kwh(&response)
+    }
+
+    /// Set a thermostat (mts) to manual mode at `celsius`
+    pub async fn set_temperature(
+        &self,
+        device_uuid: &str,
+        channel: i32,
+        celsius: f64,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let payload = thermostat_mode_payload(channel, celsius);
+
+        self.send_command(device_uuid, "Appliance.Control.Thermostat.Mode", "SET", payload)
+            .await?;
+
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Setpoint set to {:.1} °C", celsius)),
+            new_state: Some(DeviceState {
+                is_on: true,
+                brightness: None,
+                temperature: None,
+                power_consumption_watts: None,
+            }),
+        })
+    }
+
+    /// Get the state of one channel of a device
+    pub async fn get_state(&self, device_uuid: &str, channel: i32) -> Result<DeviceState, ProviderError> {
         let payload = serde_json::json!({});
 
         let response = self
@@ -374,35 +497,40 @@ impl MerossMqttClient {
         let system_all: SystemAllResponse = serde_json::from_value(response)
             .map_err(|e| ProviderError::Unknown(format!("Failed to parse state: {}", e)))?;
 
-        let is_on = if let Some(all) = system_all.all {
-            if let Some(digest) = all.digest {
-                if let Some(togglex) = digest.togglex {
-                    togglex.first().map(|t| t.onoff == 1).unwrap_or(false)
-                } else if let Some(toggle) = digest.toggle {
-                    toggle.onoff == 1
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
+        // Only the mss3xx plugs meter electricity; a failed reading still
+        // returns the on/off state
+        let power_consumption_watts = if system_all.device_type().is_some_and(|t| t.starts_with("mss3")) {
+            self.read_power(device_uuid, channel).await.ok().map(|w| w as f32)
         } else {
-            false
+            None
         };
 
         Ok(DeviceState {
-            is_on,
+            is_on: system_all.is_on(channel),
             brightness: None,
             temperature: None,
-            power_consumption_watts: None,
+            power_consumption_watts,
         })
     }
 
+    /// Whether the client is connected and the connection is still up
+    pub async fn is_connected(&self) -> bool {
+        match &self.connection {
+            Some(connection) => connection.is_connected().await,
+            None => false,
+        }
+    }
+
+    /// Identifies the broker account, so clients can be shared per integration
+    pub fn pool_key(&self) -> String {
+        format!("{}|{}|{}|{}", self.user_id, self.key, self.mqtt_domain, self.use_tls)
+    }
+
     /// Disconnect from MQTT
     pub async fn disconnect(&self) -> Result<(), ProviderError> {
         if let Some(connection) = &self.connection {
-            let conn = connection.lock().await;
-            conn.disconnect()
+            connection
+                .disconnect()
                 .await
                 .map_err(|e| ProviderError::ConnectionError(e.to_string()))?;
         }
@@ -410,6 +538,58 @@ impl MerossMqttClient {
     }
 }
 
+/// Meross light "capacity" flag for brightness changes
+const LIGHT_CAPACITY_LUMINANCE: i32 = 4;
+
+/// Power from an `Appliance.Control.Electricity` payload (reported in mW)
+fn electricity_power_w(payload: &Value) -> Result<f64, ProviderError> {
+    payload
+        .get("electricity")
+        .and_then(|e| e.get("power"))
+        .and_then(|p| p.as_f64())
+        .map(|milliwatts| milliwatts / 1000.0)
+        .ok_or_else(|| ProviderError::Unknown("No power in electricity response".to_string()))
+}
+
+/// Daily energy from an `Appliance.Control.ConsumptionX` payload (reported
+/// in Wh per date), oldest first
+fn consumption_daily_kwh(payload: &Value) -> Result<Vec<DailyEnergy>, ProviderError> {
+    let entries = payload
+        .get("consumptionx")
+        .and_then(|c| c.as_array())
+        .ok_or_else(|| ProviderError::Unknown("No consumptionx in response".to_string()))?;
+
+    let mut days: Vec<DailyEnergy> = entries
+        .iter()
+        .filter_map(|entry| {
+            let date = NaiveDate::parse_from_str(entry.get("date")?.as_str()?, "%Y-%m-%d").ok()?;
+            let watt_hours = entry.get("value")?.as_f64()?;
+            Some(DailyEnergy {
+                date,
+                energy_kwh: watt_hours / 1000.0,
+            })
+        })
+        .collect();
+    days.sort_by_key(|d| d.date);
+    Ok(days)
+}
+
+/// Meross thermostat mode used for a fixed setpoint
+const THERMOSTAT_MODE_MANUAL: i32 = 4;
+
+/// Payload switching a thermostat channel to manual mode at `celsius`.
+/// Meross temperatures are in tenths of a degree.
+fn thermostat_mode_payload(channel: i32, celsius: f64) -> Value {
+    serde_json::json!({
+        "mode": [{
+            "channel": channel,
+            "onoff": 1,
+            "mode": THERMOSTAT_MODE_MANUAL,
+            "manualTemp": (celsius * 10.0).round() as i32
+        }]
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -492,6 +672,25 @@ mod tests {
         assert_eq!(client.mqtt_domain, "eu-iotx.meross.com");
     }
 
+    #[test]
+    fn test_from_credentials_use_tls() {
+        let credentials = serde_json::json!({
+            "user_id": "user123",
+            "key": "secret_key"
+        });
+        assert!(MerossMqttClient::from_credentials(&credentials).unwrap().use_tls);
+
+        let credentials = serde_json::json!({
+            "user_id": "user123",
+            "key": "secret_key",
+            "mqtt_domain": "127.0.0.1:1883",
+            "use_tls": false
+        });
+        let client = MerossMqttClient::from_credentials(&credentials).unwrap();
+        assert!(!client.use_tls);
+        assert_eq!(client.mqtt_domain, "127.0.0.1:1883");
+    }
+
     #[test]
     fn test_meross_message_serialization() {
         let msg = MerossMessage {
@@ -512,4 +711,70 @@ mod tests {
         assert!(json.contains("Appliance.Control.ToggleX"));
         assert!(json.contains("togglex"));
     }
+
+    #[test]
+    fn test_thermostat_mode_payload() {
+        let payload = thermostat_mode_payload(0, 21.5);
+        assert_eq!(payload["mode"][0]["manualTemp"], 215);
+        assert_eq!(payload["mode"][0]["mode"], THERMOSTAT_MODE_MANUAL);
+        assert_eq!(payload["mode"][0]["onoff"], 1);
+    }
+
+    #[test]
+    fn test_system_all_is_on_per_channel() {
+        let response: SystemAllResponse = serde_json::from_value(serde_json::json!({
+            "all": {"digest": {"togglex": [
+                {"channel": 0, "onoff": 0},
+                {"channel": 1, "onoff": 1},
+                {"channel": 2, "onoff": 0}
+            ]}}
+        }))
+        .unwrap();
+        assert!(!response.is_on(0));
+        assert!(response.is_on(1));
+        assert!(!response.is_on(2));
+        assert!(!response.is_on(5));
+
+        let single: SystemAllResponse =
+            serde_json::from_value(serde_json::json!({"all": {"digest": {"toggle": {"onoff": 1}}}})).unwrap();
+        assert!(single.is_on(0));
+    }
+
+    #[test]
+    fn test_system_all_device_type() {
+        let response: SystemAllResponse = serde_json::from_value(serde_json::json!({
+            "all": {"system": {"hardware": {"type": "mss310", "version": "6.0.0"}}, "digest": {}}
+        }))
+        .unwrap();
+        assert_eq!(response.device_type(), Some("mss310"));
+
+        let response: SystemAllResponse = serde_json::from_value(serde_json::json!({"all": {}})).unwrap();
+        assert_eq!(response.device_type(), None);
+    }
+
+    #[test]
+    fn test_consumption_daily_kwh() {
+        let payload = serde_json::json!({"consumptionx": [
+            {"date": "2026-10-17", "time": 1792274399, "value": 1250},
+            {"date": "2026-10-16", "time": 1792187999, "value": 800},
+            {"date": "bad", "value": 1}
+        ]});
+
+        let days = consumption_daily_kwh(&payload).unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, NaiveDate::from_ymd_opt(2026, 10, 16).unwrap());
+        assert!((days[0].energy_kwh - 0.8).abs() < 1e-9);
+        assert!((days[1].energy_kwh - 1.25).abs() < 1e-9);
+
+        assert!(consumption_daily_kwh(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_electricity_power_w() {
+        let payload = serde_json::json!({
+            "electricity": {"channel": 0, "current": 4210, "voltage": 2302, "power": 965250}
+        });
+        assert!((electricity_power_w(&payload).unwrap() - 965.25).abs() < 1e-9);
+        assert!(electricity_power_w(&serde_json::json!({})).is_err());
+    }
 }
@@ -0,0 +1,276 @@
+//! Long-lived Meross MQTT connections
+//!
+//! Connecting to the Meross broker (TLS handshake, CONNECT, SUBSCRIBE) costs
+//! far more than a command, and the broker throttles accounts that connect
+//! too often. The pool keeps one connection per integration (Meross account
+//! and key) and shares it between all operations. Keep-alive pings are sent
+//! by the MQTT event loop; a dropped connection is replaced on next use and
+//! connections unused for a while are closed.
+
+use super::meross_mqtt::MerossMqttClient;
+use super::ProviderError;
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Close connections not used for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Connection of one integration. Connecting happens under the slot's lock,
+/// so concurrent operations on one integration connect only once.
+#[derive(Default)]
+struct Slot {
+    client: Option<Arc<MerossMqttClient>>,
+    last_used: Option<Instant>,
+}
+
+pub struct MerossConnectionPool {
+    slots: Mutex<HashMap<String, Arc<Mutex<Slot>>>>,
+    idle_timeout: Duration,
+}
+
+impl Default for MerossConnectionPool {
+    fn default() -> Self {
+        Self::new(IDLE_TIMEOUT)
+    }
+}
+
+impl MerossConnectionPool {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            slots: Mutex::new(HashMap::new()),
+            idle_timeout,
+        }
+    }
+
+    /// Connected client for the session credentials, reusing the pooled
+    /// connection while it's up
+    pub async fn client(&self, credentials: &Value) -> Result<Arc<MerossMqttClient>, ProviderError> {
+        let mut client = MerossMqttClient::from_credentials(credentials)?;
+        let slot = self.slot(&client.pool_key()).await;
+        let mut slot = slot.lock().await;
+
+        if let Some(pooled) = slot.client.clone()
+            && pooled.is_connected().await
+        {
+            slot.last_used = Some(Instant::now());
+            return Ok(pooled);
+        }
+        if slot.client.take().is_some() {
+            info!("Meross MQTT connection dropped, reconnecting");
+        }
+
+        client.connect().await?;
+        let client = Arc::new(client);
+        slot.client = Some(client.clone());
+        slot.last_used = Some(Instant::now());
+        Ok(client)
+    }
+
+    /// Run `op` with the pooled client. If it fails because the connection
+    /// went down, reconnect and try once more.
+    pub async fn with_client<T, F, Fut>(&self, credentials: &Value, op: F) -> Result<T, ProviderError>
+    where
+        F: Fn(Arc<MerossMqttClient>) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let client = self.client(credentials).await?;
+        match op(client.clone()).await {
+            Err(ProviderError::ConnectionError(e)) if !client.is_connected().await => {
+                warn!("Meross MQTT request failed on a dropped connection ({}), retrying", e);
+                op(self.client(credentials).await?).await
+            }
+            result => result,
+        }
+    }
+
+    /// Slot for `key`, closing idle connections of other integrations
+    async fn slot(&self, key: &str) -> Arc<Mutex<Slot>> {
+        let mut slots = self.slots.lock().await;
+        let now = Instant::now();
+        let mut idle = Vec::new();
+        slots.retain(|k, slot| {
+            if k == key {
+                return true;
+            }
+            // Slots in use are locked; leave them alone
+            let Ok(mut slot) = slot.try_lock() else { return true };
+            if slot.last_used.is_some_and(|used| now.duration_since(used) < self.idle_timeout) {
+                return true;
+            }
+            idle.extend(slot.client.take());
+            false
+        });
+        let slot = slots.entry(key.to_string()).or_default().clone();
+        drop(slots);
+
+        for client in idle {
+            info!("Closing idle Meross MQTT connection");
+            let _ = client.disconnect().await;
+        }
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrations::meross_mqtt::MerossMessage;
+    use crate::integrations::test_broker::TestBroker;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn open_connections(pool: &MerossConnectionPool) -> usize {
+        let slots = pool.slots.lock().await;
+        let mut open = 0;
+        for slot in slots.values() {
+            if slot.lock().await.client.is_some() {
+                open += 1;
+            }
+        }
+        open
+    }
+
+    fn credentials(user_id: &str) -> Value {
+        json!({"token": "t", "user_id": user_id, "key": "k", "mqtt_domain": "127.0.0.1:1"})
+    }
+
+    fn broker_credentials(broker: &TestBroker) -> Value {
+        let address = broker.url.trim_start_matches("mqtt://");
+        json!({"token": "t", "user_id": "u1", "key": "k", "mqtt_domain": address, "use_tls": false})
+    }
+
+    /// Act as the devices: answer each batch of `batch` commands, last one
+    /// first, on the topic the command came from
+    fn answer_commands(broker: &TestBroker, batch: usize) {
+        let broker = broker.clone();
+        tokio::spawn(async move {
+            let mut answered = 0;
+            loop {
+                let published = broker.published();
+                if published.len() < answered + batch {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    continue;
+                }
+                for (_, payload) in published[answered..answered + batch].iter().rev() {
+                    let mut message: MerossMessage = serde_json::from_str(payload).unwrap();
+                    message.header.method = format!("{}ACK", message.header.method);
+                    message.payload = json!({});
+                    broker.publish(&message.header.from, &serde_json::to_string(&message).unwrap(), false);
+                }
+                answered += batch;
+            }
+        });
+    }
+
+    async fn wait_until_dropped(client: &MerossMqttClient) {
+        for _ in 0..100 {
+            if !client.is_connected().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("connection still up");
+    }
+
+    #[actix_rt::test]
+    async fn test_connection_is_reused() {
+        let broker = TestBroker::start(None).await;
+        let pool = MerossConnectionPool::default();
+        let first = pool.client(&broker_credentials(&broker)).await.unwrap();
+        let second = pool.client(&broker_credentials(&broker)).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(broker.client_count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_dropped_connection_is_replaced() {
+        let broker = TestBroker::start(None).await;
+        answer_commands(&broker, 1);
+        let pool = MerossConnectionPool::default();
+        let first = pool.client(&broker_credentials(&broker)).await.unwrap();
+        broker.drop_clients();
+        wait_until_dropped(&first).await;
+
+        let second = pool.client(&broker_credentials(&broker)).await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(second.turn_on("dev", 0).await.is_ok());
+        assert_eq!(broker.client_count(), 1);
+        assert_eq!(open_connections(&pool).await, 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_with_client_retries_after_disconnect() {
+        let broker = TestBroker::start(None).await;
+        answer_commands(&broker, 1);
+        let pool = MerossConnectionPool::default();
+        let attempts = AtomicUsize::new(0);
+        let result = pool
+            .with_client(&broker_credentials(&broker), |client| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                let broker = broker.clone();
+                async move {
+                    if attempt == 0 {
+                        broker.drop_clients();
+                        wait_until_dropped(&client).await;
+                    }
+                    client.turn_on("dev", 0).await
+                }
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        // Only the retry reached the broker
+        assert_eq!(broker.published().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_concurrent_requests_get_their_own_responses() {
+        let broker = TestBroker::start(None).await;
+        // Answer in reverse order, so responses arrive out of order
+        answer_commands(&broker, 4);
+        let pool = MerossConnectionPool::default();
+        let client = pool.client(&broker_credentials(&broker)).await.unwrap();
+        let (a, b, c, d) = tokio::join!(
+            client.turn_on("d0", 0),
+            client.turn_off("d1", 0),
+            client.turn_on("d2", 1),
+            client.turn_off("d3", 1),
+        );
+        assert!(a.unwrap().new_state.unwrap().is_on);
+        assert!(!b.unwrap().new_state.unwrap().is_on);
+        assert!(c.unwrap().new_state.unwrap().is_on);
+        assert!(!d.unwrap().new_state.unwrap().is_on);
+        assert_eq!(broker.published().len(), 4);
+        assert_eq!(broker.client_count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_missing_credentials_fail_before_connecting() {
+        let pool = MerossConnectionPool::default();
+        let result = pool.client(&json!({"token": "t"})).await;
+        assert!(matches!(result, Err(ProviderError::AuthenticationFailed(_))));
+        assert_eq!(open_connections(&pool).await, 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_connection_is_not_pooled() {
+        let pool = MerossConnectionPool::default();
+        let result = pool.client(&credentials("u1")).await;
+        assert!(matches!(result, Err(ProviderError::ConnectionError(_))));
+        assert_eq!(open_connections(&pool).await, 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_idle_slots_are_evicted() {
+        let pool = MerossConnectionPool::new(Duration::from_millis(0));
+        let _ = pool.client(&credentials("u1")).await;
+        let _ = pool.client(&credentials("u2")).await;
+        // u1's slot never connected, so it's dropped when u2 is looked up
+        assert_eq!(pool.slots.lock().await.len(), 1);
+    }
+}
@@ -4,12 +4,25 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+pub mod generic_mqtt;
+pub mod home_assistant;
 pub mod meross;
 pub mod meross_mqtt;
+pub mod meross_pool;
 pub mod mqtt;
+pub mod shelly;
+#[cfg(test)]
+mod test_broker;
+pub mod tuya;
+pub mod webhook;
 
 // Re-export providers
+pub use generic_mqtt::GenericMqttProvider;
+pub use home_assistant::HomeAssistantProvider;
 pub use meross::MerossProvider;
+pub use shelly::ShellyProvider;
+pub use tuya::TuyaProvider;
+pub use webhook::WebhookProvider;
 
 /// Device discovered from a provider
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,15 +30,25 @@ pub struct DiscoveredDevice {
     pub external_id: String,
     pub name: String,
     pub device_type: String,
+    /// What this device supports; None falls back to the provider's capabilities
+    #[serde(default)]
+    pub capabilities: Option<DeviceCapabilities>,
 }
 
 /// Device capabilities
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DeviceCapabilities {
     pub can_toggle: bool,
     pub can_dim: bool,
     pub can_set_temperature: bool,
     pub can_read_consumption: bool,
+    /// Independently switchable channels (outlets of a power strip)
+    #[serde(default = "default_channel_count")]
+    pub channel_count: u32,
+}
+
+fn default_channel_count() -> u32 {
+    1
 }
 
 impl Default for DeviceCapabilities {
@@ -35,10 +58,36 @@ impl Default for DeviceCapabilities {
             can_dim: false,
             can_set_temperature: false,
             can_read_consumption: false,
+            channel_count: 1,
         }
     }
 }
 
+impl DeviceCapabilities {
+    /// Check that a device with these capabilities supports `action`
+    /// ("turn_on", "turn_off", "set_brightness", "set_temperature" or
+    /// "read_power") on `channel`
+    pub fn check_action(&self, action: &str, channel: u32) -> Result<(), String> {
+        let supported = match action {
+            "turn_on" | "turn_off" => self.can_toggle,
+            "set_brightness" => self.can_dim,
+            "set_temperature" => self.can_set_temperature,
+            "read_power" | "read_energy" => self.can_read_consumption,
+            _ => return Err(format!("Unknown action '{}'", action)),
+        };
+        if !supported {
+            return Err(format!("Device doesn't support '{}'", action));
+        }
+        if channel >= self.channel_count {
+            return Err(format!(
+                "Channel {} out of range (device has {})",
+                channel, self.channel_count
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Result of a device action
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceActionResult {
@@ -56,6 +105,25 @@ pub struct DeviceState {
     pub power_consumption_watts: Option<f32>,
 }
 
+/// Energy a device metered on one day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyEnergy {
+    pub date: chrono::NaiveDate,
+    pub energy_kwh: f64,
+}
+
+/// Operating mode requested from a home battery
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum BatteryCommand {
+    /// Charge from the grid at up to `power_w`
+    Charge { power_w: f64 },
+    /// Discharge into the home at up to `power_w`
+    Discharge { power_w: f64 },
+    /// Neither charge from nor discharge to the grid
+    Idle,
+}
+
 /// Error types for provider operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProviderError {
@@ -65,6 +133,8 @@ pub enum ProviderError {
     RateLimited,
     InvalidCredentials,
     Timeout,
+    /// The provider or device can't perform this operation
+    Unsupported(String),
     Unknown(String),
 }
 
@@ -77,6 +147,7 @@ impl std::fmt::Display for ProviderError {
             ProviderError::RateLimited => write!(f, "Rate limited by provider"),
             ProviderError::InvalidCredentials => write!(f, "Invalid credentials"),
             ProviderError::Timeout => write!(f, "Request timeout"),
+            ProviderError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
             ProviderError::Unknown(msg) => write!(f, "Unknown error: {}", msg),
         }
     }
@@ -120,11 +191,119 @@ pub trait SmartHomeProvider: Send + Sync {
         external_id: &str,
     ) -> Result<DeviceActionResult, ProviderError>;
 
-    /// Get device capabilities
+    /// Turns on one channel of a multi-channel device
+    async fn turn_on_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        if channel == 0 {
+            return self.turn_on(credentials, external_id).await;
+        }
+        Err(ProviderError::Unsupported(format!(
+            "{} can't control channels",
+            self.display_name()
+        )))
+    }
+
+    /// Turns off one channel of a multi-channel device
+    async fn turn_off_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        if channel == 0 {
+            return self.turn_off(credentials, external_id).await;
+        }
+        Err(ProviderError::Unsupported(format!(
+            "{} can't control channels",
+            self.display_name()
+        )))
+    }
+
+    /// Sets a light's brightness, in percent
+    async fn set_brightness(
+        &self,
+        _credentials: &Value,
+        _external_id: &str,
+        _brightness: u8,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't dim devices",
+            self.display_name()
+        )))
+    }
+
+    /// Reads the power a device is drawing, in watts
+    async fn read_power(&self, _credentials: &Value, _external_id: &str) -> Result<f64, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't read power",
+            self.display_name()
+        )))
+    }
+
+    /// Reads the energy a device has metered since it was installed, in kWh
+    async fn read_energy(&self, _credentials: &Value, _external_id: &str) -> Result<f64, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't read energy",
+            self.display_name()
+        )))
+    }
+
+    /// Reads the energy a device metered per day, for the days it keeps
+    async fn read_daily_energy(
+        &self,
+        _credentials: &Value,
+        _external_id: &str,
+    ) -> Result<Vec<DailyEnergy>, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't read energy history",
+            self.display_name()
+        )))
+    }
+
+    /// Default capabilities of this provider's devices, used when discovery
+    /// doesn't report them per device
     fn get_capabilities(&self) -> DeviceCapabilities {
         DeviceCapabilities::default()
     }
 
+    /// Sets a thermostat's target temperature, in °C
+    async fn set_temperature(
+        &self,
+        _credentials: &Value,
+        _external_id: &str,
+        _celsius: f64,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't set temperatures",
+            self.display_name()
+        )))
+    }
+
+    /// Sets the operating mode of a home battery (or its inverter)
+    async fn set_battery_mode(
+        &self,
+        _credentials: &Value,
+        _external_id: &str,
+        _command: BatteryCommand,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't control batteries",
+            self.display_name()
+        )))
+    }
+
+    /// Reads a home battery's state of charge, in percent
+    async fn read_battery_soc(&self, _credentials: &Value, _external_id: &str) -> Result<f64, ProviderError> {
+        Err(ProviderError::Unsupported(format!(
+            "{} can't read batteries",
+            self.display_name()
+        )))
+    }
+
     /// Refresh credentials (re-login to get new token)
     /// Returns updated credentials with new token if successful
     async fn refresh_credentials(&self, credentials: &Value) -> Result<Value, ProviderError> {
@@ -148,8 +327,11 @@ impl ProviderRegistry {
 
         // Register all available providers
         registry.register(Arc::new(MerossProvider::new()));
-        // Future: registry.register(Arc::new(TuyaProvider::new()));
-        // Future: registry.register(Arc::new(ShellyProvider::new()));
+        registry.register(Arc::new(HomeAssistantProvider::new()));
+        registry.register(Arc::new(GenericMqttProvider::new()));
+        registry.register(Arc::new(WebhookProvider::new()));
+        registry.register(Arc::new(TuyaProvider::new()));
+        registry.register(Arc::new(ShellyProvider::new()));
 
         registry
     }
@@ -174,6 +356,15 @@ impl ProviderRegistry {
     pub fn has_provider(&self, name: &str) -> bool {
         self.providers.contains_key(name)
     }
+
+    /// Capabilities of a stored device: those discovered for it, or else its
+    /// provider's defaults
+    pub fn device_capabilities(&self, provider_name: &str, stored: Option<&Value>) -> DeviceCapabilities {
+        stored
+            .and_then(|caps| serde_json::from_value(caps.clone()).ok())
+            .or_else(|| self.get(provider_name).map(|p| p.get_capabilities()))
+            .unwrap_or_default()
+    }
 }
 
 impl Default for ProviderRegistry {
@@ -220,6 +411,52 @@ mod tests {
         assert!(!caps.can_dim);
     }
 
+    #[test]
+    fn test_registry_device_capabilities_prefers_stored() {
+        let registry = ProviderRegistry::new();
+        let stored = serde_json::json!({
+            "can_toggle": true, "can_dim": true, "can_set_temperature": false,
+            "can_read_consumption": false, "channel_count": 4
+        });
+
+        let caps = registry.device_capabilities("meross", Some(&stored));
+        assert!(caps.can_dim);
+        assert_eq!(caps.channel_count, 4);
+
+        let defaults = registry.device_capabilities("meross", None);
+        assert_eq!(defaults, registry.get("meross").unwrap().get_capabilities());
+        assert_eq!(registry.device_capabilities("unknown", None), DeviceCapabilities::default());
+    }
+
+    #[test]
+    fn test_device_capabilities_check_action() {
+        let caps = DeviceCapabilities {
+            can_set_temperature: true,
+            channel_count: 2,
+            ..Default::default()
+        };
+        assert!(caps.check_action("turn_on", 1).is_ok());
+        assert!(caps.check_action("set_temperature", 0).is_ok());
+        assert!(caps.check_action("turn_off", 2).is_err());
+        assert!(caps.check_action("set_brightness", 0).is_err());
+        assert!(caps.check_action("explode", 0).is_err());
+
+        // Capabilities reported without channels have a single one
+        let stored: DeviceCapabilities = serde_json::from_value(serde_json::json!({
+            "can_toggle": true, "can_dim": false, "can_set_temperature": false, "can_read_consumption": false
+        }))
+        .unwrap();
+        assert_eq!(stored.channel_count, 1);
+    }
+
+    #[test]
+    fn test_battery_command_serialization() {
+        let json = serde_json::to_value(BatteryCommand::Charge { power_w: 2500.0 }).unwrap();
+        assert_eq!(json, serde_json::json!({"mode": "charge", "power_w": 2500.0}));
+        let idle: BatteryCommand = serde_json::from_str(r#"{"mode": "idle"}"#).unwrap();
+        assert_eq!(idle, BatteryCommand::Idle);
+    }
+
     #[test]
     fn test_provider_error_display() {
         let err = ProviderError::AuthenticationFailed("bad token".to_string());
@@ -25,6 +25,44 @@ pub struct MqttConfig {
     pub keep_alive_secs: u64,
 }
 
+impl MqttConfig {
+    /// Config for a broker URL: `mqtt://` or `tcp://` (1883), `mqtts://` or
+    /// `ssl://` (8883), `ws://` (80) or `wss://` (443)
+    pub fn from_url(url: &str, username: Option<String>, password: Option<String>) -> Result<Self, MqttError> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| MqttError::ConnectionFailed(format!("Invalid broker URL: {}", url)))?;
+        let (use_tls, use_websocket, default_port) = match scheme {
+            "mqtt" | "tcp" => (false, false, 1883),
+            "mqtts" | "ssl" => (true, false, 8883),
+            "ws" => (false, true, 80),
+            "wss" => (true, true, 443),
+            _ => return Err(MqttError::ConnectionFailed(format!("Unsupported scheme: {}", scheme))),
+        };
+        let authority = rest.split('/').next().unwrap_or_default();
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| MqttError::ConnectionFailed(format!("Invalid port: {}", port)))?,
+            ),
+            None => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err(MqttError::ConnectionFailed(format!("Invalid broker URL: {}", url)));
+        }
+        Ok(Self {
+            broker_host: host.to_string(),
+            broker_port: port,
+            username,
+            password,
+            use_tls,
+            use_websocket,
+            ..Default::default()
+        })
+    }
+}
+
 impl Default for MqttConfig {
     fn default() -> Self {
         Self {
@@ -96,6 +134,8 @@ pub struct MqttConnection {
     client: AsyncClient,
     pending_requests: Arc<Mutex<Vec<PendingRequest>>>,
     connected: Arc<Mutex<bool>>,
+    /// Every message received, for subscribers that aren't requests
+    incoming: broadcast::Sender<MqttMessage>,
     _event_handle: tokio::task::JoinHandle<()>,
 }
 
@@ -195,6 +235,7 @@ impl MqttConnection {
 
         let pending_requests: Arc<Mutex<Vec<PendingRequest>>> = Arc::new(Mutex::new(Vec::new()));
         let connected: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
+        let (incoming, _) = broadcast::channel::<MqttMessage>(256);
 
         // Create channel to signal when connection is established
         let (conn_tx, mut conn_rx) = broadcast::channel::<Result<(), String>>(1);
@@ -202,8 +243,9 @@ impl MqttConnection {
         // Spawn event loop handler
         let pending_clone = pending_requests.clone();
         let connected_clone = connected.clone();
+        let incoming_clone = incoming.clone();
         let event_handle = tokio::spawn(async move {
-            Self::run_event_loop(eventloop, pending_clone, connected_clone, Some(conn_tx)).await;
+            Self::run_event_loop(eventloop, pending_clone, connected_clone, incoming_clone, Some(conn_tx)).await;
         });
 
         // Wait for connection to be established (with timeout)
@@ -232,6 +274,7 @@ impl MqttConnection {
             client,
             pending_requests,
             connected,
+            incoming,
             _event_handle: event_handle,
         })
     }
@@ -241,6 +284,7 @@ impl MqttConnection {
         mut eventloop: EventLoop,
         pending_requests: Arc<Mutex<Vec<PendingRequest>>>,
         connected: Arc<Mutex<bool>>,
+        incoming: broadcast::Sender<MqttMessage>,
         conn_signal: Option<broadcast::Sender<Result<(), String>>>,
     ) {
         let mut conn_signal = conn_signal;
@@ -253,6 +297,12 @@ impl MqttConnection {
 
                     debug!("MQTT received on {}: {} bytes", topic, payload.len());
 
+                    // Fails only when nobody is listening
+                    let _ = incoming.send(MqttMessage {
+                        topic: topic.clone(),
+                        payload: payload.clone(),
+                    });
+
                     // Check if any pending request matches
                     // For Meross protocol, the message_id is inside the JSON payload header
                     let mut pending = pending_requests.lock().await;
@@ -344,6 +394,11 @@ impl MqttConnection {
         self.publish(topic, &json).await
     }
 
+    /// Receive every message arriving on subscribed topics
+    pub fn messages(&self) -> broadcast::Receiver<MqttMessage> {
+        self.incoming.subscribe()
+    }
+
     /// Check if the connection is still active
     pub async fn is_connected(&self) -> bool {
         *self.connected.lock().await
@@ -429,6 +484,24 @@ mod tests {
         assert!(config.client_id.starts_with("pvpc-cheap-"));
     }
 
+    #[test]
+    fn test_mqtt_config_from_url() {
+        let config = MqttConfig::from_url("mqtt://192.168.1.10", None, None).unwrap();
+        assert_eq!(config.broker_host, "192.168.1.10");
+        assert_eq!(config.broker_port, 1883);
+        assert!(!config.use_tls && !config.use_websocket);
+
+        let config = MqttConfig::from_url("wss://broker.example.com:8884/mqtt", Some("u".into()), Some("p".into())).unwrap();
+        assert_eq!(config.broker_host, "broker.example.com");
+        assert_eq!(config.broker_port, 8884);
+        assert!(config.use_tls && config.use_websocket);
+        assert_eq!(config.username.as_deref(), Some("u"));
+
+        assert_eq!(MqttConfig::from_url("mqtts://h", None, None).unwrap().broker_port, 8883);
+        assert!(MqttConfig::from_url("http://h", None, None).is_err());
+        assert!(MqttConfig::from_url("h:1883", None, None).is_err());
+    }
+
     #[test]
     fn test_mqtt_error_display() {
         let err = MqttError::Timeout;
@@ -0,0 +1,588 @@
+//! Shelly Cloud provider
+//!
+//! Uses the Shelly Cloud control API with the account's auth key and server
+//! URI (Settings > User settings > Authorization cloud key). Each relay of
+//! a multi-relay device is discovered as its own device, with the relay
+//! index in the external id (`<id>:<relay>`).
+
+use super::{
+    DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
+    SmartHomeProvider,
+};
+use async_trait::async_trait;
+use log::{debug, info};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Deserialize, Debug)]
+struct ShellyResponse {
+    isok: bool,
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    errors: Value,
+}
+
+/// Relay outputs of a device status, in channel order.
+/// Gen1 devices report `relays[].ison`, Gen2 devices `switch:N.output`.
+fn relay_states(status: &Value) -> Vec<bool> {
+    if let Some(relays) = status.get("relays").and_then(|r| r.as_array()) {
+        return relays
+            .iter()
+            .map(|r| r.get("ison").and_then(|v| v.as_bool()).unwrap_or(false))
+            .collect();
+    }
+    gen2_switches(status)
+        .iter()
+        .map(|s| s.get("output").and_then(|v| v.as_bool()).unwrap_or(false))
+        .collect()
+}
+
+/// Gen2 `switch:N` components ordered by N
+fn gen2_switches(status: &Value) -> Vec<&Value> {
+    let mut switches: Vec<(u32, &Value)> = status
+        .as_object()
+        .map(|o| {
+            o.iter()
+                .filter_map(|(k, v)| Some((k.strip_prefix("switch:")?.parse().ok()?, v)))
+                .collect()
+        })
+        .unwrap_or_default();
+    switches.sort_by_key(|(n, _)| *n);
+    switches.into_iter().map(|(_, v)| v).collect()
+}
+
+const RELAY_SEPARATOR: char = ':';
+
+/// Device id and relay to switch. A relay encoded in the external id
+/// ("id:relay") wins over `channel`.
+fn target_relay(external_id: &str, channel: u32) -> (&str, u32) {
+    match external_id.rsplit_once(RELAY_SEPARATOR) {
+        Some((id, relay)) => match relay.parse() {
+            Ok(relay) => (id, relay),
+            Err(_) => (external_id, channel),
+        },
+        None => (external_id, channel),
+    }
+}
+
+/// Relay encoded in an external id, if any
+fn encoded_relay(external_id: &str) -> Option<usize> {
+    let (id, relay) = target_relay(external_id, 0);
+    (id != external_id).then_some(relay as usize)
+}
+
+/// Sum of a numeric field over the Gen1 meters or Gen2 switches, or the
+/// value of one relay's meter
+fn sum_meters(
+    status: &Value,
+    relay: Option<usize>,
+    gen1: fn(&Value) -> Option<f64>,
+    gen2: fn(&Value) -> Option<f64>,
+) -> Option<f64> {
+    let values: Vec<Option<f64>> = match status.get("meters").and_then(|m| m.as_array()) {
+        Some(meters) => meters.iter().map(gen1).collect(),
+        None => gen2_switches(status).into_iter().map(gen2).collect(),
+    };
+    match relay {
+        Some(relay) => values.get(relay).copied().flatten(),
+        None => {
+            let values: Vec<f64> = values.into_iter().flatten().collect();
+            (!values.is_empty()).then(|| values.iter().sum())
+        }
+    }
+}
+
+/// Instantaneous power in watts
+fn power_w(status: &Value, relay: Option<usize>) -> Option<f64> {
+    sum_meters(
+        status,
+        relay,
+        |m| m.get("power").and_then(|v| v.as_f64()),
+        |s| s.get("apower").and_then(|v| v.as_f64()),
+    )
+}
+
+/// Metered energy in kWh. Gen1 counts watt-minutes, Gen2 watt-hours.
+fn energy_kwh(status: &Value, relay: Option<usize>) -> Option<f64> {
+    sum_meters(
+        status,
+        relay,
+        |m| m.get("total").and_then(|v| v.as_f64()).map(|wmin| wmin / 60_000.0),
+        |s| s.pointer("/aenergy/total").and_then(|v| v.as_f64()).map(|wh| wh / 1000.0),
+    )
+}
+
+/// State of a device, or of one of its relays
+fn status_to_state(status: &Value, relay: Option<usize>) -> DeviceState {
+    DeviceState {
+        is_on: relay_states(status).get(relay.unwrap_or(0)).copied().unwrap_or(false),
+        brightness: None,
+        temperature: status
+            .pointer("/temperature")
+            .or_else(|| status.pointer("/switch:0/temperature/tC"))
+            .and_then(|v| v.as_f64())
+            .map(|t| t as f32),
+        power_consumption_watts: power_w(status, relay).map(|p| p as f32),
+    }
+}
+
+/// Devices for one Shelly device: the device itself when it has a single
+/// relay, otherwise one device per relay
+fn discover_device(id: &str, name: &str, status: &Value) -> Vec<DiscoveredDevice> {
+    let relays = relay_states(status).len();
+    if relays <= 1 {
+        return vec![DiscoveredDevice {
+            external_id: id.to_string(),
+            name: name.to_string(),
+            device_type: "switch".to_string(),
+            capabilities: Some(DeviceCapabilities {
+                can_read_consumption: power_w(status, None).is_some(),
+                ..Default::default()
+            }),
+        }];
+    }
+    (0..relays)
+        .map(|relay| DiscoveredDevice {
+            external_id: format!("{}{}{}", id, RELAY_SEPARATOR, relay),
+            name: format!("{} {}", name, relay + 1),
+            device_type: "switch".to_string(),
+            capabilities: Some(DeviceCapabilities {
+                can_read_consumption: power_w(status, Some(relay)).is_some(),
+                ..Default::default()
+            }),
+        })
+        .collect()
+}
+
+/// Map the `errors` object of a failed response
+fn map_errors(errors: &Value) -> ProviderError {
+    let keys: Vec<&str> = errors
+        .as_object()
+        .map(|o| o.keys().map(|k| k.as_str()).collect())
+        .unwrap_or_default();
+    let message = errors.to_string();
+    if keys.iter().any(|k| k.contains("auth") || k.contains("token")) {
+        ProviderError::AuthenticationFailed(message)
+    } else if keys.iter().any(|k| k.contains("not_found") || *k == "invalid_id") {
+        ProviderError::DeviceNotFound(message)
+    } else if keys.iter().any(|k| k.contains("max_req") || k.contains("limit")) {
+        ProviderError::RateLimited
+    } else if keys.contains(&"device_offline") {
+        ProviderError::ConnectionError(message)
+    } else {
+        ProviderError::Unknown(format!("Shelly error: {}", message))
+    }
+}
+
+pub struct ShellyProvider {
+    client: Client,
+}
+
+impl Default for ShellyProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShellyProvider {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Server URI as shown in the Shelly app, with or without scheme
+    fn server_url(credentials: &Value) -> Result<String, ProviderError> {
+        let uri = credentials
+            .get("server_uri")
+            .and_then(|v| v.as_str())
+            .ok_or(ProviderError::InvalidCredentials)?
+            .trim_end_matches('/');
+        if uri.starts_with("http://") || uri.starts_with("https://") {
+            Ok(uri.to_string())
+        } else {
+            Ok(format!("https://{}", uri))
+        }
+    }
+
+    /// POST a form to the cloud API and return the `data` field
+    async fn post(
+        &self,
+        credentials: &Value,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Value, ProviderError> {
+        let auth_key = credentials
+            .get("auth_key")
+            .and_then(|v| v.as_str())
+            .ok_or(ProviderError::InvalidCredentials)?;
+        let url = format!("{}{}", Self::server_url(credentials)?, path);
+
+        let mut form = vec![("auth_key", auth_key)];
+        form.extend_from_slice(params);
+
+        debug!("Shelly POST {}", path);
+        let response = self.client.post(&url).form(&form).send().await.map_err(|e| {
+            if e.is_timeout() {
+                ProviderError::Timeout
+            } else {
+                ProviderError::ConnectionError(e.to_string())
+            }
+        })?;
+
+        match response.status().as_u16() {
+            401 | 403 => return Err(ProviderError::AuthenticationFailed("Invalid auth key".to_string())),
+            429 => return Err(ProviderError::RateLimited),
+            _ => {}
+        }
+
+        let body: ShellyResponse = response
+            .json()
+            .await
+            .map_err(|e| ProviderError::Unknown(format!("Invalid Shelly response: {}", e)))?;
+        if !body.isok {
+            return Err(map_errors(&body.errors));
+        }
+        Ok(body.data)
+    }
+
+    async fn device_status(&self, credentials: &Value, external_id: &str) -> Result<Value, ProviderError> {
+        let (id, _) = target_relay(external_id, 0);
+        let data = self.post(credentials, "/device/status", &[("id", id)]).await?;
+        Ok(data.get("device_status").cloned().unwrap_or(Value::Null))
+    }
+
+    async fn set_relay(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+        on: bool,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let (id, channel) = target_relay(external_id, channel);
+        let channel = channel.to_string();
+        let turn = if on { "on" } else { "off" };
+        self.post(
+            credentials,
+            "/device/relay/control",
+            &[("id", id), ("channel", &channel), ("turn", turn)],
+        )
+        .await?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Relay {} turned {}", channel, turn)),
+            new_state: None,
+        })
+    }
+}
+
+#[async_trait]
+impl SmartHomeProvider for ShellyProvider {
+    fn provider_name(&self) -> &'static str {
+        "shelly"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Shelly"
+    }
+
+    /// Credentials: `auth_key` and `server_uri`. The key doesn't expire, so
+    /// login only checks it against the server.
+    async fn login(&self, credentials: &Value) -> Result<Value, ProviderError> {
+        self.post(credentials, "/device/all_status", &[]).await?;
+        Ok(credentials.clone())
+    }
+
+    async fn list_devices(&self, credentials: &Value) -> Result<Vec<DiscoveredDevice>, ProviderError> {
+        let lists = self.post(credentials, "/interface/device/get_all_lists", &[]).await?;
+        let statuses = self.post(credentials, "/device/all_status", &[]).await?;
+
+        let empty = Map::new();
+        let devices = lists.get("devices").and_then(|d| d.as_object()).unwrap_or(&empty);
+        let statuses = statuses
+            .get("devices_status")
+            .and_then(|d| d.as_object())
+            .unwrap_or(&empty);
+
+        // Devices without relays (sensors, meters) can't be switched
+        let discovered: Vec<DiscoveredDevice> = statuses
+            .iter()
+            .filter(|(_, status)| !relay_states(status).is_empty())
+            .flat_map(|(id, status)| {
+                let name = devices
+                    .get(id)
+                    .and_then(|d| d.get("name"))
+                    .and_then(|n| n.as_str())
+                    .unwrap_or(id);
+                discover_device(id, name, status)
+            })
+            .collect();
+
+        info!("Shelly found {} relays", discovered.len());
+        Ok(discovered)
+    }
+
+    async fn get_device_state(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceState, ProviderError> {
+        let status = self.device_status(credentials, external_id).await?;
+        Ok(status_to_state(&status, encoded_relay(external_id)))
+    }
+
+    async fn turn_on(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.set_relay(credentials, external_id, 0, true).await
+    }
+
+    async fn turn_off(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.set_relay(credentials, external_id, 0, false).await
+    }
+
+    async fn turn_on_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.set_relay(credentials, external_id, channel, true).await
+    }
+
+    async fn turn_off_channel(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        channel: u32,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.set_relay(credentials, external_id, channel, false).await
+    }
+
+    async fn read_power(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        let status = self.device_status(credentials, external_id).await?;
+        power_w(&status, encoded_relay(external_id))
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} has no power meter", external_id)))
+    }
+
+    async fn read_energy(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        let status = self.device_status(credentials, external_id).await?;
+        energy_kwh(&status, encoded_relay(external_id))
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} has no energy meter", external_id)))
+    }
+
+    fn get_capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            can_read_consumption: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{post, web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    const AUTH_KEY: &str = "auth-key";
+
+    type Form = web::Form<HashMap<String, String>>;
+    type Controls = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+    fn gen1_status() -> Value {
+        json!({
+            "relays": [{"ison": true}, {"ison": false}],
+            "meters": [{"power": 1500.5, "total": 120000}, {"power": 0.0, "total": 60000}],
+            "temperature": 41.2
+        })
+    }
+
+    fn gen2_status() -> Value {
+        json!({
+            "switch:0": {"output": false, "apower": 0.0, "aenergy": {"total": 2500.0}, "temperature": {"tC": 38.0}},
+            "sys": {"uptime": 100}
+        })
+    }
+
+    #[test]
+    fn test_parse_gen1_status() {
+        let status = gen1_status();
+        assert_eq!(relay_states(&status), vec![true, false]);
+        assert_eq!(power_w(&status, None), Some(1500.5));
+        assert_eq!(energy_kwh(&status, None), Some(3.0));
+        assert_eq!(energy_kwh(&status, Some(1)), Some(1.0));
+        let state = status_to_state(&status, None);
+        assert!(state.is_on);
+        assert_eq!(state.temperature, Some(41.2));
+        assert!(!status_to_state(&status, Some(1)).is_on);
+    }
+
+    #[test]
+    fn test_parse_gen2_status() {
+        let status = gen2_status();
+        assert_eq!(relay_states(&status), vec![false]);
+        assert_eq!(energy_kwh(&status, None), Some(2.5));
+        assert_eq!(status_to_state(&status, None).temperature, Some(38.0));
+        assert_eq!(relay_states(&json!({"emeters": []})), Vec::<bool>::new());
+    }
+
+    #[test]
+    fn test_target_relay() {
+        assert_eq!(target_relay("a1", 0), ("a1", 0));
+        assert_eq!(target_relay("a1", 1), ("a1", 1));
+        assert_eq!(target_relay("a1:1", 0), ("a1", 1));
+        assert_eq!(encoded_relay("a1:1"), Some(1));
+        assert_eq!(encoded_relay("a1"), None);
+    }
+
+    #[test]
+    fn test_discover_one_device_per_relay() {
+        let devices = discover_device("a1", "Shelly 2.5", &gen1_status());
+        let ids: Vec<&str> = devices.iter().map(|d| d.external_id.as_str()).collect();
+        assert_eq!(ids, vec!["a1:0", "a1:1"]);
+        assert_eq!(devices[1].name, "Shelly 2.5 2");
+        assert!(devices.iter().all(|d| d.capabilities.as_ref().unwrap().channel_count == 1));
+
+        // Single-relay devices keep their plain id
+        let devices = discover_device("b2", "Plug", &gen2_status());
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].external_id, "b2");
+    }
+
+    fn reply(form: &Form, data: Value) -> HttpResponse {
+        if form.get("auth_key").map(String::as_str) != Some(AUTH_KEY) {
+            return HttpResponse::Ok().json(json!({"isok": false, "errors": {"wrong_auth_key": "Bad key"}}));
+        }
+        HttpResponse::Ok().json(json!({"isok": true, "data": data}))
+    }
+
+    #[post("/device/all_status")]
+    async fn all_status(form: Form) -> HttpResponse {
+        reply(
+            &form,
+            json!({"devices_status": {
+                "a1": gen1_status(),
+                "b2": gen2_status(),
+                "c3": {"emeters": [{"power": 10.0}]}
+            }}),
+        )
+    }
+
+    #[post("/interface/device/get_all_lists")]
+    async fn all_lists(form: Form) -> HttpResponse {
+        reply(&form, json!({"devices": {"a1": {"name": "Shelly 2.5"}, "c3": {"name": "EM"}}}))
+    }
+
+    #[post("/device/status")]
+    async fn one_status(form: Form) -> HttpResponse {
+        match form.get("id").map(String::as_str) {
+            Some("a1") => reply(&form, json!({"online": true, "device_status": gen1_status()})),
+            Some("b2") => reply(&form, json!({"online": true, "device_status": gen2_status()})),
+            _ => HttpResponse::Ok().json(json!({"isok": false, "errors": {"device_not_found": "No such device"}})),
+        }
+    }
+
+    #[post("/device/relay/control")]
+    async fn relay_control(form: Form, controls: web::Data<Controls>) -> HttpResponse {
+        controls.lock().unwrap().push(form.0.clone());
+        reply(&form, Value::Null)
+    }
+
+    /// Start a Shelly Cloud stand-in on a free local port
+    fn mock_server() -> (String, Controls) {
+        let controls: Controls = Arc::new(Mutex::new(Vec::new()));
+        let data = web::Data::new(controls.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(all_status)
+                .service(all_lists)
+                .service(one_status)
+                .service(relay_control)
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        (format!("http://{}", addr), controls)
+    }
+
+    fn credentials(server_uri: &str, auth_key: &str) -> Value {
+        json!({"server_uri": server_uri, "auth_key": auth_key})
+    }
+
+    #[actix_rt::test]
+    async fn test_login_and_list_devices() {
+        let (url, _) = mock_server();
+        let provider = ShellyProvider::new();
+
+        let result = provider.login(&credentials(&url, "wrong")).await;
+        assert!(matches!(result, Err(ProviderError::AuthenticationFailed(_))));
+
+        let session = provider.login(&credentials(&url, AUTH_KEY)).await.unwrap();
+        let mut devices = provider.list_devices(&session).await.unwrap();
+        devices.sort_by(|a, b| a.external_id.cmp(&b.external_id));
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0].external_id, "a1:0");
+        assert_eq!(devices[0].name, "Shelly 2.5 1");
+        assert_eq!(devices[1].external_id, "a1:1");
+        assert_eq!(devices[2].name, "b2");
+        assert!(devices[2].capabilities.as_ref().unwrap().can_read_consumption);
+    }
+
+    #[actix_rt::test]
+    async fn test_state_and_metering() {
+        let (url, _) = mock_server();
+        let provider = ShellyProvider::new();
+        let creds = credentials(&url, AUTH_KEY);
+
+        assert!(provider.get_device_state(&creds, "a1").await.unwrap().is_on);
+        assert_eq!(provider.read_power(&creds, "a1").await.unwrap(), 1500.5);
+        assert!(!provider.get_device_state(&creds, "a1:1").await.unwrap().is_on);
+        assert_eq!(provider.read_power(&creds, "a1:1").await.unwrap(), 0.0);
+        assert_eq!(provider.read_energy(&creds, "b2").await.unwrap(), 2.5);
+        assert!(matches!(
+            provider.get_device_state(&creds, "zz").await,
+            Err(ProviderError::DeviceNotFound(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_relay_control_channels() {
+        let (url, controls) = mock_server();
+        let provider = ShellyProvider::new();
+        let creds = credentials(&url, AUTH_KEY);
+
+        provider.turn_on(&creds, "b2").await.unwrap();
+        provider.turn_off_channel(&creds, "a1", 1).await.unwrap();
+        provider.turn_on(&creds, "a1:1").await.unwrap();
+
+        let controls = controls.lock().unwrap().clone();
+        assert_eq!(controls[0]["id"], "b2");
+        assert_eq!(controls[0]["channel"], "0");
+        assert_eq!(controls[0]["turn"], "on");
+        assert_eq!(controls[1]["id"], "a1");
+        assert_eq!(controls[1]["channel"], "1");
+        assert_eq!(controls[1]["turn"], "off");
+        // The relay of a discovered relay device
+        assert_eq!(controls[2]["id"], "a1");
+        assert_eq!(controls[2]["channel"], "1");
+        assert_eq!(controls[2]["turn"], "on");
+    }
+}
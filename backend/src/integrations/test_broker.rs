@@ -0,0 +1,208 @@
+//! Minimal in-process MQTT 3.1.1 broker for integration tests
+//!
+//! Supports CONNECT (optionally checking username/password), SUBSCRIBE with
+//! wildcards, retained messages, QoS 0/1 PUBLISH and PINGREQ. Messages are
+//! forwarded to subscribers at QoS 0. Tests can drop every connection to
+//! simulate a broker-side disconnect.
+
+use bytes::BytesMut;
+use rumqttc::{
+    matches, ConnAck, ConnectReturnCode, Packet, PubAck, Publish, QoS, SubAck,
+    SubscribeReasonCode,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+struct Client {
+    filters: Vec<String>,
+    tx: mpsc::UnboundedSender<Packet>,
+    kick: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    clients: HashMap<usize, Client>,
+    retained: HashMap<String, Vec<u8>>,
+    published: Vec<(String, Vec<u8>)>,
+    next_id: usize,
+}
+
+impl BrokerState {
+    fn route(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        if retain {
+            self.retained.insert(topic.to_string(), payload.to_vec());
+        }
+        for client in self.clients.values() {
+            if client.filters.iter().any(|f| matches(topic, f)) {
+                let _ = client
+                    .tx
+                    .send(Packet::Publish(Publish::new(topic, QoS::AtMostOnce, payload.to_vec())));
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TestBroker {
+    pub url: String,
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl TestBroker {
+    /// Start a broker on a free local port. With `credentials`, clients must
+    /// log in with that username and password.
+    pub async fn start(credentials: Option<(&str, &str)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("mqtt://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+        let credentials = credentials.map(|(u, p)| (u.to_string(), p.to_string()));
+
+        let accept_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accept_state.clone(), credentials.clone()));
+            }
+        });
+        Self { url, state }
+    }
+
+    /// Publish from the broker side, as a device would
+    pub fn publish(&self, topic: &str, payload: &str, retain: bool) {
+        self.state.lock().unwrap().route(topic, payload.as_bytes(), retain);
+    }
+
+    /// Messages published by clients, in order
+    pub fn published(&self) -> Vec<(String, String)> {
+        self.state
+            .lock()
+            .unwrap()
+            .published
+            .iter()
+            .map(|(t, p)| (t.clone(), String::from_utf8_lossy(p).to_string()))
+            .collect()
+    }
+
+    /// Messages published by clients once at least `count` have arrived
+    pub async fn wait_for_published(&self, count: usize) -> Vec<(String, String)> {
+        for _ in 0..100 {
+            if self.state.lock().unwrap().published.len() >= count {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        self.published()
+    }
+
+    /// Close every client connection, as a broker restart would
+    pub fn drop_clients(&self) {
+        for client in self.state.lock().unwrap().clients.values() {
+            client.kick.notify_one();
+        }
+    }
+
+    /// Number of clients currently connected
+    pub fn client_count(&self) -> usize {
+        self.state.lock().unwrap().clients.len()
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<BrokerState>>, credentials: Option<(String, String)>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+
+    tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            let mut buf = BytesMut::new();
+            if packet.write(&mut buf, MAX_PACKET_SIZE).is_err() || writer.write_all(&buf).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let id = {
+        let mut state = state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    };
+    let kick = Arc::new(Notify::new());
+    let mut buf = BytesMut::new();
+    'connection: loop {
+        let packet = loop {
+            match Packet::read(&mut buf, MAX_PACKET_SIZE) {
+                Ok(packet) => break packet,
+                Err(rumqttc::Error::InsufficientBytes(_)) => {
+                    let read = tokio::select! {
+                        read = reader.read_buf(&mut buf) => read.unwrap_or(0),
+                        _ = kick.notified() => 0,
+                    };
+                    if read == 0 {
+                        break 'connection;
+                    }
+                }
+                Err(_) => break 'connection,
+            }
+        };
+
+        match packet {
+            Packet::Connect(connect) => {
+                let authorized = credentials.as_ref().is_none_or(|(u, p)| {
+                    connect
+                        .login
+                        .as_ref()
+                        .is_some_and(|l| &l.username == u && &l.password == p)
+                });
+                if !authorized {
+                    let _ = tx.send(Packet::ConnAck(ConnAck::new(ConnectReturnCode::BadUserNamePassword, false)));
+                    break;
+                }
+                state.lock().unwrap().clients.insert(
+                    id,
+                    Client {
+                        filters: Vec::new(),
+                        tx: tx.clone(),
+                        kick: kick.clone(),
+                    },
+                );
+                let _ = tx.send(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)));
+            }
+            Packet::Subscribe(subscribe) => {
+                let mut state = state.lock().unwrap();
+                let codes = subscribe
+                    .filters
+                    .iter()
+                    .map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce))
+                    .collect();
+                let _ = tx.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)));
+                for filter in &subscribe.filters {
+                    for (topic, payload) in &state.retained {
+                        if matches(topic, &filter.path) {
+                            let _ = tx.send(Packet::Publish(Publish::new(topic, QoS::AtMostOnce, payload.clone())));
+                        }
+                    }
+                }
+                if let Some(client) = state.clients.get_mut(&id) {
+                    client.filters.extend(subscribe.filters.into_iter().map(|f| f.path));
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.qos != QoS::AtMostOnce {
+                    let _ = tx.send(Packet::PubAck(PubAck::new(publish.pkid)));
+                }
+                let mut state = state.lock().unwrap();
+                state.published.push((publish.topic.clone(), publish.payload.to_vec()));
+                state.route(&publish.topic, &publish.payload, publish.retain);
+            }
+            Packet::PingReq => {
+                let _ = tx.send(Packet::PingResp);
+            }
+            Packet::Disconnect => break,
+            _ => {}
+        }
+    }
+    state.lock().unwrap().clients.remove(&id);
+}
@@ -0,0 +1,653 @@
+//! HTTP webhook provider
+//!
+//! Controls devices with simple HTTP APIs. The integration credentials
+//! describe the requests for each device:
+//!
+//! ```json
+//! {
+//!   "secrets": {"token": "..."},
+//!   "headers": {"Authorization": "Bearer {{secrets.token}}"},
+//!   "devices": [{
+//!     "id": "boiler", "name": "Boiler",
+//!     "turn_on": {"method": "POST", "url": "http://relay.lan/api/{{id}}", "body": "{\"on\": true}"},
+//!     "turn_off": {"method": "POST", "url": "http://relay.lan/api/{{id}}", "body": "{\"on\": false}"},
+//!     "state": {"url": "http://relay.lan/api/{{id}}"},
+//!     "state_path": "$.relays[0].ison",
+//!     "power_path": "$.meters[0].power"
+//!   }]
+//! }
+//! ```
+//!
+//! URLs, headers and bodies are templates: `{{id}}`, `{{name}}` and
+//! `{{secrets.<key>}}` are replaced before sending, so secrets only live in
+//! the `secrets` object.
+//!
+//! Battery inverters add a `set_battery_mode` request, where `{{mode}}`
+//! (`charge`, `discharge` or `idle`) and `{{power_w}}` are also replaced,
+//! and a `soc_path` to the state of charge in the state response.
+
+use super::{
+    BatteryCommand, DeviceActionResult, DeviceCapabilities, DeviceState, DiscoveredDevice, ProviderError,
+    SmartHomeProvider,
+};
+use async_trait::async_trait;
+use log::debug;
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Deserialize)]
+struct RequestTemplate {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WebhookDevice {
+    id: String,
+    name: String,
+    #[serde(default = "default_device_type")]
+    device_type: String,
+    turn_on: RequestTemplate,
+    turn_off: RequestTemplate,
+    #[serde(default)]
+    state: Option<RequestTemplate>,
+    /// JSONPath-style path to the on/off value in the state response
+    #[serde(default)]
+    state_path: Option<String>,
+    /// Value meaning "on"; without it booleans, non-zero numbers and
+    /// "on"/"true"/"1" count as on
+    #[serde(default)]
+    state_on: Option<String>,
+    /// JSONPath-style path to the power in watts in the state response
+    #[serde(default)]
+    power_path: Option<String>,
+    /// Request switching a battery between charge, discharge and idle
+    #[serde(default)]
+    set_battery_mode: Option<RequestTemplate>,
+    /// JSONPath-style path to the battery's state of charge, in percent
+    #[serde(default)]
+    soc_path: Option<String>,
+}
+
+fn default_device_type() -> String {
+    "switch".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WebhookConfig {
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+    /// Headers sent with every request
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    devices: Vec<WebhookDevice>,
+}
+
+impl WebhookConfig {
+    fn parse(credentials: &Value) -> Result<Self, ProviderError> {
+        serde_json::from_value(credentials.clone()).map_err(|_| ProviderError::InvalidCredentials)
+    }
+
+    fn device(&self, external_id: &str) -> Result<&WebhookDevice, ProviderError> {
+        self.devices
+            .iter()
+            .find(|d| d.id == external_id)
+            .ok_or_else(|| ProviderError::DeviceNotFound(external_id.to_string()))
+    }
+
+    /// Fill the `{{...}}` placeholders of a template. `vars` are the
+    /// request-specific placeholders.
+    fn render(&self, template: &str, device: &WebhookDevice, vars: &[(&str, String)]) -> Result<String, ProviderError> {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|e| start + e)
+                .ok_or_else(|| ProviderError::Unknown(format!("Unclosed placeholder in '{}'", template)))?;
+            out.push_str(&rest[..start]);
+            let key = rest[start + 2..end].trim();
+            match key {
+                "id" => out.push_str(&device.id),
+                "name" => out.push_str(&device.name),
+                _ => {
+                    let secret = vars
+                        .iter()
+                        .find(|(name, _)| *name == key)
+                        .map(|(_, value)| value)
+                        .or_else(|| key.strip_prefix("secrets.").and_then(|name| self.secrets.get(name)))
+                        .ok_or_else(|| ProviderError::Unknown(format!("Unknown placeholder '{{{{{}}}}}'", key)))?;
+                    out.push_str(secret);
+                }
+            }
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// Check that every template of every device renders
+    fn validate(&self) -> Result<(), ProviderError> {
+        for device in &self.devices {
+            let battery_vars = battery_vars(BatteryCommand::Idle);
+            let requests = [
+                (Some(&device.turn_on), &[][..]),
+                (Some(&device.turn_off), &[][..]),
+                (device.state.as_ref(), &[][..]),
+                (device.set_battery_mode.as_ref(), &battery_vars[..]),
+            ];
+            for (request, vars) in requests {
+                let Some(request) = request else { continue };
+                self.render(&request.url, device, vars)?;
+                if let Some(body) = &request.body {
+                    self.render(body, device, vars)?;
+                }
+                for value in self.headers.values().chain(request.headers.values()) {
+                    self.render(value, device, vars)?;
+                }
+                Method::from_bytes(request.method.to_uppercase().as_bytes())
+                    .map_err(|_| ProviderError::Unknown(format!("Invalid method '{}'", request.method)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Placeholders of a `set_battery_mode` request
+fn battery_vars(command: BatteryCommand) -> [(&'static str, String); 2] {
+    let (mode, power_w) = match command {
+        BatteryCommand::Charge { power_w } => ("charge", power_w),
+        BatteryCommand::Discharge { power_w } => ("discharge", power_w),
+        BatteryCommand::Idle => ("idle", 0.0),
+    };
+    [("mode", mode.to_string()), ("power_w", format!("{:.0}", power_w))]
+}
+
+/// Value at a JSONPath-style path: `$.relays[0].ison`, `relays.0.ison` or
+/// `$` for the whole document
+fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim().trim_start_matches('$');
+    path.split(['.', '['])
+        .map(|segment| segment.trim_end_matches(']'))
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |current, segment| match segment.parse::<usize>() {
+            Ok(index) if current.is_array() => current.get(index),
+            _ => current.get(segment.trim_matches(|c| c == '\'' || c == '"')),
+        })
+}
+
+fn value_is_on(value: &Value, state_on: Option<&str>) -> Option<bool> {
+    if let Some(expected) = state_on {
+        let actual = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        return Some(actual.eq_ignore_ascii_case(expected));
+    }
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::Number(n) => n.as_f64().map(|n| n != 0.0),
+        Value::String(s) => Some(matches!(s.to_lowercase().as_str(), "on" | "true" | "1")),
+        _ => None,
+    }
+}
+
+fn map_status(status: u16, url: &str) -> ProviderError {
+    match status {
+        401 | 403 => ProviderError::AuthenticationFailed(format!("{} returned {}", url, status)),
+        404 => ProviderError::DeviceNotFound(url.to_string()),
+        429 => ProviderError::RateLimited,
+        _ => ProviderError::Unknown(format!("{} returned {}", url, status)),
+    }
+}
+
+pub struct WebhookProvider {
+    client: Client,
+}
+
+impl Default for WebhookProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebhookProvider {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Send a templated request and return the response body
+    async fn send(
+        &self,
+        config: &WebhookConfig,
+        device: &WebhookDevice,
+        template: &RequestTemplate,
+        vars: &[(&str, String)],
+    ) -> Result<String, ProviderError> {
+        let url = config.render(&template.url, device, vars)?;
+        let method = Method::from_bytes(template.method.to_uppercase().as_bytes())
+            .map_err(|_| ProviderError::Unknown(format!("Invalid method '{}'", template.method)))?;
+
+        // Secrets may be in the URL, so only the template is logged
+        debug!("Webhook {} {}", method, template.url);
+        let mut request = self.client.request(method, &url);
+        for (name, value) in config.headers.iter().chain(template.headers.iter()) {
+            request = request.header(name, config.render(value, device, vars)?);
+        }
+        if let Some(body) = &template.body {
+            request = request.body(config.render(body, device, vars)?);
+        }
+
+        // reqwest errors include the rendered URL, which may hold secrets
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                ProviderError::Timeout
+            } else {
+                ProviderError::ConnectionError(format!("{}: {}", template.url, e.without_url()))
+            }
+        })?;
+        if !response.status().is_success() {
+            return Err(map_status(response.status().as_u16(), &template.url));
+        }
+        response
+            .text()
+            .await
+            .map_err(|e| ProviderError::ConnectionError(format!("{}: {}", template.url, e.without_url())))
+    }
+
+    async fn action(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        on: bool,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let config = WebhookConfig::parse(credentials)?;
+        let device = config.device(external_id)?;
+        let template = if on { &device.turn_on } else { &device.turn_off };
+        self.send(&config, device, template, &[]).await?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Device turned {}", if on { "on" } else { "off" })),
+            new_state: None,
+        })
+    }
+
+    /// State response of a device as JSON
+    async fn fetch_state(
+        &self,
+        config: &WebhookConfig,
+        device: &WebhookDevice,
+    ) -> Result<Value, ProviderError> {
+        let template = device
+            .state
+            .as_ref()
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} has no state request", device.id)))?;
+        let body = self.send(config, device, template, &[]).await?;
+        // Plain-text answers ("ON") are treated as a JSON string
+        Ok(serde_json::from_str(&body).unwrap_or_else(|_| Value::String(body.trim().to_string())))
+    }
+}
+
+#[async_trait]
+impl SmartHomeProvider for WebhookProvider {
+    fn provider_name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "HTTP Webhook"
+    }
+
+    /// There's no session: check that the device templates are usable
+    async fn login(&self, credentials: &Value) -> Result<Value, ProviderError> {
+        WebhookConfig::parse(credentials)?.validate()?;
+        Ok(credentials.clone())
+    }
+
+    async fn list_devices(&self, credentials: &Value) -> Result<Vec<DiscoveredDevice>, ProviderError> {
+        let config = WebhookConfig::parse(credentials)?;
+        Ok(config
+            .devices
+            .iter()
+            .map(|d| DiscoveredDevice {
+                external_id: d.id.clone(),
+                name: d.name.clone(),
+                device_type: d.device_type.clone(),
+                capabilities: Some(DeviceCapabilities {
+                    can_read_consumption: d.state.is_some() && d.power_path.is_some(),
+                    ..Default::default()
+                }),
+            })
+            .collect())
+    }
+
+    async fn get_device_state(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceState, ProviderError> {
+        let config = WebhookConfig::parse(credentials)?;
+        let device = config.device(external_id)?;
+        let response = self.fetch_state(&config, device).await?;
+
+        let state_path = device.state_path.as_deref().unwrap_or("$");
+        let is_on = json_path(&response, state_path)
+            .and_then(|v| value_is_on(v, device.state_on.as_deref()))
+            .ok_or_else(|| ProviderError::Unknown(format!("No state at '{}' in the response", state_path)))?;
+        let power = device
+            .power_path
+            .as_deref()
+            .and_then(|path| json_path(&response, path))
+            .and_then(|v| v.as_f64());
+
+        Ok(DeviceState {
+            is_on,
+            brightness: None,
+            temperature: None,
+            power_consumption_watts: power.map(|p| p as f32),
+        })
+    }
+
+    async fn turn_on(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.action(credentials, external_id, true).await
+    }
+
+    async fn turn_off(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        self.action(credentials, external_id, false).await
+    }
+
+    async fn read_power(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        self.get_device_state(credentials, external_id)
+            .await?
+            .power_consumption_watts
+            .map(f64::from)
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} reports no power", external_id)))
+    }
+
+    async fn set_battery_mode(
+        &self,
+        credentials: &Value,
+        external_id: &str,
+        command: BatteryCommand,
+    ) -> Result<DeviceActionResult, ProviderError> {
+        let config = WebhookConfig::parse(credentials)?;
+        let device = config.device(external_id)?;
+        let template = device
+            .set_battery_mode
+            .as_ref()
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} has no battery mode request", device.id)))?;
+        let vars = battery_vars(command);
+        self.send(&config, device, template, &vars).await?;
+        Ok(DeviceActionResult {
+            success: true,
+            message: Some(format!("Battery set to {}", vars[0].1)),
+            new_state: None,
+        })
+    }
+
+    async fn read_battery_soc(&self, credentials: &Value, external_id: &str) -> Result<f64, ProviderError> {
+        let config = WebhookConfig::parse(credentials)?;
+        let device = config.device(external_id)?;
+        let path = device
+            .soc_path
+            .as_deref()
+            .ok_or_else(|| ProviderError::Unsupported(format!("{} reports no state of charge", device.id)))?;
+        let response = self.fetch_state(&config, device).await?;
+        json_path(&response, path)
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| ProviderError::Unknown(format!("No state of charge at '{}' in the response", path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    const TOKEN: &str = "s3cret";
+
+    /// Requests received by the mock, as (path with query, body)
+    type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+    #[test]
+    fn test_json_path() {
+        let doc = json!({"relays": [{"ison": true}], "meters": [{"power": 12.5}], "state": "ON"});
+        assert_eq!(json_path(&doc, "$.relays[0].ison"), Some(&json!(true)));
+        assert_eq!(json_path(&doc, "meters.0.power"), Some(&json!(12.5)));
+        assert_eq!(json_path(&doc, "$['state']"), Some(&json!("ON")));
+        assert_eq!(json_path(&doc, "$"), Some(&doc));
+        assert_eq!(json_path(&doc, "$.relays[3].ison"), None);
+    }
+
+    #[test]
+    fn test_value_is_on() {
+        assert_eq!(value_is_on(&json!(true), None), Some(true));
+        assert_eq!(value_is_on(&json!("OFF"), None), Some(false));
+        assert_eq!(value_is_on(&json!(1), None), Some(true));
+        assert_eq!(value_is_on(&json!("heating"), Some("HEATING")), Some(true));
+        assert_eq!(value_is_on(&json!(2), Some("1")), Some(false));
+        assert_eq!(value_is_on(&json!(null), None), None);
+    }
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers().get("X-Api-Key").and_then(|h| h.to_str().ok()) == Some(TOKEN)
+    }
+
+    #[post("/relay/{id}")]
+    async fn relay(req: HttpRequest, body: String, received: web::Data<Received>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let uri = req.uri().to_string();
+        received.lock().unwrap().push((uri, body));
+        HttpResponse::Ok().json(json!({"ok": true}))
+    }
+
+    #[get("/status/{id}")]
+    async fn status(req: HttpRequest, path: web::Path<String>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        match path.into_inner().as_str() {
+            "boiler" => HttpResponse::Ok().json(json!({"relays": [{"ison": true}], "meters": [{"power": 1980.0}]})),
+            "inverter" => HttpResponse::Ok().json(json!({"mode": "idle", "battery": {"soc": 63.5}})),
+            "lamp" => HttpResponse::Ok().body("off"),
+            _ => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    /// Start a mock device API on a free local port
+    fn mock_server() -> (String, Received) {
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let data = web::Data::new(received.clone());
+        let server = HttpServer::new(move || {
+            App::new().app_data(data.clone()).service(relay).service(status)
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_rt::spawn(server.run());
+        (format!("http://{}", addr), received)
+    }
+
+    fn credentials(base_url: &str, token: &str) -> Value {
+        json!({
+            "secrets": {"key": token},
+            "headers": {"X-Api-Key": "{{secrets.key}}"},
+            "devices": [
+                {
+                    "id": "boiler", "name": "Boiler",
+                    "turn_on": {"method": "post", "url": format!("{}/relay/{{{{id}}}}?turn=on", base_url),
+                                "headers": {"Content-Type": "application/json"},
+                                "body": "{\"device\": \"{{name}}\", \"on\": true}"},
+                    "turn_off": {"method": "POST", "url": format!("{}/relay/{{{{id}}}}?turn=off", base_url)},
+                    "state": {"url": format!("{}/status/{{{{id}}}}", base_url)},
+                    "state_path": "$.relays[0].ison",
+                    "power_path": "$.meters[0].power"
+                },
+                {
+                    "id": "lamp", "name": "Lamp",
+                    "turn_on": {"method": "POST", "url": format!("{}/relay/lamp", base_url)},
+                    "turn_off": {"method": "POST", "url": format!("{}/relay/lamp", base_url)},
+                    "state": {"url": format!("{}/status/lamp", base_url)}
+                },
+                {
+                    "id": "inverter", "name": "Inverter", "device_type": "battery",
+                    "turn_on": {"method": "POST", "url": format!("{}/relay/inverter", base_url)},
+                    "turn_off": {"method": "POST", "url": format!("{}/relay/inverter", base_url)},
+                    "set_battery_mode": {"method": "POST", "url": format!("{}/relay/inverter?mode={{{{mode}}}}", base_url),
+                                         "body": "{\"power_w\": {{power_w}}}"},
+                    "state": {"url": format!("{}/status/inverter", base_url)},
+                    "state_path": "$.mode",
+                    "state_on": "charge",
+                    "soc_path": "$.battery.soc"
+                },
+                {
+                    "id": "gone", "name": "Gone",
+                    "turn_on": {"method": "POST", "url": format!("{}/relay/gone", base_url)},
+                    "turn_off": {"method": "POST", "url": format!("{}/relay/gone", base_url)},
+                    "state": {"url": format!("{}/status/gone", base_url)}
+                }
+            ]
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_login_validates_templates() {
+        let provider = WebhookProvider::new();
+        assert!(provider.login(&credentials("http://localhost", TOKEN)).await.is_ok());
+
+        let mut missing_secret = credentials("http://localhost", TOKEN);
+        missing_secret["headers"]["X-Api-Key"] = json!("{{secrets.other}}");
+        assert!(provider.login(&missing_secret).await.is_err());
+
+        assert!(matches!(
+            provider.login(&json!({"devices": [{"id": "x"}]})).await,
+            Err(ProviderError::InvalidCredentials)
+        ));
+
+        let devices = provider.list_devices(&credentials("http://localhost", TOKEN)).await.unwrap();
+        assert_eq!(devices.len(), 4);
+        assert!(devices[0].capabilities.as_ref().unwrap().can_read_consumption);
+        assert!(!devices[1].capabilities.as_ref().unwrap().can_read_consumption);
+    }
+
+    #[actix_rt::test]
+    async fn test_actions_send_rendered_requests() {
+        let (base_url, received) = mock_server();
+        let provider = WebhookProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        provider.turn_on(&creds, "boiler").await.unwrap();
+        provider.turn_off(&creds, "boiler").await.unwrap();
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received[0].0, "/relay/boiler?turn=on");
+        assert_eq!(received[0].1, r#"{"device": "Boiler", "on": true}"#);
+        assert_eq!(received[1], ("/relay/boiler?turn=off".to_string(), String::new()));
+
+        let result = provider.turn_on(&credentials(&base_url, "wrong"), "boiler").await;
+        assert!(matches!(result, Err(ProviderError::AuthenticationFailed(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_state_extraction() {
+        let (base_url, _) = mock_server();
+        let provider = WebhookProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        let boiler = provider.get_device_state(&creds, "boiler").await.unwrap();
+        assert!(boiler.is_on);
+        assert_eq!(boiler.power_consumption_watts, Some(1980.0));
+        assert_eq!(provider.read_power(&creds, "boiler").await.unwrap(), 1980.0);
+
+        // Plain-text state without a path
+        assert!(!provider.get_device_state(&creds, "lamp").await.unwrap().is_on);
+        assert!(matches!(
+            provider.read_power(&creds, "lamp").await,
+            Err(ProviderError::Unsupported(_))
+        ));
+        assert!(matches!(
+            provider.get_device_state(&creds, "gone").await,
+            Err(ProviderError::DeviceNotFound(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_connection_errors_hide_secrets() {
+        let provider = WebhookProvider::new();
+        // Nothing listens on port 1
+        let creds = json!({
+            "secrets": {"key": TOKEN},
+            "devices": [{
+                "id": "relay", "name": "Relay",
+                "turn_on": {"method": "POST", "url": "http://127.0.0.1:1/on?key={{secrets.key}}"},
+                "turn_off": {"method": "POST", "url": "http://127.0.0.1:1/off?key={{secrets.key}}"}
+            }]
+        });
+
+        match provider.turn_on(&creds, "relay").await {
+            Err(ProviderError::ConnectionError(message)) => {
+                assert!(!message.contains(TOKEN), "{}", message);
+                assert!(message.contains("{{secrets.key}}"), "{}", message);
+            }
+            other => panic!("expected a connection error, got {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_battery_mode_and_soc() {
+        let (base_url, received) = mock_server();
+        let provider = WebhookProvider::new();
+        let creds = credentials(&base_url, TOKEN);
+
+        provider
+            .set_battery_mode(&creds, "inverter", BatteryCommand::Charge { power_w: 2500.0 })
+            .await
+            .unwrap();
+        provider.set_battery_mode(&creds, "inverter", BatteryCommand::Idle).await.unwrap();
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received[0], ("/relay/inverter?mode=charge".to_string(), r#"{"power_w": 2500}"#.to_string()));
+        assert_eq!(received[1], ("/relay/inverter?mode=idle".to_string(), r#"{"power_w": 0}"#.to_string()));
+
+        assert_eq!(provider.read_battery_soc(&creds, "inverter").await.unwrap(), 63.5);
+        // Plain switches aren't batteries
+        assert!(matches!(
+            provider.set_battery_mode(&creds, "boiler", BatteryCommand::Idle).await,
+            Err(ProviderError::Unsupported(_))
+        ));
+        assert!(matches!(
+            provider.read_battery_soc(&creds, "boiler").await,
+            Err(ProviderError::Unsupported(_))
+        ));
+    }
+}
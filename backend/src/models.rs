@@ -1,4 +1,6 @@
-use chrono::{NaiveDateTime, NaiveTime};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use croner::Cron;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
@@ -14,6 +16,10 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
     pub created_at: NaiveDateTime,
+    /// IANA timezone name (e.g., "Europe/Madrid")
+    pub timezone: String,
+    /// Contracted power in kW; schedules never exceed it when set
+    pub contracted_power_kw: Option<f64>,
 }
 
 #[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Debug, Clone)]
@@ -64,6 +70,34 @@ pub struct Device {
     pub device_type: String,
     pub is_managed: bool,
     pub is_on: bool,
+    /// Power drawn when on, in watts
+    pub rated_power_w: Option<i32>,
+    /// Power over an appliance cycle, in watts per `PROFILE_STEP_MINUTES`
+    pub load_profile: Option<JsonValue>,
+    /// `DeviceCapabilities` discovered for this device
+    pub capabilities: Option<JsonValue>,
+}
+
+/// Length of each step of a device load profile
+pub const PROFILE_STEP_MINUTES: i64 = 15;
+
+/// Longest load profile accepted (24 hours)
+pub const MAX_PROFILE_STEPS: usize = 96;
+
+/// Parse and validate a device load profile (watts per step)
+pub fn parse_load_profile(value: &JsonValue) -> Result<Vec<f64>, String> {
+    let steps: Vec<f64> = serde_json::from_value(value.clone())
+        .map_err(|e| format!("Invalid load profile: {}", e))?;
+    if steps.is_empty() || steps.len() > MAX_PROFILE_STEPS {
+        return Err(format!(
+            "Load profile must have between 1 and {} steps",
+            MAX_PROFILE_STEPS
+        ));
+    }
+    if steps.iter().any(|w| !(0.0..=50_000.0).contains(w)) {
+        return Err("Load profile power must be between 0 and 50000 W".to_string());
+    }
+    Ok(steps)
 }
 
 #[derive(Insertable, Debug)]
@@ -92,15 +126,51 @@ pub enum RuleType {
     TimeSchedule,
     /// Manual control (no automatic triggers)
     Manual,
+    /// Standard cron expression evaluated in the user's timezone
+    Cron,
+    /// N hours within every rolling window, planned across all known prices
+    RollingWindow,
+    /// Runs after a predecessor rule's planned end (e.g., dryer after washer)
+    Chained,
+    /// Charges an energy target before a departure time (EV chargers)
+    EvCharging,
+    /// Starts an appliance cycle when its load profile is cheapest
+    ApplianceCycle,
+    /// Keeps a hot water tank warm enough at usage times (thermal model)
+    WaterHeater,
+    /// Shifts a thermostat's setpoint around expensive hours
+    Thermostat,
 }
 
 impl RuleType {
+    /// Every rule type, in declaration order
+    pub const ALL: [RuleType; 11] = [
+        RuleType::PriceThreshold,
+        RuleType::CheapestHours,
+        RuleType::TimeSchedule,
+        RuleType::Manual,
+        RuleType::Cron,
+        RuleType::RollingWindow,
+        RuleType::Chained,
+        RuleType::EvCharging,
+        RuleType::ApplianceCycle,
+        RuleType::WaterHeater,
+        RuleType::Thermostat,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             RuleType::PriceThreshold => "price_threshold",
             RuleType::CheapestHours => "cheapest_hours",
             RuleType::TimeSchedule => "time_schedule",
             RuleType::Manual => "manual",
+            RuleType::Cron => "cron",
+            RuleType::RollingWindow => "rolling_window",
+            RuleType::Chained => "chained",
+            RuleType::EvCharging => "ev_charging",
+            RuleType::ApplianceCycle => "appliance_cycle",
+            RuleType::WaterHeater => "water_heater",
+            RuleType::Thermostat => "thermostat",
         }
     }
 
@@ -110,6 +180,13 @@ impl RuleType {
             "cheapest_hours" => Some(RuleType::CheapestHours),
             "time_schedule" => Some(RuleType::TimeSchedule),
             "manual" => Some(RuleType::Manual),
+            "cron" => Some(RuleType::Cron),
+            "rolling_window" => Some(RuleType::RollingWindow),
+            "chained" => Some(RuleType::Chained),
+            "ev_charging" => Some(RuleType::EvCharging),
+            "appliance_cycle" => Some(RuleType::ApplianceCycle),
+            "water_heater" => Some(RuleType::WaterHeater),
+            "thermostat" => Some(RuleType::Thermostat),
             _ => None,
         }
     }
@@ -122,6 +199,8 @@ pub enum RuleAction {
     TurnOn,
     TurnOff,
     Toggle,
+    /// Change a thermostat's setpoint, in °C
+    SetTemperature(f64),
 }
 
 impl RuleAction {
@@ -130,6 +209,7 @@ impl RuleAction {
             RuleAction::TurnOn => "turn_on",
             RuleAction::TurnOff => "turn_off",
             RuleAction::Toggle => "toggle",
+            RuleAction::SetTemperature(_) => "set_temperature",
         }
     }
 
@@ -168,6 +248,11 @@ impl AutomationRule {
     pub fn get_action(&self) -> Option<RuleAction> {
         RuleAction::from_str(&self.action)
     }
+
+    /// Get the effective config for a date, with the matching profile applied
+    pub fn config_for_date(&self, date: NaiveDate) -> JsonValue {
+        resolve_config_for_date(&self.config, date)
+    }
 }
 
 #[derive(Insertable, Debug)]
@@ -238,6 +323,8 @@ pub struct Schedule {
 #[serde(rename_all = "snake_case")]
 pub enum ExecutionStatus {
     Pending,
+    /// Claimed by a runner that is sending the command
+    Executing,
     Executed,
     Failed,
     Retrying,
@@ -248,6 +335,7 @@ impl ExecutionStatus {
     pub fn as_str(&self) -> &'static str {
         match self {
             ExecutionStatus::Pending => "pending",
+            ExecutionStatus::Executing => "executing",
             ExecutionStatus::Executed => "executed",
             ExecutionStatus::Failed => "failed",
             ExecutionStatus::Retrying => "retrying",
@@ -258,6 +346,7 @@ impl ExecutionStatus {
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "pending" => Some(ExecutionStatus::Pending),
+            "executing" => Some(ExecutionStatus::Executing),
             "executed" => Some(ExecutionStatus::Executed),
             "failed" => Some(ExecutionStatus::Failed),
             "retrying" => Some(ExecutionStatus::Retrying),
@@ -281,9 +370,19 @@ pub struct ScheduledExecution {
     pub last_retry_at: Option<NaiveDateTime>,
     pub next_retry_at: Option<NaiveDateTime>,
     pub created_at: NaiveDateTime,
+    /// Target temperature of "set_temperature" executions
+    pub setpoint_c: Option<f64>,
 }
 
 impl ScheduledExecution {
+    /// The action to perform, with its setpoint for "set_temperature"
+    pub fn action(&self) -> Option<RuleAction> {
+        match (self.expected_action.as_str(), self.setpoint_c) {
+            ("set_temperature", Some(celsius)) => Some(RuleAction::SetTemperature(celsius)),
+            (action, _) => RuleAction::from_str(action),
+        }
+    }
+
     pub fn get_status(&self) -> Option<ExecutionStatus> {
         ExecutionStatus::from_str(&self.status)
     }
@@ -296,6 +395,7 @@ pub struct NewScheduledExecution {
     pub scheduled_hour: NaiveDateTime,
     pub expected_action: String,
     pub status: String,
+    pub setpoint_c: Option<f64>,
 }
 
 #[derive(AsChangeset, Debug)]
@@ -309,6 +409,146 @@ pub struct UpdateScheduledExecution {
     pub next_retry_at: Option<Option<NaiveDateTime>>,
 }
 
+// ============================================================================
+// Rule History Models
+// ============================================================================
+
+/// Kind of change recorded in the rule history
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleChangeType {
+    Create,
+    Update,
+    Toggle,
+    Delete,
+    Rollback,
+}
+
+impl RuleChangeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleChangeType::Create => "create",
+            RuleChangeType::Update => "update",
+            RuleChangeType::Toggle => "toggle",
+            RuleChangeType::Delete => "delete",
+            RuleChangeType::Rollback => "rollback",
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Debug, Clone)]
+#[diesel(table_name = crate::schema::rule_history)]
+pub struct RuleHistoryEntry {
+    pub id: i32,
+    pub rule_id: i32,
+    pub user_id: i32,
+    pub change_type: String,
+    pub version: i32,
+    pub before_snapshot: Option<JsonValue>,
+    pub after_snapshot: Option<JsonValue>,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::rule_history)]
+pub struct NewRuleHistoryEntry {
+    pub rule_id: i32,
+    pub user_id: i32,
+    pub change_type: String,
+    pub version: i32,
+    pub before_snapshot: Option<JsonValue>,
+    pub after_snapshot: Option<JsonValue>,
+}
+
+// ============================================================================
+// Battery Models
+// ============================================================================
+
+/// Home battery used for price arbitrage
+#[derive(Queryable, Selectable, Serialize, Deserialize, Debug, Clone)]
+#[diesel(table_name = crate::schema::batteries)]
+pub struct Battery {
+    pub id: i32,
+    pub user_id: i32,
+    /// Device receiving charge/discharge commands; None plans only
+    pub device_id: Option<i32>,
+    pub name: String,
+    pub capacity_kwh: f64,
+    pub max_charge_kw: f64,
+    pub max_discharge_kw: f64,
+    /// Round-trip efficiency (0-1]
+    pub efficiency: f64,
+    pub min_soc_percent: f64,
+    /// Last known state of charge
+    pub current_soc_percent: f64,
+    pub created_at: NaiveDateTime,
+    /// When the state of charge was last read or set
+    pub soc_updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::batteries)]
+pub struct NewBattery {
+    pub user_id: i32,
+    pub device_id: Option<i32>,
+    pub name: String,
+    pub capacity_kwh: f64,
+    pub max_charge_kw: f64,
+    pub max_discharge_kw: f64,
+    pub efficiency: f64,
+    pub min_soc_percent: f64,
+    pub current_soc_percent: f64,
+}
+
+impl Battery {
+    /// Check the physical parameters of a battery
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.capacity_kwh > 0.0 && self.capacity_kwh <= 1000.0) {
+            return Err("capacity_kwh must be between 0 and 1000".to_string());
+        }
+        if !(self.max_charge_kw > 0.0 && self.max_charge_kw <= 100.0) {
+            return Err("max_charge_kw must be between 0 and 100".to_string());
+        }
+        if !(self.max_discharge_kw > 0.0 && self.max_discharge_kw <= 100.0) {
+            return Err("max_discharge_kw must be between 0 and 100".to_string());
+        }
+        if !(self.efficiency > 0.0 && self.efficiency <= 1.0) {
+            return Err("efficiency must be between 0 and 1".to_string());
+        }
+        if !(0.0..100.0).contains(&self.min_soc_percent) {
+            return Err("min_soc_percent must be between 0 and 100".to_string());
+        }
+        if !(0.0..=100.0).contains(&self.current_soc_percent) {
+            return Err("current_soc_percent must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Energy Reading Models
+// ============================================================================
+
+/// Energy a device metered on one day
+#[derive(Queryable, Selectable, Serialize, Deserialize, Debug, Clone)]
+#[diesel(table_name = crate::schema::energy_readings)]
+pub struct EnergyReading {
+    pub id: i32,
+    pub device_id: i32,
+    pub date: NaiveDate,
+    pub energy_kwh: f64,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::energy_readings)]
+pub struct NewEnergyReading {
+    pub device_id: i32,
+    pub date: NaiveDate,
+    pub energy_kwh: f64,
+    pub updated_at: NaiveDateTime,
+}
+
 // ============================================================================
 // Configuration Structs for Rules
 // ============================================================================
@@ -317,9 +557,81 @@ pub struct UpdateScheduledExecution {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceThresholdConfig {
     /// Price threshold in €/kWh
+    #[serde(alias = "price_threshold", default = "default_price_threshold")]
     pub threshold: f64,
     /// "below" or "above"
+    #[serde(default = "default_comparison")]
     pub comparison: String,
+    /// Price at which an active rule switches off again (hysteresis band).
+    /// Defaults to `threshold`; must be above it for "below", under it for "above"
+    #[serde(default)]
+    pub threshold_off: Option<f64>,
+    /// Minimum hours to stay on or off before switching again
+    #[serde(default)]
+    pub min_dwell_hours: u32,
+    /// Compare the average of this hour and the next ones instead of a single hour
+    #[serde(default = "default_average_hours")]
+    pub average_hours: u32,
+}
+
+fn default_price_threshold() -> f64 {
+    0.10
+}
+
+fn default_comparison() -> String {
+    "below".to_string()
+}
+
+fn default_average_hours() -> u32 {
+    1
+}
+
+impl PriceThresholdConfig {
+    /// Parse and validate a price threshold rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: PriceThresholdConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid price threshold config: {}", e))?;
+        let off = parsed.off_threshold();
+        match parsed.comparison.as_str() {
+            "below" if off < parsed.threshold => {
+                return Err("threshold_off must not be below threshold".to_string());
+            }
+            "above" if off > parsed.threshold => {
+                return Err("threshold_off must not be above threshold".to_string());
+            }
+            "below" | "above" => {}
+            other => return Err(format!("Invalid comparison '{}'", other)),
+        }
+        if parsed.min_dwell_hours > 24 {
+            return Err("min_dwell_hours must be at most 24".to_string());
+        }
+        if parsed.average_hours == 0 || parsed.average_hours > 24 {
+            return Err("average_hours must be between 1 and 24".to_string());
+        }
+        Ok(parsed)
+    }
+
+    /// Price at which an active rule switches off
+    pub fn off_threshold(&self) -> f64 {
+        self.threshold_off.unwrap_or(self.threshold)
+    }
+
+    /// Whether an inactive rule should switch on at this price
+    pub fn should_activate(&self, price: f64) -> bool {
+        match self.comparison.as_str() {
+            "above" => price > self.threshold,
+            _ => price < self.threshold,
+        }
+    }
+
+    /// Whether an active rule should switch off at this price. Without
+    /// `threshold_off` this is exactly when it wouldn't switch on.
+    pub fn should_deactivate(&self, price: f64) -> bool {
+        match self.comparison.as_str() {
+            "above" => price <= self.off_threshold(),
+            _ => price >= self.off_threshold(),
+        }
+    }
 }
 
 /// Configuration for cheapest hours rules
@@ -345,6 +657,638 @@ pub struct TimeScheduleConfig {
     pub time: String,
 }
 
+/// Configuration for rolling window rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollingWindowConfig {
+    /// Hours the device must run within every window
+    pub hours_per_window: u32,
+    /// Length of the rolling window in hours
+    #[serde(default = "default_window_hours")]
+    pub window_hours: u32,
+}
+
+fn default_window_hours() -> u32 {
+    24
+}
+
+impl RollingWindowConfig {
+    /// Parse and validate a rolling window rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: RollingWindowConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid rolling window config: {}", e))?;
+        if parsed.window_hours == 0 || parsed.window_hours > 48 {
+            return Err("window_hours must be between 1 and 48".to_string());
+        }
+        if parsed.hours_per_window == 0 || parsed.hours_per_window > parsed.window_hours {
+            return Err("hours_per_window must be between 1 and window_hours".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Configuration for chained rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainedRuleConfig {
+    /// Rule that must finish first
+    pub after_rule_id: i32,
+    /// Hours the device must run after the predecessor
+    pub hours_needed: u32,
+    /// Minimum hours to wait after the predecessor's end
+    #[serde(default)]
+    pub gap_hours: u32,
+    /// Hours after the earliest start in which the run must fit
+    #[serde(default = "default_max_delay_hours")]
+    pub max_delay_hours: u32,
+    /// Whether the hours must form a single block
+    #[serde(default = "default_true")]
+    pub contiguous: bool,
+}
+
+fn default_max_delay_hours() -> u32 {
+    12
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl ChainedRuleConfig {
+    /// Parse and validate a chained rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: ChainedRuleConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid chained rule config: {}", e))?;
+        if parsed.max_delay_hours == 0 || parsed.max_delay_hours > 24 {
+            return Err("max_delay_hours must be between 1 and 24".to_string());
+        }
+        if parsed.hours_needed == 0 || parsed.hours_needed > parsed.max_delay_hours {
+            return Err("hours_needed must be between 1 and max_delay_hours".to_string());
+        }
+        if parsed.gap_hours > 24 {
+            return Err("gap_hours must be at most 24".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Configuration for EV charging rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvChargingConfig {
+    /// Energy the battery must hold at departure, in kWh. Without a state of
+    /// charge reading this is the energy to add.
+    pub target_energy_kwh: f64,
+    /// Charger power in kW
+    pub charger_power_kw: f64,
+    /// Departure time (e.g., "07:30"); charging must be done by then
+    pub departure_time: String,
+    /// Usable battery capacity in kWh, needed to use the state of charge
+    #[serde(default)]
+    pub battery_capacity_kwh: Option<f64>,
+    /// Last known state of charge in %
+    #[serde(default)]
+    pub current_soc_percent: Option<f64>,
+    /// When the state of charge was read (server local time)
+    #[serde(default)]
+    pub soc_updated_at: Option<NaiveDateTime>,
+}
+
+impl EvChargingConfig {
+    /// Parse and validate an EV charging rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: EvChargingConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid EV charging config: {}", e))?;
+        if !(parsed.target_energy_kwh > 0.0 && parsed.target_energy_kwh <= 200.0) {
+            return Err("target_energy_kwh must be between 0 and 200".to_string());
+        }
+        if !(parsed.charger_power_kw > 0.0 && parsed.charger_power_kw <= 50.0) {
+            return Err("charger_power_kw must be between 0 and 50".to_string());
+        }
+        parsed.departure()?;
+        if parsed
+            .battery_capacity_kwh
+            .is_some_and(|capacity| !(capacity > 0.0 && capacity <= 200.0))
+        {
+            return Err("battery_capacity_kwh must be between 0 and 200".to_string());
+        }
+        if let Some(soc) = parsed.current_soc_percent {
+            if !(0.0..=100.0).contains(&soc) {
+                return Err("current_soc_percent must be between 0 and 100".to_string());
+            }
+            if parsed.battery_capacity_kwh.is_none() {
+                return Err("current_soc_percent requires battery_capacity_kwh".to_string());
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn departure(&self) -> Result<NaiveTime, String> {
+        NaiveTime::parse_from_str(&self.departure_time, "%H:%M")
+            .map_err(|_| format!("Invalid departure_time '{}'. Use HH:MM", self.departure_time))
+    }
+
+    /// First departure after `now`
+    pub fn next_departure(&self, now: NaiveDateTime) -> Result<NaiveDateTime, String> {
+        let departure = now.date().and_time(self.departure()?);
+        if departure > now {
+            Ok(departure)
+        } else {
+            Ok(departure + chrono::Duration::days(1))
+        }
+    }
+
+    /// Energy still to add for the charging session starting at `session_start`.
+    /// A state of charge read before the session is stale and ignored.
+    pub fn energy_needed_kwh(&self, session_start: NaiveDateTime) -> f64 {
+        let stored = match (self.battery_capacity_kwh, self.current_soc_percent) {
+            (Some(capacity), Some(soc))
+                if self.soc_updated_at.is_none_or(|t| t >= session_start) =>
+            {
+                capacity * soc / 100.0
+            }
+            _ => 0.0,
+        };
+        (self.target_energy_kwh - stored).max(0.0)
+    }
+}
+
+/// Configuration for appliance cycle rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplianceCycleConfig {
+    /// Earliest start of the cycle (e.g., "20:00")
+    pub window_start: String,
+    /// Time the cycle must be finished by (e.g., "08:00", next day if not after the start)
+    pub window_end: String,
+}
+
+impl ApplianceCycleConfig {
+    /// Parse and validate an appliance cycle rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: ApplianceCycleConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid appliance cycle config: {}", e))?;
+        parsed.window_for(NaiveDate::default())?;
+        Ok(parsed)
+    }
+
+    /// Window opening on `date`, as (earliest start, latest end)
+    pub fn window_for(&self, date: NaiveDate) -> Result<(NaiveDateTime, NaiveDateTime), String> {
+        let parse = |s: &str| {
+            NaiveTime::parse_from_str(s, "%H:%M")
+                .map_err(|_| format!("Invalid time '{}'. Use HH:MM", s))
+        };
+        let start = date.and_time(parse(&self.window_start)?);
+        let mut end = date.and_time(parse(&self.window_end)?);
+        if end <= start {
+            end += chrono::Duration::days(1);
+        }
+        Ok((start, end))
+    }
+}
+
+/// A daily time at which hot water is needed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotWaterUsage {
+    /// Time of use (e.g., "07:00")
+    pub time: String,
+    /// Minimum tank temperature at that time, defaults to the rule's `min_temp_c`
+    #[serde(default)]
+    pub min_temp_c: Option<f64>,
+    /// Hot water drawn (replaced by cold water), in liters
+    #[serde(default)]
+    pub draw_liters: f64,
+}
+
+/// Configuration for water heater rules (simple tank thermal model)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaterHeaterConfig {
+    /// Tank volume in liters
+    pub volume_liters: f64,
+    /// Heating element power in watts
+    pub element_power_w: f64,
+    /// Standby heat loss in watts per kelvin above ambient
+    #[serde(default = "default_heat_loss_w_per_k")]
+    pub heat_loss_w_per_k: f64,
+    #[serde(default = "default_ambient_temp_c")]
+    pub ambient_temp_c: f64,
+    /// Temperature of the water replacing what is drawn
+    #[serde(default = "default_cold_water_temp_c")]
+    pub cold_water_temp_c: f64,
+    /// Thermostat cut-off temperature
+    #[serde(default = "default_max_temp_c")]
+    pub max_temp_c: f64,
+    /// Minimum temperature at usage times
+    #[serde(default = "default_min_temp_c")]
+    pub min_temp_c: f64,
+    pub usages: Vec<HotWaterUsage>,
+}
+
+fn default_heat_loss_w_per_k() -> f64 {
+    1.5
+}
+
+fn default_ambient_temp_c() -> f64 {
+    18.0
+}
+
+fn default_cold_water_temp_c() -> f64 {
+    12.0
+}
+
+fn default_max_temp_c() -> f64 {
+    65.0
+}
+
+fn default_min_temp_c() -> f64 {
+    45.0
+}
+
+impl WaterHeaterConfig {
+    /// Parse and validate a water heater rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: WaterHeaterConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid water heater config: {}", e))?;
+        if !(10.0..=1000.0).contains(&parsed.volume_liters) {
+            return Err("volume_liters must be between 10 and 1000".to_string());
+        }
+        if !(100.0..=10_000.0).contains(&parsed.element_power_w) {
+            return Err("element_power_w must be between 100 and 10000".to_string());
+        }
+        if !(0.0..=50.0).contains(&parsed.heat_loss_w_per_k) {
+            return Err("heat_loss_w_per_k must be between 0 and 50".to_string());
+        }
+        if !(parsed.max_temp_c <= 95.0 && parsed.min_temp_c < parsed.max_temp_c) {
+            return Err("min_temp_c must be below max_temp_c (at most 95)".to_string());
+        }
+        if parsed.usages.is_empty() || parsed.usages.len() > 24 {
+            return Err("usages must have between 1 and 24 entries".to_string());
+        }
+        for usage in &parsed.usages {
+            NaiveTime::parse_from_str(&usage.time, "%H:%M")
+                .map_err(|_| format!("Invalid usage time '{}'. Use HH:MM", usage.time))?;
+            if usage.min_temp_c.is_some_and(|t| t >= parsed.max_temp_c) {
+                return Err("Usage min_temp_c must be below max_temp_c".to_string());
+            }
+            if !(0.0..=parsed.volume_liters).contains(&usage.draw_liters) {
+                return Err("draw_liters must be between 0 and volume_liters".to_string());
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Usages in `[from, to)` as (time, minimum temperature, liters drawn), in order
+    pub fn usages_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<(NaiveDateTime, f64, f64)> {
+        let mut usages = Vec::new();
+        let mut date = from.date();
+        while date <= to.date() {
+            for usage in &self.usages {
+                let Ok(time) = NaiveTime::parse_from_str(&usage.time, "%H:%M") else {
+                    continue;
+                };
+                let at = date.and_time(time);
+                if at >= from && at < to {
+                    usages.push((at, usage.min_temp_c.unwrap_or(self.min_temp_c), usage.draw_liters));
+                }
+            }
+            date += chrono::Duration::days(1);
+        }
+        usages.sort_by_key(|(at, _, _)| *at);
+        usages
+    }
+}
+
+/// Configuration for thermostat rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermostatConfig {
+    /// "heat" or "cool"
+    #[serde(default = "default_thermostat_mode")]
+    pub mode: String,
+    /// Setpoint outside of shifted hours
+    pub comfort_setpoint_c: f64,
+    /// Comfort bounds the setpoint may be shifted to
+    pub min_setpoint_c: f64,
+    pub max_setpoint_c: f64,
+    /// Most expensive hours per day, where the setpoint is relaxed
+    #[serde(default = "default_peak_hours")]
+    pub peak_hours: usize,
+    /// Hours before each peak where the room is pre-heated (or pre-cooled)
+    #[serde(default = "default_preheat_hours")]
+    pub preheat_hours: usize,
+}
+
+fn default_thermostat_mode() -> String {
+    "heat".to_string()
+}
+
+fn default_peak_hours() -> usize {
+    4
+}
+
+fn default_preheat_hours() -> usize {
+    2
+}
+
+impl ThermostatConfig {
+    /// Parse and validate a thermostat rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: ThermostatConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid thermostat config: {}", e))?;
+        if parsed.mode != "heat" && parsed.mode != "cool" {
+            return Err("mode must be 'heat' or 'cool'".to_string());
+        }
+        if !(5.0..=35.0).contains(&parsed.min_setpoint_c) || !(5.0..=35.0).contains(&parsed.max_setpoint_c) {
+            return Err("Setpoints must be between 5 and 35 °C".to_string());
+        }
+        if !(parsed.min_setpoint_c <= parsed.comfort_setpoint_c
+            && parsed.comfort_setpoint_c <= parsed.max_setpoint_c)
+        {
+            return Err("comfort_setpoint_c must be between min_setpoint_c and max_setpoint_c".to_string());
+        }
+        if !(1..=12).contains(&parsed.peak_hours) {
+            return Err("peak_hours must be between 1 and 12".to_string());
+        }
+        if parsed.preheat_hours > 6 {
+            return Err("preheat_hours must be at most 6".to_string());
+        }
+        Ok(parsed)
+    }
+
+    /// Setpoints for (pre-heating, peak) hours
+    pub fn shifted_setpoints(&self) -> (f64, f64) {
+        if self.mode == "cool" {
+            (self.min_setpoint_c, self.max_setpoint_c)
+        } else {
+            (self.max_setpoint_c, self.min_setpoint_c)
+        }
+    }
+}
+
+/// Upper bound on cron occurrences expanded per day (one every 5 minutes)
+pub const MAX_CRON_OCCURRENCES_PER_DAY: usize = 288;
+
+/// Configuration for cron rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronScheduleConfig {
+    /// Cron expression with optional seconds field, e.g. "0 30 6 * * mon-fri"
+    pub expression: String,
+    /// IANA timezone overriding the user's timezone
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl CronScheduleConfig {
+    /// Parse and validate a cron rule config
+    pub fn from_config(config: &JsonValue) -> Result<Self, String> {
+        let parsed: CronScheduleConfig = serde_json::from_value(config.clone())
+            .map_err(|e| format!("Invalid cron config: {}", e))?;
+        parsed.cron()?;
+        if let Some(ref tz) = parsed.timezone {
+            parse_timezone(tz)?;
+        }
+
+        // Reject expressions that fire too often to expand into executions.
+        // In UTC every day the expression fires on has the same times, so
+        // the first such day is enough, whatever its weekday or month.
+        let epoch = DateTime::from_timestamp(0, 0).unwrap_or_default();
+        let first = parsed.cron()?.iter_from(epoch.with_timezone(&Tz::UTC), croner::Direction::Forward).next();
+        if let Some(first) = first {
+            let day_start = first.date_naive().and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+            parsed.occurrences_between(Tz::UTC, day_start, day_start + chrono::Duration::days(1))?;
+        }
+
+        Ok(parsed)
+    }
+
+    fn cron(&self) -> Result<Cron, String> {
+        self.expression
+            .parse::<Cron>()
+            .map_err(|e| format!("Invalid cron expression '{}': {}", self.expression, e))
+    }
+
+    /// Timezone to evaluate the expression in: the rule's own or the user's
+    pub fn resolve_timezone(&self, user_timezone: &str) -> Result<Tz, String> {
+        parse_timezone(self.timezone.as_deref().unwrap_or(user_timezone))
+    }
+
+    /// All occurrences in `[from, to)`, evaluated in `tz`
+    pub fn occurrences_between(
+        &self,
+        tz: Tz,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, String> {
+        let cron = self.cron()?;
+        let mut occurrences = Vec::new();
+        // The cap is checked per day, but a local day is 25 hours long when
+        // clocks go back, and its repeated hour fires twice
+        let days = ((to - from).num_seconds() as f64 / 86_400.0).ceil().max(1.0) as usize;
+        let max_occurrences = MAX_CRON_OCCURRENCES_PER_DAY * days;
+
+        for occurrence in cron.iter_from(from.with_timezone(&tz), croner::Direction::Forward) {
+            let occurrence = occurrence.with_timezone(&Utc);
+            if occurrence >= to {
+                break;
+            }
+            if occurrences.len() >= max_occurrences {
+                return Err(format!(
+                    "Cron expression '{}' fires more than {} times per day",
+                    self.expression, MAX_CRON_OCCURRENCES_PER_DAY
+                ));
+            }
+            occurrences.push(occurrence);
+        }
+
+        Ok(occurrences)
+    }
+}
+
+/// Naive times of `occurrences` in `tz`, in order. When clocks go back, the
+/// occurrences of the repeated hour map to the same naive times as the first
+/// pass through it; executions are keyed on that time, so each is kept once.
+pub fn naive_local_times<T: TimeZone>(occurrences: &[DateTime<Utc>], tz: &T) -> Vec<NaiveDateTime> {
+    let mut times: Vec<NaiveDateTime> = occurrences
+        .iter()
+        .map(|t| t.with_timezone(tz).naive_local())
+        .collect();
+    times.sort();
+    times.dedup();
+    times
+}
+
+/// Parse an IANA timezone name
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|_| format!("Invalid timezone '{}'", name))
+}
+
+/// Validate a rule config for its rule type (profiles and type-specific keys).
+/// Each profile is validated as the config it resolves to on its days.
+pub fn validate_rule_config(rule_type: &str, config: &JsonValue) -> Result<(), String> {
+    let profiles = parse_rule_profiles(config)?;
+    validate_type_config(rule_type, config)?;
+    for (index, profile) in profiles.iter().enumerate() {
+        let label = profile.name.clone().unwrap_or_else(|| format!("#{}", index + 1));
+        validate_type_config(rule_type, &merge_profile(config, Some(profile)))
+            .map_err(|e| format!("Profile {}: {}", label, e))?;
+    }
+    Ok(())
+}
+
+/// Validate the type-specific keys of a rule config
+fn validate_type_config(rule_type: &str, config: &JsonValue) -> Result<(), String> {
+    match RuleType::from_str(rule_type) {
+        Some(RuleType::CheapestHours) => {
+            if let Some(hours) = config.get("cheapest_hours")
+                && !hours.as_i64().is_some_and(|h| (1..=24).contains(&h))
+            {
+                return Err("cheapest_hours must be between 1 and 24".to_string());
+            }
+        }
+        Some(RuleType::Cron) => {
+            CronScheduleConfig::from_config(config)?;
+        }
+        Some(RuleType::RollingWindow) => {
+            RollingWindowConfig::from_config(config)?;
+        }
+        Some(RuleType::PriceThreshold) => {
+            PriceThresholdConfig::from_config(config)?;
+        }
+        Some(RuleType::Chained) => {
+            ChainedRuleConfig::from_config(config)?;
+        }
+        Some(RuleType::EvCharging) => {
+            EvChargingConfig::from_config(config)?;
+        }
+        Some(RuleType::ApplianceCycle) => {
+            ApplianceCycleConfig::from_config(config)?;
+        }
+        Some(RuleType::WaterHeater) => {
+            WaterHeaterConfig::from_config(config)?;
+        }
+        Some(RuleType::Thermostat) => {
+            ThermostatConfig::from_config(config)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Seasonal or weekday-specific parameter set for a rule
+///
+/// Stored in the rule config under "profiles". The first profile that matches
+/// a date has its `config` merged over the base config for that day, e.g.
+/// `{"date_from": "06-01", "date_to": "09-30", "config": {"cheapest_hours": 8}}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleProfile {
+    /// Optional label (e.g., "Summer", "Weekend")
+    #[serde(default)]
+    pub name: Option<String>,
+    /// First day of the range as "MM-DD" (inclusive)
+    #[serde(default)]
+    pub date_from: Option<String>,
+    /// Last day of the range as "MM-DD" (inclusive), may wrap past new year
+    #[serde(default)]
+    pub date_to: Option<String>,
+    /// Days of week: ["mon", "tue", ...]. Empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Config keys that override the base config when this profile applies
+    pub config: JsonValue,
+}
+
+impl RuleProfile {
+    /// Check whether this profile applies to a date
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        if !self.days.is_empty() {
+            let day = weekday_abbrev(date.weekday());
+            if !self.days.iter().any(|d| d.to_lowercase() == day) {
+                return false;
+            }
+        }
+
+        let from = self.date_from.as_deref().and_then(parse_month_day);
+        let to = self.date_to.as_deref().and_then(parse_month_day);
+        let current = (date.month(), date.day());
+
+        match (from, to) {
+            (Some(from), Some(to)) if from <= to => current >= from && current <= to,
+            // Range wraps the new year (e.g., "11-01" to "02-28")
+            (Some(from), Some(to)) => current >= from || current <= to,
+            (Some(from), None) => current >= from,
+            (None, Some(to)) => current <= to,
+            (None, None) => true,
+        }
+    }
+
+    /// Validate date and day formats
+    pub fn validate(&self) -> Result<(), String> {
+        for value in [&self.date_from, &self.date_to].into_iter().flatten() {
+            if parse_month_day(value).is_none() {
+                return Err(format!("Invalid profile date '{}'. Use MM-DD", value));
+            }
+        }
+        for day in &self.days {
+            if !WEEKDAY_ABBREVS.contains(&day.to_lowercase().as_str()) {
+                return Err(format!("Invalid profile day '{}'", day));
+            }
+        }
+        if !self.config.is_object() {
+            return Err("Profile config must be an object".to_string());
+        }
+        Ok(())
+    }
+}
+
+const WEEKDAY_ABBREVS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Lowercase three-letter abbreviation used in rule configs
+pub fn weekday_abbrev(weekday: Weekday) -> &'static str {
+    WEEKDAY_ABBREVS[weekday.num_days_from_monday() as usize]
+}
+
+/// Parse "MM-DD" into (month, day). Uses a leap year so "02-29" is accepted
+fn parse_month_day(value: &str) -> Option<(u32, u32)> {
+    NaiveDate::parse_from_str(&format!("2000-{}", value), "%Y-%m-%d")
+        .ok()
+        .map(|d| (d.month(), d.day()))
+}
+
+/// Parse the "profiles" list of a rule config (empty if missing)
+pub fn parse_rule_profiles(config: &JsonValue) -> Result<Vec<RuleProfile>, String> {
+    match config.get("profiles") {
+        None | Some(JsonValue::Null) => Ok(Vec::new()),
+        Some(value) => {
+            let profiles: Vec<RuleProfile> = serde_json::from_value(value.clone())
+                .map_err(|e| format!("Invalid profiles: {}", e))?;
+            for profile in &profiles {
+                profile.validate()?;
+            }
+            Ok(profiles)
+        }
+    }
+}
+
+/// Resolve the config that applies on a date: the base config (without
+/// "profiles") with the first matching profile's keys merged on top
+pub fn resolve_config_for_date(config: &JsonValue, date: NaiveDate) -> JsonValue {
+    let profiles = parse_rule_profiles(config).unwrap_or_default();
+    merge_profile(config, profiles.iter().find(|p| p.matches(date)))
+}
+
+/// The base config (without "profiles") with `profile`'s keys merged on top
+fn merge_profile(config: &JsonValue, profile: Option<&RuleProfile>) -> JsonValue {
+    let mut resolved = config.clone();
+
+    if let Some(obj) = resolved.as_object_mut() {
+        obj.remove("profiles");
+
+        if let Some(overrides) = profile.and_then(|p| p.config.as_object()) {
+            for (key, value) in overrides {
+                obj.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    resolved
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -357,6 +1301,9 @@ mod tests {
             Some(RuleType::CheapestHours)
         );
         assert_eq!(RuleType::from_str("invalid"), None);
+        for rule_type in RuleType::ALL {
+            assert_eq!(RuleType::from_str(rule_type.as_str()), Some(rule_type));
+        }
     }
 
     #[test]
@@ -371,12 +1318,45 @@ mod tests {
         let config = PriceThresholdConfig {
             threshold: 0.10,
             comparison: "below".to_string(),
+            threshold_off: None,
+            min_dwell_hours: 0,
+            average_hours: 1,
         };
         let json = serde_json::to_string(&config).unwrap();
         assert!(json.contains("0.1"));
         assert!(json.contains("below"));
     }
 
+    #[test]
+    fn test_price_threshold_config_accepts_legacy_payload() {
+        // As sent by the Android app
+        let config = PriceThresholdConfig::from_config(&serde_json::json!({"price_threshold": 0.12})).unwrap();
+        assert_eq!(config.threshold, 0.12);
+        assert_eq!(config.comparison, "below");
+        assert_eq!(config.off_threshold(), 0.12);
+        assert_eq!(config.average_hours, 1);
+        assert!(validate_rule_config("price_threshold", &serde_json::json!({"price_threshold": 0.12})).is_ok());
+
+        let config = PriceThresholdConfig::from_config(&serde_json::json!({})).unwrap();
+        assert_eq!(config.threshold, 0.10);
+    }
+
+    #[test]
+    fn test_price_threshold_config_validates_hysteresis() {
+        let band = serde_json::json!({"threshold": 0.10, "comparison": "below", "threshold_off": 0.12});
+        let config = PriceThresholdConfig::from_config(&band).unwrap();
+        assert!(!config.should_activate(0.10));
+        assert!(config.should_activate(0.09));
+        assert!(!config.should_deactivate(0.11));
+        assert!(config.should_deactivate(0.12));
+
+        let inverted = serde_json::json!({"threshold": 0.10, "comparison": "below", "threshold_off": 0.08});
+        assert!(PriceThresholdConfig::from_config(&inverted).is_err());
+        let above = serde_json::json!({"threshold": 0.20, "comparison": "above", "threshold_off": 0.18});
+        assert!(PriceThresholdConfig::from_config(&above).is_ok());
+        assert!(PriceThresholdConfig::from_config(&serde_json::json!({"threshold": 0.10, "comparison": "below", "average_hours": 0})).is_err());
+    }
+
     #[test]
     fn test_cheapest_hours_config_serialization() {
         let config = CheapestHoursConfig {
@@ -400,4 +1380,332 @@ mod tests {
         assert!(json.contains("mon"));
         assert!(json.contains("06:30"));
     }
+
+    fn profiled_config() -> JsonValue {
+        serde_json::json!({
+            "cheapest_hours": 4,
+            "time_range_start": "00:00",
+            "time_range_end": "08:00",
+            "profiles": [
+                {"name": "Summer", "date_from": "06-01", "date_to": "09-30", "config": {"cheapest_hours": 8}},
+                {"name": "Weekend", "days": ["sat", "sun"], "config": {"time_range_end": "11:00"}}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_resolve_config_without_matching_profile() {
+        // Wednesday in January: base config only
+        let date = NaiveDate::from_ymd_opt(2025, 1, 15).unwrap();
+        let resolved = resolve_config_for_date(&profiled_config(), date);
+        assert_eq!(resolved["cheapest_hours"], 4);
+        assert_eq!(resolved["time_range_end"], "08:00");
+        assert!(resolved.get("profiles").is_none());
+    }
+
+    #[test]
+    fn test_resolve_config_first_matching_profile_wins() {
+        // Saturday in July matches both; "Summer" comes first
+        let date = NaiveDate::from_ymd_opt(2025, 7, 12).unwrap();
+        let resolved = resolve_config_for_date(&profiled_config(), date);
+        assert_eq!(resolved["cheapest_hours"], 8);
+        assert_eq!(resolved["time_range_end"], "08:00");
+
+        // Saturday in January only matches "Weekend"
+        let date = NaiveDate::from_ymd_opt(2025, 1, 18).unwrap();
+        let resolved = resolve_config_for_date(&profiled_config(), date);
+        assert_eq!(resolved["cheapest_hours"], 4);
+        assert_eq!(resolved["time_range_end"], "11:00");
+    }
+
+    #[test]
+    fn test_profile_date_range_wraps_new_year() {
+        let profile = RuleProfile {
+            name: Some("Winter".to_string()),
+            date_from: Some("11-01".to_string()),
+            date_to: Some("02-28".to_string()),
+            days: vec![],
+            config: serde_json::json!({}),
+        };
+        assert!(profile.matches(NaiveDate::from_ymd_opt(2025, 12, 24).unwrap()));
+        assert!(profile.matches(NaiveDate::from_ymd_opt(2025, 2, 1).unwrap()));
+        assert!(!profile.matches(NaiveDate::from_ymd_opt(2025, 6, 1).unwrap()));
+    }
+
+    #[test]
+    fn test_validate_rule_config_checks_profile_overrides() {
+        assert!(validate_rule_config("cheapest_hours", &profiled_config()).is_ok());
+
+        let config = serde_json::json!({
+            "cheapest_hours": 4,
+            "profiles": [{"name": "Summer", "date_from": "06-01", "config": {"cheapest_hours": -3}}]
+        });
+        let err = validate_rule_config("cheapest_hours", &config).unwrap_err();
+        assert!(err.contains("Summer"));
+
+        // The override is checked merged over the base config
+        let config = serde_json::json!({
+            "hours_per_window": 4,
+            "window_hours": 12,
+            "profiles": [{"days": ["sat", "sun"], "config": {"hours_per_window": 13}}]
+        });
+        assert!(validate_rule_config("rolling_window", &config).is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_profiles_rejects_invalid_dates() {
+        let config = serde_json::json!({
+            "profiles": [{"date_from": "13-01", "config": {}}]
+        });
+        assert!(parse_rule_profiles(&config).is_err());
+
+        let config = serde_json::json!({
+            "profiles": [{"days": ["monday"], "config": {}}]
+        });
+        assert!(parse_rule_profiles(&config).is_err());
+
+        assert!(parse_rule_profiles(&profiled_config()).is_ok());
+    }
+
+    #[test]
+    fn test_cron_occurrences_in_timezone() {
+        // 06:30:15 Madrid on weekdays; 2025-01-15 is a Wednesday (UTC+1)
+        let config = CronScheduleConfig::from_config(&serde_json::json!({
+            "expression": "15 30 6 * * mon-fri"
+        }))
+        .unwrap();
+        let tz = config.resolve_timezone("Europe/Madrid").unwrap();
+
+        let from = DateTime::parse_from_rfc3339("2025-01-15T00:00:00Z").unwrap().with_timezone(&Utc);
+        let occurrences = config
+            .occurrences_between(tz, from, from + chrono::Duration::days(4))
+            .unwrap();
+
+        // Wed, Thu, Fri - not the weekend
+        assert_eq!(occurrences.len(), 3);
+        assert_eq!(occurrences[0].to_rfc3339(), "2025-01-15T05:30:15+00:00");
+    }
+
+    #[test]
+    fn test_cron_occurrences_on_dst_fall_back_day() {
+        // Madrid goes back from 03:00 CEST to 02:00 CET on 2026-10-25: a 25 hour day
+        let config = CronScheduleConfig::from_config(&serde_json::json!({"expression": "*/5 * * * *"})).unwrap();
+        let tz = config.resolve_timezone("Europe/Madrid").unwrap();
+        let day_start = |d: u32| {
+            tz.with_ymd_and_hms(2026, 10, d, 0, 0, 0)
+                .earliest()
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        let occurrences = config.occurrences_between(tz, day_start(25), day_start(26)).unwrap();
+        assert_eq!(occurrences.len(), 300);
+
+        // 02:00-02:55 happen twice but can be planned once
+        let times = naive_local_times(&occurrences, &tz);
+        assert_eq!(times.len(), 288);
+        let repeated = NaiveDate::from_ymd_opt(2026, 10, 25).unwrap().and_hms_opt(2, 30, 0).unwrap();
+        assert_eq!(times.iter().filter(|t| **t == repeated).count(), 1);
+    }
+
+    #[test]
+    fn test_cron_config_timezone_override() {
+        let config = CronScheduleConfig::from_config(&serde_json::json!({
+            "expression": "0 8 * * *",
+            "timezone": "America/New_York"
+        }))
+        .unwrap();
+        assert_eq!(
+            config.resolve_timezone("Europe/Madrid").unwrap(),
+            chrono_tz::America::New_York
+        );
+    }
+
+    #[test]
+    fn test_cron_config_validation() {
+        let invalid = [
+            serde_json::json!({"expression": "not a cron"}),
+            serde_json::json!({"expression": "0 8 * * *", "timezone": "Mars/Olympus"}),
+            // Every second is far too frequent to expand
+            serde_json::json!({"expression": "* * * * * *"}),
+            // Too frequent on days other than the 1st of January 1970 (a Thursday)
+            serde_json::json!({"expression": "* * * * mon"}),
+            serde_json::json!({"expression": "*/2 * * * * sat,sun"}),
+            serde_json::json!({"expression": "* 0-5 29 2 *"}),
+            serde_json::json!({}),
+        ];
+        for config in invalid {
+            assert!(validate_rule_config("cron", &config).is_err(), "{}", config);
+        }
+
+        // Every 5 minutes is the limit
+        assert!(validate_rule_config("cron", &serde_json::json!({"expression": "*/5 * * * *"})).is_ok());
+        assert!(validate_rule_config("cron", &serde_json::json!({"expression": "*/5 * * * mon"})).is_ok());
+        // Non-cron rules don't need an expression
+        assert!(validate_rule_config("manual", &serde_json::json!({})).is_ok());
+    }
+
+    #[test]
+    fn test_chained_rule_config_validation() {
+        let config =
+            ChainedRuleConfig::from_config(&serde_json::json!({"after_rule_id": 4, "hours_needed": 2}))
+                .unwrap();
+        assert_eq!(config.after_rule_id, 4);
+        assert_eq!(config.max_delay_hours, 12);
+        assert!(config.contiguous);
+
+        assert!(validate_rule_config("chained", &serde_json::json!({"hours_needed": 2})).is_err());
+        assert!(validate_rule_config(
+            "chained",
+            &serde_json::json!({"after_rule_id": 4, "hours_needed": 6, "max_delay_hours": 4})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_rolling_window_config_validation() {
+        let config = RollingWindowConfig::from_config(&serde_json::json!({"hours_per_window": 6}))
+            .unwrap();
+        assert_eq!(config.window_hours, 24);
+
+        assert!(validate_rule_config("rolling_window", &serde_json::json!({"hours_per_window": 0})).is_err());
+        assert!(validate_rule_config(
+            "rolling_window",
+            &serde_json::json!({"hours_per_window": 8, "window_hours": 6})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_ev_charging_config_validation() {
+        let config = serde_json::json!({
+            "target_energy_kwh": 20.0,
+            "charger_power_kw": 7.4,
+            "departure_time": "07:30"
+        });
+        assert!(validate_rule_config("ev_charging", &config).is_ok());
+
+        let mut bad = config.clone();
+        bad["departure_time"] = serde_json::json!("7h");
+        assert!(validate_rule_config("ev_charging", &bad).is_err());
+
+        // A state of charge needs the battery capacity
+        let mut bad = config.clone();
+        bad["current_soc_percent"] = serde_json::json!(40.0);
+        assert!(validate_rule_config("ev_charging", &bad).is_err());
+    }
+
+    #[test]
+    fn test_ev_charging_departure_and_energy_needed() {
+        let config = EvChargingConfig::from_config(&serde_json::json!({
+            "target_energy_kwh": 48.0,
+            "charger_power_kw": 7.4,
+            "departure_time": "07:00",
+            "battery_capacity_kwh": 60.0,
+            "current_soc_percent": 50.0,
+            "soc_updated_at": "2026-03-10T20:00:00"
+        }))
+        .unwrap();
+
+        let evening = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(21, 15, 0).unwrap();
+        let departure = config.next_departure(evening).unwrap();
+        assert_eq!(departure, NaiveDate::from_ymd_opt(2026, 3, 11).unwrap().and_hms_opt(7, 0, 0).unwrap());
+
+        // 48 kWh target with 30 kWh stored
+        let session_start = departure - chrono::Duration::days(1);
+        assert!((config.energy_needed_kwh(session_start) - 18.0).abs() < 1e-9);
+
+        // Next day's session ignores the old reading
+        let next_session = session_start + chrono::Duration::days(1);
+        assert!((config.energy_needed_kwh(next_session) - 48.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_appliance_cycle_window_wraps_midnight() {
+        let config = ApplianceCycleConfig::from_config(
+            &serde_json::json!({"window_start": "20:00", "window_end": "08:00"}),
+        )
+        .unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        let (start, end) = config.window_for(date).unwrap();
+        assert_eq!(start, date.and_hms_opt(20, 0, 0).unwrap());
+        assert_eq!(end, date.succ_opt().unwrap().and_hms_opt(8, 0, 0).unwrap());
+
+        assert!(validate_rule_config(
+            "appliance_cycle",
+            &serde_json::json!({"window_start": "25:00", "window_end": "08:00"})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_load_profile() {
+        let profile = parse_load_profile(&serde_json::json!([2000, 1800.5, 150])).unwrap();
+        assert_eq!(profile, vec![2000.0, 1800.5, 150.0]);
+
+        assert!(parse_load_profile(&serde_json::json!([])).is_err());
+        assert!(parse_load_profile(&serde_json::json!([-5])).is_err());
+        assert!(parse_load_profile(&serde_json::json!(vec![100; 97])).is_err());
+    }
+
+    #[test]
+    fn test_water_heater_config_defaults_and_usages() {
+        let config = WaterHeaterConfig::from_config(&serde_json::json!({
+            "volume_liters": 150,
+            "element_power_w": 2000,
+            "usages": [{"time": "21:00", "draw_liters": 60}, {"time": "07:00", "min_temp_c": 50}]
+        }))
+        .unwrap();
+        assert_eq!(config.min_temp_c, 45.0);
+
+        let from = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(8, 0, 0).unwrap();
+        let usages = config.usages_between(from, from + chrono::Duration::hours(24));
+        let times: Vec<String> = usages.iter().map(|(t, _, _)| t.format("%d %H:%M").to_string()).collect();
+        assert_eq!(times, vec!["10 21:00", "11 07:00"]);
+        assert_eq!(usages[0].1, 45.0);
+        assert_eq!(usages[1].1, 50.0);
+
+        assert!(validate_rule_config(
+            "water_heater",
+            &serde_json::json!({"volume_liters": 150, "element_power_w": 2000, "usages": []})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_thermostat_config_validation() {
+        let config = ThermostatConfig::from_config(&serde_json::json!({
+            "comfort_setpoint_c": 20,
+            "min_setpoint_c": 18,
+            "max_setpoint_c": 22
+        }))
+        .unwrap();
+        assert_eq!(config.mode, "heat");
+        assert_eq!(config.shifted_setpoints(), (22.0, 18.0));
+
+        let bad = |config: serde_json::Value| validate_rule_config("thermostat", &config).is_err();
+        assert!(bad(serde_json::json!({"comfort_setpoint_c": 24, "min_setpoint_c": 18, "max_setpoint_c": 22})));
+        assert!(bad(serde_json::json!({"mode": "fan", "comfort_setpoint_c": 20, "min_setpoint_c": 18, "max_setpoint_c": 22})));
+    }
+
+    #[test]
+    fn test_scheduled_execution_action_carries_setpoint() {
+        let execution = ScheduledExecution {
+            id: 1,
+            rule_id: 1,
+            scheduled_hour: NaiveDateTime::default(),
+            expected_action: "set_temperature".to_string(),
+            status: "pending".to_string(),
+            executed_at: None,
+            execution_id: None,
+            retry_count: 0,
+            last_retry_at: None,
+            next_retry_at: None,
+            created_at: NaiveDateTime::default(),
+            setpoint_c: Some(21.5),
+        };
+        assert_eq!(execution.action(), Some(RuleAction::SetTemperature(21.5)));
+        assert_eq!(RuleAction::SetTemperature(21.5).as_str(), "set_temperature");
+        assert_eq!(RuleAction::from_str("set_temperature"), None);
+    }
 }
@@ -1,6 +1,23 @@
 // @generated automatically by Diesel CLI.
 // Manual additions for new tables
 
+diesel::table! {
+    batteries (id) {
+        id -> Int4,
+        user_id -> Int4,
+        device_id -> Nullable<Int4>,
+        name -> Text,
+        capacity_kwh -> Float8,
+        max_charge_kw -> Float8,
+        max_discharge_kw -> Float8,
+        efficiency -> Float8,
+        min_soc_percent -> Float8,
+        current_soc_percent -> Float8,
+        created_at -> Timestamp,
+        soc_updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     devices (id) {
         id -> Int4,
@@ -10,6 +27,27 @@ diesel::table! {
         device_type -> Text,
         is_managed -> Bool,
         is_on -> Bool,
+        rated_power_w -> Nullable<Int4>,
+        load_profile -> Nullable<Jsonb>,
+        capabilities -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    energy_readings (id) {
+        id -> Int4,
+        device_id -> Int4,
+        date -> Date,
+        energy_kwh -> Float8,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    optimized_rule_dates (rule_id, date) {
+        rule_id -> Int4,
+        date -> Date,
+        created_at -> Timestamp,
     }
 }
 
@@ -52,6 +90,8 @@ diesel::table! {
         username -> Text,
         password_hash -> Text,
         created_at -> Timestamp,
+        timezone -> Text,
+        contracted_power_kw -> Nullable<Float8>,
     }
 }
 
@@ -99,10 +139,27 @@ diesel::table! {
         last_retry_at -> Nullable<Timestamp>,
         next_retry_at -> Nullable<Timestamp>,
         created_at -> Timestamp,
+        setpoint_c -> Nullable<Float8>,
+    }
+}
+
+diesel::table! {
+    rule_history (id) {
+        id -> Int4,
+        rule_id -> Int4,
+        user_id -> Int4,
+        change_type -> Text,
+        version -> Int4,
+        before_snapshot -> Nullable<Jsonb>,
+        after_snapshot -> Nullable<Jsonb>,
+        changed_at -> Timestamp,
     }
 }
 
+diesel::joinable!(batteries -> devices (device_id));
+diesel::joinable!(batteries -> users (user_id));
 diesel::joinable!(devices -> user_integrations (integration_id));
+diesel::joinable!(energy_readings -> devices (device_id));
 diesel::joinable!(schedules -> devices (device_id));
 diesel::joinable!(schedules -> users (user_id));
 diesel::joinable!(user_integrations -> users (user_id));
@@ -110,12 +167,18 @@ diesel::joinable!(automation_rules -> users (user_id));
 diesel::joinable!(automation_rules -> devices (device_id));
 diesel::joinable!(rule_executions -> automation_rules (rule_id));
 diesel::joinable!(scheduled_executions -> automation_rules (rule_id));
+diesel::joinable!(rule_history -> users (user_id));
+diesel::joinable!(optimized_rule_dates -> automation_rules (rule_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     automation_rules,
+    batteries,
     devices,
+    energy_readings,
+    optimized_rule_dates,
     prices,
     rule_executions,
+    rule_history,
     scheduled_executions,
     schedules,
     user_integrations,
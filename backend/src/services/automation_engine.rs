@@ -3,17 +3,36 @@ use crate::{
     integrations::{ProviderError, ProviderRegistry, DeviceState},
     models::{
         AutomationRule, CheapestHoursConfig, ExecutionStatus, NewRuleExecution, Price,
-        PriceThresholdConfig, RuleAction, ScheduledExecution, TimeScheduleConfig,
+        PriceThresholdConfig, RuleAction, RuleType, ScheduledExecution, TimeScheduleConfig,
         UpdateScheduledExecution,
     },
     schema::{automation_rules, devices, prices, rule_executions, scheduled_executions, user_integrations},
+    services::battery_planner::{fresh_soc, BatteryPlannerService},
+    services::schedule_computation::{ScheduleComputationService, DUE_EXECUTION_GRACE_MINUTES},
+    services::scheduler::plan_price_threshold_hours,
 };
-use chrono::{Local, NaiveDateTime, NaiveTime, Timelike, Weekday, Datelike};
+use chrono::{Local, NaiveDateTime, NaiveTime, Timelike, Utc, Weekday, Datelike};
 use diesel::prelude::*;
 use log::{error, info, warn};
 use serde_json::Value as JsonValue;
 use std::sync::Arc;
 
+/// Rule types whose executions fire at a point in time instead of holding an hour
+pub(crate) const POINT_IN_TIME_RULE_TYPES: [&str; 3] = ["cron", "appliance_cycle", "thermostat"];
+
+/// Move a pending execution to `executing`. The status check and update are
+/// one statement, so when runners overlap only one of them gets the row.
+fn claim_scheduled_execution(conn: &mut PgConnection, scheduled_id: i32) -> bool {
+    diesel::update(
+        scheduled_executions::table
+            .filter(scheduled_executions::id.eq(scheduled_id))
+            .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str())),
+    )
+    .set(scheduled_executions::status.eq(ExecutionStatus::Executing.as_str()))
+    .execute(conn)
+    .is_ok_and(|updated| updated == 1)
+}
+
 /// Result of evaluating a rule
 #[derive(Debug, Clone)]
 pub struct RuleEvaluation {
@@ -125,24 +144,70 @@ impl AutomationEngine {
     }
 
     /// Evaluate a rule to determine if it should trigger
-    fn evaluate_rule(
+    pub fn evaluate_rule(
         &self,
         rule: &AutomationRule,
         now: &NaiveDateTime,
         current_price: Option<f64>,
     ) -> RuleEvaluation {
         let action = RuleAction::from_str(&rule.action).unwrap_or(RuleAction::TurnOn);
+        // Apply the seasonal/weekday profile that matches today
+        let config = rule.config_for_date(now.date());
 
         match rule.rule_type.as_str() {
-            "price_threshold" => self.evaluate_price_threshold(rule, current_price, action),
-            "cheapest_hours" => self.evaluate_cheapest_hours(rule, now, action),
-            "time_schedule" => self.evaluate_time_schedule(rule, now, action),
+            "price_threshold" => {
+                self.evaluate_price_threshold(rule, &config, now, current_price, action)
+            }
+            "cheapest_hours" => self.evaluate_cheapest_hours(rule, &config, now, action),
+            "time_schedule" => self.evaluate_time_schedule(rule, &config, now, action),
             "manual" => RuleEvaluation {
                 rule_id: rule.id,
                 should_trigger: false,
                 action,
                 reason: "Manual rules don't auto-trigger".to_string(),
             },
+            "cron" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Cron rules run from their scheduled executions".to_string(),
+            },
+            "rolling_window" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Rolling window rules run from their scheduled executions".to_string(),
+            },
+            "chained" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Chained rules run from their scheduled executions".to_string(),
+            },
+            "ev_charging" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "EV charging rules run from their scheduled executions".to_string(),
+            },
+            "appliance_cycle" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Appliance cycle rules run from their scheduled executions".to_string(),
+            },
+            "water_heater" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Water heater rules run from their scheduled executions".to_string(),
+            },
+            "thermostat" => RuleEvaluation {
+                rule_id: rule.id,
+                should_trigger: false,
+                action,
+                reason: "Thermostat rules run from their scheduled setpoint changes".to_string(),
+            },
             _ => RuleEvaluation {
                 rule_id: rule.id,
                 should_trigger: false,
@@ -156,10 +221,12 @@ impl AutomationEngine {
     fn evaluate_price_threshold(
         &self,
         rule: &AutomationRule,
+        config: &JsonValue,
+        now: &NaiveDateTime,
         current_price: Option<f64>,
         action: RuleAction,
     ) -> RuleEvaluation {
-        let config: PriceThresholdConfig = match serde_json::from_value(rule.config.clone()) {
+        let config = match PriceThresholdConfig::from_config(config) {
             Ok(c) => c,
             Err(_) => {
                 return RuleEvaluation {
@@ -183,31 +250,59 @@ impl AutomationEngine {
             }
         };
 
-        let should_trigger = match config.comparison.as_str() {
-            "below" => price < config.threshold,
-            "above" => price > config.threshold,
-            _ => false,
-        };
+        // Replay from yesterday so hysteresis and dwell time see the previous state
+        let hour_start = now.date().and_hms_opt(now.hour(), 0, 0).unwrap_or(*now);
+        let prices = self.get_prices_between(
+            hour_start - chrono::Duration::days(1),
+            hour_start + chrono::Duration::hours(config.average_hours as i64),
+        );
+        let should_trigger = plan_price_threshold_hours(&prices, &config).contains(&hour_start);
 
         RuleEvaluation {
             rule_id: rule.id,
             should_trigger,
             action,
             reason: format!(
-                "Price {:.4} €/kWh is {} threshold {:.4}",
-                price, config.comparison, config.threshold
+                "Price {:.4} €/kWh{} is {} threshold {:.4} (off at {:.4}, min dwell {}h): {}",
+                price,
+                if config.average_hours > 1 {
+                    format!(" (next {}h averaged)", config.average_hours)
+                } else {
+                    String::new()
+                },
+                config.comparison,
+                config.threshold,
+                config.off_threshold(),
+                config.min_dwell_hours,
+                if should_trigger { "active" } else { "inactive" }
             ),
         }
     }
 
+    /// Get known prices in `[from, to)`
+    fn get_prices_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Price> {
+        let mut conn = match self.pool.get() {
+            Ok(c) => c,
+            Err(_) => return Vec::new(),
+        };
+
+        prices::table
+            .filter(prices::timestamp.ge(from))
+            .filter(prices::timestamp.lt(to))
+            .order(prices::timestamp.asc())
+            .load(&mut conn)
+            .unwrap_or_default()
+    }
+
     /// Evaluate a cheapest hours rule
     fn evaluate_cheapest_hours(
         &self,
         rule: &AutomationRule,
+        config: &JsonValue,
         now: &NaiveDateTime,
         action: RuleAction,
     ) -> RuleEvaluation {
-        let config: CheapestHoursConfig = match serde_json::from_value(rule.config.clone()) {
+        let config: CheapestHoursConfig = match serde_json::from_value(config.clone()) {
             Ok(c) => c,
             Err(_) => {
                 return RuleEvaluation {
@@ -365,10 +460,11 @@ impl AutomationEngine {
     fn evaluate_time_schedule(
         &self,
         rule: &AutomationRule,
+        config: &JsonValue,
         now: &NaiveDateTime,
         action: RuleAction,
     ) -> RuleEvaluation {
-        let config: TimeScheduleConfig = match serde_json::from_value(rule.config.clone()) {
+        let config: TimeScheduleConfig = match serde_json::from_value(config.clone()) {
             Ok(c) => c,
             Err(_) => {
                 return RuleEvaluation {
@@ -618,6 +714,9 @@ impl AutomationEngine {
                     provider.turn_on(credentials, external_id).await
                 }
             }
+            RuleAction::SetTemperature(celsius) => {
+                provider.set_temperature(credentials, external_id, *celsius).await
+            }
         }
     }
 
@@ -690,6 +789,8 @@ impl AutomationEngine {
                 .inner_join(automation_rules::table)
                 .filter(scheduled_executions::scheduled_hour.eq(current_hour_start))
                 .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
+                // Cron, appliance cycle and thermostat executions are run by execute_due_cron_executions
+                .filter(automation_rules::rule_type.ne_all(POINT_IN_TIME_RULE_TYPES))
                 .select((ScheduledExecution::as_select(), AutomationRule::as_select()))
                 .load(&mut conn)
                 .unwrap_or_default();
@@ -709,9 +810,13 @@ impl AutomationEngine {
         }
 
         // Find rules that should turn OFF (not scheduled for current hour but have "turn_on" action)
+        // Cron rules fire at points in time and don't hold the device on for an hour;
+        // appliance cycles must not be interrupted once started and thermostat
+        // setpoints hold until the next change
         let rules_to_turn_off: Vec<AutomationRule> = automation_rules::table
             .filter(automation_rules::is_enabled.eq(true))
             .filter(automation_rules::action.eq("turn_on"))
+            .filter(automation_rules::rule_type.ne_all(POINT_IN_TIME_RULE_TYPES))
             .filter(automation_rules::id.ne_all(&scheduled_rule_ids))
             .load(&mut conn)
             .unwrap_or_default();
@@ -751,6 +856,166 @@ impl AutomationEngine {
             }
         }
 
+        // The executions above logged fresh tank temperatures
+        let schedule_service = ScheduleComputationService::new(self.pool.clone());
+        if let Err(e) = schedule_service.replan_water_heater_rules() {
+            error!("Failed to replan water heater rules: {}", e);
+        }
+
+        results
+    }
+
+    /// Send each controlled battery the command planned for the current hour.
+    /// Returns the number of batteries that accepted their command.
+    pub async fn execute_battery_plans(&self) -> usize {
+        let planner = BatteryPlannerService::new(self.pool.clone());
+        let batteries = match planner.controlled_batteries() {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Failed to load batteries: {}", e);
+                return 0;
+            }
+        };
+
+        let mut applied = 0;
+        for mut battery in batteries {
+            let mut conn = match self.pool.get() {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Failed to get connection: {}", e);
+                    return applied;
+                }
+            };
+            let device_info: Option<(String, String, String)> = devices::table
+                .inner_join(user_integrations::table)
+                .filter(devices::id.nullable().eq(battery.device_id))
+                .select((
+                    devices::external_id,
+                    user_integrations::provider_name,
+                    user_integrations::credentials_json,
+                ))
+                .first(&mut conn)
+                .optional()
+                .unwrap_or(None);
+            drop(conn);
+            let Some((external_id, provider_name, credentials_json)) = device_info else {
+                warn!("Battery {}: device or integration not found", battery.id);
+                continue;
+            };
+            let Some(provider) = self.provider_registry.get(&provider_name) else {
+                warn!("Battery {}: provider '{}' not found", battery.id, provider_name);
+                continue;
+            };
+            let credentials: JsonValue = serde_json::from_str(&credentials_json).unwrap_or_default();
+
+            // Plan from the battery's own reading when the provider has one
+            match provider.read_battery_soc(&credentials, &external_id).await {
+                Ok(soc) => {
+                    let now = Utc::now().naive_utc();
+                    battery.current_soc_percent = soc;
+                    battery.soc_updated_at = now;
+                    if let Err(e) = planner.record_soc(battery.id, soc, now) {
+                        warn!("Battery {}: failed to store state of charge: {}", battery.id, e);
+                    }
+                }
+                Err(ProviderError::Unsupported(_)) => {}
+                Err(e) => warn!("Battery {}: failed to read state of charge: {}", battery.id, e),
+            }
+            if fresh_soc(&battery, Utc::now().naive_utc()).is_none() {
+                warn!(
+                    "Battery {}: state of charge last known at {}, not sending commands",
+                    battery.id, battery.soc_updated_at
+                );
+                continue;
+            }
+
+            let plan = match planner.plan(&battery) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    warn!("Failed to plan battery {}: {}", battery.id, e);
+                    continue;
+                }
+            };
+            let Some(step) = plan.steps.first() else {
+                warn!("No prices to plan battery {}", battery.id);
+                continue;
+            };
+
+            match provider
+                .set_battery_mode(&credentials, &external_id, step.command())
+                .await
+            {
+                Ok(result) if result.success => {
+                    info!(
+                        "Battery {}: {:?} at {:.2} kW ({:.3} €/kWh)",
+                        battery.id, step.action, step.power_kw, step.price
+                    );
+                    // Until the next reading, assume the battery follows the plan
+                    if let Err(e) = planner.record_step(battery.id, step) {
+                        warn!("Battery {}: failed to store state of charge: {}", battery.id, e);
+                    }
+                    applied += 1;
+                }
+                Ok(result) => warn!(
+                    "Battery {} rejected {:?}: {}",
+                    battery.id,
+                    step.action,
+                    result.message.unwrap_or_default()
+                ),
+                Err(e) => warn!("Battery {} command failed: {}", battery.id, e),
+            }
+        }
+
+        applied
+    }
+
+    /// Execute pending cron, appliance cycle and thermostat executions that are
+    /// due, along with the stops of partial EV charging slots.
+    /// Cron occurrences can fall at any second, so this runs more often than hourly.
+    pub async fn execute_due_cron_executions(&self) -> Vec<ExecutionResult> {
+        let mut results = Vec::new();
+        let now = Local::now().naive_local();
+        let grace_start = now - chrono::Duration::minutes(DUE_EXECUTION_GRACE_MINUTES);
+
+        let mut conn = match self.pool.get() {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to get connection: {}", e);
+                return results;
+            }
+        };
+
+        let due_executions: Vec<(ScheduledExecution, AutomationRule)> =
+            scheduled_executions::table
+                .inner_join(automation_rules::table)
+                .filter(
+                    automation_rules::rule_type.eq_any(POINT_IN_TIME_RULE_TYPES).or(
+                        automation_rules::rule_type
+                            .eq(RuleType::EvCharging.as_str())
+                            .and(scheduled_executions::expected_action.eq(RuleAction::TurnOff.as_str())),
+                    ),
+                )
+                .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
+                .filter(scheduled_executions::scheduled_hour.le(now))
+                .filter(scheduled_executions::scheduled_hour.ge(grace_start))
+                .order(scheduled_executions::scheduled_hour.asc())
+                .select((ScheduledExecution::as_select(), AutomationRule::as_select()))
+                .load(&mut conn)
+                .unwrap_or_default();
+
+        if !due_executions.is_empty() {
+            info!("Found {} due cron executions", due_executions.len());
+        }
+
+        for (scheduled, rule) in due_executions {
+            // Another runner may have picked it up since it was loaded
+            if !claim_scheduled_execution(&mut conn, scheduled.id) {
+                continue;
+            }
+            let result = self.execute_scheduled_execution(&scheduled, &rule).await;
+            results.push(result);
+        }
+
         results
     }
 
@@ -761,7 +1026,7 @@ impl AutomationEngine {
         rule: &AutomationRule,
     ) -> ExecutionResult {
         let current_price = self.get_current_price(&Local::now().naive_local());
-        let action = RuleAction::from_str(&scheduled.expected_action).unwrap_or(RuleAction::TurnOn);
+        let action = scheduled.action().unwrap_or(RuleAction::TurnOn);
 
         let evaluation = RuleEvaluation {
             rule_id: rule.id,
@@ -835,6 +1100,8 @@ impl AutomationEngine {
                         .set(&update)
                         .execute(&mut conn)
                         .ok();
+
+                    self.replan_successors(sched.rule_id);
                 } else {
                     // Schedule retry in 1 minute
                     let next_retry = now + chrono::Duration::minutes(1);
@@ -856,6 +1123,14 @@ impl AutomationEngine {
         }
     }
 
+    /// Replan the rules chained after a rule whose execution failed
+    fn replan_successors(&self, rule_id: i32) {
+        let schedule_service = ScheduleComputationService::new(self.pool.clone());
+        if let Err(e) = schedule_service.replan_successors(rule_id) {
+            error!("Failed to replan rules chained after rule {}: {}", rule_id, e);
+        }
+    }
+
     /// Retry failed scheduled executions that are due
     pub async fn retry_failed_executions(&self) -> Vec<ExecutionResult> {
         let mut results = Vec::new();
@@ -906,6 +1181,7 @@ impl AutomationEngine {
                     "Scheduled execution {} for rule {} marked as missed - hour passed",
                     scheduled.id, rule.id
                 );
+                self.replan_successors(rule.id);
             } else {
                 // Try again
                 let result = self.execute_scheduled_execution(&scheduled, &rule).await;
@@ -0,0 +1,306 @@
+use crate::db::DbPool;
+use crate::integrations::BatteryCommand;
+use crate::models::{Battery, Price};
+use crate::schema::batteries;
+use crate::services::price_fetcher::PriceService;
+use chrono::{Duration, Local, NaiveDateTime, Timelike, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+
+/// State of charge levels the planner distinguishes (0.5% steps)
+pub const SOC_LEVELS: usize = 200;
+
+const EPSILON: f64 = 1e-9;
+
+/// Stored states of charge older than this are too uncertain to send
+/// commands from
+pub const MAX_SOC_AGE_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryAction {
+    Charge,
+    Idle,
+    Discharge,
+}
+
+/// What the battery does during one hour
+#[derive(Debug, Clone, Serialize)]
+pub struct BatteryStep {
+    pub hour: NaiveDateTime,
+    pub action: BatteryAction,
+    /// Grid-side power (drawn when charging, delivered when discharging)
+    pub power_kw: f64,
+    pub price: f64,
+    /// State of charge at the end of the hour
+    pub soc_percent: f64,
+}
+
+impl BatteryStep {
+    /// Provider-agnostic command for this step
+    pub fn command(&self) -> BatteryCommand {
+        match self.action {
+            BatteryAction::Charge => BatteryCommand::Charge {
+                power_w: self.power_kw * 1000.0,
+            },
+            BatteryAction::Discharge => BatteryCommand::Discharge {
+                power_w: self.power_kw * 1000.0,
+            },
+            BatteryAction::Idle => BatteryCommand::Idle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatteryPlan {
+    pub battery_id: i32,
+    pub start_soc_percent: f64,
+    pub steps: Vec<BatteryStep>,
+    /// Value of the discharged energy minus the cost of the charged energy, in €
+    pub expected_profit_eur: f64,
+}
+
+/// Most profitable charge/idle/discharge schedule over `prices` (one per hour).
+///
+/// Dynamic programming over `SOC_LEVELS` state of charge levels. Losses are
+/// split evenly between charging and discharging (`√efficiency` each), the
+/// battery never discharges below `min_soc_percent` and ends with at least
+/// the charge it started with, so the profit comes from arbitrage only.
+/// Discharged energy is valued at the hour's price (it replaces grid energy).
+pub fn plan_battery(battery: &Battery, prices: &[Price]) -> BatteryPlan {
+    let mut prices = prices.to_vec();
+    prices.sort_by_key(|p| p.timestamp);
+    prices.dedup_by_key(|p| p.timestamp);
+
+    let levels = SOC_LEVELS;
+    let step_kwh = battery.capacity_kwh / levels as f64;
+    let leg_efficiency = battery.efficiency.sqrt();
+    let max_up = (battery.max_charge_kw * leg_efficiency / step_kwh + EPSILON).floor() as usize;
+    let max_down = (battery.max_discharge_kw / leg_efficiency / step_kwh + EPSILON).floor() as usize;
+    let level_of = |percent: f64| percent / 100.0 * levels as f64;
+    let start = (level_of(battery.current_soc_percent).round() as usize).min(levels);
+    let min_level = ((level_of(battery.min_soc_percent) - EPSILON).ceil().max(0.0) as usize).min(levels);
+    // A battery below its minimum may stay there, but never discharge further
+    let lowest = min_level.min(start);
+
+    let gain = |price: f64, from: usize, to: usize| {
+        if to > from {
+            -price * (to - from) as f64 * step_kwh / leg_efficiency
+        } else {
+            price * (from - to) as f64 * step_kwh * leg_efficiency
+        }
+    };
+
+    // value[t][l]: best profit from hour t on, holding level l
+    let hours = prices.len();
+    let mut value = vec![vec![f64::NEG_INFINITY; levels + 1]; hours + 1];
+    let mut choice = vec![vec![0usize; levels + 1]; hours];
+    for v in &mut value[hours][start..] {
+        *v = 0.0;
+    }
+    for t in (0..hours).rev() {
+        let price = prices[t].price;
+        let (current, next) = value.split_at_mut(t + 1);
+        for level in lowest..=levels {
+            let mut best = f64::NEG_INFINITY;
+            let mut best_to = level;
+            let reachable = level.saturating_sub(max_down).max(lowest)..=(level + max_up).min(levels);
+            for (to, future) in next[0].iter().enumerate() {
+                if !reachable.contains(&to) || (to < level && to < min_level) {
+                    continue;
+                }
+                let v = gain(price, level, to) + future;
+                // On ties, move as little as possible
+                if v > best + EPSILON
+                    || (v > best - EPSILON && to.abs_diff(level) < best_to.abs_diff(level))
+                {
+                    best = v;
+                    best_to = to;
+                }
+            }
+            current[t][level] = best;
+            choice[t][level] = best_to;
+        }
+    }
+
+    let mut steps = Vec::with_capacity(hours);
+    let mut level = start;
+    for (t, price) in prices.iter().enumerate() {
+        let to = choice[t][level];
+        let (action, power_kw) = if to > level {
+            (BatteryAction::Charge, (to - level) as f64 * step_kwh / leg_efficiency)
+        } else if to < level {
+            (BatteryAction::Discharge, (level - to) as f64 * step_kwh * leg_efficiency)
+        } else {
+            (BatteryAction::Idle, 0.0)
+        };
+        steps.push(BatteryStep {
+            hour: price.timestamp,
+            action,
+            power_kw,
+            price: price.price,
+            soc_percent: to as f64 * 100.0 / levels as f64,
+        });
+        level = to;
+    }
+
+    BatteryPlan {
+        battery_id: battery.id,
+        start_soc_percent: battery.current_soc_percent,
+        steps,
+        expected_profit_eur: if hours == 0 { 0.0 } else { value[0][start] },
+    }
+}
+
+/// The stored state of charge, if it was read or set recently enough to plan
+/// from. `now` is UTC, like `soc_updated_at`.
+pub fn fresh_soc(battery: &Battery, now: NaiveDateTime) -> Option<f64> {
+    (now - battery.soc_updated_at <= Duration::minutes(MAX_SOC_AGE_MINUTES)).then_some(battery.current_soc_percent)
+}
+
+pub struct BatteryPlannerService {
+    pool: DbPool,
+}
+
+impl BatteryPlannerService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Plan a battery from the current hour to the end of the known prices
+    pub fn plan(&self, battery: &Battery) -> Result<BatteryPlan, String> {
+        let now = Local::now().naive_local();
+        let hour_start = now.date().and_hms_opt(now.hour(), 0, 0).unwrap();
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(now.date())
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(now.date() + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        prices.retain(|p| p.timestamp >= hour_start);
+
+        Ok(plan_battery(battery, &prices))
+    }
+
+    /// Store a battery's state of charge as of `at` (UTC)
+    pub fn record_soc(&self, battery_id: i32, soc_percent: f64, at: NaiveDateTime) -> Result<(), String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        diesel::update(batteries::table.find(battery_id))
+            .set((
+                batteries::current_soc_percent.eq(soc_percent),
+                batteries::soc_updated_at.eq(at),
+            ))
+            .execute(&mut conn)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Store the state of charge a step is expected to reach, as of the end
+    /// of its hour
+    pub fn record_step(&self, battery_id: i32, step: &BatteryStep) -> Result<(), String> {
+        let until_hour_end = step.hour + Duration::hours(1) - Local::now().naive_local();
+        self.record_soc(battery_id, step.soc_percent, Utc::now().naive_utc() + until_hour_end)
+    }
+
+    /// Batteries that receive commands through a device
+    pub fn controlled_batteries(&self) -> Result<Vec<Battery>, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        batteries::table
+            .filter(batteries::device_id.is_not_null())
+            .load(&mut conn)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn battery(soc: f64, efficiency: f64) -> Battery {
+        Battery {
+            id: 1,
+            user_id: 1,
+            device_id: None,
+            name: "Home".to_string(),
+            capacity_kwh: 10.0,
+            max_charge_kw: 5.0,
+            max_discharge_kw: 5.0,
+            efficiency,
+            min_soc_percent: 10.0,
+            current_soc_percent: soc,
+            created_at: NaiveDateTime::default(),
+            soc_updated_at: NaiveDateTime::default(),
+        }
+    }
+
+    fn prices(values: &[f64]) -> Vec<Price> {
+        let day = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        values
+            .iter()
+            .enumerate()
+            .map(|(h, p)| Price {
+                timestamp: day.and_hms_opt(h as u32, 0, 0).unwrap(),
+                price: *p,
+                source: "test".to_string(),
+            })
+            .collect()
+    }
+
+    fn actions(plan: &BatteryPlan) -> Vec<BatteryAction> {
+        plan.steps.iter().map(|s| s.action).collect()
+    }
+
+    #[test]
+    fn test_plan_charges_cheap_and_discharges_expensive() {
+        use BatteryAction::*;
+        let plan = plan_battery(&battery(10.0, 1.0), &prices(&[0.05, 0.05, 0.20, 0.30, 0.30]));
+
+        assert_eq!(actions(&plan), vec![Charge, Charge, Idle, Discharge, Discharge]);
+        // 9 kWh bought at 0.05, ending back at 10%
+        assert!((plan.steps[4].soc_percent - 10.0).abs() < 1e-9);
+        assert!((plan.expected_profit_eur - 9.0 * 0.25).abs() < 1e-6);
+        let charged_kw: f64 = plan.steps[..2].iter().map(|s| s.power_kw).sum();
+        assert!((charged_kw - 9.0).abs() < 1e-9);
+        assert!(matches!(plan.steps[0].command(), BatteryCommand::Charge { .. }));
+        assert_eq!(plan.steps[2].command(), BatteryCommand::Idle);
+    }
+
+    #[test]
+    fn test_plan_skips_spreads_below_losses() {
+        // 0.8 round trip: buying at 0.10 to sell at 0.12 loses money
+        let plan = plan_battery(&battery(50.0, 0.8), &prices(&[0.10, 0.12, 0.10, 0.12]));
+        assert!(plan.steps.iter().all(|s| s.action == BatteryAction::Idle));
+        assert!(plan.expected_profit_eur.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_plan_respects_min_soc_and_final_charge() {
+        let plan = plan_battery(&battery(50.0, 0.9), &prices(&[0.40, 0.40, 0.05, 0.05]));
+
+        // Sells the stored energy while expensive and buys it back later
+        let actions = actions(&plan);
+        assert!(actions[..2].contains(&BatteryAction::Discharge));
+        assert!(actions[2..].iter().all(|a| *a != BatteryAction::Discharge));
+        assert!(plan.steps.iter().all(|s| s.soc_percent >= 10.0 - 1e-9));
+        assert!(plan.steps.last().unwrap().soc_percent >= 50.0 - 1e-9);
+        assert!(plan.expected_profit_eur > 0.0);
+    }
+
+    #[test]
+    fn test_stale_soc_is_not_planned_from() {
+        let mut battery = battery(40.0, 0.9);
+        battery.soc_updated_at = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(8, 0, 0).unwrap();
+        let at = |h, m| NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(h, m, 0).unwrap();
+
+        assert_eq!(fresh_soc(&battery, at(8, 30)), Some(40.0));
+        assert_eq!(fresh_soc(&battery, at(9, 0)), Some(40.0));
+        assert_eq!(fresh_soc(&battery, at(9, 1)), None);
+        // Expected states of charge are stored as of the end of their hour
+        assert_eq!(fresh_soc(&battery, at(7, 30)), Some(40.0));
+    }
+}
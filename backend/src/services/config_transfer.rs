@@ -0,0 +1,860 @@
+use crate::db::DbPool;
+use crate::models::{
+    parse_load_profile, validate_rule_config, AutomationRule, ChainedRuleConfig, Device,
+    NewAutomationRule, RuleAction, RuleChangeType, RuleType,
+};
+use crate::schema::{automation_rules, devices, user_integrations};
+use crate::services::rule_history::record_rule_change;
+use crate::services::schedule_computation::{validate_rule_chain, ScheduleComputationService};
+use chrono::Utc;
+use diesel::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Current version of the export document format
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// Serialization format of an export document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+/// How to handle a rule that already exists (same name on the same device)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing rule and ignore the imported one
+    #[default]
+    Skip,
+    /// Replace the existing rule with the imported one
+    Overwrite,
+    /// Abort the whole import
+    Fail,
+}
+
+/// Stable reference to a device across accounts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRef {
+    pub provider: String,
+    pub external_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedDevice {
+    pub provider: String,
+    pub external_id: String,
+    pub name: String,
+    pub device_type: String,
+    #[serde(default = "default_true")]
+    pub is_managed: bool,
+    /// Power drawn when on, in watts
+    #[serde(default)]
+    pub rated_power_w: Option<i32>,
+    /// Power over an appliance cycle, in watts per 15 minutes
+    #[serde(default)]
+    pub load_profile: Option<JsonValue>,
+}
+
+/// Stable reference to a rule across accounts: its device and name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleRef {
+    pub device: DeviceRef,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedRule {
+    pub name: String,
+    pub device: DeviceRef,
+    pub rule_type: String,
+    pub action: String,
+    #[serde(default)]
+    pub config: JsonValue,
+    #[serde(default = "default_true")]
+    pub is_enabled: bool,
+    #[serde(default = "default_priority")]
+    pub priority: i32,
+    /// Predecessor of a chained rule, in place of `config.after_rule_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<RuleRef>,
+}
+
+/// Versioned document holding a user's device and rule setup.
+/// Devices are grouped by the integration (provider) that discovered them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDocument {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported_at: Option<String>,
+    #[serde(default)]
+    pub devices: Vec<ExportedDevice>,
+    #[serde(default)]
+    pub rules: Vec<ExportedRule>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_priority() -> i32 {
+    100
+}
+
+/// Why an imported item could not be applied as-is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// The device is not known in the target account (sync it first)
+    MissingDevice,
+    /// A rule with the same name already exists on the device
+    RuleExists,
+    /// The predecessor of a chained rule is neither in the account nor imported
+    MissingPredecessor,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportConflict {
+    pub kind: ConflictKind,
+    pub device: DeviceRef,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_name: Option<String>,
+}
+
+/// Action the import will take for a single rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleImportAction {
+    Create { device_id: i32 },
+    Overwrite { rule_id: i32 },
+    Skip,
+}
+
+/// Where the predecessor of an imported chained rule comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predecessor {
+    /// A rule already in the account
+    Existing(i32),
+    /// The rule created by the import at this index of `rule_actions`
+    Imported(usize),
+}
+
+/// Result of planning (and optionally applying) an import
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub devices_updated: usize,
+    pub rules_created: usize,
+    pub rules_updated: usize,
+    pub rules_skipped: usize,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Device already present in the target account, with its provider
+pub struct ExistingDevice {
+    pub device: Device,
+    pub provider: String,
+}
+
+/// Pure import plan computed from the document and the target account state
+pub struct ImportPlan {
+    /// (device id, imported settings) for devices that will be updated
+    pub device_updates: Vec<(i32, ExportedDevice)>,
+    pub rule_actions: Vec<(RuleImportAction, ExportedRule)>,
+    /// Predecessor of each chained rule with an `after` reference, by index
+    /// of `rule_actions`
+    pub predecessors: Vec<(usize, Predecessor)>,
+    pub report: ImportReport,
+}
+
+/// Serialize a document in the requested format
+pub fn serialize_document(doc: &ConfigDocument, format: DocumentFormat) -> Result<String, String> {
+    match format {
+        DocumentFormat::Json => serde_json::to_string_pretty(doc).map_err(|e| e.to_string()),
+        DocumentFormat::Yaml => serde_yaml::to_string(doc).map_err(|e| e.to_string()),
+    }
+}
+
+/// Parse a document and check that its version is supported
+pub fn parse_document(body: &str, format: DocumentFormat) -> Result<ConfigDocument, String> {
+    let doc: ConfigDocument = match format {
+        DocumentFormat::Json => serde_json::from_str(body).map_err(|e| e.to_string())?,
+        DocumentFormat::Yaml => serde_yaml::from_str(body).map_err(|e| e.to_string())?,
+    };
+
+    if doc.version == 0 || doc.version > DOCUMENT_VERSION {
+        return Err(format!(
+            "Unsupported document version {} (supported: {})",
+            doc.version, DOCUMENT_VERSION
+        ));
+    }
+
+    for device in &doc.devices {
+        if let Some(ref load_profile) = device.load_profile {
+            parse_load_profile(load_profile)
+                .map_err(|e| format!("Device '{}': {}", device.name, e))?;
+        }
+    }
+
+    for rule in &doc.rules {
+        if RuleType::from_str(&rule.rule_type).is_none() {
+            return Err(format!("Rule '{}': invalid rule_type '{}'", rule.name, rule.rule_type));
+        }
+        if RuleAction::from_str(&rule.action).is_none() {
+            return Err(format!("Rule '{}': invalid action '{}'", rule.name, rule.action));
+        }
+        validate_rule_config(&rule.rule_type, &config_with_predecessor(rule, 0))
+            .map_err(|e| format!("Rule '{}': {}", rule.name, e))?;
+    }
+
+    Ok(doc)
+}
+
+/// Config of an imported rule with `after` resolved to the rule `after_rule_id`
+pub fn config_with_predecessor(rule: &ExportedRule, after_rule_id: i32) -> JsonValue {
+    let mut config = rule.config.clone();
+    if rule.after.is_some()
+        && let Some(fields) = config.as_object_mut()
+    {
+        fields.insert("after_rule_id".to_string(), serde_json::json!(after_rule_id));
+    }
+    config
+}
+
+/// Whether importing `exported` would change `device`. Older documents don't
+/// carry the rated power or load profile, which then stay as they are.
+fn device_differs(device: &Device, exported: &ExportedDevice) -> bool {
+    device.name != exported.name
+        || device.is_managed != exported.is_managed
+        || exported.rated_power_w.is_some_and(|w| device.rated_power_w != Some(w))
+        || exported
+            .load_profile
+            .as_ref()
+            .is_some_and(|p| device.load_profile.as_ref() != Some(p))
+}
+
+/// Work out what an import would do without touching the database
+pub fn plan_import(
+    doc: &ConfigDocument,
+    existing_devices: &[ExistingDevice],
+    existing_rules: &[AutomationRule],
+    policy: ConflictPolicy,
+) -> ImportPlan {
+    let find_device = |r: &DeviceRef| {
+        existing_devices
+            .iter()
+            .find(|d| d.provider == r.provider && d.device.external_id == r.external_id)
+            .map(|d| &d.device)
+    };
+
+    let mut report = ImportReport::default();
+    let mut device_updates = Vec::new();
+
+    for exported in &doc.devices {
+        let device_ref = DeviceRef {
+            provider: exported.provider.clone(),
+            external_id: exported.external_id.clone(),
+        };
+        match find_device(&device_ref) {
+            Some(device) => {
+                if device_differs(device, exported) {
+                    device_updates.push((device.id, exported.clone()));
+                }
+            }
+            None => report.conflicts.push(ImportConflict {
+                kind: ConflictKind::MissingDevice,
+                device: device_ref,
+                rule_name: None,
+            }),
+        }
+    }
+    report.devices_updated = device_updates.len();
+
+    let mut rule_actions = Vec::new();
+    for rule in &doc.rules {
+        let device = match find_device(&rule.device) {
+            Some(d) => d,
+            None => {
+                report.conflicts.push(ImportConflict {
+                    kind: ConflictKind::MissingDevice,
+                    device: rule.device.clone(),
+                    rule_name: Some(rule.name.clone()),
+                });
+                report.rules_skipped += 1;
+                rule_actions.push((RuleImportAction::Skip, rule.clone()));
+                continue;
+            }
+        };
+
+        let existing = existing_rules
+            .iter()
+            .find(|r| r.device_id == device.id && r.name == rule.name);
+
+        let action = match existing {
+            None => {
+                report.rules_created += 1;
+                RuleImportAction::Create { device_id: device.id }
+            }
+            Some(existing) => {
+                report.conflicts.push(ImportConflict {
+                    kind: ConflictKind::RuleExists,
+                    device: rule.device.clone(),
+                    rule_name: Some(rule.name.clone()),
+                });
+                if policy == ConflictPolicy::Overwrite {
+                    report.rules_updated += 1;
+                    RuleImportAction::Overwrite { rule_id: existing.id }
+                } else {
+                    report.rules_skipped += 1;
+                    RuleImportAction::Skip
+                }
+            }
+        };
+        rule_actions.push((action, rule.clone()));
+    }
+
+    // Chained rules follow a rule of the account or one the import creates.
+    // Skipping a rule can leave another without its predecessor, so repeat
+    // until nothing changes.
+    let predecessors = loop {
+        let mut predecessors = Vec::new();
+        let mut missing = None;
+        for (index, (action, rule)) in rule_actions.iter().enumerate() {
+            let Some(ref after) = rule.after else { continue };
+            if *action == RuleImportAction::Skip {
+                continue;
+            }
+            let device = find_device(&after.device);
+            let imported = rule_actions.iter().position(|(a, r)| {
+                matches!(a, RuleImportAction::Create { .. }) && r.device == after.device && r.name == after.name
+            });
+            let existing = device.and_then(|device| {
+                existing_rules
+                    .iter()
+                    .find(|r| r.device_id == device.id && r.name == after.name)
+            });
+            match (imported, existing) {
+                (Some(i), _) => predecessors.push((index, Predecessor::Imported(i))),
+                (None, Some(existing)) => predecessors.push((index, Predecessor::Existing(existing.id))),
+                (None, None) => {
+                    missing = Some(index);
+                    break;
+                }
+            }
+        }
+        let Some(index) = missing else { break predecessors };
+
+        let (action, rule) = &mut rule_actions[index];
+        match action {
+            RuleImportAction::Create { .. } => report.rules_created -= 1,
+            RuleImportAction::Overwrite { .. } => report.rules_updated -= 1,
+            RuleImportAction::Skip => {}
+        }
+        report.rules_skipped += 1;
+        *action = RuleImportAction::Skip;
+        report.conflicts.push(ImportConflict {
+            kind: ConflictKind::MissingPredecessor,
+            device: rule.device.clone(),
+            rule_name: Some(rule.name.clone()),
+        });
+    };
+
+    ImportPlan {
+        device_updates,
+        rule_actions,
+        predecessors,
+        report,
+    }
+}
+
+/// Rules as exported, referencing devices by `(provider, external_id)`.
+/// Rules of devices not in `devices` are left out.
+pub fn export_rules(rules: &[AutomationRule], devices: &[ExistingDevice]) -> Vec<ExportedRule> {
+    let device_ref = |device_id: i32| {
+        devices
+            .iter()
+            .find(|d| d.device.id == device_id)
+            .map(|d| DeviceRef {
+                provider: d.provider.clone(),
+                external_id: d.device.external_id.clone(),
+            })
+    };
+
+    // Rule ids mean nothing in another account: chained rules name their
+    // predecessor by device and name instead
+    let predecessor_ref = |rule: &AutomationRule| {
+        if rule.rule_type != RuleType::Chained.as_str() {
+            return None;
+        }
+        let after_rule_id = ChainedRuleConfig::from_config(&rule.config).ok()?.after_rule_id;
+        let predecessor = rules.iter().find(|r| r.id == after_rule_id)?;
+        Some(RuleRef {
+            device: device_ref(predecessor.device_id)?,
+            name: predecessor.name.clone(),
+        })
+    };
+
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let after = predecessor_ref(rule);
+            let mut config = rule.config.clone();
+            if after.is_some()
+                && let Some(fields) = config.as_object_mut()
+            {
+                fields.remove("after_rule_id");
+            }
+            Some(ExportedRule {
+                device: device_ref(rule.device_id)?,
+                name: rule.name.clone(),
+                rule_type: rule.rule_type.clone(),
+                action: rule.action.clone(),
+                config,
+                is_enabled: rule.is_enabled,
+                priority: rule.priority,
+                after,
+            })
+        })
+        .collect()
+}
+
+/// Service for exporting and importing a user's devices and rules
+pub struct ConfigTransferService {
+    pool: DbPool,
+}
+
+impl ConfigTransferService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    fn load_devices(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<ExistingDevice>, String> {
+        let rows: Vec<(Device, String)> = devices::table
+            .inner_join(user_integrations::table)
+            .filter(user_integrations::user_id.eq(user_id))
+            .select((Device::as_select(), user_integrations::provider_name))
+            .order(devices::id.asc())
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(device, provider)| ExistingDevice { device, provider })
+            .collect())
+    }
+
+    /// Export all devices and rules of a user
+    pub fn export(&self, user_id: i32) -> Result<ConfigDocument, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let existing = self.load_devices(&mut conn, user_id)?;
+        let rules: Vec<AutomationRule> = automation_rules::table
+            .filter(automation_rules::user_id.eq(user_id))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let exported_rules = export_rules(&rules, &existing);
+
+        let exported_devices = existing
+            .iter()
+            .map(|d| ExportedDevice {
+                provider: d.provider.clone(),
+                external_id: d.device.external_id.clone(),
+                name: d.device.name.clone(),
+                device_type: d.device.device_type.clone(),
+                is_managed: d.device.is_managed,
+                rated_power_w: d.device.rated_power_w,
+                load_profile: d.device.load_profile.clone(),
+            })
+            .collect();
+
+        Ok(ConfigDocument {
+            version: DOCUMENT_VERSION,
+            exported_at: Some(Utc::now().to_rfc3339()),
+            devices: exported_devices,
+            rules: exported_rules,
+        })
+    }
+
+    /// Import a document into a user's account.
+    /// With `dry_run` only the report is computed. With `ConflictPolicy::Fail`
+    /// the import is aborted (Err with the report) if any conflict is found.
+    pub fn import(
+        &self,
+        user_id: i32,
+        doc: &ConfigDocument,
+        policy: ConflictPolicy,
+        dry_run: bool,
+    ) -> Result<ImportReport, ImportError> {
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|e| ImportError::Database(e.to_string()))?;
+
+        let existing = self
+            .load_devices(&mut conn, user_id)
+            .map_err(ImportError::Database)?;
+        let existing_rules: Vec<AutomationRule> = automation_rules::table
+            .filter(automation_rules::user_id.eq(user_id))
+            .load(&mut conn)
+            .map_err(|e| ImportError::Database(e.to_string()))?;
+
+        let plan = plan_import(doc, &existing, &existing_rules, policy);
+        let mut report = plan.report;
+        report.dry_run = dry_run;
+
+        if policy == ConflictPolicy::Fail && !report.conflicts.is_empty() {
+            return Err(ImportError::Conflicts(report));
+        }
+
+        // Chained rules of older documents reference rule ids, which must
+        // exist in this account
+        for (action, rule) in &plan.rule_actions {
+            let rule_id = match action {
+                RuleImportAction::Create { .. } => None,
+                RuleImportAction::Overwrite { rule_id } => Some(*rule_id),
+                RuleImportAction::Skip => continue,
+            };
+            if rule.after.is_none() {
+                validate_rule_chain(&mut conn, user_id, rule_id, &rule.rule_type, &rule.config)
+                    .map_err(|e| ImportError::Invalid(format!("Rule '{}': {}", rule.name, e)))?;
+            }
+        }
+
+        if dry_run {
+            return Ok(report);
+        }
+
+        let touched_rules = conn
+            .transaction::<Vec<i32>, ImportError, _>(|conn| {
+                for (device_id, exported) in &plan.device_updates {
+                    diesel::update(devices::table.filter(devices::id.eq(device_id)))
+                        .set((
+                            devices::name.eq(&exported.name),
+                            devices::is_managed.eq(exported.is_managed),
+                        ))
+                        .execute(conn)?;
+                    // Older documents don't carry the rated power; keep the current one
+                    if let Some(rated_power_w) = exported.rated_power_w {
+                        diesel::update(devices::table.filter(devices::id.eq(device_id)))
+                            .set(devices::rated_power_w.eq(rated_power_w))
+                            .execute(conn)?;
+                    }
+                    if let Some(ref load_profile) = exported.load_profile {
+                        diesel::update(devices::table.filter(devices::id.eq(device_id)))
+                            .set(devices::load_profile.eq(load_profile))
+                            .execute(conn)?;
+                    }
+                }
+
+                // (before, after, change) of each written rule, by index of `rule_actions`
+                let mut written: Vec<(usize, Option<&AutomationRule>, AutomationRule, RuleChangeType)> = Vec::new();
+                for (index, (action, rule)) in plan.rule_actions.iter().enumerate() {
+                    match action {
+                        RuleImportAction::Create { device_id } => {
+                            let new_rule = NewAutomationRule {
+                                user_id,
+                                device_id: *device_id,
+                                name: rule.name.clone(),
+                                rule_type: rule.rule_type.clone(),
+                                action: rule.action.clone(),
+                                config: rule.config.clone(),
+                                is_enabled: rule.is_enabled,
+                                priority: rule.priority,
+                            };
+                            let created: AutomationRule =
+                                diesel::insert_into(automation_rules::table)
+                                    .values(&new_rule)
+                                    .get_result(conn)?;
+                            written.push((index, None, created, RuleChangeType::Create));
+                        }
+                        RuleImportAction::Overwrite { rule_id } => {
+                            let updated: AutomationRule = diesel::update(
+                                automation_rules::table.filter(automation_rules::id.eq(rule_id)),
+                            )
+                            .set((
+                                automation_rules::rule_type.eq(&rule.rule_type),
+                                automation_rules::action.eq(&rule.action),
+                                automation_rules::config.eq(&rule.config),
+                                automation_rules::is_enabled.eq(rule.is_enabled),
+                                automation_rules::priority.eq(rule.priority),
+                                automation_rules::updated_at.eq(Utc::now().naive_utc()),
+                            ))
+                            .get_result(conn)?;
+                            let before = existing_rules.iter().find(|r| r.id == *rule_id);
+                            written.push((index, before, updated, RuleChangeType::Update));
+                        }
+                        RuleImportAction::Skip => {}
+                    }
+                }
+
+                // Predecessors are resolved once every imported rule has its id
+                for (index, predecessor) in &plan.predecessors {
+                    let after_rule_id = match predecessor {
+                        Predecessor::Existing(rule_id) => *rule_id,
+                        Predecessor::Imported(i) => match written.iter().find(|(w, ..)| w == i) {
+                            Some((_, _, created, _)) => created.id,
+                            None => continue,
+                        },
+                    };
+                    let Some(entry) = written.iter_mut().find(|(w, ..)| w == index) else { continue };
+                    let config = config_with_predecessor(&plan.rule_actions[*index].1, after_rule_id);
+                    entry.2 = diesel::update(automation_rules::table.filter(automation_rules::id.eq(entry.2.id)))
+                        .set(automation_rules::config.eq(&config))
+                        .get_result(conn)?;
+                }
+
+                let mut touched = Vec::new();
+                for (_, before, after, change) in &written {
+                    validate_rule_chain(conn, user_id, Some(after.id), &after.rule_type, &after.config)
+                        .map_err(|e| ImportError::Invalid(format!("Rule '{}': {}", after.name, e)))?;
+                    record_rule_change(conn, user_id, *change, *before, Some(after))?;
+                    touched.push(after.id);
+                }
+                Ok(touched)
+            })?;
+
+        // Replan the user's rules around the imported ones
+        if !touched_rules.is_empty() {
+            let schedule_service = ScheduleComputationService::new(self.pool.clone());
+            let changed: Vec<(i32, i32)> = automation_rules::table
+                .filter(automation_rules::id.eq_any(&touched_rules))
+                .select((automation_rules::id, automation_rules::device_id))
+                .load(&mut conn)
+                .unwrap_or_default();
+            if let Err(e) = schedule_service.recompute_after_rules_change(user_id, &changed) {
+                warn!("Failed to compute schedules for imported rules: {}", e);
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Errors returned by an import
+#[derive(Debug)]
+pub enum ImportError {
+    /// Conflicts found with `ConflictPolicy::Fail`; nothing was written
+    Conflicts(ImportReport),
+    /// A rule can't be imported into this account
+    Invalid(String),
+    Database(String),
+}
+
+impl From<diesel::result::Error> for ImportError {
+    fn from(e: diesel::result::Error) -> Self {
+        ImportError::Database(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    fn device(id: i32, external_id: &str) -> ExistingDevice {
+        ExistingDevice {
+            device: Device {
+                id,
+                integration_id: 1,
+                external_id: external_id.to_string(),
+                name: format!("Device {}", id),
+                device_type: "plug".to_string(),
+                is_managed: true,
+                is_on: false,
+                rated_power_w: None,
+                load_profile: None,
+                capabilities: None,
+            },
+            provider: "meross".to_string(),
+        }
+    }
+
+    fn rule(id: i32, device_id: i32, name: &str) -> AutomationRule {
+        let ts = NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        AutomationRule {
+            id,
+            user_id: 1,
+            device_id,
+            name: name.to_string(),
+            rule_type: "cheapest_hours".to_string(),
+            action: "turn_on".to_string(),
+            config: json!({}),
+            is_enabled: true,
+            priority: 100,
+            created_at: ts,
+            updated_at: ts,
+            last_triggered_at: None,
+        }
+    }
+
+    fn sample_doc() -> ConfigDocument {
+        parse_document(
+            r#"
+version: 1
+devices:
+  - provider: meross
+    external_id: plug-1
+    name: Boiler
+    device_type: plug
+rules:
+  - name: Night heating
+    device: { provider: meross, external_id: plug-1 }
+    rule_type: cheapest_hours
+    action: turn_on
+    config: { cheapest_hours: 3 }
+  - name: Pool
+    device: { provider: meross, external_id: plug-9 }
+    rule_type: cheapest_hours
+    action: turn_on
+"#,
+            DocumentFormat::Yaml,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_document_rejects_unknown_version() {
+        let err = parse_document(r#"{"version": 2}"#, DocumentFormat::Json).unwrap_err();
+        assert!(err.contains("Unsupported document version"));
+    }
+
+    #[test]
+    fn test_document_roundtrip_json_and_yaml() {
+        let doc = sample_doc();
+        for format in [DocumentFormat::Json, DocumentFormat::Yaml] {
+            let text = serialize_document(&doc, format).unwrap();
+            let parsed = parse_document(&text, format).unwrap();
+            assert_eq!(parsed.rules.len(), 2);
+            assert_eq!(parsed.rules[0].config["cheapest_hours"], 3);
+            assert_eq!(parsed.rules[1].priority, 100);
+        }
+    }
+
+    #[test]
+    fn test_plan_import_detects_missing_devices_and_creates_rules() {
+        let plan = plan_import(&sample_doc(), &[device(5, "plug-1")], &[], ConflictPolicy::Skip);
+
+        assert_eq!(plan.report.devices_updated, 1); // name differs
+        assert_eq!(plan.report.rules_created, 1);
+        assert_eq!(plan.report.rules_skipped, 1);
+        assert_eq!(plan.report.conflicts.len(), 1);
+        assert_eq!(plan.report.conflicts[0].kind, ConflictKind::MissingDevice);
+        assert_eq!(plan.rule_actions[0].0, RuleImportAction::Create { device_id: 5 });
+    }
+
+    #[test]
+    fn test_plan_import_updates_rated_power() {
+        let mut doc = sample_doc();
+        doc.devices[0].name = "Device 5".to_string();
+        let existing = [device(5, "plug-1")];
+
+        let plan = plan_import(&doc, &existing, &[], ConflictPolicy::Skip);
+        assert_eq!(plan.report.devices_updated, 0);
+
+        doc.devices[0].rated_power_w = Some(2000);
+        let plan = plan_import(&doc, &existing, &[], ConflictPolicy::Skip);
+        assert_eq!(plan.report.devices_updated, 1);
+        assert_eq!(plan.device_updates[0].0, 5);
+    }
+
+    #[test]
+    fn test_plan_import_updates_load_profile() {
+        let mut doc = sample_doc();
+        doc.devices[0].name = "Device 5".to_string();
+        let mut existing = device(5, "plug-1");
+        existing.device.load_profile = Some(json!([2000, 2000, 150]));
+
+        doc.devices[0].load_profile = Some(json!([2000, 2000, 150]));
+        let plan = plan_import(&doc, std::slice::from_ref(&existing), &[], ConflictPolicy::Skip);
+        assert_eq!(plan.report.devices_updated, 0);
+
+        doc.devices[0].load_profile = Some(json!([1800, 1800, 1800, 100]));
+        let plan = plan_import(&doc, &[existing], &[], ConflictPolicy::Skip);
+        assert_eq!(plan.report.devices_updated, 1);
+    }
+
+    fn chained(id: i32, device_id: i32, name: &str, after_rule_id: i32) -> AutomationRule {
+        AutomationRule {
+            rule_type: "chained".to_string(),
+            config: json!({"after_rule_id": after_rule_id, "hours_needed": 2}),
+            ..rule(id, device_id, name)
+        }
+    }
+
+    #[test]
+    fn test_chained_rules_round_trip_by_reference() {
+        // Washer (rule 7) and dryer following it, on two plugs
+        let source_devices = [device(1, "plug-1"), device(2, "plug-2")];
+        let rules = [rule(7, 1, "Washer"), chained(8, 2, "Dryer", 7)];
+        let doc = ConfigDocument {
+            version: DOCUMENT_VERSION,
+            exported_at: None,
+            devices: Vec::new(),
+            rules: export_rules(&rules, &source_devices),
+        };
+        let text = serialize_document(&doc, DocumentFormat::Yaml).unwrap();
+        assert!(!text.contains("after_rule_id"));
+        let doc = parse_document(&text, DocumentFormat::Yaml).unwrap();
+        let after = doc.rules[1].after.clone().unwrap();
+        assert_eq!(after.name, "Washer");
+        assert_eq!(after.device.external_id, "plug-1");
+
+        // Another account with the same plugs under other ids: the dryer
+        // follows the washer the import creates
+        let target_devices = [device(11, "plug-1"), device(12, "plug-2")];
+        let plan = plan_import(&doc, &target_devices, &[], ConflictPolicy::Skip);
+        assert_eq!(plan.report.rules_created, 2);
+        assert_eq!(plan.predecessors, vec![(1, Predecessor::Imported(0))]);
+        let config = config_with_predecessor(&plan.rule_actions[1].1, 31);
+        assert_eq!(config["after_rule_id"], 31);
+        assert_eq!(config["hours_needed"], 2);
+
+        // An account that already has the washer keeps it as the predecessor
+        let plan = plan_import(&doc, &target_devices, &[rule(21, 11, "Washer")], ConflictPolicy::Skip);
+        assert_eq!(plan.predecessors, vec![(1, Predecessor::Existing(21))]);
+
+        // Without the washer's plug the dryer has nothing to follow
+        let plan = plan_import(&doc, &[device(12, "plug-2")], &[], ConflictPolicy::Skip);
+        assert!(plan.predecessors.is_empty());
+        assert_eq!(plan.rule_actions[1].0, RuleImportAction::Skip);
+        assert_eq!(plan.report.rules_created, 0);
+        assert_eq!(plan.report.rules_skipped, 2);
+        assert!(plan
+            .report
+            .conflicts
+            .iter()
+            .any(|c| c.kind == ConflictKind::MissingPredecessor && c.rule_name.as_deref() == Some("Dryer")));
+    }
+
+    #[test]
+    fn test_plan_import_conflict_policies() {
+        let devices = [device(5, "plug-1")];
+        let rules = [rule(42, 5, "Night heating")];
+
+        let skip = plan_import(&sample_doc(), &devices, &rules, ConflictPolicy::Skip);
+        assert_eq!(skip.rule_actions[0].0, RuleImportAction::Skip);
+        assert_eq!(skip.report.rules_created, 0);
+
+        let overwrite = plan_import(&sample_doc(), &devices, &rules, ConflictPolicy::Overwrite);
+        assert_eq!(overwrite.rule_actions[0].0, RuleImportAction::Overwrite { rule_id: 42 });
+        assert_eq!(overwrite.report.rules_updated, 1);
+        assert!(overwrite
+            .report
+            .conflicts
+            .iter()
+            .any(|c| c.kind == ConflictKind::RuleExists));
+    }
+}
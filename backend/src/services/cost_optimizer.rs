@@ -0,0 +1,771 @@
+use crate::db::DbPool;
+use crate::models::{AutomationRule, ExecutionStatus, NewScheduledExecution, Price, RuleAction};
+use crate::schema::{automation_rules, devices, optimized_rule_dates, scheduled_executions, users};
+use crate::services::schedule_computation::{planned_loads, ScheduleComputationService};
+use crate::services::scheduler::{hourly_load_w, plan_within_power_limit, PlannedLoad, PowerBudget, PowerDemand};
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+use diesel::prelude::*;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Search nodes explored before settling for the best plan found so far
+pub const MAX_SEARCH_NODES: usize = 200_000;
+
+/// Power assumed for the cost of a device without a rated power, in watts
+const UNKNOWN_POWER_COST_W: f64 = 1000.0;
+
+const EPSILON: f64 = 1e-9;
+
+/// A load that can run in any `hours_needed` of its candidate hours
+#[derive(Debug, Clone)]
+pub struct FlexibleLoad {
+    pub rule_id: i32,
+    /// Power drawn when on (0 when unknown: never limited)
+    pub power_w: f64,
+    pub hours_needed: usize,
+    pub contiguous: bool,
+    pub candidates: Vec<Price>,
+}
+
+impl FlexibleLoad {
+    /// Cost of running one hour at `price`, in €
+    fn hour_cost(&self, price: f64) -> f64 {
+        let power_w = if self.power_w > 0.0 {
+            self.power_w
+        } else {
+            UNKNOWN_POWER_COST_W
+        };
+        price * power_w / 1000.0
+    }
+
+    /// Cost of running in the given hours, in €
+    pub fn cost_of(&self, hours: &[NaiveDateTime]) -> f64 {
+        self.candidates
+            .iter()
+            .filter(|p| hours.contains(&p.timestamp))
+            .map(|p| self.hour_cost(p.price))
+            .sum()
+    }
+}
+
+/// Joint schedule of several loads
+#[derive(Debug, Clone)]
+pub struct JointPlan {
+    /// Hours per load (same order as the input); None if the load didn't fit
+    pub placements: Vec<Option<Vec<NaiveDateTime>>>,
+    pub cost: f64,
+    /// False if the search stopped at `MAX_SEARCH_NODES`
+    pub optimal: bool,
+}
+
+/// Plan loads one after the other, each taking its cheapest hours that still
+/// fit (the per-rule behaviour of the schedule computation)
+pub fn greedy_plan(
+    loads: &[FlexibleLoad],
+    fixed_load_w: &HashMap<NaiveDateTime, f64>,
+    limit_w: f64,
+) -> JointPlan {
+    let mut load_w = fixed_load_w.clone();
+    let mut placements = Vec::new();
+    let mut cost = 0.0;
+
+    for load in loads {
+        let demand = PowerDemand::Flexible {
+            candidates: load.candidates.clone(),
+            hours_needed: load.hours_needed,
+            contiguous: load.contiguous,
+        };
+        let budget = PowerBudget {
+            power_w: load.power_w,
+            limit_w,
+            load_w: load_w.clone(),
+        };
+        let hours = plan_within_power_limit(&demand, &budget);
+        for hour in &hours {
+            *load_w.entry(*hour).or_default() += load.power_w;
+        }
+        cost += load.cost_of(&hours);
+        placements.push((!hours.is_empty()).then_some(hours));
+    }
+
+    JointPlan {
+        placements,
+        cost,
+        optimal: false,
+    }
+}
+
+/// Ways a single load can be placed, cheapest first
+enum LoadOptions {
+    /// Contiguous blocks with their cost
+    Blocks(Vec<(Vec<NaiveDateTime>, f64)>),
+    /// Candidate hours sorted by cost, with prefix sums of their costs
+    Hours(Vec<(NaiveDateTime, f64)>, Vec<f64>),
+}
+
+impl LoadOptions {
+    fn new(load: &FlexibleLoad) -> Self {
+        let mut prices = load.candidates.clone();
+        prices.sort_by_key(|p| p.timestamp);
+        prices.dedup_by_key(|p| p.timestamp);
+
+        if load.contiguous {
+            let needed = load.hours_needed.max(1);
+            let mut blocks: Vec<(Vec<NaiveDateTime>, f64)> = prices
+                .windows(needed)
+                .filter(|w| (w[needed - 1].timestamp - w[0].timestamp).num_hours() == needed as i64 - 1)
+                .map(|w| {
+                    let cost = w.iter().map(|p| load.hour_cost(p.price)).sum();
+                    (w.iter().map(|p| p.timestamp).collect(), cost)
+                })
+                .collect();
+            blocks.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            LoadOptions::Blocks(blocks)
+        } else {
+            let mut hours: Vec<(NaiveDateTime, f64)> = prices
+                .iter()
+                .map(|p| (p.timestamp, load.hour_cost(p.price)))
+                .collect();
+            hours.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+            let mut prefix = vec![0.0];
+            for (_, cost) in &hours {
+                prefix.push(prefix.last().unwrap() + cost);
+            }
+            LoadOptions::Hours(hours, prefix)
+        }
+    }
+
+    /// Cost of the cheapest placement ignoring the power limit
+    fn min_cost(&self, needed: usize) -> Option<f64> {
+        match self {
+            LoadOptions::Blocks(blocks) => blocks.first().map(|(_, cost)| *cost),
+            LoadOptions::Hours(hours, prefix) => (hours.len() >= needed).then(|| prefix[needed]),
+        }
+    }
+}
+
+/// Branch-and-bound search over the placements of all loads
+struct Solver<'a> {
+    loads: &'a [FlexibleLoad],
+    options: Vec<LoadOptions>,
+    /// Lower bound of the cost of loads `i..`
+    suffix_min: Vec<f64>,
+    limit_w: f64,
+    load_w: HashMap<NaiveDateTime, f64>,
+    current: Vec<Vec<NaiveDateTime>>,
+    best: Option<Vec<Vec<NaiveDateTime>>>,
+    best_cost: f64,
+    nodes: usize,
+    aborted: bool,
+}
+
+impl<'a> Solver<'a> {
+    fn new(loads: &'a [FlexibleLoad], fixed_load_w: &HashMap<NaiveDateTime, f64>, limit_w: f64) -> Option<Self> {
+        let options: Vec<LoadOptions> = loads.iter().map(LoadOptions::new).collect();
+        let min_costs: Option<Vec<f64>> = loads
+            .iter()
+            .zip(&options)
+            .map(|(load, opts)| opts.min_cost(load.hours_needed))
+            .collect();
+        let min_costs = min_costs?;
+
+        let mut suffix_min = vec![0.0; loads.len() + 1];
+        for i in (0..loads.len()).rev() {
+            suffix_min[i] = suffix_min[i + 1] + min_costs[i];
+        }
+
+        Some(Self {
+            loads,
+            options,
+            suffix_min,
+            limit_w,
+            load_w: fixed_load_w.clone(),
+            current: vec![Vec::new(); loads.len()],
+            best: None,
+            best_cost: f64::INFINITY,
+            nodes: 0,
+            aborted: false,
+        })
+    }
+
+    fn fits(&self, hour: &NaiveDateTime, power_w: f64) -> bool {
+        self.load_w.get(hour).copied().unwrap_or(0.0) + power_w <= self.limit_w
+    }
+
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes > MAX_SEARCH_NODES {
+            self.aborted = true;
+        }
+        !self.aborted
+    }
+
+    fn place(&mut self, hour: NaiveDateTime, power_w: f64) {
+        *self.load_w.entry(hour).or_default() += power_w;
+    }
+
+    fn unplace(&mut self, hour: NaiveDateTime, power_w: f64) {
+        *self.load_w.entry(hour).or_default() -= power_w;
+    }
+
+    fn search_load(&mut self, i: usize, cost: f64) {
+        if !self.visit() || cost + self.suffix_min[i] >= self.best_cost - EPSILON {
+            return;
+        }
+        if i == self.loads.len() {
+            self.best = Some(self.current.clone());
+            self.best_cost = cost;
+            return;
+        }
+
+        let power_w = self.loads[i].power_w;
+        if let LoadOptions::Blocks(blocks) = &self.options[i] {
+            let blocks = blocks.clone();
+            for (hours, block_cost) in blocks {
+                // Blocks are sorted by cost: the rest can only be worse
+                if cost + block_cost + self.suffix_min[i + 1] >= self.best_cost - EPSILON {
+                    break;
+                }
+                if !hours.iter().all(|h| self.fits(h, power_w)) {
+                    continue;
+                }
+                hours.iter().for_each(|h| self.place(*h, power_w));
+                self.current[i] = hours.clone();
+                self.search_load(i + 1, cost + block_cost);
+                self.current[i].clear();
+                hours.iter().for_each(|h| self.unplace(*h, power_w));
+                if self.aborted {
+                    return;
+                }
+            }
+        } else {
+            self.pick_hours(i, 0, cost);
+        }
+    }
+
+    /// Decide, cheapest first, whether each candidate hour is used by load `i`
+    fn pick_hours(&mut self, i: usize, pos: usize, cost: f64) {
+        if !self.visit() {
+            return;
+        }
+        let remaining = self.loads[i].hours_needed - self.current[i].len();
+        if remaining == 0 {
+            self.search_load(i + 1, cost);
+            return;
+        }
+
+        let LoadOptions::Hours(hours, prefix) = &self.options[i] else {
+            return;
+        };
+        if hours.len() - pos < remaining {
+            return;
+        }
+        let bound = cost + prefix[pos + remaining] - prefix[pos] + self.suffix_min[i + 1];
+        if bound >= self.best_cost - EPSILON {
+            return;
+        }
+
+        let (hour, hour_cost) = hours[pos];
+        let power_w = self.loads[i].power_w;
+        if self.fits(&hour, power_w) {
+            self.place(hour, power_w);
+            self.current[i].push(hour);
+            self.pick_hours(i, pos + 1, cost + hour_cost);
+            self.current[i].pop();
+            self.unplace(hour, power_w);
+        }
+        self.pick_hours(i, pos + 1, cost);
+    }
+}
+
+/// Find the minimum-cost joint schedule of `loads` (sorted by priority) that
+/// keeps every hour within `limit_w`. If no schedule fits them all, the lowest
+/// priority loads are left out until one does.
+pub fn optimize_loads(
+    loads: &[FlexibleLoad],
+    fixed_load_w: &HashMap<NaiveDateTime, f64>,
+    limit_w: f64,
+) -> JointPlan {
+    // Loads without enough candidate hours can never be placed
+    let placeable: Vec<usize> = (0..loads.len())
+        .filter(|&i| LoadOptions::new(&loads[i]).min_cost(loads[i].hours_needed).is_some())
+        .collect();
+    let subset: Vec<FlexibleLoad> = placeable.iter().map(|&i| loads[i].clone()).collect();
+
+    let greedy = greedy_plan(&subset, fixed_load_w, limit_w);
+    let greedy_complete = subset
+        .iter()
+        .zip(&greedy.placements)
+        .all(|(load, p)| p.as_ref().is_some_and(|h| h.len() == load.hours_needed));
+
+    let mut placements = vec![None; loads.len()];
+    let mut cost = greedy.cost;
+    let mut optimal = false;
+    let mut found = false;
+
+    for included in (0..=subset.len()).rev() {
+        let Some(mut solver) = Solver::new(&subset[..included], fixed_load_w, limit_w) else {
+            continue;
+        };
+        // The greedy plan is a valid upper bound when it placed everything
+        if greedy_complete && included == subset.len() {
+            solver.best = Some(greedy.placements.iter().flatten().cloned().collect());
+            solver.best_cost = greedy.cost;
+        }
+        solver.search_load(0, 0.0);
+
+        if let Some(best) = solver.best {
+            for (k, mut hours) in best.into_iter().enumerate() {
+                hours.sort();
+                placements[placeable[k]] = Some(hours);
+            }
+            cost = solver.best_cost;
+            optimal = !solver.aborted;
+            found = true;
+            break;
+        }
+        if solver.aborted {
+            break;
+        }
+    }
+
+    if !found {
+        for (k, hours) in greedy.placements.into_iter().enumerate() {
+            placements[placeable[k]] = hours;
+        }
+    }
+
+    JointPlan {
+        placements,
+        cost,
+        optimal,
+    }
+}
+
+/// What the joint plan saves over the greedy one, counting only loads both
+/// plans run in full: a load one of them drops or cuts short serves less
+/// energy, so its cost isn't comparable
+pub fn comparable_savings(loads: &[FlexibleLoad], greedy: &JointPlan, plan: &JointPlan) -> f64 {
+    loads
+        .iter()
+        .enumerate()
+        .filter_map(|(i, load)| {
+            let served = |hours: &Option<Vec<NaiveDateTime>>| {
+                hours.as_ref().filter(|h| h.len() == load.hours_needed).cloned()
+            };
+            let greedy_hours = served(&greedy.placements[i])?;
+            let hours = served(&plan.placements[i])?;
+            Some(load.cost_of(&greedy_hours) - load.cost_of(&hours))
+        })
+        .sum()
+}
+
+/// Planned hours and cost of one rule
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadReport {
+    pub rule_id: i32,
+    pub rule_name: String,
+    pub device_id: i32,
+    pub power_w: Option<i32>,
+    pub hours_needed: usize,
+    pub contiguous: bool,
+    pub hours: Vec<NaiveDateTime>,
+    pub cost_eur: f64,
+    pub greedy_hours: Vec<NaiveDateTime>,
+    pub greedy_cost_eur: f64,
+}
+
+/// Result of optimising a user's flexible loads for a day
+#[derive(Debug, Clone, Serialize)]
+pub struct OptimizationReport {
+    pub date: NaiveDate,
+    pub contracted_power_kw: Option<f64>,
+    /// False if the search was cut short and the plan may not be the cheapest
+    pub optimal: bool,
+    pub applied: bool,
+    pub loads: Vec<LoadReport>,
+    /// Rules that could not be fitted within the contracted power
+    pub unscheduled_rule_ids: Vec<i32>,
+    pub expected_cost_eur: f64,
+    pub greedy_cost_eur: f64,
+    /// Savings over the loads both plans run in full
+    pub savings_eur: f64,
+}
+
+/// Service that jointly schedules a user's flexible loads
+pub struct CostOptimizerService {
+    pool: DbPool,
+}
+
+impl CostOptimizerService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Optimise the flexible loads of the user's enabled turn_on rules for a
+    /// date around the power their fixed loads reserve and, if `apply` is
+    /// set, replace the pending executions of the rules that fit
+    pub fn optimize(&self, user_id: i32, date: NaiveDate, apply: bool) -> Result<OptimizationReport, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let schedule_service = ScheduleComputationService::new(self.pool.clone());
+
+        let contracted_power_kw: Option<f64> = users::table
+            .filter(users::id.eq(user_id))
+            .select(users::contracted_power_kw)
+            .first(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let limit_w = contracted_power_kw.map_or(f64::INFINITY, |kw| kw * 1000.0);
+
+        let rules: Vec<(AutomationRule, Option<i32>)> = automation_rules::table
+            .inner_join(devices::table)
+            .filter(automation_rules::user_id.eq(user_id))
+            .filter(automation_rules::is_enabled.eq(true))
+            .filter(automation_rules::action.eq(RuleAction::TurnOn.as_str()))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .select((AutomationRule::as_select(), devices::rated_power_w))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        // Hours up to the current one are already decided
+        let now = Local::now().naive_local();
+        let horizon_start = now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+        let active_statuses = [
+            ExecutionStatus::Executed.as_str(),
+            ExecutionStatus::Pending.as_str(),
+            ExecutionStatus::Retrying.as_str(),
+        ];
+
+        let mut loads = Vec::new();
+        let mut load_rules = Vec::new();
+        let mut reserved = Vec::new();
+        for (rule, power_w) in rules {
+            let demand = schedule_service.calculate_demand_for_rule(&rule, date)?;
+            let (candidates, hours_needed, contiguous) = match demand {
+                PowerDemand::Flexible {
+                    candidates,
+                    hours_needed,
+                    contiguous,
+                } => (candidates, hours_needed, contiguous),
+                // Fixed loads keep their hours and reserve their power
+                PowerDemand::Fixed(hours) => {
+                    reserved.extend(
+                        hours
+                            .into_iter()
+                            .filter(|h| *h >= horizon_start)
+                            .map(|at| PlannedLoad {
+                                at,
+                                device_id: rule.device_id,
+                                power_w,
+                                cycle: None,
+                            }),
+                    );
+                    continue;
+                }
+            };
+            let (Some(first), Some(last)) = (
+                candidates.iter().map(|p| p.timestamp).min(),
+                candidates.iter().map(|p| p.timestamp).max(),
+            ) else {
+                continue;
+            };
+
+            // Hours of the window that already ran count towards the runtime
+            let done: i64 = scheduled_executions::table
+                .filter(scheduled_executions::rule_id.eq(rule.id))
+                .filter(scheduled_executions::scheduled_hour.ge(first))
+                .filter(scheduled_executions::scheduled_hour.le(last))
+                .filter(scheduled_executions::scheduled_hour.lt(horizon_start))
+                .filter(scheduled_executions::status.eq_any(active_statuses))
+                .count()
+                .get_result(&mut conn)
+                .map_err(|e| e.to_string())?;
+            let hours_needed = hours_needed.saturating_sub(done as usize);
+            let candidates: Vec<Price> = candidates
+                .into_iter()
+                .filter(|p| p.timestamp >= horizon_start)
+                .collect();
+            if hours_needed == 0 || candidates.is_empty() {
+                continue;
+            }
+
+            loads.push(FlexibleLoad {
+                rule_id: rule.id,
+                power_w: power_w.unwrap_or(0) as f64,
+                hours_needed,
+                contiguous,
+                candidates,
+            });
+            load_rules.push((rule, power_w));
+        }
+
+        let fixed_load_w = self.fixed_load(&mut conn, user_id, &loads, reserved)?;
+        let greedy = greedy_plan(&loads, &fixed_load_w, limit_w);
+        let plan = optimize_loads(&loads, &fixed_load_w, limit_w);
+
+        let mut reports = Vec::new();
+        let mut unscheduled_rule_ids = Vec::new();
+        for (i, (load, (rule, power_w))) in loads.iter().zip(&load_rules).enumerate() {
+            let hours = plan.placements[i].clone().unwrap_or_default();
+            let greedy_hours = greedy.placements[i].clone().unwrap_or_default();
+            if hours.is_empty() {
+                unscheduled_rule_ids.push(rule.id);
+            }
+            reports.push(LoadReport {
+                rule_id: rule.id,
+                rule_name: rule.name.clone(),
+                device_id: rule.device_id,
+                power_w: *power_w,
+                hours_needed: load.hours_needed,
+                contiguous: load.contiguous,
+                cost_eur: load.cost_of(&hours),
+                hours,
+                greedy_cost_eur: load.cost_of(&greedy_hours),
+                greedy_hours,
+            });
+        }
+
+        if apply {
+            self.apply_plan(&mut conn, &load_rules, &loads, &plan, date, horizon_start)?;
+            for (rule, _) in &load_rules {
+                schedule_service.replan_successors(rule.id)?;
+            }
+            info!(
+                "Applied optimised schedule for user {} on {} ({:.4} € vs {:.4} € greedy)",
+                user_id, date, plan.cost, greedy.cost
+            );
+        }
+        if !plan.optimal {
+            warn!("Optimiser search for user {} on {} was cut short", user_id, date);
+        }
+
+        Ok(OptimizationReport {
+            date,
+            contracted_power_kw,
+            optimal: plan.optimal,
+            applied: apply,
+            loads: reports,
+            unscheduled_rule_ids,
+            expected_cost_eur: plan.cost,
+            greedy_cost_eur: greedy.cost,
+            savings_eur: comparable_savings(&loads, &greedy, &plan),
+        })
+    }
+
+    /// Power per hour of the `reserved` fixed loads and the user's other
+    /// planned executions (not being optimised)
+    fn fixed_load(
+        &self,
+        conn: &mut PgConnection,
+        user_id: i32,
+        loads: &[FlexibleLoad],
+        reserved: Vec<PlannedLoad>,
+    ) -> Result<HashMap<NaiveDateTime, f64>, String> {
+        let hours = loads.iter().flat_map(|l| l.candidates.iter().map(|p| p.timestamp));
+        let (Some(from), Some(to)) = (hours.clone().min(), hours.max()) else {
+            return Ok(HashMap::new());
+        };
+        let rule_ids: Vec<i32> = loads.iter().map(|l| l.rule_id).collect();
+
+        let mut planned: Vec<PlannedLoad> = planned_loads(conn, user_id, from, to)?
+            .into_iter()
+            .filter(|(rule_id, _)| !rule_ids.contains(rule_id))
+            .map(|(_, load)| load)
+            .collect();
+        // A device already planned in an hour draws its power once
+        planned.extend(reserved);
+        Ok(hourly_load_w(&planned))
+    }
+
+    /// Replace the future pending executions of the optimised rules in their
+    /// windows, and mark the rules so the per-rule planner keeps this plan.
+    /// Rules that didn't fit keep their own (greedy) plan.
+    fn apply_plan(
+        &self,
+        conn: &mut PgConnection,
+        load_rules: &[(AutomationRule, Option<i32>)],
+        loads: &[FlexibleLoad],
+        plan: &JointPlan,
+        date: NaiveDate,
+        horizon_start: NaiveDateTime,
+    ) -> Result<(), String> {
+        conn.transaction::<(), diesel::result::Error, _>(|conn| {
+            for (i, (rule, _)) in load_rules.iter().enumerate() {
+                let Some(hours) = &plan.placements[i] else { continue };
+                let last = loads[i].candidates.iter().map(|p| p.timestamp).max();
+                let Some(last) = last else { continue };
+
+                diesel::delete(
+                    scheduled_executions::table
+                        .filter(scheduled_executions::rule_id.eq(rule.id))
+                        .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
+                        .filter(scheduled_executions::scheduled_hour.ge(horizon_start))
+                        .filter(scheduled_executions::scheduled_hour.le(last)),
+                )
+                .execute(conn)?;
+
+                let new_executions: Vec<NewScheduledExecution> = hours
+                    .iter()
+                    .map(|hour| NewScheduledExecution {
+                        rule_id: rule.id,
+                        scheduled_hour: *hour,
+                        expected_action: rule.action.clone(),
+                        status: ExecutionStatus::Pending.as_str().to_string(),
+                        setpoint_c: None,
+                    })
+                    .collect();
+                diesel::insert_into(scheduled_executions::table)
+                    .values(&new_executions)
+                    .on_conflict((
+                        scheduled_executions::rule_id,
+                        scheduled_executions::scheduled_hour,
+                    ))
+                    .do_nothing()
+                    .execute(conn)?;
+
+                diesel::insert_into(optimized_rule_dates::table)
+                    .values((
+                        optimized_rule_dates::rule_id.eq(rule.id),
+                        optimized_rule_dates::date.eq(date),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hour(h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 18)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    fn prices(values: &[f64]) -> Vec<Price> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(h, p)| Price {
+                timestamp: hour(h as u32),
+                price: *p,
+                source: "test".to_string(),
+            })
+            .collect()
+    }
+
+    fn load(rule_id: i32, power_w: f64, hours_needed: usize, contiguous: bool, candidates: &[Price]) -> FlexibleLoad {
+        FlexibleLoad {
+            rule_id,
+            power_w,
+            hours_needed,
+            contiguous,
+            candidates: candidates.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_optimizer_beats_greedy_under_power_limit() {
+        // The high priority heater grabs the cheap hour 1, which pushes the
+        // 2h contiguous EV block onto an expensive hour; swapping is cheaper
+        let day = prices(&[0.30, 0.05, 0.06, 0.30, 0.10, 0.30]);
+        let loads = vec![
+            load(1, 2000.0, 1, false, &day),
+            load(2, 3000.0, 2, true, &day),
+        ];
+        let limit = 4600.0;
+
+        let greedy = greedy_plan(&loads, &HashMap::new(), limit);
+        assert_eq!(greedy.placements[0], Some(vec![hour(1)]));
+        assert_eq!(greedy.placements[1], Some(vec![hour(2), hour(3)]));
+
+        let plan = optimize_loads(&loads, &HashMap::new(), limit);
+        assert!(plan.optimal);
+        assert_eq!(plan.placements[0], Some(vec![hour(4)]));
+        assert_eq!(plan.placements[1], Some(vec![hour(1), hour(2)]));
+        assert!(plan.cost < greedy.cost);
+        assert!((plan.cost - (0.2 + 0.15 + 0.18)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_savings_ignore_loads_not_served_in_full() {
+        let day = prices(&[0.05, 0.10, 0.20]);
+        let loads = vec![load(1, 1000.0, 1, false, &day), load(2, 1000.0, 2, false, &day)];
+        let greedy = JointPlan {
+            placements: vec![Some(vec![hour(2)]), Some(vec![hour(0), hour(1)])],
+            cost: 0.35,
+            optimal: false,
+        };
+        // The joint plan drops load 2, so only load 1 is compared
+        let plan = JointPlan {
+            placements: vec![Some(vec![hour(0)]), None],
+            cost: 0.05,
+            optimal: true,
+        };
+        assert!((comparable_savings(&loads, &greedy, &plan) - 0.15).abs() < 1e-9);
+
+        // A load the greedy plan cut short isn't compared either
+        let greedy = JointPlan {
+            placements: vec![Some(vec![hour(2)]), Some(vec![hour(0)])],
+            cost: 0.25,
+            optimal: false,
+        };
+        let plan = JointPlan {
+            placements: vec![Some(vec![hour(0)]), Some(vec![hour(1), hour(2)])],
+            cost: 0.35,
+            optimal: true,
+        };
+        assert!((comparable_savings(&loads, &greedy, &plan) - 0.15).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_optimizer_respects_fixed_load() {
+        let day = prices(&[0.05, 0.10, 0.20]);
+        let fixed = HashMap::from([(hour(0), 4000.0)]);
+        let loads = vec![load(1, 1000.0, 1, false, &day)];
+
+        let plan = optimize_loads(&loads, &fixed, 4600.0);
+        assert_eq!(plan.placements[0], Some(vec![hour(1)]));
+    }
+
+    #[test]
+    fn test_optimizer_drops_lowest_priority_when_infeasible() {
+        let day = prices(&[0.05, 0.10]);
+        let loads = vec![
+            load(1, 3000.0, 2, false, &day),
+            load(2, 3000.0, 1, false, &day),
+        ];
+
+        let plan = optimize_loads(&loads, &HashMap::new(), 4600.0);
+        assert_eq!(plan.placements[0], Some(vec![hour(0), hour(1)]));
+        assert_eq!(plan.placements[1], None);
+    }
+
+    #[test]
+    fn test_optimizer_skips_loads_that_never_fit() {
+        let day = prices(&[0.05, 0.10]);
+        // Needs a 3h block but only 2 hours are known
+        let loads = vec![load(1, 1000.0, 3, true, &day), load(2, 1000.0, 1, false, &day)];
+
+        let plan = optimize_loads(&loads, &HashMap::new(), 4600.0);
+        assert_eq!(plan.placements[0], None);
+        assert_eq!(plan.placements[1], Some(vec![hour(0)]));
+    }
+
+    #[test]
+    fn test_optimizer_without_limit_matches_cheapest_hours() {
+        let day = prices(&[0.30, 0.05, 0.20, 0.01]);
+        let loads = vec![load(1, 0.0, 2, false, &day)];
+
+        let plan = optimize_loads(&loads, &HashMap::new(), f64::INFINITY);
+        assert_eq!(plan.placements[0], Some(vec![hour(1), hour(3)]));
+        // Unknown power is costed as 1 kW
+        assert!((plan.cost - 0.06).abs() < 1e-9);
+    }
+}
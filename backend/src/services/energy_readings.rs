@@ -0,0 +1,230 @@
+use crate::db::DbPool;
+use crate::integrations::{DailyEnergy, ProviderError, ProviderRegistry};
+use crate::models::{Device, EnergyReading, NewEnergyReading, Price, UserIntegration};
+use crate::schema::{devices, energy_readings, prices, user_integrations};
+use chrono::{NaiveDate, Utc};
+use diesel::prelude::*;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A day of a device's energy with its estimated cost
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyEnergyCost {
+    pub date: NaiveDate,
+    pub energy_kwh: f64,
+    /// Average price of the day, in €/kWh; None without prices for the day
+    pub average_price: Option<f64>,
+    /// Energy at the day's average price; None without prices for the day
+    pub estimated_cost: Option<f64>,
+}
+
+/// Average price per day
+pub fn average_daily_prices(prices: &[Price]) -> HashMap<NaiveDate, f64> {
+    let mut totals: HashMap<NaiveDate, (f64, usize)> = HashMap::new();
+    for price in prices {
+        let total = totals.entry(price.timestamp.date()).or_default();
+        total.0 += price.price;
+        total.1 += 1;
+    }
+    totals
+        .into_iter()
+        .map(|(date, (sum, count))| (date, sum / count as f64))
+        .collect()
+}
+
+/// Cost of each day's energy at that day's average price. Hourly usage isn't
+/// known, so devices run mostly in cheap hours cost less than estimated.
+pub fn estimate_costs(readings: &[EnergyReading], average_prices: &HashMap<NaiveDate, f64>) -> Vec<DailyEnergyCost> {
+    readings
+        .iter()
+        .map(|r| {
+            let average_price = average_prices.get(&r.date).copied();
+            DailyEnergyCost {
+                date: r.date,
+                energy_kwh: r.energy_kwh,
+                average_price,
+                estimated_cost: average_price.map(|p| r.energy_kwh * p),
+            }
+        })
+        .collect()
+}
+
+pub struct EnergyReadingService {
+    pool: DbPool,
+}
+
+impl EnergyReadingService {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Store daily readings of a device, replacing those of the same day
+    pub fn store(&self, device_id: i32, days: &[DailyEnergy]) -> Result<usize, String> {
+        if days.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let now = Utc::now().naive_utc();
+
+        let rows: Vec<NewEnergyReading> = days
+            .iter()
+            .map(|d| NewEnergyReading {
+                device_id,
+                date: d.date,
+                energy_kwh: d.energy_kwh,
+                updated_at: now,
+            })
+            .collect();
+
+        diesel::insert_into(energy_readings::table)
+            .values(&rows)
+            .on_conflict((energy_readings::device_id, energy_readings::date))
+            .do_update()
+            .set((
+                energy_readings::energy_kwh.eq(diesel::upsert::excluded(energy_readings::energy_kwh)),
+                energy_readings::updated_at.eq(now),
+            ))
+            .execute(&mut conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Stored readings of a device from `from` on, with their estimated cost
+    pub fn history(&self, device_id: i32, from: NaiveDate) -> Result<Vec<DailyEnergyCost>, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let readings: Vec<EnergyReading> = energy_readings::table
+            .filter(energy_readings::device_id.eq(device_id))
+            .filter(energy_readings::date.ge(from))
+            .order(energy_readings::date.asc())
+            .select(EnergyReading::as_select())
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let day_prices: Vec<Price> = prices::table
+            .filter(prices::timestamp.ge(from.and_hms_opt(0, 0, 0).unwrap()))
+            .order(prices::timestamp.asc())
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        Ok(estimate_costs(&readings, &average_daily_prices(&day_prices)))
+    }
+
+    /// Read and store the daily energy of every device that meters
+    /// consumption. Returns the number of devices updated.
+    pub async fn sync_all(&self, registry: &ProviderRegistry) -> usize {
+        let rows: Vec<(Device, UserIntegration)> = {
+            let mut conn = match self.pool.get() {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Energy sync: database connection error: {}", e);
+                    return 0;
+                }
+            };
+            match devices::table
+                .inner_join(user_integrations::table)
+                .filter(user_integrations::is_active.eq(true))
+                .select((Device::as_select(), UserIntegration::as_select()))
+                .load(&mut conn)
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    warn!("Energy sync: failed to load devices: {}", e);
+                    return 0;
+                }
+            }
+        };
+
+        // Log in once per integration
+        let mut sessions: HashMap<i32, Option<serde_json::Value>> = HashMap::new();
+        let mut updated = 0;
+        for (device, integration) in rows {
+            let capabilities = registry.device_capabilities(&integration.provider_name, device.capabilities.as_ref());
+            if !capabilities.can_read_consumption {
+                continue;
+            }
+            let Some(provider) = registry.get(&integration.provider_name) else {
+                continue;
+            };
+
+            let session = match sessions.get(&integration.id) {
+                Some(session) => session.clone(),
+                None => {
+                    let session = match serde_json::from_str(&integration.credentials_json) {
+                        Ok(credentials) => provider.login(&credentials).await.ok(),
+                        Err(_) => None,
+                    };
+                    if session.is_none() {
+                        warn!("Energy sync: can't log in to integration {}", integration.id);
+                    }
+                    sessions.insert(integration.id, session.clone());
+                    session
+                }
+            };
+            let Some(session) = session else {
+                continue;
+            };
+
+            match provider.read_daily_energy(&session, &device.external_id).await {
+                Ok(days) => match self.store(device.id, &days) {
+                    Ok(_) => updated += 1,
+                    Err(e) => warn!("Energy sync: failed to store readings of device {}: {}", device.id, e),
+                },
+                Err(ProviderError::Unsupported(_)) => {}
+                Err(e) => warn!("Energy sync: failed to read device {}: {}", device.id, e),
+            }
+        }
+
+        info!("Energy sync: updated {} devices", updated);
+        updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(timestamp: &str, price: f64) -> Price {
+        Price {
+            timestamp: chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M").unwrap(),
+            price,
+            source: "esios".to_string(),
+        }
+    }
+
+    fn reading(date: NaiveDate, energy_kwh: f64) -> EnergyReading {
+        EnergyReading {
+            id: 1,
+            device_id: 1,
+            date,
+            energy_kwh,
+            updated_at: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_average_daily_prices() {
+        let prices = vec![
+            price("2026-10-16 00:00", 0.10),
+            price("2026-10-16 01:00", 0.20),
+            price("2026-10-17 00:00", 0.30),
+        ];
+        let averages = average_daily_prices(&prices);
+        let day = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        assert!((averages[&day(16)] - 0.15).abs() < 1e-9);
+        assert!((averages[&day(17)] - 0.30).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimate_costs() {
+        let day = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        let averages = HashMap::from([(day(16), 0.15)]);
+        let costs = estimate_costs(&[reading(day(16), 2.0), reading(day(17), 1.0)], &averages);
+
+        assert_eq!(costs[0].average_price, Some(0.15));
+        assert!((costs[0].estimated_cost.unwrap() - 0.30).abs() < 1e-9);
+        // No prices for the day
+        assert_eq!(costs[1].estimated_cost, None);
+        assert_eq!(costs[1].energy_kwh, 1.0);
+    }
+}
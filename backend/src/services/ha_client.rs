@@ -1,36 +1,97 @@
 use reqwest::Error;
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Timeout for each Home Assistant request
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// State of an entity, as returned by `/api/states`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HaEntityState {
+    pub entity_id: String,
+    /// "on", "off", "unavailable", or the HVAC mode for climate entities
+    pub state: String,
+    #[serde(default)]
+    pub attributes: Value,
+}
 
 pub struct HomeAssistantClient {
     base_url: String,
     token: String,
+    client: reqwest::Client,
+}
+
+/// Domain of an entity id ("light" for "light.kitchen")
+pub fn entity_domain(entity_id: &str) -> &str {
+    entity_id.split('.').next().unwrap_or(entity_id)
 }
 
 impl HomeAssistantClient {
     pub fn new(base_url: String, token: String) -> Self {
-        Self { base_url, token }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_default();
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+            client,
+        }
+    }
+
+    /// Check the URL and token against the API root
+    pub async fn check_api(&self) -> Result<(), Error> {
+        self.client
+            .get(format!("{}/api/", self.base_url))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// All entity states
+    pub async fn get_states(&self) -> Result<Vec<HaEntityState>, Error> {
+        self.client
+            .get(format!("{}/api/states", self.base_url))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// State of one entity
+    pub async fn get_state(&self, entity_id: &str) -> Result<HaEntityState, Error> {
+        self.client
+            .get(format!("{}/api/states/{}", self.base_url, entity_id))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
     }
 
     pub async fn turn_on(&self, entity_id: &str) -> Result<(), Error> {
-        self.call_service("switch", "turn_on", entity_id).await
+        self.call_service(entity_domain(entity_id), "turn_on", json!({ "entity_id": entity_id }))
+            .await
     }
 
     pub async fn turn_off(&self, entity_id: &str) -> Result<(), Error> {
-        self.call_service("switch", "turn_off", entity_id).await
+        self.call_service(entity_domain(entity_id), "turn_off", json!({ "entity_id": entity_id }))
+            .await
     }
 
-    async fn call_service(
-        &self,
-        domain: &str,
-        service: &str,
-        entity_id: &str,
-    ) -> Result<(), Error> {
+    /// Call a service, e.g. `climate.set_temperature`, with its service data
+    pub async fn call_service(&self, domain: &str, service: &str, data: Value) -> Result<(), Error> {
         let url = format!("{}/api/services/{}/{}", self.base_url, domain, service);
-        let client = reqwest::Client::new();
-        client
+        self.client
             .post(&url)
-            .header("Authorization", format!("Bearer {}", self.token))
-            .json(&json!({ "entity_id": entity_id }))
+            .bearer_auth(&self.token)
+            .json(&data)
             .send()
             .await?
             .error_for_status()?;
@@ -82,6 +143,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_base_url_trailing_slash_is_trimmed() {
+        let client = HomeAssistantClient::new("http://ha.local:8123/".to_string(), "t".to_string());
+        assert_eq!(client.base_url, "http://ha.local:8123");
+    }
+
+    #[test]
+    fn test_entity_domain() {
+        assert_eq!(entity_domain("light.kitchen"), "light");
+        assert_eq!(entity_domain("climate.living_room"), "climate");
+        assert_eq!(entity_domain("nodomain"), "nodomain");
+    }
+
     #[test]
     fn test_authorization_header_format() {
         let token = "my_long_lived_access_token";
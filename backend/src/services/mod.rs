@@ -1,6 +1,14 @@
 pub mod auth;
 pub mod automation_engine;
+pub mod battery_planner;
+pub mod config_transfer;
+pub mod cost_optimizer;
+pub mod energy_readings;
 pub mod ha_client;
 pub mod price_fetcher;
+pub mod rule_history;
+pub mod rule_templates;
 pub mod schedule_computation;
+pub mod schedule_explainer;
 pub mod scheduler;
+pub mod thermal_model;
@@ -0,0 +1,288 @@
+use crate::models::{
+    validate_rule_config, AutomationRule, NewRuleHistoryEntry, RuleChangeType, RuleHistoryEntry,
+    RuleType,
+};
+use crate::schema::{automation_rules, devices, rule_history, user_integrations};
+use crate::services::schedule_computation::validate_rule_chain;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+/// Rule fields that are restored on rollback.
+/// Ownership, device and timestamps are never rolled back.
+#[derive(Deserialize, AsChangeset, Insertable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::automation_rules)]
+pub struct RestorableRuleFields {
+    pub name: String,
+    pub rule_type: String,
+    pub action: String,
+    pub config: JsonValue,
+    pub is_enabled: bool,
+    pub priority: i32,
+}
+
+/// Errors returned by a rollback
+#[derive(Debug)]
+pub enum RollbackError {
+    RuleNotFound,
+    VersionNotFound,
+    /// The device of a deleted rule no longer exists
+    DeviceNotFound,
+    /// The device can no longer do what the restored rule asks of it
+    UnsupportedDevice(String),
+    InvalidSnapshot(String),
+    Database(String),
+}
+
+impl From<diesel::result::Error> for RollbackError {
+    fn from(e: diesel::result::Error) -> Self {
+        RollbackError::Database(e.to_string())
+    }
+}
+
+fn snapshot(rule: &AutomationRule) -> Option<JsonValue> {
+    serde_json::to_value(rule).ok()
+}
+
+/// Extract the restorable fields from a history snapshot.
+/// The snapshot may predate the current config rules, so its config is validated again.
+pub fn restorable_fields(snapshot: &JsonValue) -> Result<RestorableRuleFields, String> {
+    let fields = RestorableRuleFields::deserialize(snapshot).map_err(|e| e.to_string())?;
+    if RuleType::from_str(&fields.rule_type).is_none() {
+        return Err(format!("Unknown rule type '{}'", fields.rule_type));
+    }
+    validate_rule_config(&fields.rule_type, &fields.config)?;
+    Ok(fields)
+}
+
+/// Device of the rule in a snapshot
+pub fn snapshot_device_id(snapshot: &JsonValue) -> Option<i32> {
+    snapshot.get("device_id")?.as_i64()?.try_into().ok()
+}
+
+/// Record a change to a rule with its before/after snapshots.
+/// `user_id` is the acting user.
+pub fn record_rule_change(
+    conn: &mut PgConnection,
+    user_id: i32,
+    change_type: RuleChangeType,
+    before: Option<&AutomationRule>,
+    after: Option<&AutomationRule>,
+) -> QueryResult<RuleHistoryEntry> {
+    let rule_id = match after.or(before) {
+        Some(rule) => rule.id,
+        None => return Err(diesel::result::Error::NotFound),
+    };
+
+    let last_version: Option<i32> = rule_history::table
+        .filter(rule_history::rule_id.eq(rule_id))
+        .select(diesel::dsl::max(rule_history::version))
+        .first(conn)?;
+
+    let entry = NewRuleHistoryEntry {
+        rule_id,
+        user_id,
+        change_type: change_type.as_str().to_string(),
+        version: last_version.unwrap_or(0) + 1,
+        before_snapshot: before.and_then(snapshot),
+        after_snapshot: after.and_then(snapshot),
+    };
+
+    diesel::insert_into(rule_history::table)
+        .values(&entry)
+        .get_result(conn)
+}
+
+/// Get the history of a rule, newest first
+pub fn get_rule_history(
+    conn: &mut PgConnection,
+    rule_id: i32,
+    user_id: i32,
+) -> QueryResult<Vec<RuleHistoryEntry>> {
+    rule_history::table
+        .filter(rule_history::rule_id.eq(rule_id))
+        .filter(rule_history::user_id.eq(user_id))
+        .order(rule_history::version.desc())
+        .load(conn)
+}
+
+/// Restore a rule to the state it had right after `version`.
+/// For a delete entry, the state before the delete is restored. A deleted
+/// rule is re-created under its old id, on the device of its last snapshot.
+/// `check_device` checks that the device (id) supports the restored rule type.
+pub fn rollback_rule(
+    conn: &mut PgConnection,
+    rule_id: i32,
+    user_id: i32,
+    version: i32,
+    check_device: impl FnOnce(&mut PgConnection, i32, &str) -> Result<(), String>,
+) -> Result<AutomationRule, RollbackError> {
+    conn.transaction(|conn| {
+        let current: Option<AutomationRule> = automation_rules::table
+            .filter(automation_rules::id.eq(rule_id))
+            .filter(automation_rules::user_id.eq(user_id))
+            .first(conn)
+            .optional()?;
+
+        let entry: Option<RuleHistoryEntry> = rule_history::table
+            .filter(rule_history::rule_id.eq(rule_id))
+            .filter(rule_history::user_id.eq(user_id))
+            .filter(rule_history::version.eq(version))
+            .first(conn)
+            .optional()?;
+        let entry = match (entry, &current) {
+            (Some(entry), _) => entry,
+            (None, Some(_)) => return Err(RollbackError::VersionNotFound),
+            (None, None) => {
+                // Without any history the rule never existed for this user
+                let known = rule_history::table
+                    .filter(rule_history::rule_id.eq(rule_id))
+                    .filter(rule_history::user_id.eq(user_id))
+                    .count()
+                    .get_result::<i64>(conn)?
+                    > 0;
+                return Err(if known { RollbackError::VersionNotFound } else { RollbackError::RuleNotFound });
+            }
+        };
+
+        let target = entry
+            .after_snapshot
+            .as_ref()
+            .or(entry.before_snapshot.as_ref())
+            .ok_or_else(|| RollbackError::InvalidSnapshot("Empty snapshot".to_string()))?;
+        let fields = restorable_fields(target).map_err(RollbackError::InvalidSnapshot)?;
+        // The predecessor may have been deleted or chained onto this rule since
+        validate_rule_chain(conn, user_id, Some(rule_id), &fields.rule_type, &fields.config)
+            .map_err(RollbackError::InvalidSnapshot)?;
+
+        let device_id = match &current {
+            Some(rule) => rule.device_id,
+            None => {
+                let last: RuleHistoryEntry = rule_history::table
+                    .filter(rule_history::rule_id.eq(rule_id))
+                    .filter(rule_history::user_id.eq(user_id))
+                    .order(rule_history::version.desc())
+                    .first(conn)?;
+                let device_id = last
+                    .before_snapshot
+                    .as_ref()
+                    .or(last.after_snapshot.as_ref())
+                    .and_then(snapshot_device_id)
+                    .ok_or_else(|| RollbackError::InvalidSnapshot("No device in the last snapshot".to_string()))?;
+
+                let owns_device = devices::table
+                    .inner_join(user_integrations::table)
+                    .filter(devices::id.eq(device_id))
+                    .filter(user_integrations::user_id.eq(user_id))
+                    .count()
+                    .get_result::<i64>(conn)?
+                    > 0;
+                if !owns_device {
+                    return Err(RollbackError::DeviceNotFound);
+                }
+                device_id
+            }
+        };
+        // The device's capabilities may have changed since the snapshot
+        check_device(conn, device_id, &fields.rule_type).map_err(RollbackError::UnsupportedDevice)?;
+
+        let now = Utc::now().naive_utc();
+        let restored: AutomationRule = match &current {
+            Some(_) => diesel::update(automation_rules::table.filter(automation_rules::id.eq(rule_id)))
+                .set((&fields, automation_rules::updated_at.eq(now)))
+                .get_result(conn)?,
+            None => diesel::insert_into(automation_rules::table)
+                .values((
+                    automation_rules::id.eq(rule_id),
+                    automation_rules::user_id.eq(user_id),
+                    automation_rules::device_id.eq(device_id),
+                    &fields,
+                    automation_rules::updated_at.eq(now),
+                ))
+                .get_result(conn)?,
+        };
+
+        record_rule_change(
+            conn,
+            user_id,
+            RuleChangeType::Rollback,
+            current.as_ref(),
+            Some(&restored),
+        )?;
+
+        Ok(restored)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    #[test]
+    fn test_restorable_fields_from_rule_snapshot() {
+        let ts = NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let rule = AutomationRule {
+            id: 3,
+            user_id: 1,
+            device_id: 2,
+            name: "Boiler".to_string(),
+            rule_type: "cheapest_hours".to_string(),
+            action: "turn_on".to_string(),
+            config: json!({"cheapest_hours": 4}),
+            is_enabled: false,
+            priority: 20,
+            created_at: ts,
+            updated_at: ts,
+            last_triggered_at: None,
+        };
+
+        let fields = restorable_fields(&snapshot(&rule).unwrap()).unwrap();
+        assert_eq!(
+            fields,
+            RestorableRuleFields {
+                name: "Boiler".to_string(),
+                rule_type: "cheapest_hours".to_string(),
+                action: "turn_on".to_string(),
+                config: json!({"cheapest_hours": 4}),
+                is_enabled: false,
+                priority: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_device_id() {
+        assert_eq!(snapshot_device_id(&json!({"id": 3, "device_id": 7})), Some(7));
+        assert_eq!(snapshot_device_id(&json!({"id": 3})), None);
+        assert_eq!(snapshot_device_id(&json!({"device_id": "7"})), None);
+    }
+
+    #[test]
+    fn test_restorable_fields_rejects_incomplete_snapshot() {
+        assert!(restorable_fields(&json!({"name": "Boiler"})).is_err());
+    }
+
+    #[test]
+    fn test_restorable_fields_rejects_invalid_config() {
+        let snapshot = json!({
+            "name": "Dehumidifier",
+            "rule_type": "price_threshold",
+            "action": "turn_on",
+            "config": {"threshold": 0.12, "comparison": "sideways"},
+            "is_enabled": true,
+            "priority": 150,
+        });
+        assert!(restorable_fields(&snapshot).is_err());
+
+        let mut unknown_type = snapshot.clone();
+        unknown_type["rule_type"] = json!("sauna");
+        assert!(restorable_fields(&unknown_type).is_err());
+    }
+}
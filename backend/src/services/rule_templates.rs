@@ -0,0 +1,291 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value as JsonValue};
+
+/// Type of a template parameter, used by clients to render the right input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterType {
+    Integer,
+    Number,
+    Time,
+}
+
+/// A value the user is prompted for when instantiating a template.
+/// The `key` is the rule config key the value is written to.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateParameter {
+    pub key: &'static str,
+    pub label: &'static str,
+    #[serde(rename = "type")]
+    pub param_type: ParameterType,
+    pub default: JsonValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+/// Built-in rule template for a common appliance
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleTemplate {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub rule_type: &'static str,
+    pub action: &'static str,
+    pub priority: i32,
+    pub parameters: Vec<TemplateParameter>,
+    /// Config values that are not exposed as parameters
+    pub base_config: JsonValue,
+}
+
+fn hours_param(label: &'static str, default: i64, max: f64) -> TemplateParameter {
+    TemplateParameter {
+        key: "cheapest_hours",
+        label,
+        param_type: ParameterType::Integer,
+        default: json!(default),
+        min: Some(1.0),
+        max: Some(max),
+    }
+}
+
+fn time_param(key: &'static str, label: &'static str, default: &str) -> TemplateParameter {
+    TemplateParameter {
+        key,
+        label,
+        param_type: ParameterType::Time,
+        default: json!(default),
+        min: None,
+        max: None,
+    }
+}
+
+/// Full template catalog
+pub fn all_templates() -> Vec<RuleTemplate> {
+    vec![
+        RuleTemplate {
+            id: "water_heater",
+            name: "Water heater",
+            description: "Heat water during the cheapest night hours",
+            rule_type: "cheapest_hours",
+            action: "turn_on",
+            priority: 50,
+            parameters: vec![
+                hours_param("Heating hours per day", 3, 12.0),
+                time_param("time_range_start", "Window start", "00:00"),
+                time_param("time_range_end", "Window end", "07:00"),
+            ],
+            base_config: json!({ "contiguous": false }),
+        },
+        RuleTemplate {
+            id: "ev_charger",
+            name: "EV charger",
+            description: "Charge the car overnight in the cheapest hours before departure",
+            rule_type: "cheapest_hours",
+            action: "turn_on",
+            priority: 40,
+            parameters: vec![
+                hours_param("Charging hours", 5, 12.0),
+                time_param("time_range_start", "Plug-in time", "21:00"),
+                time_param("time_range_end", "Departure time", "07:00"),
+            ],
+            base_config: json!({ "contiguous": false }),
+        },
+        RuleTemplate {
+            id: "dishwasher",
+            name: "Dishwasher",
+            description: "Run one uninterrupted cycle in the cheapest block",
+            rule_type: "cheapest_hours",
+            action: "turn_on",
+            priority: 100,
+            parameters: vec![
+                hours_param("Cycle length (hours)", 2, 4.0),
+                time_param("time_range_start", "Earliest start", "22:00"),
+                time_param("time_range_end", "Must finish by", "07:00"),
+            ],
+            base_config: json!({ "contiguous": true }),
+        },
+        RuleTemplate {
+            id: "washing_machine",
+            name: "Washing machine",
+            description: "Run one uninterrupted wash during the day in the cheapest block",
+            rule_type: "cheapest_hours",
+            action: "turn_on",
+            priority: 100,
+            parameters: vec![
+                hours_param("Cycle length (hours)", 2, 4.0),
+                time_param("time_range_start", "Earliest start", "09:00"),
+                time_param("time_range_end", "Must finish by", "20:00"),
+            ],
+            base_config: json!({ "contiguous": true }),
+        },
+        RuleTemplate {
+            id: "pool_pump",
+            name: "Pool pump",
+            description: "Filter the pool in the cheapest hours, running longer in summer",
+            rule_type: "cheapest_hours",
+            action: "turn_on",
+            priority: 120,
+            parameters: vec![
+                hours_param("Filtering hours per day", 4, 24.0),
+                time_param("time_range_start", "Window start", "00:00"),
+                time_param("time_range_end", "Window end", "23:00"),
+            ],
+            base_config: json!({
+                "contiguous": false,
+                "profiles": [
+                    {
+                        "name": "Summer",
+                        "date_from": "06-01",
+                        "date_to": "09-15",
+                        "config": { "cheapest_hours": 8 }
+                    }
+                ]
+            }),
+        },
+        RuleTemplate {
+            id: "dehumidifier",
+            name: "Dehumidifier",
+            description: "Run whenever the price is below a threshold",
+            rule_type: "price_threshold",
+            action: "turn_on",
+            priority: 150,
+            parameters: vec![TemplateParameter {
+                key: "threshold",
+                label: "Maximum price (€/kWh)",
+                param_type: ParameterType::Number,
+                default: json!(0.12),
+                min: Some(0.0),
+                max: Some(1.0),
+            }],
+            base_config: json!({ "comparison": "below" }),
+        },
+    ]
+}
+
+/// Find a template by its id
+pub fn find_template(id: &str) -> Option<RuleTemplate> {
+    all_templates().into_iter().find(|t| t.id == id)
+}
+
+impl RuleTemplate {
+    /// Build a rule config from user supplied parameter values.
+    /// Missing parameters fall back to their defaults; unknown keys are rejected.
+    pub fn build_config(&self, params: &JsonValue) -> Result<JsonValue, String> {
+        let provided = match params {
+            JsonValue::Null => Map::new(),
+            JsonValue::Object(map) => map.clone(),
+            _ => return Err("params must be a JSON object".to_string()),
+        };
+
+        if let Some(unknown) = provided
+            .keys()
+            .find(|k| !self.parameters.iter().any(|p| p.key == k.as_str()))
+        {
+            return Err(format!(
+                "Unknown parameter '{}' for template '{}'",
+                unknown, self.id
+            ));
+        }
+
+        let mut config = match &self.base_config {
+            JsonValue::Object(map) => map.clone(),
+            _ => Map::new(),
+        };
+
+        for param in &self.parameters {
+            let value = provided.get(param.key).unwrap_or(&param.default);
+            param.validate(value)?;
+            config.insert(param.key.to_string(), value.clone());
+        }
+
+        Ok(JsonValue::Object(config))
+    }
+}
+
+impl TemplateParameter {
+    fn validate(&self, value: &JsonValue) -> Result<(), String> {
+        let number = match self.param_type {
+            ParameterType::Integer => Some(value.as_i64().ok_or_else(|| {
+                format!("Parameter '{}' must be an integer", self.key)
+            })? as f64),
+            ParameterType::Number => Some(value.as_f64().ok_or_else(|| {
+                format!("Parameter '{}' must be a number", self.key)
+            })?),
+            ParameterType::Time => {
+                let valid = value
+                    .as_str()
+                    .map(|s| chrono::NaiveTime::parse_from_str(s, "%H:%M").is_ok())
+                    .unwrap_or(false);
+                if !valid {
+                    return Err(format!("Parameter '{}' must be a time (HH:MM)", self.key));
+                }
+                None
+            }
+        };
+
+        let out_of_range = number.is_some_and(|n| {
+            self.min.is_some_and(|min| n < min) || self.max.is_some_and(|max| n > max)
+        });
+        if out_of_range {
+            return Err(format!(
+                "Parameter '{}' must be between {} and {}",
+                self.key,
+                self.min.unwrap_or(f64::MIN),
+                self.max.unwrap_or(f64::MAX)
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::validate_rule_config;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_catalog_ids_are_unique_and_defaults_valid() {
+        let templates = all_templates();
+        let ids: HashSet<_> = templates.iter().map(|t| t.id).collect();
+        assert_eq!(ids.len(), templates.len());
+
+        for template in &templates {
+            let config = template.build_config(&JsonValue::Null).unwrap();
+            let result = validate_rule_config(template.rule_type, &config);
+            assert!(result.is_ok(), "{}: {:?}", template.id, result);
+        }
+    }
+
+    #[test]
+    fn test_build_config_uses_defaults_and_overrides() {
+        let template = find_template("dishwasher").unwrap();
+        let config = template
+            .build_config(&json!({ "cheapest_hours": 3, "time_range_start": "23:00" }))
+            .unwrap();
+
+        assert_eq!(config["cheapest_hours"], 3);
+        assert_eq!(config["time_range_start"], "23:00");
+        assert_eq!(config["time_range_end"], "07:00");
+        assert_eq!(config["contiguous"], true);
+    }
+
+    #[test]
+    fn test_build_config_rejects_invalid_params() {
+        let template = find_template("water_heater").unwrap();
+
+        assert!(template.build_config(&json!({ "cheapest_hours": 30 })).is_err());
+        assert!(template.build_config(&json!({ "cheapest_hours": "3" })).is_err());
+        assert!(template.build_config(&json!({ "time_range_end": "7am" })).is_err());
+        assert!(template.build_config(&json!({ "colour": "blue" })).is_err());
+        assert!(template.build_config(&json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_find_template_unknown() {
+        assert!(find_template("sauna").is_none());
+    }
+}
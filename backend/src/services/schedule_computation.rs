@@ -1,10 +1,230 @@
 use crate::db::DbPool;
-use crate::models::{AutomationRule, ExecutionStatus, NewScheduledExecution, Price, ScheduledExecution};
-use crate::schema::{automation_rules, devices, scheduled_executions};
+use crate::models::{
+    naive_local_times, parse_load_profile, ApplianceCycleConfig, AutomationRule, ChainedRuleConfig,
+    CronScheduleConfig, EvChargingConfig, ExecutionStatus, NewScheduledExecution, Price,
+    PriceThresholdConfig, RollingWindowConfig, RuleAction, RuleType, ScheduledExecution,
+    ThermostatConfig, WaterHeaterConfig, MAX_PROFILE_STEPS, PROFILE_STEP_MINUTES,
+};
+use crate::schema::{
+    automation_rules, devices, optimized_rule_dates, rule_executions, scheduled_executions, users,
+};
 use crate::services::price_fetcher::PriceService;
-use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+use crate::services::thermal_model::{plan_heating, simulate, TankModel, Usage};
+use crate::services::scheduler::{
+    charged_energy_kwh, find_cheapest_profile_start, hourly_load_w, hours_overlapping, plan_chained_hours, plan_ev_charging,
+    plan_price_threshold_hours, plan_rolling_hours, plan_thermostat_setpoints, plan_within_power_limit, PlannedLoad,
+    PowerBudget, PowerDemand, CHAIN_RUN_GAP_HOURS,
+};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
 use diesel::prelude::*;
+use diesel::PgConnection;
 use log::{error, info, warn};
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+
+/// How late a due (non hourly) execution may still run before it's missed
+pub const DUE_EXECUTION_GRACE_MINUTES: i64 = 5;
+
+/// How long a claimed execution may stay `executing` before it's considered lost
+pub const EXECUTION_CLAIM_TIMEOUT_MINUTES: i64 = 15;
+
+/// Oldest device temperature reading used as the starting point of a thermal plan
+pub const MAX_TEMPERATURE_READING_AGE_HOURS: i64 = 12;
+
+/// Maximum number of rules in a chain (e.g., washer → dryer → iron)
+pub const MAX_CHAIN_DEPTH: usize = 8;
+
+/// Check that `rule_id` (None for a new rule) can follow `after_rule_id`.
+/// `predecessor_of` returns the predecessor of a chained rule.
+pub fn check_rule_chain(
+    rule_id: Option<i32>,
+    after_rule_id: i32,
+    predecessor_of: impl Fn(i32) -> Option<i32>,
+) -> Result<(), String> {
+    let mut current = Some(after_rule_id);
+    let mut depth = 1;
+    while let Some(id) = current {
+        if Some(id) == rule_id {
+            return Err("Chained rules cannot form a cycle".to_string());
+        }
+        depth += 1;
+        if depth > MAX_CHAIN_DEPTH {
+            return Err(format!("Rule chains are limited to {} rules", MAX_CHAIN_DEPTH));
+        }
+        current = predecessor_of(id);
+    }
+    Ok(())
+}
+
+/// Validate the predecessor of a chained rule: it must be another rule of the
+/// same user and must not lead back to `rule_id`
+pub fn validate_rule_chain(
+    conn: &mut PgConnection,
+    user_id: i32,
+    rule_id: Option<i32>,
+    rule_type: &str,
+    config: &JsonValue,
+) -> Result<(), String> {
+    if rule_type != RuleType::Chained.as_str() {
+        return Ok(());
+    }
+    let config = ChainedRuleConfig::from_config(config)?;
+
+    let rules: Vec<(i32, String, JsonValue)> = automation_rules::table
+        .filter(automation_rules::user_id.eq(user_id))
+        .select((
+            automation_rules::id,
+            automation_rules::rule_type,
+            automation_rules::config,
+        ))
+        .load(conn)
+        .map_err(|e| e.to_string())?;
+
+    if !rules.iter().any(|(id, _, _)| *id == config.after_rule_id) {
+        return Err(format!("Predecessor rule {} not found", config.after_rule_id));
+    }
+
+    let predecessors: HashMap<i32, i32> = rules
+        .iter()
+        .filter(|(id, rule_type, _)| {
+            rule_type == RuleType::Chained.as_str() && Some(*id) != rule_id
+        })
+        .filter_map(|(id, _, config)| {
+            ChainedRuleConfig::from_config(config)
+                .ok()
+                .map(|c| (*id, c.after_rule_id))
+        })
+        .collect();
+
+    check_rule_chain(rule_id, config.after_rule_id, |id| {
+        predecessors.get(&id).copied()
+    })
+}
+
+/// Chained rules among `rules` that follow `rule_id`
+pub fn chained_successors(rules: Vec<AutomationRule>, rule_id: i32) -> Vec<AutomationRule> {
+    rules
+        .into_iter()
+        .filter(|r| {
+            r.rule_type == RuleType::Chained.as_str()
+                && ChainedRuleConfig::from_config(&r.config).is_ok_and(|c| c.after_rule_id == rule_id)
+        })
+        .collect()
+}
+
+/// Turn_on execution with its rule (id, type) and device (id, power, load profile)
+type PlannedRow = (NaiveDateTime, i32, String, i32, Option<i32>, Option<JsonValue>);
+
+/// Turn_on executions of a user's rules (with their rule id) that draw power
+/// between `from` and `to`, including appliance cycles started earlier and
+/// still running
+pub fn planned_loads(
+    conn: &mut PgConnection,
+    user_id: i32,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<Vec<(i32, PlannedLoad)>, String> {
+    let longest_cycle = chrono::Duration::minutes(PROFILE_STEP_MINUTES * MAX_PROFILE_STEPS as i64);
+    let rows: Vec<PlannedRow> = scheduled_executions::table
+        .inner_join(automation_rules::table.inner_join(devices::table))
+        .filter(automation_rules::user_id.eq(user_id))
+        .filter(scheduled_executions::expected_action.eq(RuleAction::TurnOn.as_str()))
+        .filter(scheduled_executions::status.eq_any([
+            ExecutionStatus::Executed.as_str(),
+            ExecutionStatus::Pending.as_str(),
+            ExecutionStatus::Retrying.as_str(),
+        ]))
+        .filter(scheduled_executions::scheduled_hour.ge(from - longest_cycle))
+        .filter(scheduled_executions::scheduled_hour.le(to))
+        .select((
+            scheduled_executions::scheduled_hour,
+            automation_rules::id,
+            automation_rules::rule_type,
+            devices::id,
+            devices::rated_power_w,
+            devices::load_profile,
+        ))
+        .load(conn)
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(at, rule_id, rule_type, device_id, power_w, load_profile)| {
+            let cycle = if rule_type == RuleType::ApplianceCycle.as_str() {
+                let profile = load_profile.and_then(|p| parse_load_profile(&p).ok())?;
+                Some(chrono::Duration::minutes(PROFILE_STEP_MINUTES * profile.len() as i64))
+            } else {
+                None
+            };
+            let end = at + cycle.unwrap_or_default();
+            (end > from || at >= from).then_some((
+                rule_id,
+                PlannedLoad {
+                    at,
+                    device_id,
+                    power_w,
+                    cycle,
+                },
+            ))
+        })
+        .collect())
+}
+
+/// Days whose windows are planned when a rule is replanned from scratch.
+/// An appliance cycle started in yesterday's window may still be running
+/// overnight, and planning that window puts back its turn_off.
+pub fn replan_dates(rule_type: &str, today: NaiveDate) -> Vec<NaiveDate> {
+    let tomorrow = today + chrono::Duration::days(1);
+    if rule_type == RuleType::ApplianceCycle.as_str() {
+        vec![today - chrono::Duration::days(1), today, tomorrow]
+    } else {
+        vec![today, tomorrow]
+    }
+}
+
+/// A rule replanned after other rules of its user changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetReplan {
+    pub rule_id: i32,
+    /// Dates whose plan the cost optimiser set, kept as they are
+    pub kept_dates: Vec<NaiveDate>,
+}
+
+/// Rules to replan after the rules `changed` ((rule id, device id), possibly
+/// removed or disabled) changed, in priority order. The changed rules are
+/// planned from scratch. With a contracted power, the turn_on rules of other
+/// devices share its budget with them and are replanned too, except on the
+/// dates the cost optimiser planned them. A device's own rules don't count
+/// against each other, and without a contracted power no budget moves.
+pub fn budget_replans(
+    rules: &[AutomationRule],
+    changed: &[(i32, i32)],
+    has_power_limit: bool,
+    optimized: &HashSet<(i32, NaiveDate)>,
+    today: NaiveDate,
+) -> Vec<BudgetReplan> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            if changed.iter().any(|(id, _)| *id == rule.id) {
+                return Some(BudgetReplan {
+                    rule_id: rule.id,
+                    kept_dates: Vec::new(),
+                });
+            }
+            let shares_budget = has_power_limit
+                && rule.action == RuleAction::TurnOn.as_str()
+                && changed.iter().any(|(_, device_id)| *device_id != rule.device_id);
+            shares_budget.then(|| BudgetReplan {
+                rule_id: rule.id,
+                kept_dates: replan_dates(&rule.rule_type, today)
+                    .into_iter()
+                    .filter(|date| optimized.contains(&(rule.id, *date)))
+                    .collect(),
+            })
+        })
+        .collect()
+}
 
 /// Service for computing and managing scheduled executions
 pub struct ScheduleComputationService {
@@ -20,9 +240,10 @@ impl ScheduleComputationService {
     pub fn compute_schedule_for_date(&self, date: NaiveDate) -> Result<usize, String> {
         let mut conn = self.pool.get().map_err(|e| e.to_string())?;
 
-        // Get all enabled rules
+        // Get all enabled rules; higher priority rules claim the power budget first
         let rules: Vec<AutomationRule> = automation_rules::table
             .filter(automation_rules::is_enabled.eq(true))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
             .load(&mut conn)
             .map_err(|e| e.to_string())?;
 
@@ -61,9 +282,60 @@ impl ScheduleComputationService {
         rule: &AutomationRule,
         date: NaiveDate,
     ) -> Result<usize, String> {
+        // Rolling window rules are planned over the whole known horizon, not per day
+        // Chained rules follow their predecessor's plan, EV charging its departure,
+        // thermostats the peaks of the known prices
+        let horizon_plan = if rule.rule_type == RuleType::RollingWindow.as_str() {
+            Some(self.replan_rolling_rule(conn, rule))
+        } else if rule.rule_type == RuleType::Chained.as_str() {
+            Some(self.replan_chained_rule(conn, rule))
+        } else if rule.rule_type == RuleType::EvCharging.as_str() {
+            Some(self.replan_ev_charging_rule(conn, rule))
+        } else if rule.rule_type == RuleType::WaterHeater.as_str() {
+            Some(self.replan_water_heater_rule(conn, rule))
+        } else if rule.rule_type == RuleType::Thermostat.as_str() {
+            Some(self.replan_thermostat_rule(conn, rule))
+        } else {
+            None
+        };
+        if let Some(result) = horizon_plan {
+            let count = result?;
+            self.replan_successors_internal(conn, rule.id, 1);
+            return Ok(count);
+        }
+
+        // Appliance cycles start at a point in time rather than holding hours
+        if rule.rule_type == RuleType::ApplianceCycle.as_str() {
+            let count = self.plan_appliance_cycle(conn, rule, date)?;
+            self.replan_successors_internal(conn, rule.id, 1);
+            return Ok(count);
+        }
+
+        // A plan applied by the cost optimiser is kept until the rule is replanned
+        if self.is_optimized(conn, rule.id, date) {
+            return Ok(0);
+        }
+
         // Get timestamps to schedule based on rule type
         // Returns NaiveDateTime to handle overnight windows spanning two days
-        let timestamps_to_schedule = self.calculate_timestamps_for_rule(rule, date)?;
+        let demand = self.calculate_demand_for_rule(rule, date)?;
+        let timestamps_to_schedule = match self.power_budget(conn, rule, &demand.hours_considered())? {
+            Some(budget) => {
+                let plan = plan_within_power_limit(&demand, &budget);
+                let wanted = demand.hours().len();
+                if plan.len() < wanted {
+                    warn!(
+                        "Rule {} gets {} of {} hours on {} to stay within the contracted power",
+                        rule.id,
+                        plan.len(),
+                        wanted,
+                        date
+                    );
+                }
+                plan
+            }
+            None => demand.hours(),
+        };
 
         let mut count = 0;
         for scheduled_hour in timestamps_to_schedule {
@@ -72,6 +344,7 @@ impl ScheduleComputationService {
                 scheduled_hour,
                 expected_action: rule.action.clone(),
                 status: ExecutionStatus::Pending.as_str().to_string(),
+                setpoint_c: None,
             };
 
             // Upsert: insert or ignore if exists
@@ -96,36 +369,659 @@ impl ScheduleComputationService {
             }
         }
 
+        self.replan_successors_internal(conn, rule.id, 1);
+
         Ok(count)
     }
 
-    /// Calculate timestamps for scheduling a rule on a given date
-    /// For overnight windows (e.g., 19:00-08:00), returns timestamps from both days
-    fn calculate_timestamps_for_rule(
+    /// Whether the cost optimiser set the rule's plan for `date`
+    fn is_optimized(&self, conn: &mut PgConnection, rule_id: i32, date: NaiveDate) -> bool {
+        diesel::select(diesel::dsl::exists(
+            optimized_rule_dates::table
+                .filter(optimized_rule_dates::rule_id.eq(rule_id))
+                .filter(optimized_rule_dates::date.eq(date)),
+        ))
+        .get_result(conn)
+        .unwrap_or(false)
+    }
+
+    /// Replan the chained rules that follow a rule (after its plan moved,
+    /// failed or was removed)
+    pub fn replan_successors(&self, rule_id: i32) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        Ok(self.replan_successors_internal(&mut conn, rule_id, 1))
+    }
+
+    fn replan_successors_internal(&self, conn: &mut PgConnection, rule_id: i32, depth: usize) -> usize {
+        if depth > MAX_CHAIN_DEPTH {
+            warn!("Rule chain starting at rule {} is too deep, not replanning", rule_id);
+            return 0;
+        }
+
+        let successors: Vec<AutomationRule> = match automation_rules::table
+            .filter(automation_rules::rule_type.eq(RuleType::Chained.as_str()))
+            .filter(automation_rules::is_enabled.eq(true))
+            .load::<AutomationRule>(conn)
+        {
+            Ok(rules) => chained_successors(rules, rule_id),
+            Err(e) => {
+                error!("Failed to load successors of rule {}: {}", rule_id, e);
+                return 0;
+            }
+        };
+
+        let mut total = 0;
+        for successor in successors {
+            match self.replan_chained_rule(conn, &successor) {
+                Ok(count) => total += count,
+                Err(e) => error!("Failed to replan chained rule {}: {}", successor.id, e),
+            }
+            total += self.replan_successors_internal(conn, successor.id, depth + 1);
+        }
+        total
+    }
+
+    /// Plan a chained rule after each run of its predecessor, replacing its
+    /// future pending executions
+    fn replan_chained_rule(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+    ) -> Result<usize, String> {
+        let now = Local::now().naive_local();
+        let today = now.date();
+        let config = ChainedRuleConfig::from_config(&rule.config_for_date(today))?;
+
+        let horizon_start =
+            now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+        // Far enough back to see the predecessor run a pending window belongs to
+        let lookback_start = horizon_start
+            - chrono::Duration::hours(
+                (config.gap_hours + config.max_delay_hours) as i64 + CHAIN_RUN_GAP_HOURS,
+            );
+        let active_statuses = [
+            ExecutionStatus::Executed.as_str(),
+            ExecutionStatus::Pending.as_str(),
+            ExecutionStatus::Retrying.as_str(),
+        ];
+
+        // Failed or missed predecessor hours don't count: the run ends earlier or not at all
+        let predecessor_hours: Vec<NaiveDateTime> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(config.after_rule_id))
+            .filter(scheduled_executions::scheduled_hour.ge(lookback_start))
+            .filter(scheduled_executions::status.eq_any(active_statuses))
+            .select(scheduled_executions::scheduled_hour)
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+
+        let committed: Vec<NaiveDateTime> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.ge(lookback_start))
+            .filter(scheduled_executions::scheduled_hour.lt(horizon_start))
+            .filter(scheduled_executions::status.eq_any(active_statuses))
+            .select(scheduled_executions::scheduled_hour)
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(today)
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(today + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        prices.retain(|p| p.timestamp >= horizon_start);
+        self.retain_within_power_limit(conn, rule, &mut prices)?;
+
+        let plan = plan_chained_hours(&prices, &predecessor_hours, &committed, &config);
+        self.replace_future_pending(conn, rule, horizon_start, plan)
+    }
+
+    /// Replace a rule's pending executions from `horizon_start` on with `plan`
+    fn replace_future_pending(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        horizon_start: NaiveDateTime,
+        plan: Vec<NaiveDateTime>,
+    ) -> Result<usize, String> {
+        let new_executions: Vec<NewScheduledExecution> = plan
+            .into_iter()
+            .map(|scheduled_hour| NewScheduledExecution {
+                rule_id: rule.id,
+                scheduled_hour,
+                expected_action: rule.action.clone(),
+                status: ExecutionStatus::Pending.as_str().to_string(),
+                setpoint_c: None,
+            })
+            .collect();
+        self.replace_future_executions(conn, rule.id, horizon_start, new_executions)
+    }
+
+    /// Replace a rule's pending executions from `horizon_start` on with `new_executions`
+    fn replace_future_executions(
+        &self,
+        conn: &mut PgConnection,
+        rule_id: i32,
+        horizon_start: NaiveDateTime,
+        new_executions: Vec<NewScheduledExecution>,
+    ) -> Result<usize, String> {
+        self.replace_pending_executions(conn, rule_id, horizon_start, None, new_executions)
+    }
+
+    /// Replace a rule's pending executions in `[from, until]` with `new_executions`
+    fn replace_pending_executions(
+        &self,
+        conn: &mut PgConnection,
+        rule_id: i32,
+        from: NaiveDateTime,
+        until: Option<NaiveDateTime>,
+        new_executions: Vec<NewScheduledExecution>,
+    ) -> Result<usize, String> {
+        conn.transaction::<usize, diesel::result::Error, _>(|conn| {
+            let pending = scheduled_executions::table
+                .filter(scheduled_executions::rule_id.eq(rule_id))
+                .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
+                .filter(scheduled_executions::scheduled_hour.ge(from));
+            match until {
+                Some(until) => diesel::delete(pending.filter(scheduled_executions::scheduled_hour.le(until)))
+                    .execute(conn)?,
+                None => diesel::delete(pending).execute(conn)?,
+            };
+
+            diesel::insert_into(scheduled_executions::table)
+                .values(&new_executions)
+                .on_conflict((
+                    scheduled_executions::rule_id,
+                    scheduled_executions::scheduled_hour,
+                ))
+                .do_nothing()
+                .execute(conn)
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    /// Replan the enabled water heater rules, e.g. after new temperature readings
+    pub fn replan_water_heater_rules(&self) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let rules: Vec<AutomationRule> = automation_rules::table
+            .filter(automation_rules::rule_type.eq(RuleType::WaterHeater.as_str()))
+            .filter(automation_rules::is_enabled.eq(true))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let mut total = 0;
+        for rule in rules {
+            match self.replan_water_heater_rule(&mut conn, &rule) {
+                Ok(count) => total += count,
+                Err(e) => error!("Failed to replan water heater rule {}: {}", rule.id, e),
+            }
+        }
+        Ok(total)
+    }
+
+    /// Plan a water heater rule over the known prices: the cheapest heating
+    /// hours that keep the tank warm enough at every usage, replacing its
+    /// future pending executions
+    fn replan_water_heater_rule(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+    ) -> Result<usize, String> {
+        let now = Local::now().naive_local();
+        let today = now.date();
+        let config = WaterHeaterConfig::from_config(&rule.config_for_date(today))?;
+        let model = TankModel::from_config(&config);
+
+        let horizon_start =
+            now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(today)
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(today + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        prices.retain(|p| p.timestamp >= horizon_start);
+        // Usages after the known prices are planned once those prices are published
+        let horizon_end = prices
+            .iter()
+            .map(|p| p.timestamp + chrono::Duration::hours(1))
+            .max()
+            .unwrap_or(horizon_start);
+        self.retain_within_power_limit(conn, rule, &mut prices)?;
+
+        let start_temp_c = self.estimate_tank_temperature(conn, rule, &config, &model, horizon_start)?;
+        let usages: Vec<Usage> = config
+            .usages_between(horizon_start, horizon_end + chrono::Duration::hours(1))
+            .into_iter()
+            .map(|(at, min_temp_c, draw_liters)| Usage {
+                at,
+                min_temp_c,
+                draw_liters,
+            })
+            .collect();
+
+        let plan = plan_heating(&model, &prices, horizon_start, start_temp_c, &usages);
+        for at in &plan.unmet {
+            warn!(
+                "Rule {} can't reach the minimum water temperature for {}",
+                rule.id, at
+            );
+        }
+        info!(
+            "Water heater rule {} heats {} hours from {:.1} °C (expected cost {:.3} €)",
+            rule.id,
+            plan.hours.len(),
+            start_temp_c,
+            plan.cost_eur
+        );
+
+        self.replace_future_pending(conn, rule, horizon_start, plan.hours)
+    }
+
+    /// Plan a thermostat rule's setpoint changes over the known prices,
+    /// replacing its future pending executions
+    fn replan_thermostat_rule(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+    ) -> Result<usize, String> {
+        let now = Local::now().naive_local();
+        let today = now.date();
+        let config = ThermostatConfig::from_config(&rule.config_for_date(today))?;
+
+        let horizon_start =
+            now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(today)
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(today + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        // Peaks are ranked over whole days, including the hours already past
+        let mut changes = plan_thermostat_setpoints(&prices, &config);
+        let current = changes.iter().rev().find(|(at, _)| *at <= horizon_start).cloned();
+        changes.retain(|(at, _)| *at > horizon_start);
+        // The first planned hour always sets the setpoint it should have
+        if let Some((_, setpoint)) = current {
+            changes.insert(0, (horizon_start, setpoint));
+        }
+
+        let new_executions: Vec<NewScheduledExecution> = changes
+            .into_iter()
+            .map(|(scheduled_hour, setpoint)| NewScheduledExecution {
+                rule_id: rule.id,
+                scheduled_hour,
+                expected_action: RuleAction::SetTemperature(setpoint).as_str().to_string(),
+                status: ExecutionStatus::Pending.as_str().to_string(),
+                setpoint_c: Some(setpoint),
+            })
+            .collect();
+        self.replace_future_executions(conn, rule.id, horizon_start, new_executions)
+    }
+
+    /// Tank temperature at `at`: the latest temperature reported by the device
+    /// (logged with its rule executions), carried forward through the hours the
+    /// rule heated since. Without a recent reading the tank is assumed to be at
+    /// its minimum temperature.
+    fn estimate_tank_temperature(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        config: &WaterHeaterConfig,
+        model: &TankModel,
+        at: NaiveDateTime,
+    ) -> Result<f64, String> {
+        let oldest = at - chrono::Duration::hours(MAX_TEMPERATURE_READING_AGE_HOURS);
+        let states: Vec<(NaiveDateTime, Option<JsonValue>, Option<JsonValue>)> = rule_executions::table
+            .inner_join(automation_rules::table)
+            .filter(automation_rules::device_id.eq(rule.device_id))
+            .filter(rule_executions::executed_at.ge(oldest))
+            .filter(rule_executions::executed_at.lt(at))
+            .order(rule_executions::executed_at.desc())
+            .select((
+                rule_executions::executed_at,
+                rule_executions::device_state_after,
+                rule_executions::device_state_before,
+            ))
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+
+        let temperature = |state: &Option<JsonValue>| {
+            state
+                .as_ref()
+                .and_then(|s| s.get("temperature"))
+                .and_then(|t| t.as_f64())
+        };
+        let Some((read_at, reading)) = states
+            .iter()
+            .find_map(|(t, after, before)| temperature(after).or(temperature(before)).map(|c| (*t, c)))
+        else {
+            return Ok(config.min_temp_c);
+        };
+
+        let read_hour = read_at.date().and_hms_opt(read_at.hour(), 0, 0).unwrap();
+        let heated: HashSet<NaiveDateTime> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.ge(read_hour))
+            .filter(scheduled_executions::scheduled_hour.lt(at))
+            .filter(scheduled_executions::expected_action.eq(RuleAction::TurnOn.as_str()))
+            .filter(scheduled_executions::status.eq_any([
+                ExecutionStatus::Executed.as_str(),
+                ExecutionStatus::Pending.as_str(),
+                ExecutionStatus::Retrying.as_str(),
+            ]))
+            .select(scheduled_executions::scheduled_hour)
+            .load::<NaiveDateTime>(conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+
+        // Water drawn since the reading cools the tank too
+        let mut usages: Vec<Usage> = config
+            .usages_between(read_at, at)
+            .into_iter()
+            .map(|(at, min_temp_c, draw_liters)| Usage {
+                at,
+                min_temp_c,
+                draw_liters,
+            })
+            .collect();
+        usages.push(Usage {
+            at,
+            min_temp_c: 0.0,
+            draw_liters: 0.0,
+        });
+        Ok(*simulate(model, read_hour, reading, &heated, &usages).last().unwrap())
+    }
+
+    /// Plan the cycle of an appliance cycle rule in the window opening on
+    /// `date`: start when the device's load profile is cheapest and turn the
+    /// device off once the cycle is over. A cycle that already started is
+    /// never moved.
+    fn plan_appliance_cycle(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        date: NaiveDate,
+    ) -> Result<usize, String> {
+        let config = ApplianceCycleConfig::from_config(&rule.config_for_date(date))?;
+        let (window_start, window_end) = config.window_for(date)?;
+
+        let load_profile: Option<JsonValue> = devices::table
+            .filter(devices::id.eq(rule.device_id))
+            .select(devices::load_profile)
+            .first(conn)
+            .map_err(|e| e.to_string())?;
+        let profile = parse_load_profile(
+            &load_profile.ok_or_else(|| format!("Device {} has no load profile", rule.device_id))?,
+        )?;
+
+        let cycle = chrono::Duration::minutes(PROFILE_STEP_MINUTES * profile.len() as i64);
+        let execution = |scheduled_hour: NaiveDateTime, action: &str| NewScheduledExecution {
+            rule_id: rule.id,
+            scheduled_hour,
+            expected_action: action.to_string(),
+            status: ExecutionStatus::Pending.as_str().to_string(),
+            setpoint_c: None,
+        };
+
+        let started: Option<NaiveDateTime> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.ge(window_start))
+            .filter(scheduled_executions::scheduled_hour.le(window_end))
+            .filter(scheduled_executions::expected_action.eq(&rule.action))
+            .filter(scheduled_executions::status.eq_any([
+                ExecutionStatus::Executed.as_str(),
+                ExecutionStatus::Retrying.as_str(),
+            ]))
+            .select(scheduled_executions::scheduled_hour)
+            .first(conn)
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(start) = started {
+            // Only make sure the running cycle still gets switched off at its end
+            if rule.action != RuleAction::TurnOn.as_str() || start + cycle <= Local::now().naive_local() {
+                return Ok(0);
+            }
+            return diesel::insert_into(scheduled_executions::table)
+                .values(&execution(start + cycle, RuleAction::TurnOff.as_str()))
+                .on_conflict((
+                    scheduled_executions::rule_id,
+                    scheduled_executions::scheduled_hour,
+                ))
+                .do_nothing()
+                .execute(conn)
+                .map_err(|e| e.to_string());
+        }
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(window_start.date())
+            .map_err(|e| e.to_string())?;
+        if window_end.date() != window_start.date() {
+            prices.extend(
+                price_service
+                    .get_prices_for_date(window_end.date())
+                    .unwrap_or_default(),
+            );
+        }
+
+        // Every hour the cycle overlaps must fit within the contracted power
+        self.retain_within_power_limit(conn, rule, &mut prices)?;
+
+        let earliest = window_start.max(Local::now().naive_local());
+        let Some((start, cost)) =
+            find_cheapest_profile_start(&prices, &profile, earliest, window_end)
+        else {
+            // Keep any earlier plan until the whole window is priced
+            info!(
+                "No priced start for appliance cycle rule {} between {} and {}",
+                rule.id, earliest, window_end
+            );
+            return Ok(0);
+        };
+        info!(
+            "Appliance cycle rule {} starts at {} (expected cost {:.3} €)",
+            rule.id, start, cost
+        );
+
+        let mut new_executions = vec![execution(start, &rule.action)];
+        if rule.action == RuleAction::TurnOn.as_str() {
+            new_executions.push(execution(start + cycle, RuleAction::TurnOff.as_str()));
+        }
+
+        self.replace_pending_executions(conn, rule.id, window_start, Some(window_end), new_executions)
+    }
+
+    /// Plan an EV charging rule: the energy still missing for the next
+    /// departure goes into the cheapest hours before it, replacing the
+    /// rule's future pending executions
+    fn replan_ev_charging_rule(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+    ) -> Result<usize, String> {
+        let now = Local::now().naive_local();
+        let today = now.date();
+        let config = EvChargingConfig::from_config(&rule.config_for_date(today))?;
+
+        let horizon_start =
+            now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+        let departure = config.next_departure(now)?;
+        let session_start = departure - chrono::Duration::days(1);
+
+        // Charging since the session started (or since the state of charge was read) counts
+        let charged_from = config
+            .soc_updated_at
+            .filter(|t| *t > session_start)
+            .unwrap_or(session_start);
+        let ran: Vec<(NaiveDateTime, String)> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.ge(session_start))
+            .filter(scheduled_executions::scheduled_hour.lt(horizon_start))
+            .filter(scheduled_executions::status.eq_any([
+                ExecutionStatus::Executed.as_str(),
+                ExecutionStatus::Pending.as_str(),
+                ExecutionStatus::Retrying.as_str(),
+            ]))
+            .select((
+                scheduled_executions::scheduled_hour,
+                scheduled_executions::expected_action,
+            ))
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+        let (stops, starts): (Vec<_>, Vec<_>) = ran
+            .into_iter()
+            .partition(|(_, action)| action == RuleAction::TurnOff.as_str());
+        let starts: Vec<NaiveDateTime> = starts.into_iter().map(|(t, _)| t).collect();
+        let stops: Vec<NaiveDateTime> = stops.into_iter().map(|(t, _)| t).collect();
+        let charged = charged_energy_kwh(&starts, &stops, charged_from, config.charger_power_kw);
+        let energy_kwh = (config.energy_needed_kwh(session_start) - charged).max(0.0);
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(today)
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(today + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        prices.retain(|p| p.timestamp >= horizon_start);
+        self.retain_within_power_limit(conn, rule, &mut prices)?;
+
+        let plan = plan_ev_charging(&prices, departure, energy_kwh, config.charger_power_kw);
+        let delivered = plan.hours.len() as f64 * config.charger_power_kw;
+        if plan.stop_at.is_none() && delivered + 1e-6 < energy_kwh {
+            warn!(
+                "Rule {} can only charge {:.1} of {:.1} kWh before {}",
+                rule.id, delivered, energy_kwh, departure
+            );
+        }
+
+        let execution = |scheduled_hour: NaiveDateTime, action: &str| NewScheduledExecution {
+            rule_id: rule.id,
+            scheduled_hour,
+            expected_action: action.to_string(),
+            status: ExecutionStatus::Pending.as_str().to_string(),
+            setpoint_c: None,
+        };
+        let mut new_executions: Vec<NewScheduledExecution> = plan
+            .hours
+            .iter()
+            .map(|hour| execution(*hour, &rule.action))
+            .collect();
+        if let Some(stop_at) = plan.stop_at {
+            new_executions.push(execution(stop_at, RuleAction::TurnOff.as_str()));
+        }
+
+        self.replace_future_executions(conn, rule.id, horizon_start, new_executions)
+    }
+
+    /// Plan a rolling window rule across all known prices (up to tomorrow once
+    /// published), replacing its future pending executions
+    fn replan_rolling_rule(
+        &self,
+        conn: &mut diesel::PgConnection,
+        rule: &AutomationRule,
+    ) -> Result<usize, String> {
+        let now = Local::now().naive_local();
+        let today = now.date();
+        let config = RollingWindowConfig::from_config(&rule.config_for_date(today))?;
+
+        // The current hour has already been executed by the hourly run
+        let horizon_start =
+            now.date().and_hms_opt(now.hour(), 0, 0).unwrap() + chrono::Duration::hours(1);
+        let lookback_start =
+            horizon_start - chrono::Duration::hours(config.window_hours as i64 - 1);
+
+        let price_service = PriceService::new(self.pool.clone());
+        let mut prices = price_service
+            .get_prices_for_date(today)
+            .map_err(|e| e.to_string())?;
+        prices.extend(
+            price_service
+                .get_prices_for_date(today + chrono::Duration::days(1))
+                .unwrap_or_default(),
+        );
+        prices.retain(|p| p.timestamp >= horizon_start);
+        self.retain_within_power_limit(conn, rule, &mut prices)?;
+
+        // Hours already run (or about to) inside the lookback count towards the windows
+        let committed: Vec<NaiveDateTime> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.ge(lookback_start))
+            .filter(scheduled_executions::scheduled_hour.lt(horizon_start))
+            .filter(scheduled_executions::status.eq_any([
+                ExecutionStatus::Executed.as_str(),
+                ExecutionStatus::Pending.as_str(),
+                ExecutionStatus::Retrying.as_str(),
+            ]))
+            .select(scheduled_executions::scheduled_hour)
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+
+        // Only enforce windows reaching into the past if the rule has a history there
+        let has_history = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::scheduled_hour.lt(horizon_start))
+            .select(scheduled_executions::id)
+            .first::<i32>(conn)
+            .optional()
+            .map_err(|e| e.to_string())?
+            .is_some();
+        let origin = if has_history { lookback_start } else { horizon_start };
+
+        let plan = plan_rolling_hours(
+            &prices,
+            &committed,
+            origin,
+            config.hours_per_window as usize,
+            config.window_hours as usize,
+        );
+
+        self.replace_future_pending(conn, rule, horizon_start, plan)
+    }
+
+    /// Calculate the hours a rule wants on a given date
+    /// For overnight windows (e.g., 19:00-08:00), includes hours from both days
+    pub fn calculate_demand_for_rule(
         &self,
         rule: &AutomationRule,
         date: NaiveDate,
-    ) -> Result<Vec<NaiveDateTime>, String> {
+    ) -> Result<PowerDemand, String> {
         let price_service = PriceService::new(self.pool.clone());
+        // Seasonal/weekday profiles pick the parameter set for this day
+        let config = rule.config_for_date(date);
 
         match rule.rule_type.as_str() {
             "cheapest_hours" => {
-                let hours_needed = rule
-                    .config
+                let hours_needed = config
                     .get("cheapest_hours")
                     .and_then(|v| v.as_i64())
                     .unwrap_or(6) as usize;
 
                 // Check for optional time window
-                let start_hour = rule
-                    .config
+                let start_hour = config
                     .get("time_range_start")
                     .and_then(|v| v.as_str())
                     .and_then(|s| s.split(':').next())
                     .and_then(|h| h.parse::<u32>().ok());
 
-                let end_hour = rule
-                    .config
+                let end_hour = config
                     .get("time_range_end")
                     .and_then(|v| v.as_str())
                     .and_then(|s| s.split(':').next())
@@ -180,45 +1076,52 @@ impl ScheduleComputationService {
                     }
                 };
 
-                // Sort by price and take the cheapest N hours
-                let mut sorted_prices = filtered_prices;
-                sorted_prices.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-
-                let cheapest_timestamps: Vec<NaiveDateTime> = sorted_prices
-                    .iter()
-                    .take(hours_needed)
-                    .map(|p| p.timestamp)
-                    .collect();
-
-                Ok(cheapest_timestamps)
+                // Appliances that can't be interrupted need a single block
+                let contiguous = config
+                    .get("contiguous")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+
+                // The cheapest N hours (or block) are picked once the power budget is known
+                Ok(PowerDemand::Flexible {
+                    candidates: filtered_prices,
+                    hours_needed,
+                    contiguous,
+                })
             }
             "price_threshold" => {
-                let threshold = rule
-                    .config
-                    .get("price_threshold")
-                    .and_then(|v| v.as_f64())
-                    .unwrap_or(0.10);
-
-                let prices = price_service
-                    .get_prices_for_date(date)
-                    .map_err(|e| e.to_string())?;
-
-                let timestamps: Vec<NaiveDateTime> = prices
-                    .iter()
-                    .filter(|p| p.price <= threshold)
-                    .map(|p| p.timestamp)
-                    .collect();
-
-                Ok(timestamps)
+                let threshold_config = PriceThresholdConfig::from_config(&config)?;
+
+                // Yesterday carries the hysteresis state over midnight and
+                // tomorrow feeds the rolling average of the last hours
+                let mut prices = price_service
+                    .get_prices_for_date(date - chrono::Duration::days(1))
+                    .unwrap_or_default();
+                prices.extend(
+                    price_service
+                        .get_prices_for_date(date)
+                        .map_err(|e| e.to_string())?,
+                );
+                prices.extend(
+                    price_service
+                        .get_prices_for_date(date + chrono::Duration::days(1))
+                        .unwrap_or_default(),
+                );
+
+                let timestamps: Vec<NaiveDateTime> =
+                    plan_price_threshold_hours(&prices, &threshold_config)
+                        .into_iter()
+                        .filter(|t| t.date() == date)
+                        .collect();
+
+                Ok(PowerDemand::Fixed(timestamps))
             }
             "time_schedule" => {
-                let start_str = rule
-                    .config
+                let start_str = config
                     .get("time_range_start")
                     .and_then(|v| v.as_str())
                     .unwrap_or("00:00");
-                let end_str = rule
-                    .config
+                let end_str = config
                     .get("time_range_end")
                     .and_then(|v| v.as_str())
                     .unwrap_or("23:59");
@@ -251,27 +1154,149 @@ impl ScheduleComputationService {
                     today_hours.into_iter().chain(tomorrow_hours).collect()
                 };
 
-                Ok(timestamps)
+                Ok(PowerDemand::Fixed(timestamps))
+            }
+            "cron" => {
+                let cron_config = CronScheduleConfig::from_config(&config)?;
+                let tz = cron_config.resolve_timezone(&self.get_user_timezone(rule.user_id)?)?;
+
+                // Expand over the server-local day, whatever the rule's timezone
+                let day_start = |d: NaiveDate| {
+                    Local
+                        .from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap())
+                        .earliest()
+                        .map(|t| t.with_timezone(&Utc))
+                        .ok_or_else(|| format!("Invalid local date {}", d))
+                };
+                let from = day_start(date)?;
+                let to = day_start(date + chrono::Duration::days(1))?;
+
+                let occurrences = cron_config.occurrences_between(tz, from, to)?;
+                let timestamps = naive_local_times(&occurrences, &Local);
+                if timestamps.len() < occurrences.len() {
+                    info!(
+                        "Rule {} has {} occurrences in the hour repeated on {} when clocks go back; they run once",
+                        rule.id,
+                        occurrences.len() - timestamps.len(),
+                        date
+                    );
+                }
+
+                Ok(PowerDemand::Fixed(timestamps))
             }
-            _ => Ok(vec![]), // Manual or unknown rule types don't schedule
+            "rolling_window" | "ev_charging" | "water_heater" | "appliance_cycle" => {
+                // Their own planners pick the hours (rolling windows, partial charging
+                // slots, tank temperature, cycle starts); what they planned is fixed
+                Ok(PowerDemand::Fixed(self.planned_hours(rule, date)?))
+            }
+            _ => Ok(PowerDemand::Fixed(vec![])), // Manual or unknown rule types don't schedule
+        }
+    }
+
+    /// Hours of `date` in which a rule's planned (or run) turn_on executions
+    /// keep its device on, including every hour of its appliance cycles
+    fn planned_hours(&self, rule: &AutomationRule, date: NaiveDate) -> Result<Vec<NaiveDateTime>, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let from = date.and_hms_opt(0, 0, 0).unwrap();
+        let to = date.and_hms_opt(23, 0, 0).unwrap();
+
+        let mut hours: Vec<NaiveDateTime> = planned_loads(&mut conn, rule.user_id, from, to)?
+            .into_iter()
+            .filter(|(rule_id, _)| *rule_id == rule.id)
+            .flat_map(|(_, load)| match load.cycle {
+                Some(cycle) => hours_overlapping(load.at, load.at + cycle),
+                None => vec![load.at],
+            })
+            .filter(|hour| hour.date() == date)
+            .collect();
+        hours.sort();
+        hours.dedup();
+        Ok(hours)
+    }
+
+    /// Power budget of a turn_on rule's device against the user's other devices.
+    /// None when the user has no contracted power or the device power is unknown.
+    fn power_budget(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        hours: &[NaiveDateTime],
+    ) -> Result<Option<PowerBudget>, String> {
+        let (Some(from), Some(to)) = (hours.iter().min(), hours.iter().max()) else {
+            return Ok(None);
+        };
+        if rule.action != RuleAction::TurnOn.as_str() {
+            return Ok(None);
+        }
+
+        let limit_kw: Option<f64> = users::table
+            .filter(users::id.eq(rule.user_id))
+            .select(users::contracted_power_kw)
+            .first(conn)
+            .map_err(|e| e.to_string())?;
+        let power_w: Option<i32> = devices::table
+            .filter(devices::id.eq(rule.device_id))
+            .select(devices::rated_power_w)
+            .first(conn)
+            .map_err(|e| e.to_string())?;
+        let (Some(limit_kw), Some(power_w)) = (limit_kw, power_w) else {
+            return Ok(None);
+        };
+
+        // Other devices of the user planned (or running) to be on in those hours
+        let planned: Vec<PlannedLoad> = planned_loads(conn, rule.user_id, *from, *to)?
+            .into_iter()
+            .map(|(_, load)| load)
+            .filter(|load| load.device_id != rule.device_id)
+            .collect();
+
+        Ok(Some(PowerBudget {
+            power_w: power_w as f64,
+            limit_w: limit_kw * 1000.0,
+            load_w: hourly_load_w(&planned),
+        }))
+    }
+
+    /// Drop the hours in which the rule's device would exceed the contracted power
+    fn retain_within_power_limit(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        prices: &mut Vec<Price>,
+    ) -> Result<(), String> {
+        let hours: Vec<NaiveDateTime> = prices.iter().map(|p| p.timestamp).collect();
+        if let Some(budget) = self.power_budget(conn, rule, &hours)? {
+            prices.retain(|p| budget.fits(&p.timestamp));
         }
+        Ok(())
+    }
+
+    /// Get the timezone configured for a user
+    fn get_user_timezone(&self, user_id: i32) -> Result<String, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        users::table
+            .filter(users::id.eq(user_id))
+            .select(users::timezone)
+            .first(&mut conn)
+            .map_err(|e| e.to_string())
     }
 
-    /// Check if a rule has an overnight time window (crosses midnight)
-    pub fn rule_has_overnight_window(&self, rule: &AutomationRule) -> bool {
+    /// Check if a rule has an overnight time window (crosses midnight) on a given date
+    pub fn rule_has_overnight_window(&self, rule: &AutomationRule, date: NaiveDate) -> bool {
         if rule.rule_type != "cheapest_hours" && rule.rule_type != "time_schedule" {
             return false;
         }
 
-        let start_hour = rule
-            .config
+        let config = rule.config_for_date(date);
+
+        let start_hour = config
             .get("time_range_start")
             .and_then(|v| v.as_str())
             .and_then(|s| s.split(':').next())
             .and_then(|h| h.parse::<u32>().ok());
 
-        let end_hour = rule
-            .config
+        let end_hour = config
             .get("time_range_end")
             .and_then(|v| v.as_str())
             .and_then(|s| s.split(':').next())
@@ -298,7 +1323,11 @@ impl ScheduleComputationService {
         let mut total_recomputed = 0;
 
         for rule in rules {
-            if self.rule_has_overnight_window(&rule) {
+            if self.rule_has_overnight_window(&rule, today) {
+                if self.is_optimized(&mut conn, rule.id, today) {
+                    info!("Keeping the optimised plan of overnight rule {}", rule.id);
+                    continue;
+                }
                 info!(
                     "Recomputing overnight rule {} ({}) now that tomorrow's prices are available",
                     rule.id, rule.name
@@ -337,17 +1366,16 @@ impl ScheduleComputationService {
     /// This is called before recomputing to replace with updated calculations
     fn delete_pending_overnight_for_rule(&self, rule: &AutomationRule, date: NaiveDate) -> Result<usize, String> {
         let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let config = rule.config_for_date(date);
 
-        let start_hour = rule
-            .config
+        let start_hour = config
             .get("time_range_start")
             .and_then(|v| v.as_str())
             .and_then(|s| s.split(':').next())
             .and_then(|h| h.parse::<u32>().ok())
             .unwrap_or(0);
 
-        let end_hour = rule
-            .config
+        let end_hour = config
             .get("time_range_end")
             .and_then(|v| v.as_str())
             .and_then(|s| s.split(':').next())
@@ -373,7 +1401,7 @@ impl ScheduleComputationService {
         Ok(count)
     }
 
-    /// Mark pending hours that have passed as "missed"
+    /// Mark pending hours that have passed, and claims that never finished, as "missed"
     pub fn mark_missed_hours(&self) -> Result<usize, String> {
         let mut conn = self.pool.get().map_err(|e| e.to_string())?;
 
@@ -382,21 +1410,52 @@ impl ScheduleComputationService {
         // e.g., if it's 14:14, then 13:00 is missed (we should have executed at 13:00)
         let current_hour_start = now.date().and_hms_opt(now.hour(), 0, 0).unwrap();
 
+        // Cron executions at arbitrary times keep a grace period so the last
+        // minutes of the previous hour can still be picked up
+        let grace_cutoff = now - chrono::Duration::minutes(DUE_EXECUTION_GRACE_MINUTES);
+
         // Mark all pending scheduled executions where the hour has passed as missed
         // Use lt (less than) because the current hour might still be executing
-        let count = diesel::update(
+        let mut missed_rule_ids: Vec<i32> = diesel::update(
             scheduled_executions::table
                 .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
-                .filter(scheduled_executions::scheduled_hour.lt(current_hour_start)),
+                .filter(scheduled_executions::scheduled_hour.lt(current_hour_start))
+                .filter(scheduled_executions::scheduled_hour.lt(grace_cutoff)),
         )
         .set(scheduled_executions::status.eq(ExecutionStatus::Missed.as_str()))
-        .execute(&mut conn)
+        .returning(scheduled_executions::rule_id)
+        .get_results(&mut conn)
         .map_err(|e| e.to_string())?;
 
+        // A claimed execution still running long after it was due means the
+        // runner crashed or the provider call hung
+        let claim_cutoff = now - chrono::Duration::minutes(EXECUTION_CLAIM_TIMEOUT_MINUTES);
+        let stale_rule_ids: Vec<i32> = diesel::update(
+            scheduled_executions::table
+                .filter(scheduled_executions::status.eq(ExecutionStatus::Executing.as_str()))
+                .filter(scheduled_executions::scheduled_hour.lt(claim_cutoff)),
+        )
+        .set(scheduled_executions::status.eq(ExecutionStatus::Missed.as_str()))
+        .returning(scheduled_executions::rule_id)
+        .get_results(&mut conn)
+        .map_err(|e| e.to_string())?;
+        if !stale_rule_ids.is_empty() {
+            warn!("Marked {} stale claimed executions as missed", stale_rule_ids.len());
+        }
+        missed_rule_ids.extend(stale_rule_ids);
+        let count = missed_rule_ids.len();
+
         if count > 0 {
             info!("Marked {} scheduled executions as missed", count);
         }
 
+        // A missed predecessor hour moves the end of its chain
+        missed_rule_ids.sort_unstable();
+        missed_rule_ids.dedup();
+        for rule_id in missed_rule_ids {
+            self.replan_successors_internal(&mut conn, rule_id, 1);
+        }
+
         Ok(count)
     }
 
@@ -446,21 +1505,332 @@ impl ScheduleComputationService {
             "Deleted {} pending scheduled executions for rule {}",
             count, rule_id
         );
+
+        // The rule is planned on its own again
+        diesel::delete(optimized_rule_dates::table.filter(optimized_rule_dates::rule_id.eq(rule_id)))
+            .execute(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        // Rules chained after this one lose (or move) their predecessor's run
+        self.replan_successors_internal(&mut conn, rule_id, 1);
+
         Ok(count)
     }
 
+    /// Recompute the enabled cron rules of a user (e.g., after a timezone change)
+    pub fn recompute_cron_rules_for_user(&self, user_id: i32) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let rule_ids: Vec<i32> = automation_rules::table
+            .filter(automation_rules::user_id.eq(user_id))
+            .filter(automation_rules::rule_type.eq(RuleType::Cron.as_str()))
+            .filter(automation_rules::is_enabled.eq(true))
+            .select(automation_rules::id)
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let mut total = 0;
+        for rule_id in rule_ids {
+            total += self.recompute_schedule_for_rule(rule_id)?;
+        }
+        Ok(total)
+    }
+
+    /// Recompute all enabled rules of a user in priority order (e.g., after
+    /// the contracted power changed), freeing every pending hour first
+    pub fn recompute_rules_for_user(&self, user_id: i32) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let rule_ids: Vec<i32> = automation_rules::table
+            .filter(automation_rules::user_id.eq(user_id))
+            .filter(automation_rules::is_enabled.eq(true))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .select(automation_rules::id)
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        for rule_id in &rule_ids {
+            self.delete_schedule_for_rule(*rule_id)?;
+        }
+
+        let mut total = 0;
+        for rule_id in rule_ids {
+            total += self.compute_schedule_after_reset(rule_id);
+        }
+        Ok(total)
+    }
+
+    /// Replan a user's rules after one of them was created or changed. Rules
+    /// share the user's power budget by priority, so a new or changed rule
+    /// can take hours from other rules or give them back.
+    pub fn recompute_after_rule_change(&self, rule_id: i32) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let (user_id, device_id): (i32, i32) = automation_rules::table
+            .filter(automation_rules::id.eq(rule_id))
+            .select((automation_rules::user_id, automation_rules::device_id))
+            .first(&mut conn)
+            .map_err(|e| e.to_string())?;
+        drop(conn);
+
+        self.recompute_after_rules_change(user_id, &[(rule_id, device_id)])
+    }
+
+    /// Replan the rules affected by changes to the rules `changed` ((rule id,
+    /// device id)) of a user, which may have been created, changed, disabled
+    /// or deleted. See `budget_replans` for which rules are replanned.
+    pub fn recompute_after_rules_change(&self, user_id: i32, changed: &[(i32, i32)]) -> Result<usize, String> {
+        for (rule_id, _) in changed {
+            self.delete_schedule_for_rule(*rule_id)?;
+        }
+
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        let limit_kw: Option<f64> = users::table
+            .filter(users::id.eq(user_id))
+            .select(users::contracted_power_kw)
+            .first(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let rules: Vec<AutomationRule> = automation_rules::table
+            .filter(automation_rules::user_id.eq(user_id))
+            .filter(automation_rules::is_enabled.eq(true))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let rule_ids: Vec<i32> = rules.iter().map(|r| r.id).collect();
+        let optimized: HashSet<(i32, NaiveDate)> = optimized_rule_dates::table
+            .filter(optimized_rule_dates::rule_id.eq_any(&rule_ids))
+            .select((optimized_rule_dates::rule_id, optimized_rule_dates::date))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+
+        let today = Local::now().date_naive();
+        let replans = budget_replans(&rules, changed, limit_kw.is_some(), &optimized, today);
+
+        // Free the hours of every rule first, so each one is planned against
+        // the rules before it in priority order only
+        for replan in &replans {
+            if changed.iter().any(|(id, _)| *id == replan.rule_id) {
+                continue;
+            }
+            let rule = rules.iter().find(|r| r.id == replan.rule_id).unwrap();
+            if let Err(e) = self.release_pending_executions(&mut conn, rule, &replan.kept_dates) {
+                warn!("Failed to free the pending executions of rule {}: {}", rule.id, e);
+            }
+        }
+
+        let mut total = 0;
+        for replan in replans {
+            let rule = rules.iter().find(|r| r.id == replan.rule_id).unwrap();
+            // Optimised dates are skipped by the per-rule planner
+            for date in replan_dates(&rule.rule_type, today) {
+                match self.compute_schedule_for_rule_internal(&mut conn, rule, date) {
+                    Ok(count) => total += count,
+                    Err(e) => error!("Failed to compute schedule for rule {}: {}", rule.id, e),
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Delete a rule's pending executions, except in the windows of the dates
+    /// the cost optimiser planned (whose markers are kept)
+    fn release_pending_executions(
+        &self,
+        conn: &mut PgConnection,
+        rule: &AutomationRule,
+        kept_dates: &[NaiveDate],
+    ) -> Result<usize, String> {
+        let mut kept_windows = Vec::new();
+        for date in kept_dates {
+            let hours = self.calculate_demand_for_rule(rule, *date)?.hours_considered();
+            if let (Some(from), Some(to)) = (hours.iter().min(), hours.iter().max()) {
+                kept_windows.push((*from, *to));
+            }
+        }
+
+        let pending: Vec<(i32, NaiveDateTime)> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq(rule.id))
+            .filter(scheduled_executions::status.eq(ExecutionStatus::Pending.as_str()))
+            .select((scheduled_executions::id, scheduled_executions::scheduled_hour))
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+        let released: Vec<i32> = pending
+            .into_iter()
+            .filter(|(_, hour)| !kept_windows.iter().any(|(from, to)| hour >= from && hour <= to))
+            .map(|(id, _)| id)
+            .collect();
+
+        diesel::delete(scheduled_executions::table.filter(scheduled_executions::id.eq_any(&released)))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Recompute the enabled appliance cycle rules of a device (e.g., after its
+    /// load profile changed)
+    pub fn recompute_appliance_cycles_for_device(&self, device_id: i32) -> Result<usize, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let rule_ids: Vec<i32> = automation_rules::table
+            .filter(automation_rules::device_id.eq(device_id))
+            .filter(automation_rules::rule_type.eq(RuleType::ApplianceCycle.as_str()))
+            .filter(automation_rules::is_enabled.eq(true))
+            .select(automation_rules::id)
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let mut total = 0;
+        for rule_id in rule_ids {
+            total += self.recompute_schedule_for_rule(rule_id)?;
+        }
+        Ok(total)
+    }
+
     /// Recompute schedule for a rule (delete pending and recompute)
     pub fn recompute_schedule_for_rule(&self, rule_id: i32) -> Result<usize, String> {
         // Delete pending schedules
         self.delete_schedule_for_rule(rule_id)?;
 
-        // Recompute for today and tomorrow
-        let today = Local::now().date_naive();
-        let tomorrow = today + chrono::Duration::days(1);
+        Ok(self.compute_schedule_after_reset(rule_id))
+    }
+
+    /// Plan a rule again after its pending executions were deleted
+    fn compute_schedule_after_reset(&self, rule_id: i32) -> usize {
+        let rule_type: String = match self.pool.get().map_err(|e| e.to_string()).and_then(|mut conn| {
+            automation_rules::table
+                .filter(automation_rules::id.eq(rule_id))
+                .select(automation_rules::rule_type)
+                .first(&mut conn)
+                .map_err(|e| e.to_string())
+        }) {
+            Ok(rule_type) => rule_type,
+            Err(e) => {
+                error!("Failed to load rule {}: {}", rule_id, e);
+                return 0;
+            }
+        };
+
+        replan_dates(&rule_type, Local::now().date_naive())
+            .into_iter()
+            .map(|date| self.compute_schedule_for_rule(rule_id, date).unwrap_or(0))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_rule_chain_accepts_linear_chain() {
+        // 3 follows 2, 2 follows 1
+        let predecessors = HashMap::from([(3, 2), (2, 1)]);
+        assert!(check_rule_chain(Some(4), 3, |id| predecessors.get(&id).copied()).is_ok());
+        assert!(check_rule_chain(None, 3, |id| predecessors.get(&id).copied()).is_ok());
+    }
+
+    #[test]
+    fn test_check_rule_chain_rejects_cycles() {
+        let predecessors = HashMap::from([(3, 2), (2, 1)]);
+        assert!(check_rule_chain(Some(1), 3, |id| predecessors.get(&id).copied()).is_err());
+        assert!(check_rule_chain(Some(5), 5, |_| None).is_err());
+    }
+
+    #[test]
+    fn test_replan_dates_include_running_appliance_cycles() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let yesterday = NaiveDate::from_ymd_opt(2026, 10, 17).unwrap();
+        let tomorrow = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+
+        assert_eq!(
+            replan_dates(RuleType::ApplianceCycle.as_str(), today),
+            vec![yesterday, today, tomorrow]
+        );
+        assert_eq!(replan_dates(RuleType::CheapestHours.as_str(), today), vec![today, tomorrow]);
+    }
+
+    fn rule(id: i32, device_id: i32, action: &str) -> AutomationRule {
+        let created = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        AutomationRule {
+            id,
+            user_id: 1,
+            device_id,
+            name: format!("Rule {}", id),
+            rule_type: RuleType::CheapestHours.as_str().to_string(),
+            action: action.to_string(),
+            config: serde_json::json!({"cheapest_hours": 3}),
+            is_enabled: true,
+            priority: id,
+            created_at: created,
+            updated_at: created,
+            last_triggered_at: None,
+        }
+    }
 
-        let count_today = self.compute_schedule_for_rule(rule_id, today).unwrap_or(0);
-        let count_tomorrow = self.compute_schedule_for_rule(rule_id, tomorrow).unwrap_or(0);
+    #[test]
+    fn test_editing_a_rule_keeps_other_optimised_plans() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let tomorrow = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let rules = vec![rule(1, 10, "turn_on"), rule(2, 20, "turn_on")];
+        // Both rules were optimised today; rule 1 is edited
+        let optimized = HashSet::from([(1, today), (2, today)]);
+
+        let replans = budget_replans(&rules, &[(1, 10)], true, &optimized, today);
+        assert_eq!(
+            replans,
+            vec![
+                BudgetReplan { rule_id: 1, kept_dates: vec![] },
+                BudgetReplan { rule_id: 2, kept_dates: vec![today] },
+            ]
+        );
+        assert!(!replans[1].kept_dates.contains(&tomorrow));
+    }
+
+    #[test]
+    fn test_budget_replans_only_rules_sharing_the_budget() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let rules = vec![
+            rule(1, 10, "turn_on"),
+            rule(2, 10, "turn_on"),
+            rule(3, 20, "turn_off"),
+            rule(4, 30, "turn_on"),
+        ];
+        let replanned = |changed: &[(i32, i32)], limit: bool| -> Vec<i32> {
+            budget_replans(&rules, changed, limit, &HashSet::new(), today)
+                .into_iter()
+                .map(|r| r.rule_id)
+                .collect()
+        };
+
+        // Same device rules and turn_off rules don't share a power budget
+        assert_eq!(replanned(&[(1, 10)], true), vec![1, 4]);
+        // Without a contracted power nothing else moves
+        assert_eq!(replanned(&[(1, 10)], false), vec![1]);
+        // A deleted rule frees its hours for the others
+        assert_eq!(replanned(&[(9, 30)], true), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_chained_successors_of_a_deleted_predecessor() {
+        let mut dryer = rule(2, 20, "turn_on");
+        dryer.rule_type = RuleType::Chained.as_str().to_string();
+        dryer.config = serde_json::json!({"after_rule_id": 1, "hours_needed": 2});
+        let mut iron = dryer.clone();
+        iron.id = 3;
+        iron.config = serde_json::json!({"after_rule_id": 2, "hours_needed": 1});
+        let rules = vec![rule(1, 10, "turn_on"), dryer, iron];
+
+        let ids = |id: i32| -> Vec<i32> {
+            chained_successors(rules.clone(), id).into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(ids(1), vec![2]);
+        assert_eq!(ids(2), vec![3]);
+        assert!(ids(3).is_empty());
+    }
 
-        Ok(count_today + count_tomorrow)
+    #[test]
+    fn test_check_rule_chain_limits_depth() {
+        let predecessors: HashMap<i32, i32> = (2..=20).map(|id| (id, id - 1)).collect();
+        assert!(check_rule_chain(None, 20, |id| predecessors.get(&id).copied()).is_err());
     }
 }
@@ -0,0 +1,385 @@
+use crate::db::DbPool;
+use crate::integrations::ProviderRegistry;
+use crate::models::{
+    AutomationRule, Device, RuleAction, RuleExecution, ScheduledExecution,
+};
+use crate::schema::{automation_rules, devices, prices, rule_executions, scheduled_executions};
+use crate::services::automation_engine::{AutomationEngine, POINT_IN_TIME_RULE_TYPES};
+use chrono::{NaiveDateTime, Timelike};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Price of the explained hour and its rank within the day (1 = cheapest)
+#[derive(Debug, Clone, Serialize)]
+pub struct HourPrice {
+    pub price: f64,
+    pub rank: usize,
+    pub hours_in_day: usize,
+}
+
+/// A scheduled execution of a rule falling in the explained hour
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledSlot {
+    pub id: i32,
+    pub scheduled_at: String,
+    pub expected_action: String,
+    pub status: String,
+}
+
+/// Rule engine evaluation (the reason otherwise only written to the logs)
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluationSummary {
+    pub should_trigger: bool,
+    pub action: String,
+    pub reason: String,
+}
+
+/// How a single rule of the device was considered
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleExplanation {
+    pub rule_id: i32,
+    pub rule_name: String,
+    pub rule_type: String,
+    pub action: String,
+    pub priority: i32,
+    pub is_enabled: bool,
+    /// None for disabled rules
+    pub evaluation: Option<EvaluationSummary>,
+    /// Precomputed executions in this hour (what the hourly run acts on)
+    pub scheduled: Vec<ScheduledSlot>,
+    /// Whether the rule has any execution scheduled that day (enables auto turn-off)
+    pub has_schedule_today: bool,
+}
+
+/// One action the scheduler takes on the device during the hour, in order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedAction {
+    pub rule_id: i32,
+    pub action: String,
+    pub source: String,
+}
+
+/// Final outcome for the hour
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    /// "turn_on", "turn_off", "toggle" or None when nothing acts on the device
+    pub action: Option<String>,
+    pub rule_id: Option<i32>,
+    pub reason: String,
+}
+
+/// Full explanation of a device's schedule at a point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceExplanation {
+    pub device_id: i32,
+    pub device_name: String,
+    pub at: String,
+    pub hour_start: String,
+    pub price: Option<HourPrice>,
+    pub rules: Vec<RuleExplanation>,
+    pub actions: Vec<PlannedAction>,
+    /// Actions superseded by a later one in the same hour
+    pub overrides: Vec<String>,
+    pub decision: Decision,
+    /// What was actually executed for this device's rules during the hour
+    pub executions: Vec<RuleExecution>,
+}
+
+/// Rank of `price` among the day's prices (1 = cheapest)
+pub fn price_rank(day_prices: &[f64], price: f64) -> usize {
+    day_prices.iter().filter(|p| **p < price).count() + 1
+}
+
+/// Replay the hourly run for one device: scheduled executions first (by
+/// priority), then the automatic turn-off of turn_on rules not scheduled this
+/// hour. The last action wins.
+pub fn decide(rules: &[RuleExplanation]) -> (Vec<PlannedAction>, Vec<String>, Decision) {
+    let mut enabled: Vec<&RuleExplanation> = rules.iter().filter(|r| r.is_enabled).collect();
+    enabled.sort_by_key(|r| (r.priority, r.rule_id));
+
+    let mut actions = Vec::new();
+    for rule in &enabled {
+        for slot in &rule.scheduled {
+            actions.push(PlannedAction {
+                rule_id: rule.rule_id,
+                action: slot.expected_action.clone(),
+                source: format!("scheduled at {} ({})", slot.scheduled_at, slot.status),
+            });
+        }
+    }
+
+    for rule in &enabled {
+        let point_event = POINT_IN_TIME_RULE_TYPES.contains(&rule.rule_type.as_str());
+        if rule.action == RuleAction::TurnOn.as_str()
+            && !point_event
+            && rule.scheduled.is_empty()
+            && rule.has_schedule_today
+        {
+            actions.push(PlannedAction {
+                rule_id: rule.rule_id,
+                action: RuleAction::TurnOff.as_str().to_string(),
+                source: "not scheduled this hour - auto turn off".to_string(),
+            });
+        }
+    }
+
+    let overrides = actions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, action)| {
+            let later = actions[i + 1..]
+                .iter()
+                .find(|a| a.action != action.action)?;
+            Some(format!(
+                "Rule {} ({}) is overridden by rule {} ({}, {})",
+                action.rule_id, action.action, later.rule_id, later.action, later.source
+            ))
+        })
+        .collect();
+
+    let decision = match actions.last() {
+        Some(last) => Decision {
+            action: Some(last.action.clone()),
+            rule_id: Some(last.rule_id),
+            reason: format!("Rule {}: {}", last.rule_id, last.source),
+        },
+        None => Decision {
+            action: None,
+            rule_id: None,
+            reason: "No rule acts on this device during this hour".to_string(),
+        },
+    };
+
+    (actions, overrides, decision)
+}
+
+/// Service that explains why a device is (or will be) on or off
+pub struct ScheduleExplainerService {
+    pool: DbPool,
+    provider_registry: Arc<ProviderRegistry>,
+}
+
+impl ScheduleExplainerService {
+    pub fn new(pool: DbPool, provider_registry: Arc<ProviderRegistry>) -> Self {
+        Self {
+            pool,
+            provider_registry,
+        }
+    }
+
+    /// Explain the schedule of a device at a given (local) time
+    pub fn explain(&self, device: &Device, at: NaiveDateTime) -> Result<DeviceExplanation, String> {
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+
+        let hour_start = at.date().and_hms_opt(at.hour(), 0, 0).unwrap();
+        let hour_end = hour_start + chrono::Duration::hours(1);
+        let day_start = at.date().and_hms_opt(0, 0, 0).unwrap();
+        let day_end = day_start + chrono::Duration::days(1);
+
+        let day_prices: Vec<(NaiveDateTime, f64)> = prices::table
+            .filter(prices::timestamp.ge(day_start))
+            .filter(prices::timestamp.lt(day_end))
+            .select((prices::timestamp, prices::price))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let current_price = day_prices
+            .iter()
+            .find(|(ts, _)| *ts == hour_start)
+            .map(|(_, p)| *p);
+        let all_prices: Vec<f64> = day_prices.iter().map(|(_, p)| *p).collect();
+        let price = current_price.map(|p| HourPrice {
+            price: p,
+            rank: price_rank(&all_prices, p),
+            hours_in_day: all_prices.len(),
+        });
+
+        let rules: Vec<AutomationRule> = automation_rules::table
+            .filter(automation_rules::device_id.eq(device.id))
+            .order((automation_rules::priority.asc(), automation_rules::id.asc()))
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+        let rule_ids: Vec<i32> = rules.iter().map(|r| r.id).collect();
+
+        let day_schedule: Vec<ScheduledExecution> = scheduled_executions::table
+            .filter(scheduled_executions::rule_id.eq_any(&rule_ids))
+            .filter(scheduled_executions::scheduled_hour.ge(day_start))
+            .filter(scheduled_executions::scheduled_hour.lt(day_end))
+            .order(scheduled_executions::scheduled_hour.asc())
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let engine = AutomationEngine::new(self.pool.clone(), self.provider_registry.clone());
+        let explanations: Vec<RuleExplanation> = rules
+            .iter()
+            .map(|rule| {
+                let evaluation = rule.is_enabled.then(|| {
+                    let eval = engine.evaluate_rule(rule, &at, current_price);
+                    EvaluationSummary {
+                        should_trigger: eval.should_trigger,
+                        action: eval.action.as_str().to_string(),
+                        reason: eval.reason,
+                    }
+                });
+                let scheduled = day_schedule
+                    .iter()
+                    .filter(|s| s.rule_id == rule.id)
+                    .filter(|s| s.scheduled_hour >= hour_start && s.scheduled_hour < hour_end)
+                    .map(|s| ScheduledSlot {
+                        id: s.id,
+                        scheduled_at: s.scheduled_hour.to_string(),
+                        expected_action: s.expected_action.clone(),
+                        status: s.status.clone(),
+                    })
+                    .collect();
+
+                RuleExplanation {
+                    rule_id: rule.id,
+                    rule_name: rule.name.clone(),
+                    rule_type: rule.rule_type.clone(),
+                    action: rule.action.clone(),
+                    priority: rule.priority,
+                    is_enabled: rule.is_enabled,
+                    evaluation,
+                    scheduled,
+                    has_schedule_today: day_schedule.iter().any(|s| s.rule_id == rule.id),
+                }
+            })
+            .collect();
+
+        let executions: Vec<RuleExecution> = rule_executions::table
+            .filter(rule_executions::rule_id.eq_any(&rule_ids))
+            .filter(rule_executions::executed_at.ge(hour_start))
+            .filter(rule_executions::executed_at.lt(hour_end))
+            .order(rule_executions::executed_at.asc())
+            .load(&mut conn)
+            .map_err(|e| e.to_string())?;
+
+        let (actions, overrides, decision) = decide(&explanations);
+
+        Ok(DeviceExplanation {
+            device_id: device.id,
+            device_name: device.name.clone(),
+            at: at.to_string(),
+            hour_start: hour_start.to_string(),
+            price,
+            rules: explanations,
+            actions,
+            overrides,
+            decision,
+            executions,
+        })
+    }
+
+    /// Load a device if it belongs to the user
+    pub fn find_user_device(&self, device_id: i32, user_id: i32) -> Result<Option<Device>, String> {
+        use crate::schema::user_integrations;
+
+        let mut conn = self.pool.get().map_err(|e| e.to_string())?;
+        devices::table
+            .inner_join(user_integrations::table)
+            .filter(devices::id.eq(device_id))
+            .filter(user_integrations::user_id.eq(user_id))
+            .select(Device::as_select())
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        id: i32,
+        priority: i32,
+        action: &str,
+        scheduled: &[&str],
+        has_today: bool,
+    ) -> RuleExplanation {
+        RuleExplanation {
+            rule_id: id,
+            rule_name: format!("Rule {}", id),
+            rule_type: "cheapest_hours".to_string(),
+            action: action.to_string(),
+            priority,
+            is_enabled: true,
+            evaluation: None,
+            scheduled: scheduled
+                .iter()
+                .enumerate()
+                .map(|(i, a)| ScheduledSlot {
+                    id: i as i32,
+                    scheduled_at: "2026-01-18 14:00:00".to_string(),
+                    expected_action: a.to_string(),
+                    status: "pending".to_string(),
+                })
+                .collect(),
+            has_schedule_today: has_today,
+        }
+    }
+
+    #[test]
+    fn test_price_rank() {
+        let prices = [0.10, 0.05, 0.20, 0.05];
+        assert_eq!(price_rank(&prices, 0.05), 1);
+        assert_eq!(price_rank(&prices, 0.10), 3);
+        assert_eq!(price_rank(&prices, 0.20), 4);
+    }
+
+    #[test]
+    fn test_decide_scheduled_rules_by_priority() {
+        let rules = vec![
+            rule(1, 100, "turn_on", &["turn_on"], true),
+            rule(2, 10, "turn_off", &["turn_off"], true),
+        ];
+        let (actions, overrides, decision) = decide(&rules);
+
+        // Priority 10 runs first, then rule 1 turns the device back on
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].rule_id, 2);
+        assert_eq!(decision.action.as_deref(), Some("turn_on"));
+        assert_eq!(decision.rule_id, Some(1));
+        assert_eq!(overrides.len(), 1);
+    }
+
+    #[test]
+    fn test_decide_inverse_turn_off() {
+        let rules = vec![
+            rule(1, 100, "turn_on", &[], true),
+            // Never scheduled today: not an automated rule, no turn off
+            rule(2, 100, "turn_on", &[], false),
+        ];
+        let (actions, _, decision) = decide(&rules);
+
+        assert_eq!(actions.len(), 1);
+        assert_eq!(decision.action.as_deref(), Some("turn_off"));
+        assert_eq!(decision.rule_id, Some(1));
+    }
+
+    #[test]
+    fn test_decide_no_turn_off_between_point_in_time_events() {
+        // An appliance cycle started earlier and stops at a later minute:
+        // nothing is scheduled this hour, but the device stays on
+        let mut cycle = rule(1, 100, "turn_on", &[], true);
+        cycle.rule_type = "appliance_cycle".to_string();
+        let mut cron = rule(2, 100, "turn_on", &[], true);
+        cron.rule_type = "cron".to_string();
+        let (actions, _, decision) = decide(&[cycle, cron]);
+
+        assert!(actions.is_empty());
+        assert!(decision.action.is_none());
+    }
+
+    #[test]
+    fn test_decide_ignores_disabled_rules() {
+        let mut disabled = rule(1, 100, "turn_on", &["turn_on"], true);
+        disabled.is_enabled = false;
+        let (actions, overrides, decision) = decide(&[disabled]);
+
+        assert!(actions.is_empty());
+        assert!(overrides.is_empty());
+        assert!(decision.action.is_none());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::models::Price;
-use chrono::NaiveDateTime;
+use crate::models::{ChainedRuleConfig, Price, PriceThresholdConfig, ThermostatConfig, PROFILE_STEP_MINUTES};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use std::collections::{HashMap, HashSet};
 
 pub fn find_cheapest_hours(prices: &[Price], duration_minutes: i32) -> Vec<NaiveDateTime> {
     // Basic logic: Pick the N cheapest hours.
@@ -20,10 +21,561 @@ pub fn find_cheapest_hours(prices: &[Price], duration_minutes: i32) -> Vec<Naive
         .collect()
 }
 
+/// Pick the contiguous block of `hours_needed` hours with the lowest total price.
+/// Returns an empty schedule when there are not enough hours available.
+pub fn find_cheapest_contiguous_block(prices: &[Price], hours_needed: usize) -> Vec<NaiveDateTime> {
+    if hours_needed == 0 || prices.len() < hours_needed {
+        return Vec::new();
+    }
+
+    let mut sorted_prices = prices.to_vec();
+    sorted_prices.sort_by_key(|p| p.timestamp);
+
+    let mut best_start = 0;
+    let mut best_sum = f64::MAX;
+    for (i, window) in sorted_prices.windows(hours_needed).enumerate() {
+        // Skip windows with gaps (e.g. missing hours in the price data)
+        let span = window[hours_needed - 1].timestamp - window[0].timestamp;
+        if span.num_hours() != hours_needed as i64 - 1 {
+            continue;
+        }
+        let sum: f64 = window.iter().map(|p| p.price).sum();
+        if sum < best_sum {
+            best_sum = sum;
+            best_start = i;
+        }
+    }
+
+    if best_sum == f64::MAX {
+        return Vec::new();
+    }
+
+    sorted_prices[best_start..best_start + hours_needed]
+        .iter()
+        .map(|p| p.timestamp)
+        .collect()
+}
+
+/// Plan hours so that every rolling window of `window_hours` consecutive hours
+/// contains at least `hours_per_window` selected hours.
+///
+/// `prices` is the known horizon (future hours that can still be scheduled) and
+/// `committed` are hours already run or scheduled before it, which count towards
+/// the windows that overlap them. Only windows starting at or after `origin`
+/// (e.g., when the rule was created) are enforced, and only once they are fully
+/// known; if no full window fits yet, the known part must hold the required hours.
+///
+/// Windows are processed in order of their end; a window that falls short gets
+/// its cheapest free hours, preferring later hours on ties since those also count
+/// towards the following windows.
+pub fn plan_rolling_hours(
+    prices: &[Price],
+    committed: &[NaiveDateTime],
+    origin: NaiveDateTime,
+    hours_per_window: usize,
+    window_hours: usize,
+) -> Vec<NaiveDateTime> {
+    if prices.is_empty() || hours_per_window == 0 || window_hours == 0 {
+        return Vec::new();
+    }
+
+    let mut sorted_prices = prices.to_vec();
+    sorted_prices.sort_by_key(|p| p.timestamp);
+    let last = sorted_prices[sorted_prices.len() - 1].timestamp;
+    let span = Duration::hours(window_hours as i64 - 1);
+
+    // (start, end) of each enforced window, inclusive
+    let mut windows: Vec<(NaiveDateTime, NaiveDateTime)> = sorted_prices
+        .iter()
+        .map(|p| (p.timestamp - span, p.timestamp))
+        .filter(|(start, _)| *start >= origin)
+        .collect();
+    if windows.is_empty() {
+        windows.push((origin, last));
+    }
+
+    let mut selected = vec![false; sorted_prices.len()];
+    for (start, end) in windows {
+        let in_window: Vec<usize> = (0..sorted_prices.len())
+            .filter(|&i| sorted_prices[i].timestamp >= start && sorted_prices[i].timestamp <= end)
+            .collect();
+
+        let already = committed.iter().filter(|t| **t >= start && **t <= end).count()
+            + in_window.iter().filter(|&&i| selected[i]).count();
+        let missing = hours_per_window.saturating_sub(already);
+        if missing == 0 {
+            continue;
+        }
+
+        let mut candidates: Vec<usize> = in_window.into_iter().filter(|&i| !selected[i]).collect();
+        candidates.sort_by(|&a, &b| {
+            sorted_prices[a]
+                .price
+                .partial_cmp(&sorted_prices[b].price)
+                .unwrap()
+                .then(b.cmp(&a))
+        });
+        for i in candidates.into_iter().take(missing) {
+            selected[i] = true;
+        }
+    }
+
+    sorted_prices
+        .iter()
+        .zip(selected)
+        .filter(|(_, s)| *s)
+        .map(|(p, _)| p.timestamp)
+        .collect()
+}
+
+/// Plan the hours a price threshold rule is active, in chronological order.
+///
+/// Each hour compares the average price of that hour and the following
+/// `average_hours - 1` known hours. An inactive rule switches on when the
+/// average crosses `threshold` and only switches off again past
+/// `threshold_off`; after any switch the state is held for `min_dwell_hours`.
+/// The state starts inactive at the first known price, so callers pass the
+/// previous day's prices too when they want it carried over midnight.
+pub fn plan_price_threshold_hours(
+    prices: &[Price],
+    config: &PriceThresholdConfig,
+) -> Vec<NaiveDateTime> {
+    let mut sorted_prices = prices.to_vec();
+    sorted_prices.sort_by_key(|p| p.timestamp);
+    let lookahead = Duration::hours(config.average_hours.max(1) as i64);
+
+    let mut active = false;
+    let mut hours_in_state = config.min_dwell_hours;
+    let mut active_hours = Vec::new();
+
+    for (i, price) in sorted_prices.iter().enumerate() {
+        let window: Vec<f64> = sorted_prices[i..]
+            .iter()
+            .take_while(|p| p.timestamp < price.timestamp + lookahead)
+            .map(|p| p.price)
+            .collect();
+        let average = window.iter().sum::<f64>() / window.len() as f64;
+
+        if hours_in_state >= config.min_dwell_hours {
+            let switch = if active {
+                config.should_deactivate(average)
+            } else {
+                config.should_activate(average)
+            };
+            if switch {
+                active = !active;
+                hours_in_state = 0;
+            }
+        }
+        hours_in_state = hours_in_state.saturating_add(1);
+
+        if active {
+            active_hours.push(price.timestamp);
+        }
+    }
+
+    active_hours
+}
+
+/// Predecessor hours further apart than this belong to separate runs
+pub const CHAIN_RUN_GAP_HOURS: i64 = 12;
+
+/// End (exclusive) of each run of a predecessor's hours
+pub fn chain_run_ends(predecessor_hours: &[NaiveDateTime]) -> Vec<NaiveDateTime> {
+    let mut hours = predecessor_hours.to_vec();
+    hours.sort();
+    hours.dedup();
+
+    let mut ends = Vec::new();
+    for (i, hour) in hours.iter().enumerate() {
+        let last_of_run = hours
+            .get(i + 1)
+            .is_none_or(|next| *next - *hour > Duration::hours(CHAIN_RUN_GAP_HOURS));
+        if last_of_run {
+            ends.push(*hour + Duration::hours(1));
+        }
+    }
+    ends
+}
+
+/// Plan a chained rule after each run of its predecessor.
+///
+/// Each run gets `hours_needed` of the cheapest hours in
+/// `[end + gap_hours, end + gap_hours + max_delay_hours)` among `prices` (the
+/// hours that can still be scheduled). A run with `committed` hours in that
+/// range has started: it only gets the hours it still needs after them, and
+/// a contiguous run carries on in the hours right after its last one.
+pub fn plan_chained_hours(
+    prices: &[Price],
+    predecessor_hours: &[NaiveDateTime],
+    committed: &[NaiveDateTime],
+    config: &ChainedRuleConfig,
+) -> Vec<NaiveDateTime> {
+    let hours_needed = config.hours_needed as usize;
+    let mut planned: Vec<NaiveDateTime> = Vec::new();
+
+    for end in chain_run_ends(predecessor_hours) {
+        let earliest = end + Duration::hours(config.gap_hours as i64);
+        let latest = earliest + Duration::hours(config.max_delay_hours as i64);
+        let in_range = |t: &NaiveDateTime| *t >= earliest && *t < latest;
+
+        let started = committed.iter().filter(|t| in_range(t)).max().copied();
+        let done = committed.iter().filter(|t| in_range(t)).count();
+        let remaining = hours_needed.saturating_sub(done);
+        if remaining == 0 {
+            continue;
+        }
+
+        let candidates: Vec<Price> = prices
+            .iter()
+            .filter(|p| in_range(&p.timestamp) && !planned.contains(&p.timestamp))
+            .filter(|p| started.is_none_or(|last| p.timestamp > last))
+            .cloned()
+            .collect();
+
+        match (started, config.contiguous) {
+            (Some(last), true) => {
+                let next: Vec<NaiveDateTime> = (1..=remaining as i64)
+                    .map(|k| last + Duration::hours(k))
+                    .take_while(|t| candidates.iter().any(|p| p.timestamp == *t))
+                    .collect();
+                planned.extend(next);
+            }
+            (None, true) => planned.extend(find_cheapest_contiguous_block(&candidates, remaining)),
+            (_, false) => {
+                let mut sorted = candidates;
+                sorted.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+                planned.extend(sorted.iter().take(remaining).map(|p| p.timestamp));
+            }
+        }
+    }
+
+    planned.sort();
+    planned
+}
+
+/// Charging hours for an energy target
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChargingPlan {
+    /// Hours where charging starts, in order
+    pub hours: Vec<NaiveDateTime>,
+    /// When to stop within the partial last slot, if any
+    pub stop_at: Option<NaiveDateTime>,
+}
+
+/// Plan the cheapest hours that deliver `energy_kwh` at `power_kw` before
+/// `departure`. The slot that only needs part of an hour is the most
+/// expensive one picked, and it stops as soon as the energy is delivered.
+/// If there are not enough hours left, every remaining hour is used.
+pub fn plan_ev_charging(
+    prices: &[Price],
+    departure: NaiveDateTime,
+    energy_kwh: f64,
+    power_kw: f64,
+) -> ChargingPlan {
+    if energy_kwh <= 0.0 || power_kw <= 0.0 {
+        return ChargingPlan::default();
+    }
+
+    let slots = energy_kwh / power_kw;
+    let mut full_hours = (slots + 1e-9).floor() as usize;
+    let mut partial_minutes = ((slots - full_hours as f64) * 60.0 - 1e-6).ceil().max(0.0) as i64;
+    if partial_minutes >= 60 {
+        full_hours += 1;
+        partial_minutes = 0;
+    }
+    let slots_needed = full_hours + usize::from(partial_minutes > 0);
+
+    let mut candidates: Vec<Price> = prices
+        .iter()
+        .filter(|p| p.timestamp + Duration::hours(1) <= departure)
+        .cloned()
+        .collect();
+    candidates.sort_by_key(|p| p.timestamp);
+    candidates.dedup_by_key(|p| p.timestamp);
+    candidates.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap().then(a.timestamp.cmp(&b.timestamp)));
+    candidates.truncate(slots_needed);
+
+    let stop_at = (partial_minutes > 0 && candidates.len() == slots_needed)
+        .then(|| candidates.last().map(|p| p.timestamp + Duration::minutes(partial_minutes)))
+        .flatten();
+
+    let mut hours: Vec<NaiveDateTime> = candidates.iter().map(|p| p.timestamp).collect();
+    hours.sort();
+    ChargingPlan { hours, stop_at }
+}
+
+/// Energy delivered since `from` by a charging plan that already ran, where
+/// `starts` are the charging hours and `stops` the stops within those hours
+pub fn charged_energy_kwh(
+    starts: &[NaiveDateTime],
+    stops: &[NaiveDateTime],
+    from: NaiveDateTime,
+    power_kw: f64,
+) -> f64 {
+    starts
+        .iter()
+        .map(|start| {
+            let end = stops
+                .iter()
+                .copied()
+                .filter(|stop| stop > start && *stop < *start + Duration::hours(1))
+                .min()
+                .unwrap_or(*start + Duration::hours(1));
+            let begin = (*start).max(from);
+            if end > begin {
+                (end - begin).num_minutes() as f64 / 60.0 * power_kw
+            } else {
+                0.0
+            }
+        })
+        .sum()
+}
+
+/// Cheapest start of an uninterruptible cycle drawing `profile_w` (watts per
+/// `PROFILE_STEP_MINUTES`), starting at or after `earliest` on a step boundary
+/// and ending by `latest_end`. Each step is priced at the hour it falls in;
+/// starts that reach into hours without a price are skipped. Returns the
+/// start and its cost in €, the earliest one on ties.
+pub fn find_cheapest_profile_start(
+    prices: &[Price],
+    profile_w: &[f64],
+    earliest: NaiveDateTime,
+    latest_end: NaiveDateTime,
+) -> Option<(NaiveDateTime, f64)> {
+    if profile_w.is_empty() {
+        return None;
+    }
+    let price_at: HashMap<NaiveDateTime, f64> =
+        prices.iter().map(|p| (p.timestamp, p.price)).collect();
+    let step = Duration::minutes(PROFILE_STEP_MINUTES);
+    let cycle = step * profile_w.len() as i32;
+    let step_hours = PROFILE_STEP_MINUTES as f64 / 60.0;
+
+    // Round up to the next step boundary
+    let day_start = earliest.date().and_hms_opt(0, 0, 0).unwrap();
+    let steps_into_day = ((earliest - day_start).num_seconds() as f64
+        / step.num_seconds() as f64)
+        .ceil() as i32;
+    let mut start = day_start + step * steps_into_day;
+
+    let mut best: Option<(NaiveDateTime, f64)> = None;
+    while start + cycle <= latest_end {
+        let cost: Option<f64> = profile_w
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let t = start + step * i as i32;
+                let hour = t.date().and_hms_opt(t.hour(), 0, 0).unwrap();
+                price_at.get(&hour).map(|price| w / 1000.0 * step_hours * price)
+            })
+            .sum();
+        if let Some(cost) = cost
+            && best.is_none_or(|(_, best_cost)| cost < best_cost - 1e-12)
+        {
+            best = Some((start, cost));
+        }
+        start += step;
+    }
+    best
+}
+
+/// Thermostat setpoint changes over `prices`: the first hour and every hour
+/// whose setpoint differs from the previous one.
+///
+/// The `peak_hours` most expensive hours of each day get the relaxed setpoint.
+/// Up to `preheat_hours` hours right before each run of peak hours are boosted,
+/// but only when cheaper than that run on average. Other hours keep the
+/// comfort setpoint.
+pub fn plan_thermostat_setpoints(prices: &[Price], config: &ThermostatConfig) -> Vec<(NaiveDateTime, f64)> {
+    let mut prices = prices.to_vec();
+    prices.sort_by_key(|p| p.timestamp);
+    prices.dedup_by_key(|p| p.timestamp);
+
+    let mut by_day: HashMap<NaiveDate, Vec<&Price>> = HashMap::new();
+    for price in &prices {
+        by_day.entry(price.timestamp.date()).or_default().push(price);
+    }
+    let mut peak: HashSet<NaiveDateTime> = HashSet::new();
+    for day in by_day.values_mut() {
+        day.sort_by(|a, b| b.price.total_cmp(&a.price).then(a.timestamp.cmp(&b.timestamp)));
+        peak.extend(day.iter().take(config.peak_hours).map(|p| p.timestamp));
+    }
+
+    let mut boost: HashSet<NaiveDateTime> = HashSet::new();
+    let mut i = 0;
+    while i < prices.len() {
+        if !peak.contains(&prices[i].timestamp) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < prices.len()
+            && peak.contains(&prices[i].timestamp)
+            && (i == start || prices[i].timestamp == prices[i - 1].timestamp + Duration::hours(1))
+        {
+            i += 1;
+        }
+        let run_average = prices[start..i].iter().map(|p| p.price).sum::<f64>() / (i - start) as f64;
+        let mut expected = prices[start].timestamp;
+        for price in prices[..start].iter().rev().take(config.preheat_hours) {
+            expected -= Duration::hours(1);
+            if price.timestamp != expected || peak.contains(&price.timestamp) {
+                break;
+            }
+            if price.price < run_average {
+                boost.insert(price.timestamp);
+            }
+        }
+    }
+
+    let (boost_c, peak_c) = config.shifted_setpoints();
+    let mut changes: Vec<(NaiveDateTime, f64)> = Vec::new();
+    for price in &prices {
+        let setpoint = if peak.contains(&price.timestamp) {
+            peak_c
+        } else if boost.contains(&price.timestamp) {
+            boost_c
+        } else {
+            config.comfort_setpoint_c
+        };
+        if changes.last().is_none_or(|(_, last)| *last != setpoint) {
+            changes.push((price.timestamp, setpoint));
+        }
+    }
+    changes
+}
+
+/// Hours a rule wants to run, as seen by the load balancer
+#[derive(Debug, Clone)]
+pub enum PowerDemand {
+    /// Any `hours_needed` of the candidate hours (cheapest first)
+    Flexible {
+        candidates: Vec<Price>,
+        hours_needed: usize,
+        contiguous: bool,
+    },
+    /// Exactly these hours; the ones that don't fit are dropped
+    Fixed(Vec<NaiveDateTime>),
+}
+
+impl PowerDemand {
+    /// Every hour the demand could use
+    pub fn hours_considered(&self) -> Vec<NaiveDateTime> {
+        match self {
+            PowerDemand::Flexible { candidates, .. } => {
+                candidates.iter().map(|p| p.timestamp).collect()
+            }
+            PowerDemand::Fixed(hours) => hours.clone(),
+        }
+    }
+
+    /// The hours picked when there is no power limit
+    pub fn hours(&self) -> Vec<NaiveDateTime> {
+        plan_within_power_limit(self, &PowerBudget::unlimited())
+    }
+}
+
+/// Power available to one device, in watts
+#[derive(Debug, Clone, Default)]
+pub struct PowerBudget {
+    /// Power the device draws when on
+    pub power_w: f64,
+    /// Contracted power of the household
+    pub limit_w: f64,
+    /// Power already committed per hour by the other devices
+    pub load_w: HashMap<NaiveDateTime, f64>,
+}
+
+impl PowerBudget {
+    /// A budget that never limits anything
+    pub fn unlimited() -> Self {
+        Self {
+            limit_w: f64::INFINITY,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the device can be on during `hour`
+    pub fn fits(&self, hour: &NaiveDateTime) -> bool {
+        self.load_w.get(hour).copied().unwrap_or(0.0) + self.power_w <= self.limit_w
+    }
+}
+
+/// A turn_on planned (or run) for one of the user's devices
+#[derive(Debug, Clone)]
+pub struct PlannedLoad {
+    pub at: NaiveDateTime,
+    pub device_id: i32,
+    pub power_w: Option<i32>,
+    /// Length of an appliance cycle started at `at`; None for hourly executions
+    pub cycle: Option<Duration>,
+}
+
+/// Hours (on the hour) overlapping `[start, end)`
+pub fn hours_overlapping(start: NaiveDateTime, end: NaiveDateTime) -> Vec<NaiveDateTime> {
+    let mut hour = start.date().and_hms_opt(start.hour(), 0, 0).unwrap();
+    let mut hours = Vec::new();
+    while hour < end {
+        hours.push(hour);
+        hour += Duration::hours(1);
+    }
+    hours
+}
+
+/// Power per hour drawn by planned loads. A device turned on by several
+/// rules draws its power once; an appliance cycle draws it in every hour it
+/// overlaps. Devices without a known power don't count.
+pub fn hourly_load_w(planned: &[PlannedLoad]) -> HashMap<NaiveDateTime, f64> {
+    let mut on: HashMap<(NaiveDateTime, i32), f64> = HashMap::new();
+    for load in planned {
+        let hours = match load.cycle {
+            Some(cycle) => hours_overlapping(load.at, load.at + cycle),
+            None => vec![load.at],
+        };
+        for hour in hours {
+            on.insert((hour, load.device_id), load.power_w.unwrap_or(0) as f64);
+        }
+    }
+
+    let mut load_w = HashMap::new();
+    for ((hour, _), power_w) in on {
+        *load_w.entry(hour).or_default() += power_w;
+    }
+    load_w
+}
+
+/// Keep a rule's plan within the contracted power: only hours where the
+/// device fits next to the other devices' load are used.
+pub fn plan_within_power_limit(demand: &PowerDemand, budget: &PowerBudget) -> Vec<NaiveDateTime> {
+    let fits = |t: &NaiveDateTime| budget.fits(t);
+
+    match demand {
+        PowerDemand::Flexible {
+            candidates,
+            hours_needed,
+            contiguous,
+        } => {
+            let available: Vec<Price> = candidates
+                .iter()
+                .filter(|p| fits(&p.timestamp))
+                .cloned()
+                .collect();
+            if *contiguous {
+                return find_cheapest_contiguous_block(&available, *hours_needed);
+            }
+            let mut sorted = available;
+            sorted.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+            sorted.iter().take(*hours_needed).map(|p| p.timestamp).collect()
+        }
+        PowerDemand::Fixed(hours) => hours.iter().filter(|t| fits(t)).copied().collect(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{NaiveDate, Timelike};
+    use chrono::{Datelike, NaiveDate, Timelike};
 
     fn make_price(hour: u32, price: f64) -> Price {
         let date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
@@ -143,4 +695,481 @@ mod tests {
         assert!(hours.contains(&22));
         assert!(hours.contains(&21));
     }
+
+    #[test]
+    fn test_find_cheapest_contiguous_block() {
+        let prices = vec![
+            make_price(0, 0.05),
+            make_price(1, 0.30),
+            make_price(2, 0.10),
+            make_price(3, 0.11),
+            make_price(4, 0.40),
+        ];
+
+        let result = find_cheapest_contiguous_block(&prices, 2);
+        let hours: Vec<u32> = result.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_find_cheapest_contiguous_block_skips_gaps() {
+        // Hours 1 and 3 are adjacent in the slice but not in time
+        let prices = vec![make_price(1, 0.01), make_price(3, 0.01), make_price(4, 0.20)];
+
+        let result = find_cheapest_contiguous_block(&prices, 2);
+        let hours: Vec<u32> = result.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![3, 4]);
+        assert!(find_cheapest_contiguous_block(&prices, 4).is_empty());
+    }
+
+    fn make_price_at(day: u32, hour: u32, price: f64) -> Price {
+        let timestamp = NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap();
+        Price {
+            timestamp,
+            price,
+            source: "test".to_string(),
+        }
+    }
+
+    fn threshold_config(threshold_off: Option<f64>, min_dwell_hours: u32, average_hours: u32) -> PriceThresholdConfig {
+        PriceThresholdConfig {
+            threshold: 0.10,
+            comparison: "below".to_string(),
+            threshold_off,
+            min_dwell_hours,
+            average_hours,
+        }
+    }
+
+    fn active_hours(prices: &[Price], config: &PriceThresholdConfig) -> Vec<u32> {
+        plan_price_threshold_hours(prices, config)
+            .iter()
+            .map(|t| t.hour())
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_price_threshold_plain() {
+        let prices: Vec<Price> = [0.09, 0.11, 0.10, 0.12]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        // A price exactly at the threshold doesn't switch the device on
+        assert_eq!(active_hours(&prices, &threshold_config(None, 0, 1)), vec![0]);
+    }
+
+    #[test]
+    fn test_plan_price_threshold_hysteresis_band() {
+        // Prices hovering around the threshold stay on inside the band
+        let prices: Vec<Price> = [0.09, 0.11, 0.105, 0.13, 0.11]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        assert_eq!(active_hours(&prices, &threshold_config(Some(0.12), 0, 1)), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_plan_price_threshold_min_dwell() {
+        let prices: Vec<Price> = [0.09, 0.15, 0.15, 0.09, 0.15, 0.15]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        // On at 0 and held until 2, off from 2 and held until 4
+        assert_eq!(active_hours(&prices, &threshold_config(None, 2, 1)), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_plan_price_threshold_rolling_average() {
+        // A single cheap hour between expensive ones is smoothed away
+        let prices: Vec<Price> = [0.15, 0.05, 0.15, 0.15, 0.08, 0.08, 0.08]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        assert_eq!(active_hours(&prices, &threshold_config(None, 0, 1)), vec![1, 4, 5, 6]);
+        assert_eq!(active_hours(&prices, &threshold_config(None, 0, 3)), vec![4, 5, 6]);
+    }
+
+    fn chain_config(hours_needed: u32, gap_hours: u32, contiguous: bool) -> ChainedRuleConfig {
+        ChainedRuleConfig {
+            after_rule_id: 1,
+            hours_needed,
+            gap_hours,
+            max_delay_hours: 6,
+            contiguous,
+        }
+    }
+
+    #[test]
+    fn test_chain_run_ends_splits_on_long_gaps() {
+        let hours: Vec<NaiveDateTime> = [1, 3, 4].iter().map(|h| make_price(*h, 0.0).timestamp).collect();
+        let ends = chain_run_ends(&hours);
+        assert_eq!(ends.len(), 1);
+        assert_eq!(ends[0].hour(), 5);
+
+        let next_day = hours[0] + Duration::hours(24);
+        assert_eq!(chain_run_ends(&[hours[0], next_day]).len(), 2);
+    }
+
+    #[test]
+    fn test_plan_chained_hours_after_predecessor_end() {
+        let prices: Vec<Price> = [0.05, 0.05, 0.20, 0.15, 0.10, 0.10, 0.30, 0.01, 0.01, 0.01]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        let predecessor = vec![prices[0].timestamp, prices[1].timestamp];
+
+        // Window is [2, 8): cheapest contiguous pair inside it is 4-5
+        let plan = plan_chained_hours(&prices, &predecessor, &[], &chain_config(2, 0, true));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![4, 5]);
+
+        // A gap of one hour moves the window to [3, 9)
+        let plan = plan_chained_hours(&prices, &predecessor, &[], &chain_config(2, 1, true));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![7, 8]);
+
+        let plan = plan_chained_hours(&prices, &predecessor, &[], &chain_config(2, 0, false));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![4, 7]);
+    }
+
+    #[test]
+    fn test_plan_chained_hours_finishes_started_runs() {
+        let prices: Vec<Price> = [0.1, 0.1, 0.1, 0.3, 0.2, 0.01, 0.01, 0.1, 0.1, 0.1]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        let predecessor = vec![prices[0].timestamp];
+        // The run started at 02:00; only hours from 03:00 on can still be planned
+        let committed = vec![prices[2].timestamp];
+        let future = &prices[3..];
+
+        // A contiguous run carries on right after its last hour
+        let plan = plan_chained_hours(future, &predecessor, &committed, &chain_config(3, 0, true));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![3, 4]);
+
+        // Otherwise it takes the cheapest hours it still needs
+        let plan = plan_chained_hours(future, &predecessor, &committed, &chain_config(3, 0, false));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![5, 6]);
+
+        // A finished run is left alone
+        let committed = vec![prices[1].timestamp, prices[2].timestamp];
+        assert!(plan_chained_hours(future, &predecessor, &committed, &chain_config(2, 0, true)).is_empty());
+        assert!(plan_chained_hours(&prices, &[], &[], &chain_config(2, 0, true)).is_empty());
+    }
+
+    #[test]
+    fn test_plan_ev_charging_partial_slot_in_most_expensive_hour() {
+        let prices: Vec<Price> = [0.30, 0.10, 0.05, 0.20, 0.08, 0.25]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        let departure = prices[5].timestamp;
+
+        // 10 kWh at 4 kW: 2 full hours and 30 minutes
+        let plan = plan_ev_charging(&prices, departure, 10.0, 4.0);
+        let hours: Vec<u32> = plan.hours.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![1, 2, 4]);
+        assert_eq!(plan.stop_at, Some(prices[1].timestamp + Duration::minutes(30)));
+
+        // Exact multiples need no partial slot
+        let plan = plan_ev_charging(&prices, departure, 8.0, 4.0);
+        assert_eq!(plan.hours.len(), 2);
+        assert_eq!(plan.stop_at, None);
+    }
+
+    #[test]
+    fn test_plan_ev_charging_uses_every_hour_before_departure() {
+        let prices: Vec<Price> = (0..6).map(|h| make_price(h, 0.10)).collect();
+        let departure = prices[2].timestamp + Duration::minutes(30);
+
+        // Only hours 0 and 1 end before departure
+        let plan = plan_ev_charging(&prices, departure, 20.0, 4.0);
+        let hours: Vec<u32> = plan.hours.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![0, 1]);
+        assert_eq!(plan.stop_at, None);
+    }
+
+    #[test]
+    fn test_charged_energy_counts_partial_slots() {
+        let starts = vec![make_price(1, 0.0).timestamp, make_price(3, 0.0).timestamp];
+        let stops = vec![starts[1] + Duration::minutes(15)];
+
+        // 1h + 15min at 4 kW
+        assert!((charged_energy_kwh(&starts, &stops, starts[0], 4.0) - 5.0).abs() < 1e-9);
+        // Only what ran after the state of charge reading counts
+        let from = starts[0] + Duration::minutes(30);
+        assert!((charged_energy_kwh(&starts, &stops, from, 4.0) - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_profile_start_weighs_heavy_phase() {
+        // A 1h cycle drawing 2000 W in the first 15 minutes, then 200 W
+        let profile = [2000.0, 200.0, 200.0, 200.0];
+        let prices: Vec<Price> = [0.20, 0.05, 0.30, 0.30]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        let earliest = prices[0].timestamp;
+        let latest_end = prices[3].timestamp + Duration::hours(1);
+
+        // The heavy first phase must land in the cheap hour 1, so starting
+        // at 01:00 beats straddling hours 0-1 or sliding into hour 2
+        let (start, cost) = find_cheapest_profile_start(&prices, &profile, earliest, latest_end).unwrap();
+        assert_eq!(start, prices[1].timestamp);
+        assert!((cost - 2.6 * 0.25 * 0.05).abs() < 1e-9);
+
+        // Hours without a price can't be covered
+        let (start, _) =
+            find_cheapest_profile_start(&prices[..2], &profile, earliest, latest_end).unwrap();
+        assert_eq!(start, prices[1].timestamp);
+    }
+
+    #[test]
+    fn test_profile_start_rounds_up_and_respects_end() {
+        let profile = [1000.0; 8];
+        let prices: Vec<Price> = (0..4).map(|h| make_price(h, 0.10)).collect();
+        let earliest = prices[0].timestamp + Duration::minutes(7);
+
+        let (start, _) = find_cheapest_profile_start(
+            &prices,
+            &profile,
+            earliest,
+            prices[3].timestamp,
+        )
+        .unwrap();
+        assert_eq!(start, prices[0].timestamp + Duration::minutes(15));
+
+        // A 2h cycle doesn't fit in 1h45
+        assert!(find_cheapest_profile_start(
+            &prices,
+            &profile,
+            earliest,
+            prices[2].timestamp
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_plan_within_power_limit_moves_to_free_hours() {
+        // Cheapest hours 0 and 1 are taken by a 3 kW device under a 4.6 kW limit
+        let prices: Vec<Price> = [0.05, 0.06, 0.10, 0.12, 0.08]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+        let load = HashMap::from([(prices[0].timestamp, 3000.0), (prices[1].timestamp, 3000.0)]);
+        let demand = PowerDemand::Flexible {
+            candidates: prices.clone(),
+            hours_needed: 2,
+            contiguous: false,
+        };
+
+        let budget = |power_w| PowerBudget {
+            power_w,
+            limit_w: 4600.0,
+            load_w: load.clone(),
+        };
+
+        let plan = plan_within_power_limit(&demand, &budget(2000.0));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![4, 2]);
+
+        // A small device still fits next to the big one
+        let plan = plan_within_power_limit(&demand, &budget(1500.0));
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_plan_within_power_limit_fixed_hours() {
+        let hours: Vec<NaiveDateTime> = (0..3).map(|h| make_price(h, 0.1).timestamp).collect();
+        let budget = PowerBudget {
+            power_w: 1000.0,
+            limit_w: 4600.0,
+            load_w: HashMap::from([(hours[1], 4000.0)]),
+        };
+
+        let plan = plan_within_power_limit(&PowerDemand::Fixed(hours.clone()), &budget);
+        assert_eq!(plan, vec![hours[0], hours[2]]);
+    }
+
+    #[test]
+    fn test_appliance_cycle_occupies_every_hour_it_overlaps() {
+        let at = |h: u32, m: u32| make_price(h, 0.1).timestamp.with_minute(m).unwrap();
+        assert_eq!(
+            hours_overlapping(at(14, 15), at(15, 45)),
+            vec![at(14, 0), at(15, 0)]
+        );
+        assert_eq!(hours_overlapping(at(14, 0), at(15, 0)), vec![at(14, 0)]);
+
+        // A 3 kW washer cycle from 14:15 to 15:45 and a 1 kW heater at 15:00
+        let planned = vec![
+            PlannedLoad {
+                at: at(14, 15),
+                device_id: 1,
+                power_w: Some(3000),
+                cycle: Some(Duration::minutes(90)),
+            },
+            PlannedLoad {
+                at: at(15, 0),
+                device_id: 2,
+                power_w: Some(1000),
+                cycle: None,
+            },
+        ];
+        let load_w = hourly_load_w(&planned);
+        assert_eq!(load_w.get(&at(14, 0)), Some(&3000.0));
+        assert_eq!(load_w.get(&at(15, 0)), Some(&4000.0));
+        assert_eq!(load_w.get(&at(16, 0)), None);
+
+        // A 2 kW cheapest_hours rule under 4.6 kW can't use the cycle's hours
+        let prices: Vec<Price> = [(14, 0.05), (15, 0.06), (16, 0.10), (17, 0.12)]
+            .iter()
+            .map(|(h, p)| make_price(*h, *p))
+            .collect();
+        let demand = PowerDemand::Flexible {
+            candidates: prices,
+            hours_needed: 2,
+            contiguous: false,
+        };
+        let budget = PowerBudget {
+            power_w: 2000.0,
+            limit_w: 4600.0,
+            load_w,
+        };
+        let plan = plan_within_power_limit(&demand, &budget);
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![16, 17]);
+    }
+
+    #[test]
+    fn test_plan_rolling_hours_crosses_midnight() {
+        // 10:00 on the 15th to 23:00 on the 16th; the cheapest hours straddle midnight
+        let mut prices = Vec::new();
+        for hour in 10..24 {
+            let price = if hour >= 22 { 0.01 } else { 0.20 };
+            prices.push(make_price_at(15, hour, price));
+        }
+        for hour in 0..24 {
+            let price = if hour < 2 { 0.01 } else { 0.15 };
+            prices.push(make_price_at(16, hour, price));
+        }
+        let origin = prices[0].timestamp;
+
+        let plan = plan_rolling_hours(&prices, &[], origin, 4, 24);
+        let hours: Vec<(u32, u32)> = plan.iter().map(|t| (t.day(), t.hour())).collect();
+
+        // The cheap block around midnight is used as a whole instead of being split
+        // per day; the last window (00:00-23:00 on the 16th) then needs 2 more hours
+        assert_eq!(
+            hours,
+            vec![(15, 22), (15, 23), (16, 0), (16, 1), (16, 22), (16, 23)]
+        );
+    }
+
+    #[test]
+    fn test_plan_rolling_hours_every_window_satisfied() {
+        let prices: Vec<Price> = (0..48)
+            .map(|i| make_price_at(15 + i / 24, i % 24, ((i * 7) % 11) as f64 / 100.0))
+            .collect();
+        let origin = prices[0].timestamp;
+
+        let plan = plan_rolling_hours(&prices, &[], origin, 3, 24);
+        for price in &prices[..=24] {
+            let from = price.timestamp;
+            let to = from + Duration::hours(23);
+            let count = plan.iter().filter(|t| **t >= from && **t <= to).count();
+            assert!(count >= 3, "window starting {} has {} hours", from, count);
+        }
+    }
+
+    #[test]
+    fn test_plan_rolling_hours_counts_committed_hours() {
+        // Three hours already ran early on the 15th; the window ending at
+        // 06:00 on the 16th still contains them
+        let committed: Vec<_> = (4..7).map(|h| make_price_at(15, h, 0.0).timestamp).collect();
+        let prices: Vec<Price> = (0..7).map(|h| make_price_at(16, h, 0.30)).collect();
+        let origin = make_price_at(15, 0, 0.0).timestamp;
+
+        let plan = plan_rolling_hours(&prices, &committed, origin, 3, 24);
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+
+        // The windows ending at 04:00, 05:00 and 06:00 lose one committed hour each
+        assert_eq!(hours, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_plan_rolling_hours_partial_horizon() {
+        // Less than a full window known: the known part holds the required hours
+        let prices = vec![
+            make_price_at(15, 20, 0.30),
+            make_price_at(15, 21, 0.10),
+            make_price_at(15, 22, 0.05),
+            make_price_at(15, 23, 0.20),
+        ];
+        let plan = plan_rolling_hours(&prices, &[], prices[0].timestamp, 2, 24);
+        let hours: Vec<u32> = plan.iter().map(|t| t.hour()).collect();
+        assert_eq!(hours, vec![21, 22]);
+    }
+
+    fn thermostat_config(mode: &str) -> ThermostatConfig {
+        ThermostatConfig {
+            mode: mode.to_string(),
+            comfort_setpoint_c: 20.0,
+            min_setpoint_c: 18.0,
+            max_setpoint_c: 22.0,
+            peak_hours: 2,
+            preheat_hours: 2,
+        }
+    }
+
+    #[test]
+    fn test_plan_thermostat_setpoints_preheats_before_peak() {
+        let prices: Vec<Price> = [0.10, 0.08, 0.09, 0.30, 0.35, 0.12]
+            .iter()
+            .enumerate()
+            .map(|(h, p)| make_price(h as u32, *p))
+            .collect();
+
+        let changes = plan_thermostat_setpoints(&prices, &thermostat_config("heat"));
+        let changes: Vec<(u32, f64)> = changes.iter().map(|(t, c)| (t.hour(), *c)).collect();
+        assert_eq!(changes, vec![(0, 20.0), (1, 22.0), (3, 18.0), (5, 20.0)]);
+
+        // Cooling inverts the shift
+        let changes = plan_thermostat_setpoints(&prices, &thermostat_config("cool"));
+        assert_eq!(changes[1].1, 18.0);
+        assert_eq!(changes[2].1, 22.0);
+    }
+
+    #[test]
+    fn test_plan_thermostat_setpoints_skips_expensive_preheat() {
+        // Peaks are per day: the evening before 00:00 costs more than that peak
+        let prices = vec![
+            make_price_at(15, 21, 0.60),
+            make_price_at(15, 22, 0.50),
+            make_price_at(15, 23, 0.50),
+            make_price_at(16, 0, 0.30),
+            make_price_at(16, 1, 0.05),
+        ];
+        let mut config = thermostat_config("heat");
+        config.peak_hours = 1;
+
+        let changes = plan_thermostat_setpoints(&prices, &config);
+        let changes: Vec<(u32, f64)> = changes.iter().map(|(t, c)| (t.hour(), *c)).collect();
+        assert_eq!(changes, vec![(21, 18.0), (22, 20.0), (0, 18.0), (1, 20.0)]);
+    }
 }
@@ -0,0 +1,269 @@
+use crate::models::{Price, WaterHeaterConfig};
+use chrono::{Duration, NaiveDateTime, Timelike};
+use std::collections::{HashMap, HashSet};
+
+/// Heat capacity of water, in Wh per liter and kelvin
+const WATER_HEAT_CAPACITY_WH_PER_L_K: f64 = 1.163;
+
+const EPSILON: f64 = 1e-6;
+
+/// Hot water tank modelled as a single well-mixed volume, in 1 hour steps
+#[derive(Debug, Clone)]
+pub struct TankModel {
+    pub volume_liters: f64,
+    pub element_power_w: f64,
+    pub heat_loss_w_per_k: f64,
+    pub ambient_temp_c: f64,
+    pub cold_water_temp_c: f64,
+    pub max_temp_c: f64,
+}
+
+impl TankModel {
+    pub fn from_config(config: &WaterHeaterConfig) -> Self {
+        Self {
+            volume_liters: config.volume_liters,
+            element_power_w: config.element_power_w,
+            heat_loss_w_per_k: config.heat_loss_w_per_k,
+            ambient_temp_c: config.ambient_temp_c,
+            cold_water_temp_c: config.cold_water_temp_c,
+            max_temp_c: config.max_temp_c,
+        }
+    }
+
+    /// Temperature after one hour, with the element on or off. The thermostat
+    /// stops heating at `max_temp_c`.
+    pub fn step(&self, temp_c: f64, heating: bool) -> f64 {
+        let capacity_wh_per_k = self.volume_liters * WATER_HEAT_CAPACITY_WH_PER_L_K;
+        let loss_w = self.heat_loss_w_per_k * (temp_c - self.ambient_temp_c);
+        let mut next = temp_c - loss_w / capacity_wh_per_k;
+        if heating && next < self.max_temp_c {
+            next = (next + self.element_power_w / capacity_wh_per_k).min(self.max_temp_c);
+        }
+        next
+    }
+
+    /// Temperature after drawing `liters`, replaced by cold water
+    pub fn draw(&self, temp_c: f64, liters: f64) -> f64 {
+        let liters = liters.clamp(0.0, self.volume_liters);
+        (temp_c * (self.volume_liters - liters) + self.cold_water_temp_c * liters) / self.volume_liters
+    }
+}
+
+/// A time hot water is needed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub at: NaiveDateTime,
+    pub min_temp_c: f64,
+    pub draw_liters: f64,
+}
+
+/// Heating hours chosen for a tank
+#[derive(Debug, Clone, Default)]
+pub struct HeatingPlan {
+    pub hours: Vec<NaiveDateTime>,
+    /// Usages that can't reach their minimum temperature
+    pub unmet: Vec<NaiveDateTime>,
+    pub cost_eur: f64,
+}
+
+fn hour_start(t: NaiveDateTime) -> NaiveDateTime {
+    t.date().and_hms_opt(t.hour(), 0, 0).unwrap()
+}
+
+/// Simulate the tank from `start` (a whole hour) at `start_temp_c`, returning
+/// the temperature right before each usage. Heating only counts for whole
+/// hours that end before a usage.
+pub fn simulate(
+    model: &TankModel,
+    start: NaiveDateTime,
+    start_temp_c: f64,
+    heating: &HashSet<NaiveDateTime>,
+    usages: &[Usage],
+) -> Vec<f64> {
+    let mut temps = Vec::with_capacity(usages.len());
+    let mut temp = start_temp_c;
+    let mut hour = start;
+
+    for usage in usages {
+        let usage_hour = hour_start(usage.at);
+        while hour < usage_hour {
+            temp = model.step(temp, heating.contains(&hour));
+            hour += Duration::hours(1);
+        }
+        temps.push(temp);
+        temp = model.draw(temp, usage.draw_liters);
+    }
+    temps
+}
+
+/// Cheapest heating hours keeping the tank at each usage's minimum temperature.
+///
+/// Usages are handled in order; while one is too cold, the priced hour before it
+/// with the lowest cost per degree gained at that usage is added. Usages that no
+/// remaining hour can lift are reported as unmet.
+pub fn plan_heating(
+    model: &TankModel,
+    prices: &[Price],
+    start: NaiveDateTime,
+    start_temp_c: f64,
+    usages: &[Usage],
+) -> HeatingPlan {
+    let price_at: HashMap<NaiveDateTime, f64> = prices
+        .iter()
+        .filter(|p| p.timestamp >= start)
+        .map(|p| (p.timestamp, p.price))
+        .collect();
+    let hour_cost = |hour: &NaiveDateTime| price_at[hour] * model.element_power_w / 1000.0;
+
+    let mut heating: HashSet<NaiveDateTime> = HashSet::new();
+    let mut unmet = Vec::new();
+
+    for (i, usage) in usages.iter().enumerate() {
+        let usage_hour = hour_start(usage.at);
+        loop {
+            let temp = simulate(model, start, start_temp_c, &heating, &usages[..=i])[i];
+            if temp >= usage.min_temp_c - EPSILON {
+                break;
+            }
+
+            let candidates: Vec<NaiveDateTime> = price_at
+                .keys()
+                .filter(|h| **h < usage_hour && !heating.contains(*h))
+                .copied()
+                .collect();
+            let mut best: Option<(NaiveDateTime, f64)> = None;
+            for hour in &candidates {
+                heating.insert(*hour);
+                let gain = simulate(model, start, start_temp_c, &heating, &usages[..=i])[i] - temp;
+                heating.remove(hour);
+                if gain <= EPSILON {
+                    continue;
+                }
+                // Later hours lose less heat, so they win ties
+                let score = hour_cost(hour) / gain;
+                if best.is_none_or(|(best_hour, best_score)| {
+                    score < best_score - EPSILON || (score <= best_score + EPSILON && *hour > best_hour)
+                }) {
+                    best = Some((*hour, score));
+                }
+            }
+
+            match best {
+                Some((hour, _)) => {
+                    heating.insert(hour);
+                }
+                None => {
+                    unmet.push(usage.at);
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut hours: Vec<NaiveDateTime> = heating.into_iter().collect();
+    hours.sort();
+    let cost_eur = hours.iter().map(hour_cost).sum();
+    HeatingPlan {
+        hours,
+        unmet,
+        cost_eur,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn model() -> TankModel {
+        TankModel {
+            volume_liters: 100.0,
+            element_power_w: 2000.0,
+            heat_loss_w_per_k: 2.0,
+            ambient_temp_c: 20.0,
+            cold_water_temp_c: 10.0,
+            max_temp_c: 65.0,
+        }
+    }
+
+    fn hour(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 10).unwrap().and_hms_opt(0, 0, 0).unwrap() + Duration::hours(h)
+    }
+
+    fn prices(values: &[f64]) -> Vec<Price> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(h, p)| Price {
+                timestamp: hour(h as i64),
+                price: *p,
+                source: "test".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_step_heats_loses_and_caps() {
+        let model = model();
+        // 2000 Wh into 116.3 Wh/K is ~17.2 K
+        assert!((model.step(40.0, true) - 40.0 - (2000.0 - 40.0) / 116.3).abs() < 1e-9);
+        assert!(model.step(40.0, false) < 40.0);
+        assert_eq!(model.step(60.0, true), 65.0);
+        assert!((model.draw(60.0, 50.0) - 35.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_plan_heating_picks_cheap_hours_before_usage() {
+        let model = model();
+        let usages = [Usage {
+            at: hour(6),
+            min_temp_c: 50.0,
+            draw_liters: 0.0,
+        }];
+
+        // From 30 °C the tank needs two heating hours
+        let plan = plan_heating(&model, &prices(&[0.30, 0.05, 0.25, 0.06, 0.20, 0.10, 0.01]), hour(0), 30.0, &usages);
+        assert_eq!(plan.hours, vec![hour(1), hour(3)]);
+        assert!(plan.unmet.is_empty());
+
+        let temps = simulate(&model, hour(0), 30.0, &plan.hours.iter().copied().collect(), &usages);
+        assert!(temps[0] >= 50.0);
+    }
+
+    #[test]
+    fn test_plan_heating_recovers_after_draw() {
+        let model = model();
+        let usages = [
+            Usage {
+                at: hour(2),
+                min_temp_c: 45.0,
+                draw_liters: 50.0,
+            },
+            Usage {
+                at: hour(5),
+                min_temp_c: 45.0,
+                draw_liters: 0.0,
+            },
+        ];
+
+        let plan = plan_heating(&model, &prices(&[0.10; 6]), hour(0), 50.0, &usages);
+        // Already warm for the first usage; the draw leaves ~30 °C to recover
+        assert!(plan.hours.iter().all(|h| *h >= hour(2)));
+        let temps = simulate(&model, hour(0), 50.0, &plan.hours.iter().copied().collect(), &usages);
+        assert!(temps.iter().all(|t| *t >= 45.0 - 1e-6));
+    }
+
+    #[test]
+    fn test_plan_heating_reports_unreachable_usage() {
+        let model = model();
+        let usages = [Usage {
+            at: hour(1),
+            min_temp_c: 60.0,
+            draw_liters: 0.0,
+        }];
+
+        let plan = plan_heating(&model, &prices(&[0.10, 0.10]), hour(0), 20.0, &usages);
+        assert_eq!(plan.hours, vec![hour(0)]);
+        assert_eq!(plan.unmet, vec![hour(1)]);
+    }
+}